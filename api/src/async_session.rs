@@ -0,0 +1,428 @@
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use crate::{
+    check_packet, compressed_payload, is_version_at_least, labels_payload, last_will_payload, max_text_len, sequenced_payload, timestamped_payload, Packet, PacketBuffer, PacketType, SessionError, Severity,
+    ACK, ACK_FLAG, ASSOC_ACCEPT, COMPRESSION_SINCE_VERSION, DEFAULT_ACK_TIMEOUT, DEFAULT_CONNECT_TIMEOUT, DEFAULT_WRITE_TIMEOUT, MAX_MSG_LEN, TIMESTAMP_FLAG,
+    VERSIONED_ASSOC_ACCEPT, VERSIONED_ASSOC_REQUEST,
+};
+
+/// `Session`, for async services: the same packets, sent over a `tokio::net::TcpStream` without
+/// blocking the runtime. Requires the `tokio` feature.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), api::SessionError> {
+/// use api::AsyncSession;
+///
+/// let mut session = AsyncSession::connect("localhost:44444").await?;
+/// session.send_alert("disk full").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncSession {
+    connection: TcpStream,
+    //The last name sent successfully.
+    name: Option<String>,
+    //What the server reported during association, if it reports one at all.
+    server_version: Option<String>,
+    //Broadcast packets read while waiting for an ACK, for next_incoming.
+    incoming: VecDeque<Packet>,
+    write_timeout: Option<Duration>,
+    ack_timeout: Option<Duration>,
+    //The sequence number of the next packet, once enable_sequence_numbers has been called.
+    next_sequence: Option<u32>,
+    //Whether enable_compression has been called.
+    is_compressing: bool,
+}
+
+//Wait on an I/O future, at most `limit` if there is one.
+async fn with_timeout<T, F: std::future::Future<Output = Result<T, Error>>>(limit: Option<Duration>, future: F) -> Result<T, Error> {
+    match limit {
+        Some(limit) => match timeout(limit, future).await {
+            Ok(result) => return result,
+            Err(_) => return Err(Error::from(ErrorKind::TimedOut)),
+        },
+        None => return future.await,
+    }
+}
+
+//The async twin of associate(); see there.
+async fn associate(connection: &mut TcpStream) -> Result<Option<String>, SessionError> {
+    connection.write_all(&VERSIONED_ASSOC_REQUEST).await?;
+
+    let mut buf: [u8; 2] = [0; 2];
+    if connection.read_exact(&mut buf).await.is_err() {
+        return Err(SessionError::NotAssociated);
+    }
+
+    if buf == ASSOC_ACCEPT {
+        return Ok(None);
+    }
+    if buf[1] != VERSIONED_ASSOC_ACCEPT {
+        return Err(SessionError::NotAssociated);
+    }
+
+    let mut version = vec![0; (buf[0] as usize).saturating_sub(1)];
+    connection.read_exact(&mut version).await?;
+    let version = String::from_utf8_lossy(&version).into_owned();
+
+    return Ok(Some(version));
+}
+
+//The async twin of read_from_server(); None is an ACK.
+async fn read_from_server(connection: &mut TcpStream) -> Result<Option<Packet>, SessionError> {
+    let mut header: [u8; 2] = [0; 2];
+    connection.read_exact(&mut header).await?;
+    if header == ACK {
+        return Ok(None);
+    }
+
+    let mut text = vec![0; (header[0] as usize).saturating_sub(1)];
+    connection.read_exact(&mut text).await?;
+
+    let kind = PacketType::from_type_number(header[1])?;
+    let text = if text.len() > 0 { Some(String::from_utf8_lossy(&text).into_owned()) } else { None };
+    return Ok(Some(Packet { kind: kind, text: text }));
+}
+
+impl AsyncSession {
+    /// Connect and associate with the server at `addr`, like `Session::connect`: each address the
+    /// host resolves to is tried in turn, waiting at most `DEFAULT_CONNECT_TIMEOUT` on each.
+    pub async fn connect(addr: &str) -> Result<AsyncSession, SessionError> {
+        let mut last_error = SessionError::Io(Error::new(ErrorKind::InvalidInput, "Address resolved to nothing."));
+        for socket_addr in tokio::net::lookup_host(addr).await? {
+            let attempt = async {
+                let mut connection = with_timeout(Some(DEFAULT_CONNECT_TIMEOUT), TcpStream::connect(socket_addr)).await?;
+                connection.set_nodelay(true)?;
+                let server_version = match timeout(DEFAULT_ACK_TIMEOUT, associate(&mut connection)).await {
+                    Ok(result) => result?,
+                    Err(_) => return Err(SessionError::NotAssociated),
+                };
+                return Ok(AsyncSession {
+                    connection: connection,
+                    name: None,
+                    server_version: server_version,
+                    incoming: VecDeque::new(),
+                    write_timeout: Some(DEFAULT_WRITE_TIMEOUT),
+                    ack_timeout: Some(DEFAULT_ACK_TIMEOUT),
+                    next_sequence: None,
+                    is_compressing: false,
+                });
+            };
+            match attempt.await {
+                Ok(session) => return Ok(session),
+                Err(e) => last_error = e,
+            }
+        }
+        return Err(last_error);
+    }
+
+    /// How long a send may wait on a server that isn't reading before failing with `TimedOut`.
+    /// `None` waits forever. Defaults to `DEFAULT_WRITE_TIMEOUT`.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.write_timeout = timeout;
+    }
+
+    /// How long `send_acked` waits for the server's ACK. `None` waits forever. Defaults to
+    /// `DEFAULT_ACK_TIMEOUT`.
+    pub fn set_ack_timeout(&mut self, timeout: Option<Duration>) {
+        self.ack_timeout = timeout;
+    }
+
+    pub async fn send_info(&mut self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Info, Some(msg)).await
+    }
+
+    pub async fn send_warn(&mut self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Warn, Some(msg)).await
+    }
+
+    pub async fn send_alert(&mut self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Alert, Some(msg)).await
+    }
+
+    /// See `Session::send_critical`.
+    pub async fn send_critical(&mut self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Critical, Some(msg)).await
+    }
+
+    pub async fn change_name(&mut self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Name, Some(msg)).await?;
+        self.name = Some(msg.to_string());
+        return Ok(());
+    }
+
+    /// See `Session::name`.
+    pub fn name(&self) -> Option<&str> {
+        return self.name.as_deref();
+    }
+
+    /// See `Session::server_version`.
+    pub fn server_version(&self) -> Option<&str> {
+        return self.server_version.as_deref();
+    }
+
+    /// See `Session::enable_sequence_numbers`.
+    pub fn enable_sequence_numbers(&mut self) -> Result<(), SessionError> {
+        if self.server_version.is_none() {
+            return Err(SessionError::Unsupported);
+        }
+        self.next_sequence = Some(0);
+        return Ok(());
+    }
+
+    /// See `Session::enable_compression`.
+    pub fn enable_compression(&mut self) -> Result<(), SessionError> {
+        if !self.server_version.as_deref().is_some_and(|version| is_version_at_least(version, COMPRESSION_SINCE_VERSION)) {
+            return Err(SessionError::Unsupported);
+        }
+        self.is_compressing = true;
+        return Ok(());
+    }
+
+    /// Reset the server's warn state back to NONE.
+    pub async fn reset(&mut self) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Reset, None).await
+    }
+
+    /// See `Session::raise`.
+    pub async fn raise(&mut self, severity: Severity) -> Result<(), SessionError> {
+        let packet = severity.bare_packet();
+        self.send(packet[1], &[]).await
+    }
+
+    /// See `Session::set_last_will`.
+    pub async fn set_last_will(&mut self, kind: PacketType, msg: &str) -> Result<(), SessionError> {
+        let payload = last_will_payload(kind, msg)?;
+        self.send(PacketType::LastWill.to_type_number(), &payload).await
+    }
+
+    pub async fn cancel_last_will(&mut self) -> Result<(), SessionError> {
+        self.send(PacketType::LastWill.to_type_number(), &[]).await
+    }
+
+    /// See `Session::set_labels`.
+    pub async fn set_labels(&mut self, labels: &[(&str, &str)]) -> Result<(), SessionError> {
+        if self.server_version.is_none() {
+            return Err(SessionError::Unsupported);
+        }
+
+        let payload = labels_payload(labels)?;
+        self.send(PacketType::Metadata.to_type_number(), payload.as_bytes()).await
+    }
+
+    /// See `Session::send_packet`.
+    pub async fn send_packet(&mut self, kind: PacketType, text: Option<&str>) -> Result<(), SessionError> {
+        let msg = text.unwrap_or("");
+        check_packet(kind, msg)?;
+        self.send(kind.to_type_number(), msg.as_bytes()).await
+    }
+
+    /// See `Session::send_acked`.
+    pub async fn send_acked(&mut self, kind: PacketType, text: Option<&str>) -> Result<Duration, SessionError> {
+        if self.server_version.is_none() {
+            return Err(SessionError::Unsupported);
+        }
+        let msg = text.unwrap_or("");
+        check_packet(kind, msg)?;
+
+        let sent_at = Instant::now();
+        self.send(kind.to_type_number() | ACK_FLAG, msg.as_bytes()).await?;
+
+        let wait_for_ack = async {
+            loop {
+                match read_from_server(&mut self.connection).await? {
+                    None => return Ok(sent_at.elapsed()),
+                    Some(packet) => self.incoming.push_back(packet),
+                }
+            }
+        };
+        match self.ack_timeout {
+            Some(limit) => match timeout(limit, wait_for_ack).await {
+                Ok(result) => return result,
+                Err(_) => return Err(SessionError::Io(Error::from(ErrorKind::TimedOut))),
+            },
+            None => return wait_for_ack.await,
+        }
+    }
+
+    /// See `Session::send_packet_at`.
+    pub async fn send_packet_at(&mut self, kind: PacketType, text: Option<&str>, time: SystemTime) -> Result<(), SessionError> {
+        let msg = text.unwrap_or("");
+        check_packet(kind, msg)?;
+
+        let payload = timestamped_payload(msg, time)?;
+        self.send(kind.to_type_number() | TIMESTAMP_FLAG, &payload).await
+    }
+
+    /// Wait for the next WARN, ALERT or CRITICAL another client sent, if the server was started with
+    /// `--broadcast`. The async counterpart of `Session::poll_incoming`.
+    ///
+    /// Dropping the future partway through a packet, e.g. in `tokio::select!`, loses the part
+    /// already read and leaves the session misreading the rest, so the session should be dropped too.
+    pub async fn next_incoming(&mut self) -> Result<Packet, SessionError> {
+        if let Some(packet) = self.incoming.pop_front() {
+            return Ok(packet);
+        }
+
+        loop {
+            //Skip ACKs nobody waited for, e.g. after send_acked timed out.
+            if let Some(packet) = read_from_server(&mut self.connection).await? {
+                return Ok(packet);
+            }
+        }
+    }
+
+    async fn send(&mut self, packet_type: u8, msg: &[u8]) -> Result<(), SessionError> {
+        let max_len = max_text_len(packet_type, self.next_sequence.is_some());
+        let compressed = if self.is_compressing { compressed_payload(packet_type, msg) } else { None };
+        let (packet_type, msg) = match &compressed {
+            Some((packet_type, payload)) => (*packet_type, payload.as_slice()),
+            None => (packet_type, msg),
+        };
+        //See Session::send.
+        let sequence_len = if self.next_sequence.is_some() { 4 } else { 0 };
+        if msg.len() + sequence_len > MAX_MSG_LEN {
+            return Err(SessionError::MessageTooLong { max_len: max_len });
+        }
+        let buf = match self.next_sequence {
+            Some(sequence) => {
+                let (packet_type, payload) = sequenced_payload(packet_type, msg, sequence);
+                PacketBuffer::from_payload(packet_type, &payload)?
+            },
+            None => PacketBuffer::from_payload(packet_type, msg)?,
+        };
+        with_timeout(self.write_timeout, self.connection.write_all(buf.as_bytes())).await?;
+        self.next_sequence = self.next_sequence.map(|sequence| sequence.wrapping_add(1));
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[tokio::test]
+    async fn refuses_an_empty_info_without_panicking() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request: [u8; 2] = [0; 2];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&ASSOC_ACCEPT).unwrap();
+            //Everything the client sends before hanging up.
+            let mut sent = Vec::new();
+            stream.read_to_end(&mut sent).unwrap();
+            return sent;
+        });
+
+        let mut session = AsyncSession::connect(&addr.to_string()).await.unwrap();
+        assert!(matches!(session.send_info("").await, Err(SessionError::MissingText(PacketType::Info))));
+        drop(session);
+        assert_eq!(server.join().unwrap(), Vec::<u8>::new());
+    }
+
+    //Serve one connection on an async listener: associate it with accept, ACK every packet sent with
+    //ACK_FLAG, and return every packet the client sent before hanging up, as (type byte, payload).
+    async fn serve(accept: Vec<u8>) -> (String, tokio::task::JoinHandle<Vec<(u8, Vec<u8>)>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request: [u8; 2] = [0; 2];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, VERSIONED_ASSOC_REQUEST);
+            stream.write_all(&accept).await.unwrap();
+
+            let mut packets = Vec::new();
+            let mut len: [u8; 1] = [0; 1];
+            while stream.read_exact(&mut len).await.is_ok() {
+                let mut rest = vec![0; len[0] as usize];
+                stream.read_exact(&mut rest).await.unwrap();
+                if rest[0] & ACK_FLAG != 0 {
+                    stream.write_all(&ACK).await.unwrap();
+                }
+                packets.push((rest[0], rest[1..].to_vec()));
+            }
+            return packets;
+        });
+        return (addr, server);
+    }
+
+    fn versioned_accept(version: &str) -> Vec<u8> {
+        let mut accept = vec![version.len() as u8 + 1, VERSIONED_ASSOC_ACCEPT];
+        accept.extend_from_slice(version.as_bytes());
+        return accept;
+    }
+
+    #[tokio::test]
+    async fn associates_with_a_versioned_server() {
+        let (addr, server) = serve(versioned_accept("0.2.0")).await;
+        let session = AsyncSession::connect(&addr).await.unwrap();
+        assert_eq!(session.server_version(), Some("0.2.0"));
+        drop(session);
+        assert_eq!(server.await.unwrap(), Vec::new());
+    }
+
+    #[tokio::test]
+    async fn associates_with_a_server_that_predates_versions() {
+        let (addr, server) = serve(ASSOC_ACCEPT.to_vec()).await;
+        let mut session = AsyncSession::connect(&addr).await.unwrap();
+        assert_eq!(session.server_version(), None);
+        assert!(matches!(session.send_acked(PacketType::Warn, Some("disk full")).await, Err(SessionError::Unsupported)));
+        drop(session);
+        assert_eq!(server.await.unwrap(), Vec::new());
+    }
+
+    #[tokio::test]
+    async fn refuses_a_server_that_doesnt_accept() {
+        let (addr, _server) = serve(vec![1, 0]).await;
+        assert!(matches!(AsyncSession::connect(&addr).await, Err(SessionError::NotAssociated)));
+    }
+
+    #[tokio::test]
+    async fn sends_each_packet_type() {
+        let (addr, server) = serve(versioned_accept("0.2.0")).await;
+        let mut session = AsyncSession::connect(&addr).await.unwrap();
+        session.send_info("backup started").await.unwrap();
+        session.send_warn("disk 90% full").await.unwrap();
+        session.send_alert("disk full").await.unwrap();
+        session.send_critical("datacenter on fire").await.unwrap();
+        session.change_name("db-1").await.unwrap();
+        session.reset().await.unwrap();
+        session.raise(Severity::Alert).await.unwrap();
+        session.set_last_will(PacketType::Alert, "db-1 is gone").await.unwrap();
+        session.cancel_last_will().await.unwrap();
+        session.set_labels(&[("env", "prod"), ("role", "db")]).await.unwrap();
+        session.send_acked(PacketType::Warn, Some("acked")).await.unwrap();
+        session.send_packet_at(PacketType::Info, Some("late"), SystemTime::UNIX_EPOCH + Duration::from_millis(258)).await.unwrap();
+        assert_eq!(session.name(), Some("db-1"));
+        drop(session);
+
+        let packets = server.await.unwrap();
+        let expected: Vec<(u8, Vec<u8>)> = vec![
+            (2, b"backup started".to_vec()),
+            (3, b"disk 90% full".to_vec()),
+            (4, b"disk full".to_vec()),
+            (12, b"datacenter on fire".to_vec()),
+            (5, b"db-1".to_vec()),
+            (6, Vec::new()),
+            (4, Vec::new()),
+            (7, b"\x04db-1 is gone".to_vec()),
+            (7, Vec::new()),
+            (11, b"env=prod\nrole=db".to_vec()),
+            (3 | ACK_FLAG, b"acked".to_vec()),
+            (2 | TIMESTAMP_FLAG, b"\0\0\0\0\0\0\x01\x02late".to_vec()),
+        ];
+        assert_eq!(packets, expected);
+    }
+}
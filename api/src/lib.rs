@@ -1,93 +1,1559 @@
-use std::net::TcpStream;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 use std::io::{Read, Write, Error, ErrorKind};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::fmt;
+use std::collections::VecDeque;
 
-pub struct Session {
-    connection: TcpStream,
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+mod pool;
+pub use pool::SessionPool;
+mod reconnecting;
+pub use reconnecting::{BufferPolicy, ReconnectingSession, DEFAULT_RETRY_DELAY};
+#[cfg(feature = "tokio")]
+mod async_session;
+#[cfg(feature = "tokio")]
+pub use async_session::AsyncSession;
+#[cfg(any(test, feature = "test-util"))]
+mod memory_stream;
+#[cfg(any(test, feature = "test-util"))]
+pub use memory_stream::MemoryStream;
+
+/// Names of this many bytes or more are ignored by the server.
+pub const MAX_NAME_LEN: usize = 25;
+
+/// The longest message that fits in a packet, in bytes.
+pub const MAX_MSG_LEN: usize = 254;
+
+/// The most bytes a packet can take up, its length byte included. The length byte counts the
+/// bytes after it, so it can declare at most 255 of them.
+pub const MAX_PACKET_BYTES: usize = 256;
+
+//The length byte of a packet with MAX_MSG_LEN bytes of text must not wrap around to 0, which would
+//make it the single-byte packet the server rejects, and desync everything after it.
+const _: () = assert!(MAX_MSG_LEN + 2 == MAX_PACKET_BYTES);
+
+/// The write timeout `Session::connect` starts with.
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long `Session::connect` waits on each address the server's name resolves to.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The port the server listens on unless told otherwise.
+pub const DEFAULT_PORT: u16 = 44444;
+
+/// The bytes a client opens a connection with to associate.
+pub const ASSOC_REQUEST: [u8; 2] = [1, 0];
+
+/// The server's reply to `ASSOC_REQUEST`.
+pub const ASSOC_ACCEPT: [u8; 2] = [1, 1];
+
+/// Sent instead of `ASSOC_REQUEST` to also ask for the server's version.
+pub const VERSIONED_ASSOC_REQUEST: [u8; 2] = [1, 8];
+
+/// The packet type of the server's reply to `VERSIONED_ASSOC_REQUEST`, whose payload is the version
+/// as text. Servers that predate it reply with `ASSOC_ACCEPT` instead.
+pub const VERSIONED_ASSOC_ACCEPT: u8 = 9;
+
+/// The whole of the smallest packets, which carry no text, for clients that would rather write a
+/// fixed array than link this crate, e.g. a microcontroller with a panic button.
+///
+/// The complete exchange is: write `ASSOC_REQUEST`, read the two bytes of `ASSOC_ACCEPT` back,
+/// then write any of these at any time over the same connection. Nothing else is ever read.
+pub const BARE_WARN: [u8; 2] = [1, 3];
+
+/// See `BARE_WARN`.
+pub const BARE_ALERT: [u8; 2] = [1, 4];
+
+/// See `BARE_WARN`. Only servers that report a version understand it; older ones drop the
+/// connection over it.
+pub const BARE_CRITICAL: [u8; 2] = [1, 12];
+
+/// See `BARE_WARN`.
+pub const BARE_RESET: [u8; 2] = [1, 6];
+
+/// How long `Session::send_acked` waits for the server's ACK to begin with.
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Set in the packet type byte when the payload starts with a timestamp.
+pub const TIMESTAMP_FLAG: u8 = 0b1000_0000;
+
+/// Set in the packet type byte to have the server reply with `ACK` once it has the packet.
+pub const ACK_FLAG: u8 = 0b0100_0000;
+
+/// Set in the packet type byte when the payload carries a sequence number, after the timestamp if
+/// there is one. See `Session::enable_sequence_numbers`.
+pub const SEQUENCE_FLAG: u8 = 0b0010_0000;
+
+/// Set in the packet type byte when the text, after any timestamp and sequence number, is
+/// compressed with raw deflate (RFC 1951). See `Session::enable_compression`.
+pub const COMPRESSED_FLAG: u8 = 0b0001_0000;
+
+/// Text shorter than this is never compressed, as deflating it rarely saves anything.
+pub const COMPRESSION_THRESHOLD: usize = 64;
+
+/// The most bytes of text a compressed packet can inflate to. The server closes the connection
+/// over a packet that inflates to more.
+pub const MAX_COMPRESSED_MSG_LEN: usize = 4096;
+
+/// The first server version that understands `COMPRESSED_FLAG`.
+pub const COMPRESSION_SINCE_VERSION: &str = "0.2.0";
+
+/// The server's reply to a packet sent with `ACK_FLAG`.
+pub const ACK: [u8; 2] = [1, 10];
+
+/// The kinds of packet a client can send once associated.
+///
+/// See the protocol description in the server's source for what each one carries.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PacketType {
+    Info,
+    Warn,
+    Alert,
+    Name,
+    Reset,
+    LastWill,
+    Metadata,
+    /// Above ALERT, for catastrophic events. Only servers that report a version understand it.
+    Critical,
+}
+
+impl PacketType {
+    pub fn from_type_number(type_number: u8) -> Result<PacketType, Error> {
+        match type_number {
+            2 => Ok(PacketType::Info),
+            3 => Ok(PacketType::Warn),
+            4 => Ok(PacketType::Alert),
+            5 => Ok(PacketType::Name),
+            6 => Ok(PacketType::Reset),
+            7 => Ok(PacketType::LastWill),
+            11 => Ok(PacketType::Metadata),
+            12 => Ok(PacketType::Critical),
+            _ => Err(Error::new(ErrorKind::Other, "Invalid packet type.")),
+        }
+    }
+
+    pub fn to_type_number(&self) -> u8 {
+        match self {
+            PacketType::Info => 2,
+            PacketType::Warn => 3,
+            PacketType::Alert => 4,
+            PacketType::Name => 5,
+            PacketType::Reset => 6,
+            PacketType::LastWill => 7,
+            PacketType::Metadata => 11,
+            PacketType::Critical => 12,
+        }
+    }
+
+    pub fn to_string(&self) -> &str {
+        match self {
+            PacketType::Info => "INFO",
+            PacketType::Warn => "WARN",
+            PacketType::Alert => "ALERT",
+            PacketType::Name => "NAME",
+            PacketType::Reset => "RESET",
+            PacketType::LastWill => "LASTWILL",
+            PacketType::Metadata => "METADATA",
+            PacketType::Critical => "CRITICAL",
+        }
+    }
+}
+
+/// What `Session::raise` sets the server's warn state to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Severity {
+    /// Back to NONE, by a RESET.
+    None,
+    Warn,
+    Alert,
+    Critical,
+}
+
+impl Severity {
+    /// The bare packet that raises this severity, e.g. `BARE_ALERT`.
+    pub fn bare_packet(&self) -> [u8; 2] {
+        match self {
+            Severity::None => BARE_RESET,
+            Severity::Warn => BARE_WARN,
+            Severity::Alert => BARE_ALERT,
+            Severity::Critical => BARE_CRITICAL,
+        }
+    }
+}
+
+/// A WARN, ALERT or CRITICAL another client sent, forwarded by a server started with `--broadcast`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub kind: PacketType,
+    pub text: Option<String>,
+}
+
+/// One packet's worth of bytes, length byte first, as it is written to or read from a connection.
+///
+/// Reads stop at the end of the packet its length byte declares, so whatever a peer sends nothing
+/// indexes past it, or past `MAX_PACKET_BYTES`.
+pub struct PacketBuffer {
+    bytes: [u8; MAX_PACKET_BYTES],
+}
+
+impl PacketBuffer {
+    /// An empty buffer to read a packet into: the length byte into `length_byte_mut`, then the
+    /// rest into `rest_mut`.
+    pub fn new() -> Self {
+        return PacketBuffer { bytes: [0; MAX_PACKET_BYTES] };
+    }
+
+    /// A whole packet of the given type byte and payload, or `MessageTooLong` if the payload is
+    /// more than `MAX_MSG_LEN` bytes.
+    pub fn from_payload(packet_type: u8, payload: &[u8]) -> Result<Self, SessionError> {
+        if payload.len() > MAX_MSG_LEN {
+            return Err(SessionError::MessageTooLong { max_len: MAX_MSG_LEN });
+        }
+
+        let mut buf = PacketBuffer::new();
+        //Set num_bytes in packet -- 00000000 means there is 1 byte in packet, 00000001 means there
+        //are two bytes, 11111111 means there are 256 bytes, etc.
+        //So add num of bytes in msg plus 1 byte for packet_type.
+        //num_bytes is never 00000000, the single-byte packet the server rejects, as there is always
+        //a packet_type: a bare packet is [1, type]. The check above and the assert on MAX_MSG_LEN
+        //keep it from wrapping around to 0, and a conversion that would is refused, not wrapped.
+        buf.bytes[0] = u8::try_from(payload.len() + 1).map_err(|_| SessionError::MessageTooLong { max_len: MAX_MSG_LEN })?;
+        buf.bytes[1] = packet_type;
+        buf.bytes[2..2 + payload.len()].copy_from_slice(payload);
+        return Ok(buf);
+    }
+
+    /// The number of bytes in the packet, as its length byte declares: from 1 up to
+    /// `MAX_PACKET_BYTES`.
+    pub fn len(&self) -> usize {
+        return self.bytes[0] as usize + 1;
+    }
+
+    /// Never true: even a packet without a type is its length byte.
+    pub fn is_empty(&self) -> bool {
+        return false;
+    }
+
+    /// The whole packet, ready to write.
+    pub fn as_bytes(&self) -> &[u8] {
+        return &self.bytes[..self.len()];
+    }
+
+    /// Where the length byte is read into.
+    pub fn length_byte_mut(&mut self) -> &mut [u8] {
+        return &mut self.bytes[0..1];
+    }
+
+    /// Where the rest of the packet is read into, skipping the first `num_bytes_read` bytes after
+    /// the length byte. Empty once they are all read.
+    pub fn rest_mut(&mut self, num_bytes_read: usize) -> &mut [u8] {
+        let len = self.len();
+        return &mut self.bytes[(1 + num_bytes_read).min(len)..len];
+    }
+
+    /// The byte at `index` in the packet, counting the length byte as 0, or `None` past its end.
+    pub fn byte(&self, index: usize) -> Option<u8> {
+        return self.as_bytes().get(index).copied();
+    }
+
+    /// `count` bytes of the packet from `start`, or `None` if it ends before then.
+    pub fn bytes(&self, start: usize, count: usize) -> Option<&[u8]> {
+        return self.as_bytes().get(start..start.checked_add(count)?);
+    }
+
+    /// The rest of the packet from `start`, empty if it ends before then.
+    pub fn bytes_from(&self, start: usize) -> &[u8] {
+        return self.as_bytes().get(start..).unwrap_or(&[]);
+    }
+}
+
+impl Default for PacketBuffer {
+    fn default() -> Self {
+        return PacketBuffer::new();
+    }
+}
+
+/// Separates the `key=value` labels in the payload of a METADATA packet.
+pub const LABEL_SEPARATOR: char = '\n';
+
+/// The labels in the payload of a METADATA packet, in order, or `None` if it is malformed.
+///
+/// The payload is `key=value` pairs separated by `LABEL_SEPARATOR`, e.g. `env=prod\nservice=api`.
+/// Keys are split off at the first `=` and can't be empty. An empty payload is no labels.
+pub fn parse_labels(payload: &str) -> Option<Vec<(String, String)>> {
+    if payload.len() == 0 {
+        return Some(Vec::new());
+    }
+
+    let mut labels = Vec::new();
+    for label in payload.split(LABEL_SEPARATOR) {
+        let (key, value) = label.split_once('=')?;
+        if key.len() == 0 {
+            return None;
+        }
+        labels.push((key.to_string(), value.to_string()));
+    }
+    return Some(labels);
+}
+
+/// The text of a packet sent with `COMPRESSED_FLAG`, inflated, or `None` if it isn't valid deflate
+/// or inflates to more than `MAX_COMPRESSED_MSG_LEN` bytes.
+///
+/// Inflation stops at the limit, so a small packet can't be made to take up any amount of memory.
+pub fn inflate_text(compressed: &[u8]) -> Option<Vec<u8>> {
+    let mut text = Vec::new();
+    DeflateDecoder::new(compressed).take(MAX_COMPRESSED_MSG_LEN as u64 + 1).read_to_end(&mut text).ok()?;
+    if text.len() > MAX_COMPRESSED_MSG_LEN {
+        return None;
+    }
+    return Some(text);
+}
+
+/// Why a `Session` call failed.
+#[derive(Debug)]
+pub enum SessionError {
+    /// The text doesn't fit in the packet, which holds at most `max_len` bytes of it.
+    MessageTooLong { max_len: usize },
+    /// INFO and NAME packets, and INFO wills, must have text.
+    MissingText(PacketType),
+    /// The packet can't be sent this way, e.g. a LASTWILL or METADATA through `send_packet`, or a
+    /// will that isn't an INFO, WARN, ALERT or CRITICAL.
+    WrongPacketType(PacketType),
+    /// The time given to `send_packet_at` is before the Unix epoch.
+    TimestampBeforeEpoch,
+    /// The server didn't accept the association request.
+    NotAssociated,
+    /// The label with this key can't be encoded: keys can't be empty or contain `=`, and neither
+    /// keys nor values can contain `LABEL_SEPARATOR`.
+    InvalidLabel(String),
+    /// The server is too old for the request, e.g. `send_acked` to a server that doesn't
+    /// acknowledge packets. Nothing was sent.
+    Unsupported,
+    /// Only part of a packet may have been written, by this send or an earlier one that failed.
+    /// The server would misread whatever is sent after it, so the session sends nothing more and
+    /// should be dropped.
+    ShortWrite,
+    /// The connection itself failed.
+    Io(Error),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionError::MessageTooLong { max_len } => write!(f, "Message is too long, the limit is {} bytes.", max_len),
+            SessionError::MissingText(kind) => write!(f, "{} packets must have text.", kind.to_string()),
+            SessionError::WrongPacketType(kind) => write!(f, "Can't send a {} packet this way.", kind.to_string()),
+            SessionError::TimestampBeforeEpoch => write!(f, "Timestamp is before the Unix epoch."),
+            SessionError::NotAssociated => write!(f, "Failed to associate: server did not respond with an accept."),
+            SessionError::InvalidLabel(key) => write!(f, "Invalid label {:?}: keys can't be empty or contain '=', and labels can't contain newlines.", key),
+            SessionError::Unsupported => write!(f, "The server is too old to support this."),
+            SessionError::ShortWrite => write!(f, "Could not write full message to server!"),
+            SessionError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SessionError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for SessionError {
+    fn from(e: Error) -> Self {
+        return SessionError::Io(e);
+    }
+}
+
+pub struct Session<S: Read + Write = TcpStream> {
+    connection: S,
+    //The last name sent successfully.
+    name: Option<String>,
+    //What the server reported during association, if it reports one at all.
+    server_version: Option<String>,
+    //Broadcast packets read while waiting for an ACK, for poll_incoming.
+    incoming: VecDeque<Packet>,
+    //The sequence number of the next packet, once enable_sequence_numbers has been called.
+    next_sequence: Option<u32>,
+    //Whether enable_compression has been called.
+    is_compressing: bool,
+    //Whether a send has failed, maybe partway through a packet, after which nothing more is sent.
+    is_poisoned: bool,
 }
 
 impl Session {
-    pub fn connect(addr: &str) -> Result<Session, Error> {
-        let mut connection = TcpStream::connect(addr)?;
+    /// Connect and associate with the server at `addr`, a `host:port` such as `localhost:44444`,
+    /// `192.168.1.5:44444` or `[::1]:44444`.
+    ///
+    /// A host that resolves to several addresses, e.g. an IPv6 and an IPv4 one, has each tried in
+    /// turn until one associates, waiting at most `DEFAULT_CONNECT_TIMEOUT` on each. If none does,
+    /// the last one's error is returned.
+    pub fn connect(addr: &str) -> Result<Session, SessionError> {
+        return Session::connect_any(addr.to_socket_addrs()?);
+    }
+
+    //Connect and associate with the first of addrs that will, or return the last one's error.
+    fn connect_any(addrs: impl Iterator<Item = SocketAddr>) -> Result<Session, SessionError> {
+        let mut last_error = SessionError::Io(Error::new(ErrorKind::InvalidInput, "Address resolved to nothing."));
+        for socket_addr in addrs {
+            match Session::connect_to(socket_addr) {
+                Ok(session) => return Ok(session),
+                Err(e) => last_error = e,
+            }
+        }
+        return Err(last_error);
+    }
 
-        //Attempt to associate with the server.
-        let mut buf: [u8; 2] = [1, 0];
-        let num_bytes_wrote = connection.write(&buf)?;
+    //Connect and associate with exactly one address.
+    fn connect_to(addr: SocketAddr) -> Result<Session, SessionError> {
+        let mut connection = TcpStream::connect_timeout(&addr, DEFAULT_CONNECT_TIMEOUT)?;
+        connection.set_write_timeout(Some(DEFAULT_WRITE_TIMEOUT))?;
+        connection.set_read_timeout(Some(DEFAULT_ACK_TIMEOUT))?;
+        //Packets are small and urgent; don't hold one back to batch it with the next. See
+        //set_nodelay.
+        connection.set_nodelay(true)?;
 
-        if num_bytes_wrote != 2 {
-            return Err(Error::new(ErrorKind::Other, "Failed to associate: could not write to server."));
+        let peer_addr = connection.peer_addr()?;
+        let server_version = associate_and_report(&mut connection, &peer_addr, &mut std::io::stdout())?;
+
+        return Ok(Session { connection: connection, name: None, server_version: server_version, incoming: VecDeque::new(), next_sequence: None, is_compressing: false, is_poisoned: false });
+    }
+
+    /// Like `connect`, but try up to `attempts` times, sleeping `delay` between tries, for when the
+    /// server may still be starting up. Returns the last error if every attempt fails.
+    ///
+    /// An `attempts` of 0 is treated as 1.
+    pub fn connect_retry(addr: &str, attempts: u32, delay: Duration) -> Result<Session, SessionError> {
+        let mut attempt = 1;
+        loop {
+            match Session::connect(addr) {
+                Ok(session) => return Ok(session),
+                Err(e) if attempt >= attempts => return Err(e),
+                Err(_) => (),
+            }
+            attempt += 1;
+            std::thread::sleep(delay);
         }
+    }
+
+    /// Set how long a send may block before failing with `WouldBlock` or `TimedOut`, or `None`
+    /// to block indefinitely. Starts at `DEFAULT_WRITE_TIMEOUT`. Part of the packet may have gone
+    /// out by then, so every send after fails with `ShortWrite`.
+    ///
+    /// A send only blocks when the server isn't reading, e.g. it is wedged or the link is slow
+    /// enough to fill the socket buffers. GUI clients should keep this well under a frame so
+    /// their render loop never stalls.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        return self.connection.set_write_timeout(timeout);
+    }
+
+    /// Set how long `send_acked` waits for the server's ACK before failing with `WouldBlock` or
+    /// `TimedOut`, or `None` to wait indefinitely. Starts at `DEFAULT_ACK_TIMEOUT`.
+    pub fn set_ack_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        return self.connection.set_read_timeout(timeout);
+    }
 
-        let num_bytes_read = connection.read(&mut buf)?;
+    /// Set whether packets go out as soon as they are sent (`TCP_NODELAY`), which `connect` turns
+    /// on.
+    ///
+    /// Packets are a few bytes each and an ALERT is only useful promptly, so by default none is
+    /// held back by Nagle's algorithm to be batched with the next, which can take 40ms or more.
+    /// Turning it off only makes sense for a client sending floods of INFOs over a slow link.
+    pub fn set_nodelay(&mut self, nodelay: bool) -> Result<(), Error> {
+        return self.connection.set_nodelay(nodelay);
+    }
+
+    /// This end's address. Unless there's NAT in between, it's the address the dashboard lists
+    /// the client by until it sends a name.
+    ///
+    /// ```no_run
+    /// use api::Session;
+    ///
+    /// let session = Session::connect("localhost:44444")?;
+    /// println!("Connected as {}", session.local_addr()?);
+    /// # Ok::<(), api::SessionError>(())
+    /// ```
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        return self.connection.local_addr();
+    }
 
-        if num_bytes_read != 2 {
-            return Err(Error::new(ErrorKind::Other, "Failed to associate: server did not respond."));
+    /// The server's address.
+    ///
+    /// ```no_run
+    /// use api::Session;
+    ///
+    /// let session = Session::connect("localhost:44444")?;
+    /// println!("Connected to {}", session.peer_addr()?);
+    /// # Ok::<(), api::SessionError>(())
+    /// ```
+    pub fn peer_addr(&self) -> Result<SocketAddr, Error> {
+        return self.connection.peer_addr();
+    }
+
+    /// Best-effort check that the connection to the server is still alive.
+    ///
+    /// This never sends anything. It reports a pending socket error or an orderly close by the
+    /// server, but TCP can't always tell a silently dead peer (pulled cable, crashed host) from
+    /// an idle one without writing to it, so a `true` here doesn't guarantee the next send works.
+    pub fn is_connected(&self) -> bool {
+        if self.is_poisoned {
+            return false;
+        }
+        match self.connection.take_error() {
+            Ok(None) => (),
+            _ => return false,
         }
 
-        if buf[0] != 1 && buf[1] != 1 {
-            let peer_addr = connection.peer_addr().expect("Client is connected.").to_string();
-            println!("Associated with {}.", peer_addr);
+        //Peek without blocking: WouldBlock means nothing has arrived and the connection is still
+        //open, and data waiting to be read, e.g. a broadcast packet or a late ACK, means it's alive
+        //too. Reading 0 bytes means the server closed the connection.
+        if self.connection.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut buf: [u8; 1] = [0; 1];
+        let result = self.connection.peek(&mut buf);
+        if self.connection.set_nonblocking(false).is_err() {
+            return false;
         }
 
-        return Ok(Session { connection: connection });
+        return match result {
+            Ok(0) => false,
+            Ok(_) => true,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => true,
+            Err(_) => false,
+        };
     }
 
-    pub fn send_info(&mut self, msg: &str) -> Result<(), Error> {
-        if msg.len() == 0 {
-            panic!("INFO messages MUST be non-zero length.");
+    /// The next WARN, ALERT or CRITICAL another client sent, if the server was started with
+    /// `--broadcast` and one has arrived. Never blocks.
+    ///
+    /// Connection errors also return `None`; `is_connected` tells the two apart.
+    ///
+    /// ```no_run
+    /// use api::Session;
+    ///
+    /// let mut session = Session::connect("localhost:44444")?;
+    /// loop {
+    ///     while let Some(packet) = session.poll_incoming() {
+    ///         println!("{}: {:?}", packet.kind.to_string(), packet.text);
+    ///     }
+    ///     std::thread::sleep(std::time::Duration::from_millis(100));
+    /// }
+    /// # Ok::<(), api::SessionError>(())
+    /// ```
+    pub fn poll_incoming(&mut self) -> Option<Packet> {
+        if let Some(packet) = self.incoming.pop_front() {
+            return Some(packet);
+        }
+
+        //Only read once the whole packet has arrived, so a partial one is never half consumed.
+        let mut buf: [u8; MAX_PACKET_BYTES] = [0; MAX_PACKET_BYTES];
+        self.connection.set_nonblocking(true).ok()?;
+        let result = self.connection.peek(&mut buf);
+        self.connection.set_nonblocking(false).ok()?;
+        let num_bytes_peeked = result.ok()?;
+        if num_bytes_peeked == 0 || num_bytes_peeked < buf[0] as usize + 1 {
+            return None;
+        }
+
+        match read_from_server(&mut self.connection) {
+            Ok(FromServer::Packet(packet)) => return Some(packet),
+            //An ACK nobody waited for, e.g. after send_acked timed out.
+            Ok(FromServer::Ack) => return self.poll_incoming(),
+            Err(_) => return None,
         }
-        self.send(2, msg)
     }
+}
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use std::io::{BufRead, BufReader};
 
-    pub fn send_warn(&mut self, msg: &str) -> Result<(), Error> {
-        self.send(3, msg)
+#[cfg(unix)]
+impl Session<UnixStream> {
+    /// Connect to a server listening on a Unix domain socket, i.e. started with `--unix <Path>`.
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> Result<Session<UnixStream>, SessionError> {
+        let mut connection = UnixStream::connect(path)?;
+        connection.set_write_timeout(Some(DEFAULT_WRITE_TIMEOUT))?;
+        connection.set_read_timeout(Some(DEFAULT_ACK_TIMEOUT))?;
+
+        let server_version = associate(&mut connection)?;
+
+        return Ok(Session { connection: connection, name: None, server_version: server_version, incoming: VecDeque::new(), next_sequence: None, is_compressing: false, is_poisoned: false });
     }
 
-    pub fn send_alert(&mut self, msg: &str) -> Result<(), Error> {
-        self.send(4, msg)
+    /// See `Session::set_write_timeout`.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        return self.connection.set_write_timeout(timeout);
     }
 
-    pub fn change_name(&mut self, msg: &str) -> Result<(), Error> {
-        self.send(5, msg)
+    /// See `Session::set_ack_timeout`.
+    pub fn set_ack_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        return self.connection.set_read_timeout(timeout);
     }
+}
 
-    fn send(&mut self, packet_type: u8, msg: &str) -> Result<(), Error> {
-        let mut buf: [u8; 256] = [0; 256];
+/// A read-only feed of everything a server started with `--event-socket <Path>` sees, e.g. to
+/// follow the dashboard from a shell. Unix only.
+///
+/// The server disconnects subscribers that fall too far behind; they have to connect again, and
+/// miss what happened in between.
+///
+/// A server also started with `--event-history N` first sends a `HISTORY <Count>` line, the last
+/// Count events, which have already happened, and a `STATE <Channel> <State>` line per channel.
+///
+/// ```no_run
+/// use api::EventSubscriber;
+///
+/// let mut events = EventSubscriber::connect("/tmp/ww-events.sock")?;
+/// while let Some(event) = events.next_event()? {
+///     println!("{}", event);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[cfg(unix)]
+pub struct EventSubscriber {
+    reader: BufReader<UnixStream>,
+}
 
-        buf[1] = packet_type;
+#[cfg(unix)]
+impl EventSubscriber {
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<EventSubscriber, Error> {
+        return Ok(EventSubscriber { reader: BufReader::new(UnixStream::connect(path)?) });
+    }
 
-        if msg.len() > 254 {
-            return Err(Error::new(ErrorKind::Other, "Message is too long!"));
+    /// Wait for the next event and return its line without the newline, e.g.
+    /// `1700000000000 ALERT 127.0.0.1:51234 "disk full"`: when it happened in milliseconds since
+    /// the Unix epoch, what kind of event it is, and who it's from. `None` once the server closes
+    /// the socket.
+    pub fn next_event(&mut self) -> Result<Option<String>, Error> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
         }
+        if line.ends_with('\n') {
+            line.pop();
+        }
+        return Ok(Some(line));
+    }
+}
 
-        //Set num_bytes in packet -- 00000000 means there is 1 byte in packet, 00000001 means there
-        //are two bytes, 11111111 means there are 256 bytes, etc.
-        //So add num of bytes in msg plus 1 byte for packet_type.
-        //Incidentally, num_bytes should never be 00000000 as there is always a packet_type.
-        buf[0] = msg.len() as u8 + 1;
-        let num_bytes = buf[0] as usize;
+//Attempt to associate with the server, returning its version.
+//
+//This asks for the version with a versioned association request. Servers that predate it answer
+//with a plain accept, in which case there is no version.
+fn associate<S: Read + Write>(connection: &mut S) -> Result<Option<String>, SessionError> {
+    connection.write_all(&VERSIONED_ASSOC_REQUEST)?;
+
+    //The two bytes of the accept may arrive in separate reads. A server that hangs up before
+    //sending both didn't accept.
+    let mut buf: [u8; 2] = [0; 2];
+    match connection.read_exact(&mut buf) {
+        Ok(()) => (),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Err(SessionError::NotAssociated),
+        Err(e) => return Err(SessionError::Io(e)),
+    }
+
+    if buf == ASSOC_ACCEPT {
+        return Ok(None);
+    }
+    if buf[1] != VERSIONED_ASSOC_ACCEPT {
+        return Err(SessionError::NotAssociated);
+    }
 
-        for i in 2..num_bytes + 1 {
-            buf[i] = msg.as_bytes()[i - 2];
+    //A versioned accept: the version text follows, num_bytes counting the type byte too.
+    let mut version = vec![0; (buf[0] as usize).saturating_sub(1)];
+    connection.read_exact(&mut version)?;
+    let version = String::from_utf8_lossy(&version).into_owned();
+
+    return Ok(Some(version));
+}
+
+//associate(), then say so on report, only once the server has accepted.
+fn associate_and_report<S: Read + Write>(connection: &mut S, peer_addr: &SocketAddr, report: &mut impl Write) -> Result<Option<String>, SessionError> {
+    let server_version = associate(connection)?;
+    //Only informational; a closed stdout mustn't fail the connection.
+    let _ = writeln!(report, "Associated with {}.", peer_addr);
+    return Ok(server_version);
+}
+
+//What the server sends once associated.
+enum FromServer {
+    Ack,
+    Packet(Packet),
+}
+
+//Read one whole packet from the server, blocking until it has all arrived.
+fn read_from_server<S: Read>(connection: &mut S) -> Result<FromServer, SessionError> {
+    let mut header: [u8; 2] = [0; 2];
+    connection.read_exact(&mut header)?;
+    if header == ACK {
+        return Ok(FromServer::Ack);
+    }
+
+    //num_bytes counts the type byte too.
+    let mut text = vec![0; (header[0] as usize).saturating_sub(1)];
+    connection.read_exact(&mut text)?;
+
+    let kind = PacketType::from_type_number(header[1])?;
+    let text = if text.len() > 0 { Some(String::from_utf8_lossy(&text).into_owned()) } else { None };
+    return Ok(FromServer::Packet(Packet { kind: kind, text: text }));
+}
+
+//Refuse packets the server would close the connection over.
+fn check_packet(kind: PacketType, msg: &str) -> Result<(), SessionError> {
+    match kind {
+        PacketType::Info | PacketType::Name if msg.len() == 0 => {
+            return Err(SessionError::MissingText(kind));
+        },
+        PacketType::LastWill if msg.len() > 0 => {
+            //Use set_last_will for these.
+            return Err(SessionError::WrongPacketType(kind));
+        },
+        PacketType::Metadata => {
+            //Use set_labels for these.
+            return Err(SessionError::WrongPacketType(kind));
+        },
+        _ => return Ok(()),
+    }
+}
+
+//The payload of a LASTWILL packet: the will's type byte, then its text.
+fn last_will_payload(kind: PacketType, msg: &str) -> Result<Vec<u8>, SessionError> {
+    match kind {
+        PacketType::Info if msg.len() == 0 => {
+            return Err(SessionError::MissingText(kind));
+        },
+        PacketType::Info | PacketType::Warn | PacketType::Alert | PacketType::Critical => (),
+        _ => {
+            return Err(SessionError::WrongPacketType(kind));
+        },
+    }
+    if msg.len() > MAX_MSG_LEN - 1 {
+        return Err(SessionError::MessageTooLong { max_len: MAX_MSG_LEN - 1 });
+    }
+
+    let mut payload = vec![kind.to_type_number()];
+    payload.extend_from_slice(msg.as_bytes());
+    return Ok(payload);
+}
+
+//The payload of a METADATA packet: key=value lines, parsed back with parse_labels.
+fn labels_payload(labels: &[(&str, &str)]) -> Result<String, SessionError> {
+    let mut payload = String::new();
+    for (key, value) in labels {
+        if key.len() == 0 || key.contains('=') || key.contains(LABEL_SEPARATOR) || value.contains(LABEL_SEPARATOR) {
+            return Err(SessionError::InvalidLabel(key.to_string()));
         }
+        if payload.len() > 0 {
+            payload.push(LABEL_SEPARATOR);
+        }
+        payload.push_str(&format!("{}={}", key, value));
+    }
+    return Ok(payload);
+}
 
-        // println!("DEBUG: msg {}, len {}, num_bytes {}", msg, msg.len(), num_bytes + 1);
+//The payload of a packet with TIMESTAMP_FLAG set: milliseconds since the epoch, big-endian, then the text.
+fn timestamped_payload(msg: &str, time: SystemTime) -> Result<Vec<u8>, SessionError> {
+    let millis = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as u64,
+        Err(_) => return Err(SessionError::TimestampBeforeEpoch),
+    };
+    let mut payload = millis.to_be_bytes().to_vec();
+    payload.extend_from_slice(msg.as_bytes());
+    return Ok(payload);
+}
 
-        let num_bytes_wrote = match self.connection.write(&buf[0..num_bytes + 1]) {
-            Ok(0) => {
-                return Err(Error::from(ErrorKind::UnexpectedEof));
-            },
-            Ok(n) => {
-                n
-            },
-            Err(e) => {
-                return Err(e);
+//Whether a version like 0.2.0 is at least min, comparing major, minor and patch in turn. Anything
+//after them, e.g. -dev, is ignored.
+fn is_version_at_least(version: &str, min: &str) -> bool {
+    let numbers = |version: &str| -> [u32; 3] {
+        let mut numbers = [0; 3];
+        for (i, number) in version.split('.').take(3).enumerate() {
+            let digits: String = number.chars().take_while(|c| c.is_ascii_digit()).collect();
+            numbers[i] = digits.parse().unwrap_or(0);
+        }
+        return numbers;
+    };
+    return numbers(version) >= numbers(min);
+}
+
+//The packet type and payload of a packet with COMPRESSED_FLAG set, with the text, what follows any
+//timestamp, deflated. None if the packet has no text to compress, it's too short or too long to be
+//worth it, or deflating doesn't make it shorter.
+fn compressed_payload(packet_type: u8, msg: &[u8]) -> Option<(u8, Vec<u8>)> {
+    let type_number = packet_type & !(TIMESTAMP_FLAG | ACK_FLAG | SEQUENCE_FLAG);
+    match PacketType::from_type_number(type_number) {
+        Ok(PacketType::Info | PacketType::Warn | PacketType::Alert | PacketType::Critical) => (),
+        _ => return None,
+    }
+
+    let text_start = if packet_type & TIMESTAMP_FLAG != 0 { std::cmp::min(8, msg.len()) } else { 0 };
+    let text = &msg[text_start..];
+    if text.len() < COMPRESSION_THRESHOLD || text.len() > MAX_COMPRESSED_MSG_LEN {
+        return None;
+    }
+
+    //The encoder appends to the timestamp, which stays as it is.
+    let mut encoder = DeflateEncoder::new(msg[..text_start].to_vec(), Compression::best());
+    encoder.write_all(text).ok()?;
+    let payload = encoder.finish().ok()?;
+    if payload.len() >= msg.len() {
+        return None;
+    }
+    return Some((packet_type | COMPRESSED_FLAG, payload));
+}
+
+//How many bytes of text fit in a packet of this type byte, sequenced or not: MAX_MSG_LEN less the
+//timestamp and sequence number that come before the text, and a will's type byte.
+fn max_text_len(packet_type: u8, is_sequenced: bool) -> usize {
+    let mut max_len = MAX_MSG_LEN;
+    if packet_type & TIMESTAMP_FLAG != 0 {
+        max_len -= 8;
+    }
+    if is_sequenced {
+        max_len -= 4;
+    }
+    if packet_type & !(TIMESTAMP_FLAG | ACK_FLAG | SEQUENCE_FLAG | COMPRESSED_FLAG) == PacketType::LastWill.to_type_number() {
+        max_len -= 1;
+    }
+    return max_len;
+}
+
+//The packet type and payload of a packet with SEQUENCE_FLAG set: the sequence number, a big-endian
+//u32, goes after any timestamp and before the rest of the payload.
+fn sequenced_payload(packet_type: u8, msg: &[u8], sequence: u32) -> (u8, Vec<u8>) {
+    let fields_start = if packet_type & TIMESTAMP_FLAG != 0 { std::cmp::min(8, msg.len()) } else { 0 };
+    let mut payload = msg[..fields_start].to_vec();
+    payload.extend_from_slice(&sequence.to_be_bytes());
+    payload.extend_from_slice(&msg[fields_start..]);
+    return (packet_type | SEQUENCE_FLAG, payload);
+}
+
+impl<S: Read + Write> Session<S> {
+    //Associate over an already-open stream. connect() is the usual way in; this lets the protocol
+    //run over anything that reads and writes, such as an in-memory pipe.
+    pub fn from_stream(mut connection: S) -> Result<Session<S>, SessionError> {
+        let server_version = associate(&mut connection)?;
+        return Ok(Session { connection: connection, name: None, server_version: server_version, incoming: VecDeque::new(), next_sequence: None, is_compressing: false, is_poisoned: false });
+    }
+
+    pub fn send_info(&mut self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Info, Some(msg))
+    }
+
+    pub fn send_warn(&mut self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Warn, Some(msg))
+    }
+
+    pub fn send_alert(&mut self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Alert, Some(msg))
+    }
+
+    /// Servers that don't report a version (see `server_version`) drop the connection over a
+    /// CRITICAL; send an ALERT to those instead.
+    pub fn send_critical(&mut self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Critical, Some(msg))
+    }
+
+    pub fn change_name(&mut self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Name, Some(msg))?;
+        self.name = Some(msg.to_string());
+        return Ok(());
+    }
+
+    /// The name last sent with `change_name`, if any.
+    ///
+    /// The server doesn't acknowledge names, so this is what was sent, not necessarily what the
+    /// dashboard shows: it ignores names of `MAX_NAME_LEN` bytes or more.
+    pub fn name(&self) -> Option<&str> {
+        return self.name.as_deref();
+    }
+
+    /// The server's version, e.g. `"0.1.0"`, as reported when associating. `None` if the server
+    /// is too old to report one.
+    pub fn server_version(&self) -> Option<&str> {
+        return self.server_version.as_deref();
+    }
+
+    /// Number every packet sent from now on, starting from 0, so the server can log a warning if
+    /// one never arrives, e.g. because the client dropped it. Servers that don't report a version
+    /// (see `server_version`) would drop the connection over numbered packets, so this returns
+    /// `Unsupported` instead.
+    ///
+    /// The sequence number takes up 4 bytes of every packet, leaving `MAX_MSG_LEN - 4` bytes for
+    /// the rest.
+    pub fn enable_sequence_numbers(&mut self) -> Result<(), SessionError> {
+        if self.server_version.is_none() {
+            return Err(SessionError::Unsupported);
+        }
+        self.next_sequence = Some(0);
+        return Ok(());
+    }
+
+    /// Compress the text of INFO, WARN, ALERT and CRITICAL packets sent from now on, when it's at
+    /// least `COMPRESSION_THRESHOLD` bytes and deflating it makes the packet smaller. Verbose
+    /// alerts, e.g. stack traces, then take fewer bytes on the wire, and text of up to
+    /// `MAX_COMPRESSED_MSG_LEN` bytes can be sent, as long as it deflates to fit in a packet.
+    ///
+    /// Servers older than `COMPRESSION_SINCE_VERSION` would drop the connection over a compressed
+    /// packet, so this returns `Unsupported` instead, and packets go out uncompressed.
+    ///
+    /// ```no_run
+    /// use api::Session;
+    ///
+    /// let mut session = Session::connect("localhost:44444")?;
+    /// if session.enable_compression().is_err() {
+    ///     eprintln!("Server too old to compress; long alerts will be refused.");
+    /// }
+    /// # Ok::<(), api::SessionError>(())
+    /// ```
+    pub fn enable_compression(&mut self) -> Result<(), SessionError> {
+        if !self.server_version.as_deref().is_some_and(|version| is_version_at_least(version, COMPRESSION_SINCE_VERSION)) {
+            return Err(SessionError::Unsupported);
+        }
+        self.is_compressing = true;
+        return Ok(());
+    }
+
+    /// Reset the server's warn state back to NONE.
+    pub fn reset(&mut self) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Reset, None)
+    }
+
+    /// Set the server's warn state with the smallest packet that does it: two bytes, no text.
+    /// The dashboard logs it with no message.
+    ///
+    /// ```no_run
+    /// use api::{Session, Severity};
+    ///
+    /// let mut session = Session::connect("localhost:44444")?;
+    /// session.raise(Severity::Alert)?;
+    /// # Ok::<(), api::SessionError>(())
+    /// ```
+    pub fn raise(&mut self, severity: Severity) -> Result<(), SessionError> {
+        let packet = severity.bare_packet();
+        self.send(packet[1], &[])
+    }
+
+    /// Leave a packet for the server to act on as if it had been sent, once this connection
+    /// drops for any reason. `kind` must be INFO, WARN, ALERT or CRITICAL.
+    ///
+    /// This lets a client say "if you stop hearing from me, that's an ALERT". Setting a new will
+    /// replaces the old one; call `cancel_last_will` before a planned disconnect.
+    pub fn set_last_will(&mut self, kind: PacketType, msg: &str) -> Result<(), SessionError> {
+        let payload = last_will_payload(kind, msg)?;
+        self.send(PacketType::LastWill.to_type_number(), &payload)
+    }
+
+    pub fn cancel_last_will(&mut self) -> Result<(), SessionError> {
+        self.send(PacketType::LastWill.to_type_number(), &[])
+    }
+
+    /// Label this client with key-value pairs, shown next to it in the packet log and matched by
+    /// the server's `--filter`. Replaces any labels sent before; an empty slice clears them.
+    ///
+    /// All the labels go in one METADATA packet, so together they must fit in `MAX_MSG_LEN` bytes
+    /// as `key=value` lines. Servers that don't report a version (see `server_version`) would
+    /// drop the connection over the packet, so this returns `Unsupported` without sending.
+    ///
+    /// ```no_run
+    /// use api::Session;
+    ///
+    /// let mut session = Session::connect("localhost:44444")?;
+    /// session.set_labels(&[("env", "prod"), ("service", "api")])?;
+    /// # Ok::<(), api::SessionError>(())
+    /// ```
+    pub fn set_labels(&mut self, labels: &[(&str, &str)]) -> Result<(), SessionError> {
+        if self.server_version.is_none() {
+            return Err(SessionError::Unsupported);
+        }
+
+        let payload = labels_payload(labels)?;
+        self.send(PacketType::Metadata.to_type_number(), payload.as_bytes())
+    }
+
+    /// Send a packet of any type. The typed methods above are shorthands for this.
+    ///
+    /// `None` and an empty text produce the same packet. The server closes the connection on
+    /// INFO and NAME packets without text, so those are refused here instead. LASTWILL and
+    /// METADATA packets carry more than text; use `set_last_will` and `set_labels` for those.
+    ///
+    /// ```no_run
+    /// use api::{PacketType, Session};
+    ///
+    /// let mut session = Session::connect("localhost:44444")?;
+    /// session.send_packet(PacketType::Alert, Some("disk full"))?;
+    /// session.send_packet(PacketType::Reset, None)?;
+    /// # Ok::<(), api::SessionError>(())
+    /// ```
+    pub fn send_packet(&mut self, kind: PacketType, text: Option<&str>) -> Result<(), SessionError> {
+        let msg = text.unwrap_or("");
+        check_packet(kind, msg)?;
+        self.send(kind.to_type_number(), msg.as_bytes())
+    }
+
+    /// Like `send_packet`, but wait for the server to acknowledge the packet, and return how long
+    /// that took: the round trip time to the dashboard, for spotting a slow link.
+    ///
+    /// Only servers that report a version (see `server_version`) acknowledge packets; older ones
+    /// would drop the connection, so this returns `Unsupported` without sending. If the ACK
+    /// doesn't arrive within the ack timeout, it may still arrive later and be mistaken for the
+    /// next one, so the session should be dropped.
+    ///
+    /// ```no_run
+    /// use api::{PacketType, Session};
+    ///
+    /// let mut session = Session::connect("localhost:44444")?;
+    /// let rtt = session.send_acked(PacketType::Warn, Some("disk 90% full"))?;
+    /// println!("Sent! ({}ms)", rtt.as_millis());
+    /// # Ok::<(), api::SessionError>(())
+    /// ```
+    pub fn send_acked(&mut self, kind: PacketType, text: Option<&str>) -> Result<Duration, SessionError> {
+        if self.server_version.is_none() {
+            return Err(SessionError::Unsupported);
+        }
+        let msg = text.unwrap_or("");
+        check_packet(kind, msg)?;
+
+        let sent_at = Instant::now();
+        self.send(kind.to_type_number() | ACK_FLAG, msg.as_bytes())?;
+
+        //With --broadcast, other clients' packets may arrive first. Keep them for poll_incoming.
+        loop {
+            match read_from_server(&mut self.connection)? {
+                FromServer::Ack => return Ok(sent_at.elapsed()),
+                FromServer::Packet(packet) => self.incoming.push_back(packet),
+            }
+        }
+    }
+
+    /// Like `send_packet`, but the server logs the event as happening at `time` rather than when
+    /// the packet arrives. Useful for alerts held on to while the server was unreachable.
+    ///
+    /// The timestamp takes up 8 bytes of the packet, leaving `MAX_MSG_LEN - 8` bytes for text.
+    pub fn send_packet_at(&mut self, kind: PacketType, text: Option<&str>, time: SystemTime) -> Result<(), SessionError> {
+        let msg = text.unwrap_or("");
+        check_packet(kind, msg)?;
+
+        let payload = timestamped_payload(msg, time)?;
+        self.send(kind.to_type_number() | TIMESTAMP_FLAG, &payload)
+    }
+
+    fn send(&mut self, packet_type: u8, msg: &[u8]) -> Result<(), SessionError> {
+        let max_len = max_text_len(packet_type, self.next_sequence.is_some());
+        let compressed = if self.is_compressing { compressed_payload(packet_type, msg) } else { None };
+        let (packet_type, msg) = match &compressed {
+            Some((packet_type, payload)) => (*packet_type, payload.as_slice()),
+            None => (packet_type, msg),
+        };
+        //Checked here rather than left to from_payload, so the limit reported is what's left for
+        //the text once the timestamp and sequence number are in.
+        let sequence_len = if self.next_sequence.is_some() { 4 } else { 0 };
+        if msg.len() + sequence_len > MAX_MSG_LEN {
+            return Err(SessionError::MessageTooLong { max_len: max_len });
+        }
+        let buf = match self.next_sequence {
+            Some(sequence) => {
+                let (packet_type, payload) = sequenced_payload(packet_type, msg, sequence);
+                PacketBuffer::from_payload(packet_type, &payload)?
             },
+            None => PacketBuffer::from_payload(packet_type, msg)?,
         };
+        let packet = buf.as_bytes();
 
-        if num_bytes_wrote != num_bytes + 1 {
-            return Err(Error::new(ErrorKind::Other, "Could not write full message to server!"));
+        // println!("DEBUG: msg {:?}, len {}, num_bytes {}", msg, msg.len(), packet.len());
+
+        if self.is_poisoned {
+            return Err(SessionError::ShortWrite);
+        }
+        //A write that fails or times out may have sent part of the packet, and there's no telling
+        //how much, so the connection is no good after it.
+        if let Err(e) = self.connection.write_all(packet) {
+            self.is_poisoned = true;
+            if e.kind() == ErrorKind::WriteZero {
+                return Err(SessionError::ShortWrite);
+            }
+            return Err(SessionError::Io(e));
         }
 
+        //A packet that never went out doesn't use up its number.
+        self.next_sequence = self.next_sequence.map(|sequence| sequence.wrapping_add(1));
         return Ok(());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A session associated over a MemoryStream, and the server's end of it. The server's reply is
+    //written up front, as a MemoryStream never blocks on writes.
+    fn associated(accept: &[u8]) -> (Session<MemoryStream>, MemoryStream) {
+        let (client, mut server) = MemoryStream::pair();
+        server.write_all(accept).unwrap();
+        let session = Session::from_stream(client).unwrap();
+
+        let mut request: [u8; 2] = [0; 2];
+        server.read_exact(&mut request).unwrap();
+        assert_eq!(request, VERSIONED_ASSOC_REQUEST);
+        return (session, server);
+    }
+
+    fn versioned_accept(version: &str) -> Vec<u8> {
+        let mut accept = vec![version.len() as u8 + 1, VERSIONED_ASSOC_ACCEPT];
+        accept.extend_from_slice(version.as_bytes());
+        return accept;
+    }
+
+    //One whole packet as the server reads it, length byte first.
+    fn read_packet(server: &mut MemoryStream) -> Vec<u8> {
+        let mut buf = PacketBuffer::new();
+        server.read_exact(buf.length_byte_mut()).unwrap();
+        server.read_exact(buf.rest_mut(0)).unwrap();
+        return buf.as_bytes().to_vec();
+    }
+
+    //Whether the client has written anything the server hasn't read.
+    fn has_unread(server: &mut MemoryStream) -> bool {
+        server.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        let mut buf: [u8; 1] = [0; 1];
+        return server.read(&mut buf).is_ok();
+    }
+
+    #[test]
+    fn associates_with_a_versioned_server() {
+        let (session, _server) = associated(&versioned_accept("0.2.0"));
+        assert_eq!(session.server_version(), Some("0.2.0"));
+    }
+
+    #[test]
+    fn associates_with_a_server_that_predates_versions() {
+        let (session, _server) = associated(&ASSOC_ACCEPT);
+        assert_eq!(session.server_version(), None);
+    }
+
+    #[test]
+    fn refuses_a_reply_that_is_not_an_accept() {
+        let (client, mut server) = MemoryStream::pair();
+        server.write_all(&BARE_WARN).unwrap();
+        assert!(matches!(Session::from_stream(client), Err(SessionError::NotAssociated)));
+    }
+
+    #[test]
+    fn refuses_a_server_that_hangs_up_mid_accept() {
+        let (client, mut server) = MemoryStream::pair();
+        let server_thread = std::thread::spawn(move || {
+            let mut request: [u8; 2] = [0; 2];
+            server.read_exact(&mut request).unwrap();
+            server.write_all(&ASSOC_ACCEPT[..1]).unwrap();
+        });
+        assert!(matches!(Session::from_stream(client), Err(SessionError::NotAssociated)));
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn sends_every_packet_type() {
+        let (mut session, mut server) = associated(&versioned_accept("0.2.0"));
+
+        session.send_info("hi").unwrap();
+        assert_eq!(read_packet(&mut server), [3, 2, b'h', b'i']);
+        session.send_warn("hi").unwrap();
+        assert_eq!(read_packet(&mut server), [3, 3, b'h', b'i']);
+        session.send_alert("hi").unwrap();
+        assert_eq!(read_packet(&mut server), [3, 4, b'h', b'i']);
+        session.change_name("hi").unwrap();
+        assert_eq!(read_packet(&mut server), [3, 5, b'h', b'i']);
+        session.reset().unwrap();
+        assert_eq!(read_packet(&mut server), [1, 6]);
+        session.set_last_will(PacketType::Alert, "hi").unwrap();
+        assert_eq!(read_packet(&mut server), [4, 7, 4, b'h', b'i']);
+        session.cancel_last_will().unwrap();
+        assert_eq!(read_packet(&mut server), [1, 7]);
+        session.set_labels(&[("a", "b")]).unwrap();
+        assert_eq!(read_packet(&mut server), [4, 11, b'a', b'=', b'b']);
+        session.send_critical("hi").unwrap();
+        assert_eq!(read_packet(&mut server), [3, 12, b'h', b'i']);
+        assert!(!has_unread(&mut server));
+    }
+
+    #[test]
+    fn sends_bare_packets() {
+        let (mut session, mut server) = associated(&versioned_accept("0.2.0"));
+        for severity in [Severity::Warn, Severity::Alert, Severity::Critical, Severity::None] {
+            session.raise(severity).unwrap();
+            assert_eq!(read_packet(&mut server), severity.bare_packet());
+        }
+    }
+
+    #[test]
+    fn refuses_packets_the_server_would_reject_without_sending() {
+        let (mut session, mut server) = associated(&versioned_accept("0.2.0"));
+
+        assert!(matches!(session.send_packet(PacketType::Info, None), Err(SessionError::MissingText(PacketType::Info))));
+        assert!(matches!(session.send_packet(PacketType::Name, Some("")), Err(SessionError::MissingText(PacketType::Name))));
+        assert!(matches!(session.send_packet(PacketType::LastWill, Some("hi")), Err(SessionError::WrongPacketType(PacketType::LastWill))));
+        assert!(matches!(session.send_packet(PacketType::Metadata, Some("a=b")), Err(SessionError::WrongPacketType(PacketType::Metadata))));
+        assert!(matches!(session.set_last_will(PacketType::Reset, ""), Err(SessionError::WrongPacketType(PacketType::Reset))));
+        assert!(matches!(session.set_labels(&[("", "b")]), Err(SessionError::InvalidLabel(_))));
+        assert!(matches!(session.send_warn(&"x".repeat(MAX_MSG_LEN + 1)), Err(SessionError::MessageTooLong { max_len: MAX_MSG_LEN })));
+        assert!(!has_unread(&mut server));
+    }
+
+    #[test]
+    fn refuses_an_empty_info_without_panicking() {
+        let (mut session, mut server) = associated(&versioned_accept("0.2.0"));
+        assert!(matches!(session.send_info(""), Err(SessionError::MissingText(PacketType::Info))));
+        assert!(!has_unread(&mut server));
+
+        //The session is still good for the next packet.
+        session.send_info("up").unwrap();
+        assert_eq!(read_packet(&mut server), [3, PacketType::Info.to_type_number(), b'u', b'p']);
+    }
+
+    #[test]
+    fn round_trips_the_largest_payload_through_a_packet_buffer() {
+        let payload: Vec<u8> = (0..MAX_MSG_LEN).map(|i| i as u8).collect();
+        let buf = PacketBuffer::from_payload(PacketType::Warn.to_type_number(), &payload).unwrap();
+        assert_eq!(buf.len(), MAX_PACKET_BYTES);
+        assert_eq!(buf.as_bytes()[..2], [255, PacketType::Warn.to_type_number()]);
+        assert_eq!(buf.bytes_from(2), &payload[..]);
+
+        //And back, as a server reads it.
+        let mut read = PacketBuffer::default();
+        read.length_byte_mut().copy_from_slice(&buf.as_bytes()[..1]);
+        read.rest_mut(0).copy_from_slice(&buf.as_bytes()[1..]);
+        assert_eq!(read.as_bytes(), buf.as_bytes());
+        assert!(read.rest_mut(MAX_PACKET_BYTES).is_empty());
+    }
+
+    #[test]
+    fn refuses_a_payload_over_the_largest_without_panicking() {
+        for len in [MAX_MSG_LEN + 1, MAX_PACKET_BYTES, 10_000] {
+            let result = PacketBuffer::from_payload(PacketType::Warn.to_type_number(), &vec![b'a'; len]);
+            assert!(matches!(result, Err(SessionError::MessageTooLong { max_len: MAX_MSG_LEN })), "{} bytes were accepted", len);
+        }
+    }
+
+    #[test]
+    fn reports_the_room_left_for_text_once_the_timestamp_and_sequence_number_are_in() {
+        let (mut session, mut server) = associated(&versioned_accept("0.2.0"));
+        let time = UNIX_EPOCH + Duration::from_secs(1);
+        session.send_packet_at(PacketType::Warn, Some(&"x".repeat(MAX_MSG_LEN - 8)), time).unwrap();
+        assert!(matches!(session.send_packet_at(PacketType::Warn, Some(&"x".repeat(MAX_MSG_LEN - 7)), time), Err(SessionError::MessageTooLong { max_len }) if max_len == MAX_MSG_LEN - 8));
+
+        session.enable_sequence_numbers().unwrap();
+        session.send_warn(&"x".repeat(MAX_MSG_LEN - 4)).unwrap();
+        assert!(matches!(session.send_warn(&"x".repeat(MAX_MSG_LEN - 3)), Err(SessionError::MessageTooLong { max_len }) if max_len == MAX_MSG_LEN - 4));
+        session.send_packet_at(PacketType::Warn, Some(&"x".repeat(MAX_MSG_LEN - 12)), time).unwrap();
+        assert!(matches!(session.send_packet_at(PacketType::Warn, Some(&"x".repeat(MAX_MSG_LEN - 11)), time), Err(SessionError::MessageTooLong { max_len }) if max_len == MAX_MSG_LEN - 12));
+        assert!(matches!(session.set_last_will(PacketType::Alert, &"x".repeat(MAX_MSG_LEN - 4)), Err(SessionError::MessageTooLong { max_len }) if max_len == MAX_MSG_LEN - 5));
+
+        //Only the packets that fit were sent, and nothing was half-sent.
+        for _ in 0..3 {
+            assert_eq!(read_packet(&mut server).len(), MAX_PACKET_BYTES);
+        }
+        assert!(!has_unread(&mut server));
+    }
+
+    #[test]
+    fn refuses_versioned_requests_to_a_server_without_a_version() {
+        let (mut session, mut server) = associated(&ASSOC_ACCEPT);
+        assert!(matches!(session.set_labels(&[("a", "b")]), Err(SessionError::Unsupported)));
+        assert!(matches!(session.send_acked(PacketType::Warn, None), Err(SessionError::Unsupported)));
+        assert!(matches!(session.enable_sequence_numbers(), Err(SessionError::Unsupported)));
+        assert!(matches!(session.enable_compression(), Err(SessionError::Unsupported)));
+        assert!(!has_unread(&mut server));
+    }
+
+    #[test]
+    fn reads_acks_and_broadcast_packets() {
+        let (mut session, mut server) = associated(&versioned_accept("0.2.0"));
+        server.write_all(&[5, 4, b'd', b'i', b's', b'k']).unwrap();
+        server.write_all(&ACK).unwrap();
+
+        session.send_acked(PacketType::Warn, Some("hi")).unwrap();
+        assert_eq!(read_packet(&mut server), [3, 3 | ACK_FLAG, b'h', b'i']);
+        assert_eq!(session.incoming.pop_front(), Some(Packet { kind: PacketType::Alert, text: Some("disk".to_string()) }));
+    }
+
+    //A connection that takes the first room bytes written to it, then times out like a server
+    //whose receive buffer is full.
+    struct Stalling {
+        accept: std::io::Cursor<Vec<u8>>,
+        room: usize,
+    }
+
+    impl Read for Stalling {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            return self.accept.read(buf);
+        }
+    }
+
+    impl Write for Stalling {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            if self.room == 0 {
+                return Err(Error::from(ErrorKind::TimedOut));
+            }
+            let num_bytes_wrote = buf.len().min(self.room);
+            self.room -= num_bytes_wrote;
+            return Ok(num_bytes_wrote);
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn sends_nothing_more_after_a_send_fails_partway() {
+        //Room for the association request and half a packet.
+        let mut session = Session::from_stream(Stalling { accept: std::io::Cursor::new(ASSOC_ACCEPT.to_vec()), room: 2 + 3 }).unwrap();
+        match session.send_warn("disk full") {
+            Err(SessionError::Io(e)) => assert_eq!(e.kind(), ErrorKind::TimedOut),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        session.connection.room = 100;
+        assert!(matches!(session.send_warn("disk full"), Err(SessionError::ShortWrite)));
+        assert_eq!(session.connection.room, 100);
+    }
+
+    #[test]
+    fn reports_the_addresses_of_both_ends_of_the_socket() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut connection, client_addr) = listener.accept().unwrap();
+            let mut request: [u8; 2] = [0; 2];
+            connection.read_exact(&mut request).unwrap();
+            connection.write_all(&ASSOC_ACCEPT).unwrap();
+            return client_addr;
+        });
+
+        let session = Session::connect(&addr.to_string()).unwrap();
+        assert_eq!(session.peer_addr().unwrap(), addr);
+        assert_eq!(session.local_addr().unwrap(), server.join().unwrap());
+    }
+
+    #[test]
+    fn remembers_the_last_name_sent() {
+        let (mut session, _server) = associated(&ASSOC_ACCEPT);
+        assert_eq!(session.name(), None);
+        session.change_name("first").unwrap();
+        session.change_name("second").unwrap();
+        assert_eq!(session.name(), Some("second"));
+
+        //A name refused before sending leaves the last one.
+        assert!(session.change_name("").is_err());
+        assert_eq!(session.name(), Some("second"));
+    }
+
+    fn report_association(accept: &[u8]) -> (Result<Option<String>, SessionError>, String) {
+        let (mut client, mut server) = MemoryStream::pair();
+        server.write_all(accept).unwrap();
+        let peer_addr: SocketAddr = "127.0.0.1:44444".parse().unwrap();
+        let mut report: Vec<u8> = Vec::new();
+        let result = associate_and_report(&mut client, &peer_addr, &mut report);
+        return (result, String::from_utf8(report).unwrap());
+    }
+
+    #[test]
+    fn reports_a_genuine_accept() {
+        let (result, report) = report_association(&ASSOC_ACCEPT);
+        assert!(result.is_ok());
+        assert_eq!(report, "Associated with 127.0.0.1:44444.\n");
+
+        let (result, report) = report_association(&versioned_accept("0.2.0"));
+        assert_eq!(result.unwrap().as_deref(), Some("0.2.0"));
+        assert_eq!(report, "Associated with 127.0.0.1:44444.\n");
+    }
+
+    #[test]
+    fn reports_nothing_on_a_rejection() {
+        for reply in [[1, 0], [1, 3], [0, 0]] {
+            let (result, report) = report_association(&reply);
+            assert!(matches!(result, Err(SessionError::NotAssociated)), "{:?} was accepted", reply);
+            assert_eq!(report, "");
+        }
+    }
+
+    //A port nothing is listening on, for now.
+    fn free_port() -> std::net::SocketAddr {
+        return std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    }
+
+    //Accept one connection on addr once delay has passed, and associate it.
+    fn accept_after(addr: std::net::SocketAddr, delay: Duration) -> std::thread::JoinHandle<()> {
+        return std::thread::spawn(move || {
+            std::thread::sleep(delay);
+            let listener = std::net::TcpListener::bind(addr).unwrap();
+            let (mut connection, _) = listener.accept().unwrap();
+            let mut request: [u8; 2] = [0; 2];
+            connection.read_exact(&mut request).unwrap();
+            connection.write_all(&ASSOC_ACCEPT).unwrap();
+        });
+    }
+
+    #[test]
+    fn connect_retry_waits_for_a_server_starting_up() {
+        let addr = free_port();
+        let server = accept_after(addr, Duration::from_millis(200));
+        let session = Session::connect_retry(&addr.to_string(), 50, Duration::from_millis(20)).unwrap();
+        assert_eq!(session.server_version(), None);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn connect_retry_returns_the_last_error_once_out_of_attempts() {
+        let addr = free_port();
+        let start = Instant::now();
+        let error = Session::connect_retry(&addr.to_string(), 3, Duration::from_millis(50)).err().unwrap();
+        assert!(matches!(error, SessionError::Io(e) if e.kind() == ErrorKind::ConnectionRefused));
+        //Slept between the attempts, not after the last.
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn connect_retry_tries_once_for_zero_attempts() {
+        let start = Instant::now();
+        assert!(Session::connect_retry(&free_port().to_string(), 0, Duration::from_secs(10)).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    //Accept one connection on listener and associate it as a server reporting version.
+    fn accept_one(listener: std::net::TcpListener, version: &str) -> std::thread::JoinHandle<()> {
+        let accept = versioned_accept(version);
+        return std::thread::spawn(move || {
+            let (mut connection, _) = listener.accept().unwrap();
+            let mut request: [u8; 2] = [0; 2];
+            connection.read_exact(&mut request).unwrap();
+            connection.write_all(&accept).unwrap();
+        });
+    }
+
+    #[test]
+    fn connects_over_ipv6_loopback() {
+        let listener = std::net::TcpListener::bind("[::1]:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = accept_one(listener, "0.2.0");
+
+        let session = Session::connect(&format!("[::1]:{}", port)).unwrap();
+        assert_eq!(session.server_version(), Some("0.2.0"));
+        assert!(session.connection.peer_addr().unwrap().is_ipv6());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn connect_falls_through_to_the_address_that_associates() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = accept_one(listener, "0.2.0");
+
+        let session = Session::connect_any(vec![free_port(), free_port(), addr].into_iter()).unwrap();
+        assert_eq!(session.server_version(), Some("0.2.0"));
+        assert_eq!(session.connection.peer_addr().unwrap(), addr);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn connect_returns_the_last_error_when_no_address_associates() {
+        let error = Session::connect_any(vec![free_port(), free_port()].into_iter()).err().unwrap();
+        assert!(matches!(error, SessionError::Io(e) if e.kind() == ErrorKind::ConnectionRefused));
+
+        let error = Session::connect_any(std::iter::empty()).err().unwrap();
+        assert!(matches!(error, SessionError::Io(e) if e.kind() == ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn connect_sends_packets_without_delay() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = accept_one(listener, "0.2.0");
+
+        let mut session = Session::connect(&addr.to_string()).unwrap();
+        assert!(session.connection.nodelay().unwrap());
+        session.set_nodelay(false).unwrap();
+        assert!(!session.connection.nodelay().unwrap());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn send_acked_measures_the_round_trip_over_loopback() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let delay = Duration::from_millis(5);
+        let server = std::thread::spawn(move || {
+            let (mut connection, _) = listener.accept().unwrap();
+            let mut request: [u8; 2] = [0; 2];
+            connection.read_exact(&mut request).unwrap();
+            connection.write_all(&versioned_accept("0.2.0")).unwrap();
+
+            let mut buf = PacketBuffer::new();
+            connection.read_exact(buf.length_byte_mut()).unwrap();
+            connection.read_exact(buf.rest_mut(0)).unwrap();
+            assert_eq!(buf.as_bytes(), b"\x0e\x43disk 90% full");
+            std::thread::sleep(delay);
+            connection.write_all(&ACK).unwrap();
+        });
+
+        let mut session = Session::connect(&addr.to_string()).unwrap();
+        let rtt = session.send_acked(PacketType::Warn, Some("disk 90% full")).unwrap();
+        assert!(rtt >= delay);
+        assert!(rtt < Duration::from_secs(1));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn send_times_out_on_a_server_that_never_reads() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _server = std::thread::spawn(move || {
+            let (mut connection, _) = listener.accept().unwrap();
+            let mut request: [u8; 2] = [0; 2];
+            connection.read_exact(&mut request).unwrap();
+            connection.write_all(&ASSOC_ACCEPT).unwrap();
+            //Hold the connection open, never reading from it, until the client is done.
+            std::thread::sleep(Duration::from_secs(10));
+        });
+
+        let mut session = Session::connect(&addr.to_string()).unwrap();
+        let timeout = Duration::from_millis(100);
+        session.set_write_timeout(Some(timeout)).unwrap();
+
+        //Sends succeed until the socket buffers fill, then one blocks for the timeout and fails.
+        let text = "x".repeat(MAX_MSG_LEN);
+        let start = Instant::now();
+        let error = loop {
+            let send_start = Instant::now();
+            match session.send_warn(&text) {
+                Ok(()) => assert!(start.elapsed() < Duration::from_secs(5), "the buffers never filled"),
+                Err(e) => {
+                    assert!(send_start.elapsed() < timeout * 10);
+                    break e;
+                },
+            }
+        };
+        match error {
+            SessionError::Io(e) => assert!(matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+
+        assert!(!session.is_connected());
+        assert!(matches!(session.send_warn("again"), Err(SessionError::ShortWrite)));
+    }
+
+    #[test]
+    fn refuses_a_broadcast_packet_of_an_unknown_type() {
+        let (client, mut server) = MemoryStream::pair();
+        server.write_all(&[1, 99]).unwrap();
+        let mut client = client;
+        assert!(matches!(read_from_server(&mut client), Err(SessionError::Io(_))));
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write, ErrorKind};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+//The bytes going one way, with whether the end writing them has gone.
+struct Pipe {
+    state: Mutex<PipeState>,
+    readable: Condvar,
+}
+
+struct PipeState {
+    bytes: VecDeque<u8>,
+    is_closed: bool,
+}
+
+impl Pipe {
+    fn new() -> Self {
+        return Pipe { state: Mutex::new(PipeState { bytes: VecDeque::new(), is_closed: false }), readable: Condvar::new() };
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().is_closed = true;
+        self.readable.notify_all();
+    }
+}
+
+//One end of the pair. Both pipes close once every handle to it is dropped, as a socket does.
+struct End {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+}
+
+impl Drop for End {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+/// One end of an in-memory connection, for running a client and a server against each other
+/// without a socket, e.g. `Session::from_stream` against the server's packet handling in tests.
+///
+/// Writes never block. Reads block until there is something to read, the read timeout passes, or
+/// the other end is dropped, after which they read 0 bytes, like a closed socket. Writing to a
+/// dropped end fails with `BrokenPipe`.
+///
+/// ```
+/// use api::{MemoryStream, ASSOC_ACCEPT};
+/// use std::io::{Read, Write};
+///
+/// let (mut client, mut server) = MemoryStream::pair();
+/// server.write_all(&ASSOC_ACCEPT)?;
+/// let mut buf = [0; 2];
+/// client.read_exact(&mut buf)?;
+/// assert_eq!(buf, ASSOC_ACCEPT);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct MemoryStream {
+    end: Arc<End>,
+    read_timeout: Arc<Mutex<Option<Duration>>>,
+}
+
+impl MemoryStream {
+    /// Two connected ends: what is written to one is read from the other.
+    pub fn pair() -> (MemoryStream, MemoryStream) {
+        let a_to_b = Arc::new(Pipe::new());
+        let b_to_a = Arc::new(Pipe::new());
+        let a = End { incoming: Arc::clone(&b_to_a), outgoing: Arc::clone(&a_to_b) };
+        let b = End { incoming: a_to_b, outgoing: b_to_a };
+        return (MemoryStream::from_end(a), MemoryStream::from_end(b));
+    }
+
+    fn from_end(end: End) -> Self {
+        return MemoryStream { end: Arc::new(end), read_timeout: Arc::new(Mutex::new(None)) };
+    }
+
+    /// Another handle to the same end, e.g. to write from another thread. Shares the read timeout.
+    pub fn try_clone(&self) -> io::Result<MemoryStream> {
+        return Ok(MemoryStream { end: Arc::clone(&self.end), read_timeout: Arc::clone(&self.read_timeout) });
+    }
+
+    /// Set how long a read waits before failing with `TimedOut`, or `None` to wait indefinitely.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        if timeout == Some(Duration::ZERO) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "Cannot set a 0 duration timeout."));
+        }
+        *self.read_timeout.lock().unwrap() = timeout;
+        return Ok(());
+    }
+
+    /// Close the connection now rather than once every handle is dropped: both ends then read 0
+    /// bytes once they have read what was already written.
+    pub fn shutdown(&self) {
+        self.end.incoming.close();
+        self.end.outgoing.close();
+    }
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.len() == 0 {
+            return Ok(0);
+        }
+
+        let deadline = self.read_timeout.lock().unwrap().map(|timeout| Instant::now() + timeout);
+        let pipe = &self.end.incoming;
+        let mut state = pipe.state.lock().unwrap();
+        while state.bytes.len() == 0 && !state.is_closed {
+            match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(io::Error::from(ErrorKind::TimedOut));
+                    }
+                    state = pipe.readable.wait_timeout(state, remaining).unwrap().0;
+                },
+                None => state = pipe.readable.wait(state).unwrap(),
+            }
+        }
+
+        let num_bytes_read = buf.len().min(state.bytes.len());
+        for (byte, read) in buf.iter_mut().zip(state.bytes.drain(..num_bytes_read)) {
+            *byte = read;
+        }
+        return Ok(num_bytes_read);
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let pipe = &self.end.outgoing;
+        let mut state = pipe.state.lock().unwrap();
+        if state.is_closed {
+            return Err(io::Error::from(ErrorKind::BrokenPipe));
+        }
+        state.bytes.extend(buf);
+        pipe.readable.notify_all();
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
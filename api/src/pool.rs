@@ -0,0 +1,186 @@
+use std::sync::Mutex;
+
+use crate::{PacketType, Session, SessionError};
+
+/// Associated `Session`s to one server, kept between sends, for senders that fire many short
+/// packets, e.g. a batch job raising a WARN per failed item. Each send borrows an idle session,
+/// or connects a new one if there's none, and hands it back afterwards, so a burst of sends pays
+/// for association once rather than every time, and the server isn't left with a connection
+/// thread per packet.
+///
+/// The pool is shared between threads by reference, e.g. in an `Arc`. Sends from more threads at
+/// once than the pool holds still work; the extra sessions are closed once they're done.
+///
+/// ```no_run
+/// use api::SessionPool;
+///
+/// let pool = SessionPool::new("localhost:44444", 4);
+/// for item in ["a", "b", "c"] {
+///     pool.send_warn(&format!("Could not process {}", item))?;
+/// }
+/// # Ok::<(), api::SessionError>(())
+/// ```
+pub struct SessionPool {
+    addr: String,
+    max_idle: usize,
+    idle: Mutex<Vec<Session>>,
+}
+
+impl SessionPool {
+    /// A pool of sessions to the server at `addr`, as given to `Session::connect`, keeping at most
+    /// `max_idle` of them open between sends. Nothing connects until the first send.
+    pub fn new(addr: &str, max_idle: usize) -> SessionPool {
+        return SessionPool { addr: addr.to_string(), max_idle: max_idle, idle: Mutex::new(Vec::new()) };
+    }
+
+    pub fn send_info(&self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Info, Some(msg))
+    }
+
+    pub fn send_warn(&self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Warn, Some(msg))
+    }
+
+    pub fn send_alert(&self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Alert, Some(msg))
+    }
+
+    /// See `Session::send_critical`.
+    pub fn send_critical(&self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Critical, Some(msg))
+    }
+
+    pub fn reset(&self) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Reset, None)
+    }
+
+    /// See `Session::send_packet`.
+    ///
+    /// A session that has sat idle may have been closed by the server in the meantime, e.g. by a
+    /// restart, without `is_connected` noticing. If sending on one fails, the packet is sent once
+    /// more on a new connection before the error is returned.
+    pub fn send_packet(&self, kind: PacketType, text: Option<&str>) -> Result<(), SessionError> {
+        let (mut session, is_reused) = self.acquire()?;
+        match session.send_packet(kind, text) {
+            Ok(()) => {
+                self.release(session);
+                return Ok(());
+            },
+            Err(SessionError::Io(_) | SessionError::ShortWrite) if is_reused => {
+                let mut session = Session::connect(&self.addr)?;
+                session.send_packet(kind, text)?;
+                self.release(session);
+                return Ok(());
+            },
+            //A refused packet leaves the session as good as it was.
+            Err(e @ (SessionError::MissingText(_) | SessionError::WrongPacketType(_) | SessionError::MessageTooLong { .. })) => {
+                self.release(session);
+                return Err(e);
+            },
+            Err(e) => return Err(e),
+        }
+    }
+
+    /// How many sessions are open and waiting for a send.
+    pub fn idle_count(&self) -> usize {
+        return self.idle.lock().unwrap().len();
+    }
+
+    //An idle session if there's a live one, dropping any found dead, else a new one. Also returns
+    //whether it was idle, as only those can have died unnoticed.
+    fn acquire(&self) -> Result<(Session, bool), SessionError> {
+        loop {
+            //Never hold the lock while connecting or sending, so other threads aren't held up.
+            let session = self.idle.lock().unwrap().pop();
+            match session {
+                Some(session) if session.is_connected() => return Ok((session, true)),
+                Some(_) => continue,
+                None => return Ok((Session::connect(&self.addr)?, false)),
+            }
+        }
+    }
+
+    fn release(&self, session: Session) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.max_idle {
+            idle.push(session);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ASSOC_ACCEPT;
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, SocketAddr, TcpListener};
+    use std::sync::mpsc::{channel, Receiver};
+    use std::thread;
+    use std::time::Duration;
+
+    //A server that associates every connection it accepts, and passes on the text of each packet
+    //sent over them with the number of the connection, counting from 0 in the order they came in.
+    fn serve() -> (SocketAddr, Receiver<(usize, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            for (connection_number, connection) in listener.incoming().enumerate() {
+                let mut connection = connection.unwrap();
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    let mut request: [u8; 2] = [0; 2];
+                    connection.read_exact(&mut request).unwrap();
+                    connection.write_all(&ASSOC_ACCEPT).unwrap();
+
+                    let mut num_bytes: [u8; 1] = [0; 1];
+                    while connection.read_exact(&mut num_bytes).is_ok() {
+                        let mut packet = vec![0; num_bytes[0] as usize];
+                        connection.read_exact(&mut packet).unwrap();
+                        let _ = tx.send((connection_number, String::from_utf8(packet[1..].to_vec()).unwrap()));
+                    }
+                    //Hold the connection open after the client stops sending, so its end alone
+                    //says whether it's alive.
+                    thread::sleep(Duration::from_secs(10));
+                });
+            }
+        });
+        return (addr, rx);
+    }
+
+    fn received(rx: &Receiver<(usize, String)>) -> (usize, String) {
+        return rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn associates_once_for_a_burst_of_sends() {
+        let (addr, rx) = serve();
+        let pool = SessionPool::new(&addr.to_string(), 2);
+        for i in 0..5 {
+            pool.send_warn(&i.to_string()).unwrap();
+        }
+        for i in 0..5 {
+            assert_eq!(received(&rx), (0, i.to_string()));
+        }
+        assert_eq!(pool.idle_count(), 1);
+    }
+
+    #[test]
+    fn sends_again_on_a_new_connection_when_an_idle_one_has_died() {
+        let (addr, rx) = serve();
+        let pool = SessionPool::new(&addr.to_string(), 2);
+        pool.send_warn("a").unwrap();
+        assert_eq!(received(&rx), (0, "a".to_string()));
+
+        //Kill the idle session's connection in a way is_connected can't see, as a server that went
+        //away without a trace would, so it's only found out by the send failing.
+        let idle = pool.idle.lock().unwrap();
+        idle[0].connection.shutdown(Shutdown::Write).unwrap();
+        assert!(idle[0].is_connected());
+        drop(idle);
+
+        pool.send_warn("b").unwrap();
+        assert_eq!(received(&rx), (1, "b".to_string()));
+        assert_eq!(pool.idle_count(), 1);
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{check_packet, PacketType, Session, SessionError, MAX_MSG_LEN};
+
+/// How long `ReconnectingSession` waits after a failed connection attempt before trying again.
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// What a `ReconnectingSession` does with a packet sent while the server can't be reached.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BufferPolicy {
+    /// Queue it. If the queue is full, wait until the server is back and there's room.
+    Block,
+    /// Queue it. If the queue is full, drop the oldest packet in it to make room; see
+    /// `ReconnectingSession::dropped_count`.
+    DropOldest,
+    /// Queue nothing, and return the connection error at once.
+    Error,
+}
+
+/// A `Session` that reconnects by itself, for long-running monitoring clients that shouldn't lose
+/// an ALERT just because the link blipped.
+///
+/// What happens to packets sent while the server can't be reached is up to the `BufferPolicy`:
+/// they're queued, at most `max_queued` of them, and sent in order once the connection is back,
+/// or refused. The queue is only sent from a send or `flush`, so a client that sends rarely should
+/// call `flush` now and then, e.g. from its main loop. A name set with `change_name` is sent again
+/// on every new connection.
+///
+/// ```no_run
+/// use api::{BufferPolicy, ReconnectingSession};
+///
+/// let mut session = ReconnectingSession::new("localhost:44444", BufferPolicy::DropOldest, 100);
+/// session.send_alert("disk full")?;
+/// if !session.is_connected() {
+///     eprintln!("Server unreachable, {} packet(s) queued.", session.queued_count());
+/// }
+/// # Ok::<(), api::SessionError>(())
+/// ```
+pub struct ReconnectingSession {
+    addr: String,
+    session: Option<Session>,
+    policy: BufferPolicy,
+    max_queued: usize,
+    //Packets waiting for the connection, oldest first.
+    queue: VecDeque<(PacketType, String)>,
+    name: Option<String>,
+    retry_delay: Duration,
+    //When the server may next be connected to, so one that's down isn't tried on every send.
+    next_attempt: Instant,
+    num_dropped: usize,
+}
+
+impl ReconnectingSession {
+    /// A session to the server at `addr`, as given to `Session::connect`, queueing at most
+    /// `max_queued` packets while it can't be reached. Nothing connects until the first send or
+    /// `flush`.
+    pub fn new(addr: &str, policy: BufferPolicy, max_queued: usize) -> ReconnectingSession {
+        return ReconnectingSession {
+            addr: addr.to_string(),
+            session: None,
+            policy: policy,
+            max_queued: max_queued,
+            queue: VecDeque::new(),
+            name: None,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            next_attempt: Instant::now(),
+            num_dropped: 0,
+        };
+    }
+
+    /// Change what's done with packets sent from now on while the server can't be reached.
+    /// Packets already queued stay queued.
+    pub fn set_policy(&mut self, policy: BufferPolicy) {
+        self.policy = policy;
+    }
+
+    /// Set how long to wait after a failed connection attempt before trying again. Starts at
+    /// `DEFAULT_RETRY_DELAY`. A connection that drops is tried again at once.
+    pub fn set_retry_delay(&mut self, delay: Duration) {
+        self.retry_delay = delay;
+    }
+
+    /// Whether there's a connection to the server: false from a failed send or connection attempt
+    /// until one succeeds. Never blocks or sends anything, so it's cheap to poll, e.g. to show a
+    /// reconnecting indicator.
+    pub fn is_connected(&self) -> bool {
+        return self.session.is_some();
+    }
+
+    /// How many packets are waiting for the connection to come back.
+    pub fn queued_count(&self) -> usize {
+        return self.queue.len();
+    }
+
+    /// How many packets `BufferPolicy::DropOldest` has dropped to make room, in all.
+    pub fn dropped_count(&self) -> usize {
+        return self.num_dropped;
+    }
+
+    pub fn send_info(&mut self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Info, Some(msg))
+    }
+
+    pub fn send_warn(&mut self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Warn, Some(msg))
+    }
+
+    pub fn send_alert(&mut self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Alert, Some(msg))
+    }
+
+    /// See `Session::send_critical`.
+    pub fn send_critical(&mut self, msg: &str) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Critical, Some(msg))
+    }
+
+    pub fn reset(&mut self) -> Result<(), SessionError> {
+        self.send_packet(PacketType::Reset, None)
+    }
+
+    /// Name this client, now if there's a connection, and on every connection after. Never
+    /// queued, as only the last name matters.
+    pub fn change_name(&mut self, name: &str) -> Result<(), SessionError> {
+        check_packet(PacketType::Name, name)?;
+        self.name = Some(name.to_string());
+        if let Some(session) = &mut self.session {
+            match session.change_name(name) {
+                Ok(()) => (),
+                Err(e) if is_connection_error(&e) => self.session = None,
+                Err(e) => return Err(e),
+            }
+        }
+        return Ok(());
+    }
+
+    /// See `Session::send_packet`. Anything queued is sent first, so the server gets packets in
+    /// the order they were sent.
+    ///
+    /// If the server can't be reached, the packet is handled by the `BufferPolicy`. Packets the
+    /// server would refuse anyway, e.g. an INFO without text, are refused at once whatever it is.
+    pub fn send_packet(&mut self, kind: PacketType, text: Option<&str>) -> Result<(), SessionError> {
+        let msg = text.unwrap_or("");
+        check_packet(kind, msg)?;
+        if msg.len() > MAX_MSG_LEN {
+            return Err(SessionError::MessageTooLong { max_len: MAX_MSG_LEN });
+        }
+
+        let result = match self.flush() {
+            Ok(()) => self.session.as_mut().expect("Flushed, so connected.").send_packet(kind, text),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) if !is_connection_error(&e) => return Err(e),
+            Err(e) => {
+                self.session = None;
+                return self.buffer(kind, msg, e);
+            },
+        }
+    }
+
+    /// Connect if there's no connection and the retry delay is up, then send everything queued.
+    /// Returns the connection error if anything is still queued, or there's no connection.
+    /// Never waits out the retry delay, though connecting itself can take up to
+    /// `DEFAULT_CONNECT_TIMEOUT`.
+    pub fn flush(&mut self) -> Result<(), SessionError> {
+        //A server that has gone away, e.g. restarted, often isn't noticed until the write after
+        //the one it should have failed, losing that packet, so check first.
+        if self.session.as_ref().is_some_and(|session| !session.is_connected()) {
+            self.session = None;
+        }
+        if self.session.is_none() {
+            if Instant::now() < self.next_attempt {
+                return Err(SessionError::Io(Error::new(ErrorKind::NotConnected, "Waiting to reconnect.")));
+            }
+            match self.reconnect() {
+                Ok(session) => self.session = Some(session),
+                Err(e) => {
+                    self.next_attempt = Instant::now() + self.retry_delay;
+                    return Err(e);
+                },
+            }
+        }
+
+        while let Some((kind, text)) = self.queue.front() {
+            let session = self.session.as_mut().expect("Connected above.");
+            match session.send_packet(*kind, Some(text)) {
+                Ok(()) => (),
+                Err(e) if is_connection_error(&e) => {
+                    self.session = None;
+                    return Err(e);
+                },
+                //Queued packets were checked, but one the server refuses mustn't hold up the rest.
+                Err(_) => (),
+            }
+            self.queue.pop_front();
+        }
+        return Ok(());
+    }
+
+    //A new connection, named if this client has a name.
+    fn reconnect(&self) -> Result<Session, SessionError> {
+        let mut session = Session::connect(&self.addr)?;
+        if let Some(name) = &self.name {
+            session.change_name(name)?;
+        }
+        return Ok(session);
+    }
+
+    //Handle a packet the server couldn't be reached for, per the policy.
+    fn buffer(&mut self, kind: PacketType, msg: &str, error: SessionError) -> Result<(), SessionError> {
+        match self.policy {
+            BufferPolicy::Error => return Err(error),
+            BufferPolicy::DropOldest => {
+                if self.queue.len() >= self.max_queued {
+                    self.num_dropped += 1;
+                    //With no room at all, the oldest is this one.
+                    if self.queue.pop_front().is_none() {
+                        return Ok(());
+                    }
+                }
+            },
+            BufferPolicy::Block => {
+                while self.queue.len() >= self.max_queued {
+                    thread::sleep(self.next_attempt.saturating_duration_since(Instant::now()));
+                    if self.flush().is_ok() {
+                        //Everything queued went out, so this one can go straight after it.
+                        return self.send_packet(kind, Some(msg));
+                    }
+                }
+            },
+        }
+        self.queue.push_back((kind, msg.to_string()));
+        return Ok(());
+    }
+}
+
+//Errors that mean the connection is gone, rather than that the packet was refused.
+fn is_connection_error(e: &SessionError) -> bool {
+    return matches!(e, SessionError::Io(_) | SessionError::ShortWrite | SessionError::NotAssociated);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ASSOC_ACCEPT;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread::JoinHandle;
+
+    //A port nothing is listening on, for now.
+    fn free_port() -> SocketAddr {
+        return TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    }
+
+    //Serve one connection: associate it, read num_packets packets as (type byte, text), then hang
+    //up. The listener is dropped with it, so the server has stopped once this returns.
+    fn serve_one(listener: TcpListener, num_packets: usize) -> Vec<(u8, String)> {
+        let (mut connection, _) = listener.accept().unwrap();
+        let mut request: [u8; 2] = [0; 2];
+        connection.read_exact(&mut request).unwrap();
+        connection.write_all(&ASSOC_ACCEPT).unwrap();
+
+        let mut packets = Vec::new();
+        for _ in 0..num_packets {
+            let mut num_bytes: [u8; 1] = [0; 1];
+            connection.read_exact(&mut num_bytes).unwrap();
+            let mut packet = vec![0; num_bytes[0] as usize];
+            connection.read_exact(&mut packet).unwrap();
+            packets.push((packet[0], String::from_utf8(packet[1..].to_vec()).unwrap()));
+        }
+        return packets;
+    }
+
+    //Start a server on addr now, listening before this returns.
+    fn serve(addr: SocketAddr, num_packets: usize) -> JoinHandle<Vec<(u8, String)>> {
+        let listener = TcpListener::bind(addr).unwrap();
+        return thread::spawn(move || serve_one(listener, num_packets));
+    }
+
+    //Start a server on addr once delay has passed.
+    fn serve_after(addr: SocketAddr, delay: Duration, num_packets: usize) -> JoinHandle<Vec<(u8, String)>> {
+        return thread::spawn(move || {
+            thread::sleep(delay);
+            return serve_one(TcpListener::bind(addr).unwrap(), num_packets);
+        });
+    }
+
+    fn warn(text: &str) -> (u8, String) {
+        return (PacketType::Warn.to_type_number(), text.to_string());
+    }
+
+    //A session to addr that retries on every send, connected and then cut off by a server stopping.
+    fn cut_off(addr: SocketAddr, policy: BufferPolicy, max_queued: usize) -> ReconnectingSession {
+        let server = serve(addr, 1);
+        let mut session = ReconnectingSession::new(&addr.to_string(), policy, max_queued);
+        session.set_retry_delay(Duration::ZERO);
+        session.send_warn("before").unwrap();
+        assert_eq!(server.join().unwrap(), [warn("before")]);
+        return session;
+    }
+
+    #[test]
+    fn sends_what_was_queued_once_the_server_is_back() {
+        for policy in [BufferPolicy::Block, BufferPolicy::DropOldest] {
+            let addr = free_port();
+            let mut session = cut_off(addr, policy, 10);
+
+            session.send_warn("a").unwrap();
+            session.send_warn("b").unwrap();
+            assert!(!session.is_connected());
+            assert_eq!(session.queued_count(), 2);
+
+            let server = serve(addr, 2);
+            session.flush().unwrap();
+            assert!(session.is_connected());
+            assert_eq!(session.queued_count(), 0);
+            assert_eq!(server.join().unwrap(), [warn("a"), warn("b")]);
+            assert_eq!(session.dropped_count(), 0);
+        }
+    }
+
+    #[test]
+    fn block_waits_for_room_until_the_server_is_back() {
+        let addr = free_port();
+        let mut session = cut_off(addr, BufferPolicy::Block, 1);
+        session.set_retry_delay(Duration::from_millis(20));
+        session.send_warn("a").unwrap();
+
+        let server = serve_after(addr, Duration::from_millis(200), 2);
+        let start = Instant::now();
+        session.send_warn("b").unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(server.join().unwrap(), [warn("a"), warn("b")]);
+    }
+
+    #[test]
+    fn drop_oldest_counts_what_it_drops() {
+        let addr = free_port();
+        let mut session = cut_off(addr, BufferPolicy::DropOldest, 2);
+        for text in ["a", "b", "c", "d"] {
+            session.send_warn(text).unwrap();
+        }
+        assert_eq!(session.queued_count(), 2);
+        assert_eq!(session.dropped_count(), 2);
+
+        let server = serve(addr, 2);
+        session.flush().unwrap();
+        assert_eq!(server.join().unwrap(), [warn("c"), warn("d")]);
+        assert_eq!(session.dropped_count(), 2);
+    }
+
+    #[test]
+    fn error_returns_the_connection_error_at_once() {
+        let addr = free_port();
+        let mut session = cut_off(addr, BufferPolicy::Error, 10);
+        let start = Instant::now();
+        assert!(matches!(session.send_warn("a"), Err(SessionError::Io(_))));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(session.queued_count(), 0);
+    }
+}
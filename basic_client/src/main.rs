@@ -1,7 +1,9 @@
-use api::Session;
+use api::{Session, DEFAULT_PORT};
+use std::time::Duration;
 
 fn main() {
-    let mut session = match Session::connect("localhost:44444") {
+    //Give the server a few seconds to come up if they were started together.
+    let mut session = match Session::connect_retry(&format!("localhost:{}", DEFAULT_PORT), 10, Duration::from_millis(500)) {
         Ok(s) => s,
         Err(e) => {
             eprintln!("Could not connect: {}", e);
@@ -1,4 +1,4 @@
-use api::Session;
+use api::{PacketType, Session, SessionError, MAX_MSG_LEN, MAX_NAME_LEN, DEFAULT_PORT};
 use adhocrays::*;
 
 fn button(dc: &mut DrawingContext, x: i32, y: i32, w: i32, h: i32, text: &str, bg_color: Color) -> bool {
@@ -31,7 +31,28 @@ fn button(dc: &mut DrawingContext, x: i32, y: i32, w: i32, h: i32, text: &str, b
     return is_pressed;
 }
 
-use std::time::{Duration, Instant};
+#[derive(Copy, Clone, PartialEq)]
+enum Severity {
+    Info,
+    Warn,
+    Alert,
+}
+
+//If msg is too wide to fit, show its end, as that's where the typing happens.
+fn visible_tail(msg: &str, font_size: i32, max_width: f32) -> String {
+    if measure_text_ex(get_default_font(), msg, font_size as f32, 1.5).x <= max_width {
+        return msg.to_string();
+    }
+    for (i, _) in msg.char_indices() {
+        let tail = format!("...{}", &msg[i..]);
+        if measure_text_ex(get_default_font(), &tail, font_size as f32, 1.5).x <= max_width {
+            return tail;
+        }
+    }
+    return "...".to_string();
+}
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::thread;
 
 fn sleep_until(time: Instant) {
@@ -40,37 +61,290 @@ fn sleep_until(time: Instant) {
     }
 }
 
-fn main() {
-    let mut session = match Session::connect("localhost:44444") {
-        Ok(s) => s,
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+
+//Whether the session is unusable after e, as opposed to just that one packet being refused.
+fn is_connection_error(e: &SessionError) -> bool {
+    return matches!(e, SessionError::Io(_) | SessionError::ShortWrite | SessionError::NotAssociated);
+}
+
+//What the client calls itself until the user names it.
+const DEFAULT_NAME: &str = "warn_client";
+
+//Connect on another thread, so an unreachable server doesn't stall the render loop. The new
+//session is given name, so a reconnect keeps the name the user chose.
+fn connect_in_background(addr: &str, name: &str) -> Receiver<Result<Session, SessionError>> {
+    let (tx, rx) = channel();
+    let addr = addr.to_string();
+    let name = name.to_string();
+    thread::spawn(move || {
+        let result = Session::connect(&addr).and_then(|mut session| {
+            //Sends happen on the render thread, so never let one hold up a frame for long.
+            session.set_write_timeout(Some(Duration::from_millis(20)))?;
+            //Waiting on an ACK stalls the frame, so give up on a link slow enough to notice.
+            session.set_ack_timeout(Some(Duration::from_millis(250)))?;
+            session.change_name(&name)?;
+            Ok(session)
+        });
+        //Nothing to do if the window closed in the meantime.
+        let _ = tx.send(result);
+    });
+    return rx;
+}
+
+//What a click sends.
+enum Request {
+    Packet(PacketType, String),
+    Name(String),
+    Reset,
+}
+
+//Send the request, returning what to tell the user.
+fn send_request(session: &mut Session, request: &Request) -> Result<String, SessionError> {
+    match request {
+        Request::Packet(kind, msg) => {
+            //Servers too old to ACK still get the packet, just without a round trip time.
+            match session.send_acked(*kind, Some(msg)) {
+                Ok(rtt) => return Ok(format!("Sent! ({}ms)", rtt.as_millis())),
+                Err(SessionError::Unsupported) => {
+                    session.send_packet(*kind, Some(msg))?;
+                    return Ok("Sent!".to_string());
+                },
+                Err(e) => return Err(e),
+            }
+        },
+        Request::Name(name) => {
+            session.change_name(name)?;
+            return Ok("Name changed!".to_string());
+        },
+        Request::Reset => {
+            session.reset()?;
+            return Ok("Reset!".to_string());
+        },
+    }
+}
+
+//How many times a send that failed on a connection error is tried again before the error is shown,
+//and for how long after the click at most, including waiting for the connection to come back.
+const MAX_RETRIES: u32 = 2;
+const RETRY_WINDOW: Duration = Duration::from_secs(3);
+const RETRYING_MSG: &str = "Retrying...";
+
+//A request to try again on a later frame, so the render loop never waits on it.
+struct Retry {
+    request: Request,
+    retries: u32,
+    at: Instant,
+    give_up_at: Instant,
+    last_error: SessionError,
+}
+
+//A little longer for each retry, plus up to 100ms of jitter, so clients cut off by the same hiccup
+//don't all come back at the same moment.
+fn retry_delay(retries: u32) -> Duration {
+    let jitter = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos()) % 100;
+    return Duration::from_millis(100 * retries as u64 + jitter as u64);
+}
+
+//Send the request, setting err_msg to how it went. On a connection error, the connection is
+//reestablished and the request returned to try again, unless it's out of retries.
+fn attempt(session: &mut Session, request: Request, retries: u32, give_up_at: Instant, is_connected: &mut bool, err_msg: &mut String) -> Option<Retry> {
+    match send_request(session, &request) {
+        Ok(done) => {
+            *err_msg = done;
+            return None;
+        },
         Err(e) => {
-            eprintln!("{}", e);
-            return;
+            if !is_connection_error(&e) {
+                *err_msg = format!("ERR: {}", e);
+                return None;
+            }
+            *is_connected = false;
+            if retries >= MAX_RETRIES || Instant::now() >= give_up_at {
+                *err_msg = format!("ERR: {}", e);
+                return None;
+            }
+            *err_msg = RETRYING_MSG.to_string();
+            return Some(Retry {
+                request: request,
+                retries: retries + 1,
+                at: Instant::now() + retry_delay(retries + 1),
+                give_up_at: give_up_at,
+                last_error: e,
+            });
         },
-    };
+    }
+}
+
+use std::env;
+
+fn main() {
+    //The server address may be given as the first argument.
+    let server_addr = env::args().nth(1).unwrap_or_else(|| format!("localhost:{}", DEFAULT_PORT));
 
-    session.change_name("warn_client").expect("Immediately lost connection to the server.");
     let wc = init_window_context(800, 450, "warn_client");
-    let mut msg = String::new();
     let mut err_msg = String::new();
 
     let max_fps = 30.0;
     let frame_time = Duration::from_secs_f32(1.0/max_fps);
     let mut next_frame = Instant::now();
 
+    //Connect before anything else. If it fails, say why and offer a retry rather than closing.
+    let is_valid_addr = server_addr.rsplit_once(':')
+        .map_or(false, |(host, port)| host.len() > 0 && port.parse::<u16>().is_ok());
+    let mut connecting: Option<Receiver<Result<Session, SessionError>>> = None;
+    if is_valid_addr {
+        connecting = Some(connect_in_background(&server_addr, DEFAULT_NAME));
+    }
+    else {
+        err_msg = format!("ERR: {} is not a valid host:port address.", server_addr);
+    }
+
+    let mut session: Option<Session> = None;
+    while session.is_none() {
+        if wc.window_should_close() {
+            return;
+        }
+
+        //Sleep until next frame.
+        sleep_until(next_frame);
+        next_frame += frame_time;
+
+        if let Some(rx) = &connecting {
+            match rx.try_recv() {
+                Ok(Ok(s)) => session = Some(s),
+                Ok(Err(e)) => {
+                    err_msg = format!("ERR: Could not connect to {}: {}", server_addr, e);
+                    connecting = None;
+                },
+                Err(TryRecvError::Disconnected) => {
+                    err_msg = format!("ERR: Could not connect to {}.", server_addr);
+                    connecting = None;
+                },
+                Err(TryRecvError::Empty) => (),
+            }
+        }
+
+        let mut dc = wc.init_drawing_context();
+        dc.clear_background(Color { r: 25, g: 75, b: 75, a: 255 });
+
+        let middle_height = get_screen_height() / 2;
+        let middle_width = get_screen_width() / 2;
+
+        let font_size = 20;
+        let txt;
+        let color;
+        if connecting.is_some() {
+            txt = format!("Connecting to {}...", server_addr);
+            color = colors::WHITE;
+        }
+        else {
+            txt = err_msg.clone();
+            color = colors::RED;
+        }
+        let ascii_size = measure_text_ex(get_default_font(), &txt, font_size as f32, 1.5);
+        let x = middle_width - (ascii_size.x / 2.0) as i32;
+        let y = middle_height - (ascii_size.y / 2.0) as i32;
+        dc.draw_text(&txt, x, y - 70, font_size, color);
+
+        if connecting.is_none() && is_valid_addr {
+            let w = 150;
+            let h = 50;
+            let x = middle_width - (w / 2);
+            let y = middle_height - (h / 2);
+            if button(&mut dc, x, y, w, h, "RETRY", Color { r: 24, g: 24, b: 24, a: 255 }) {
+                connecting = Some(connect_in_background(&server_addr, DEFAULT_NAME));
+            }
+        }
+    }
+    let mut session = session.unwrap();
+
+    let mut msg = String::new();
+    err_msg = String::new();
+    //The severity Enter sends at: whichever was sent last, WARN to begin with.
+    let mut severity = Severity::Warn;
+
+    //Whether the server is believed to be alive, from the last send or health check.
+    let mut is_connected = true;
+    let mut reconnect: Option<Receiver<Result<Session, SessionError>>> = None;
+    let health_check_interval = Duration::from_secs(1);
+    let mut next_health_check = Instant::now() + health_check_interval;
+    //A click whose send failed, waiting to be tried again.
+    let mut retry: Option<Retry> = None;
+
     while !wc.window_should_close() {
         //Sleep until next frame.
         sleep_until(next_frame);
         next_frame += frame_time;
 
+        //Keep track of whether the server is still there, and try to get it back when it isn't.
+        if is_connected && Instant::now() >= next_health_check {
+            is_connected = session.is_connected();
+            next_health_check = Instant::now() + health_check_interval;
+        }
+        if !is_connected {
+            if let Some(rx) = &reconnect {
+                match rx.try_recv() {
+                    Ok(Ok(s)) => {
+                        session = s;
+                        is_connected = true;
+                        reconnect = None;
+                    },
+                    Ok(Err(_)) | Err(TryRecvError::Disconnected) => {
+                        //Wait a health check interval before trying again.
+                        reconnect = None;
+                        next_health_check = Instant::now() + health_check_interval;
+                    },
+                    Err(TryRecvError::Empty) => (),
+                }
+            }
+            else if Instant::now() >= next_health_check {
+                //The last name sent successfully, which the new connection takes on.
+                reconnect = Some(connect_in_background(&server_addr, session.name().unwrap_or(DEFAULT_NAME)));
+            }
+        }
+
+        //Try a failed send again once its delay is up and the connection is back.
+        if let Some(r) = retry.take() {
+            if Instant::now() >= r.give_up_at {
+                err_msg = format!("ERR: {}", r.last_error);
+            }
+            else if Instant::now() < r.at || !is_connected {
+                retry = Some(r);
+            }
+            else {
+                retry = attempt(&mut session, r.request, r.retries, r.give_up_at, &mut is_connected, &mut err_msg);
+            }
+        }
+
         let mut dc = wc.init_drawing_context();
         dc.clear_background(Color { r: 25, g: 75, b: 75, a: 255 });
 
+        //Draw the connection status light.
+        let font_size = 20;
+        if is_connected {
+            dc.draw_rectangle(10, 10, 16, 16, colors::GREEN);
+            dc.draw_text("Connected", 34, 8, font_size, colors::WHITE);
+        }
+        else {
+            dc.draw_rectangle(10, 10, 16, 16, colors::RED);
+            dc.draw_text("Reconnecting...", 34, 8, font_size, colors::WHITE);
+        }
+
+        //Ctrl is held for the severity shortcuts, which shouldn't also type into msg.
+        let is_ctrl_down = is_key_down(Key::LEFT_CONTROL) || is_key_down(Key::RIGHT_CONTROL);
+
         //Get input into msg.
         let char_pressed = get_char_pressed();
-        if char_pressed.is_some() {
-            err_msg = "".to_string();
-            msg.push(char_pressed.unwrap());
+        if char_pressed.is_some() && !is_ctrl_down {
+            let c = char_pressed.unwrap();
+            if msg.len() + c.len_utf8() > MAX_MSG_LEN {
+                err_msg = format!("ERR: Messages are limited to {} bytes.", MAX_MSG_LEN);
+            }
+            else {
+                err_msg = "".to_string();
+                msg.push(c);
+            }
         }
 
         if is_key_pressed(Key::BACKSPACE) || is_key_pressed_repeat(Key::BACKSPACE) {
@@ -91,10 +365,22 @@ fn main() {
 
         //Draw the message that will be sent upon INFO/WARN/ALERT, etc.
         let font_size = 20;
-        let ascii_size = measure_text_ex(get_default_font(), &msg, font_size as f32, 1.5);
+        let visible_msg = visible_tail(&msg, font_size, (get_screen_width() - 40) as f32);
+        let ascii_size = measure_text_ex(get_default_font(), &visible_msg, font_size as f32, 1.5);
         let x = middle_width - (ascii_size.x / 2.0) as i32;
         let y = middle_height - (ascii_size.y / 2.0) as i32;
-        dc.draw_text(&msg, x, y - 70, font_size, colors::WHITE);
+        dc.draw_text(&visible_msg, x, y - 70, font_size, colors::WHITE);
+
+        //Draw how much of the packet the message uses, in red once it's full.
+        let txt = format!("{}/{}", msg.len(), MAX_MSG_LEN);
+        let color;
+        if msg.len() >= MAX_MSG_LEN {
+            color = colors::RED;
+        }
+        else {
+            color = colors::WHITE;
+        }
+        dc.draw_text(&txt, 10, get_screen_height() - 30, font_size, color);
 
         let txt = "Sending:";
         let ascii_size = measure_text_ex(get_default_font(), txt, font_size as f32, 1.5);
@@ -107,6 +393,9 @@ fn main() {
         if err_msg.starts_with("ERR:") {
             color = colors::RED;
         }
+        else if err_msg == RETRYING_MSG {
+            color = Color { r: 253, g: 249, b: 0, a: 255 };
+        }
         else {
             color = colors::GREEN;
         }
@@ -117,21 +406,34 @@ fn main() {
 
         //Now draw the buttons:
 
+        //Enter sends too, so typing doesn't need the mouse, and Ctrl+1/2/3 picks the severity.
+        let mut to_send: Option<Severity> = None;
+        if is_key_pressed(Key::ENTER) {
+            to_send = Some(severity);
+        }
+        if is_ctrl_down {
+            if is_key_pressed(Key::ONE) {
+                to_send = Some(Severity::Info);
+            }
+            if is_key_pressed(Key::TWO) {
+                to_send = Some(Severity::Warn);
+            }
+            if is_key_pressed(Key::THREE) {
+                to_send = Some(Severity::Alert);
+            }
+        }
+
+        //The button for the severity Enter sends at is outlined.
         let w = 150;
         let h = 50;
         let offset = 0;
         let x = middle_width - (w / 2);
         let y = middle_height - (h / 2) + offset;
+        if severity == Severity::Info {
+            dc.draw_rectangle(x - 3, y - 3, w + 6, h + 6, colors::WHITE);
+        }
         if button(&mut dc, x, y, w, h, "INFO", Color { r: 24, g: 24, b: 24, a: 255 }) {
-            if msg.len() == 0 {
-                err_msg = "ERR: INFO messages must be non-zero.".to_string();
-            }
-            else {
-                match session.send_info(&msg) {
-                    Ok(_) => err_msg = "Sent!".to_string(),
-                    Err(e) => err_msg = format!("ERR: {}", e),
-                }
-            }
+            to_send = Some(Severity::Info);
         }
 
         let w = 150;
@@ -139,11 +441,11 @@ fn main() {
         let offset = 70;
         let x = middle_width - (w / 2);
         let y = middle_height - (h / 2) + offset;
+        if severity == Severity::Warn {
+            dc.draw_rectangle(x - 3, y - 3, w + 6, h + 6, colors::WHITE);
+        }
         if button(&mut dc, x, y, w, h, "WARN", Color { r: 244, g: 131, b: 37, a: 255 }) {
-            match session.send_warn(&msg) {
-                Ok(_) => err_msg = "Sent!".to_string(),
-                Err(e) => err_msg = format!("ERR: {}", e),
-            }
+            to_send = Some(Severity::Warn);
         }
 
         let w = 150;
@@ -151,11 +453,53 @@ fn main() {
         let offset = 140;
         let x = middle_width - (w / 2);
         let y = middle_height - (h / 2) + offset;
+        if severity == Severity::Alert {
+            dc.draw_rectangle(x - 3, y - 3, w + 6, h + 6, colors::WHITE);
+        }
         if button(&mut dc, x, y, w, h, "ALERT", Color { r: 179, g: 0, b: 0, a: 255 }) {
-            match session.send_alert(&msg) {
-                Ok(_) => err_msg = "Sent!".to_string(),
-                Err(e) => err_msg = format!("ERR: {}", e),
+            to_send = Some(Severity::Alert);
+        }
+
+        if let Some(s) = to_send {
+            severity = s;
+            if s == Severity::Info && msg.len() == 0 {
+                err_msg = "ERR: INFO messages must be non-zero.".to_string();
+            }
+            else {
+                let kind = match s {
+                    Severity::Info => PacketType::Info,
+                    Severity::Warn => PacketType::Warn,
+                    Severity::Alert => PacketType::Alert,
+                };
+                retry = attempt(&mut session, Request::Packet(kind, msg.clone()), 0, Instant::now() + RETRY_WINDOW, &mut is_connected, &mut err_msg);
             }
         }
+
+        //The NAME and RESET buttons sit in a column to the right of the others.
+        let w = 150;
+        let h = 50;
+        let offset = 0;
+        let x = middle_width - (w / 2) + 170;
+        let y = middle_height - (h / 2) + offset;
+        if button(&mut dc, x, y, w, h, "NAME", Color { r: 24, g: 24, b: 24, a: 255 }) {
+            if msg.len() == 0 {
+                err_msg = "ERR: Names must be non-zero.".to_string();
+            }
+            else if msg.len() >= MAX_NAME_LEN {
+                err_msg = format!("ERR: Names must be under {} bytes.", MAX_NAME_LEN);
+            }
+            else {
+                retry = attempt(&mut session, Request::Name(msg.clone()), 0, Instant::now() + RETRY_WINDOW, &mut is_connected, &mut err_msg);
+            }
+        }
+
+        let w = 150;
+        let h = 50;
+        let offset = 70;
+        let x = middle_width - (w / 2) + 170;
+        let y = middle_height - (h / 2) + offset;
+        if button(&mut dc, x, y, w, h, "RESET", Color { r: 24, g: 24, b: 24, a: 255 }) {
+            retry = attempt(&mut session, Request::Reset, 0, Instant::now() + RETRY_WINDOW, &mut is_connected, &mut err_msg);
+        }
     }
 }
@@ -0,0 +1,64 @@
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use api::{PacketBuffer, PacketType, MAX_MSG_LEN};
+
+use crate::{logger::Log, pseudonyms::Pseudonyms, show_peer, truncate_to_bytes, Peer};
+
+//A client's side of its connection, shared between its own connection thread (for ACKs) and
+//broadcasts from everyone else's, so their writes can't interleave.
+pub type ClientWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+//With --broadcast, every WARN and ALERT is forwarded to all the other associated clients, so a
+//whole team's machines can light up when any one of them raises the alarm.
+pub struct Broadcast {
+    clients: Mutex<Vec<(Peer, ClientWriter)>>,
+    //What clients are logged as, with --anonymize-peers.
+    pseudonyms: Option<Arc<Pseudonyms>>,
+}
+
+impl Broadcast {
+    pub fn new(pseudonyms: Option<Arc<Pseudonyms>>) -> Self {
+        return Broadcast { clients: Mutex::new(Vec::new()), pseudonyms: pseudonyms };
+    }
+
+    pub fn join(&self, peer_addr: Peer, writer: ClientWriter) {
+        self.clients.lock().unwrap().push((peer_addr, writer));
+    }
+
+    pub fn leave(&self, peer_addr: Peer) {
+        self.clients.lock().unwrap().retain(|(peer, _)| *peer != peer_addr);
+    }
+
+    //Send the packet, in the same format clients send them, to every client but the one it came
+    //from. A client that can't take it within its write timeout is dropped from the broadcast;
+    //its own connection thread notices if it has gone.
+    pub fn forward(&self, from: Peer, packet_type: PacketType, text: Option<&str>, log: &Mutex<Log>) {
+        //Text that arrived compressed can be longer than a packet holds, and is forwarded cut to fit.
+        let mut text = text.unwrap_or("").to_string();
+        truncate_to_bytes(&mut text, MAX_MSG_LEN);
+        let packet = PacketBuffer::from_payload(packet_type.to_type_number(), text.as_bytes()).expect("Cut to fit above.");
+
+        //Never hold the list while writing, so a slow client only holds up this broadcast, and
+        //clients can come and go in the meantime.
+        let recipients: Vec<(Peer, ClientWriter)> = self.clients.lock().unwrap()
+            .iter()
+            .filter(|(peer, _)| *peer != from)
+            .map(|(peer, writer)| (*peer, Arc::clone(writer)))
+            .collect();
+
+        let mut failed: Vec<Peer> = Vec::new();
+        for (peer, writer) in recipients {
+            if let Err(e) = writer.lock().unwrap().write_all(packet.as_bytes()) {
+                writeln!(log.lock().unwrap(), "WARN: Could not forward a {} packet to {}: {}. No longer broadcasting to it.", packet_type.to_string(), show_peer(&peer, self.pseudonyms.as_deref()), e).unwrap();
+                failed.push(peer);
+            }
+        }
+
+        if failed.len() > 0 {
+            self.clients.lock().unwrap().retain(|(peer, _)| !failed.contains(peer));
+        }
+    }
+}
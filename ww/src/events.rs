@@ -0,0 +1,286 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    os::unix::net::UnixListener,
+    sync::{
+        mpsc::{sync_channel, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{logger::Log, pseudonyms::Pseudonyms, show_peer, Channel, LogItem, WarnStates};
+
+//How many events a consumer may fall behind by before it is disconnected.
+const CONSUMER_BUFFER: usize = 256;
+
+//A read-only fan-out of the packet log, for tools that want to react to events as they happen.
+//Every consumer connected to the socket gets every event from the time it connected, one per line:
+//
+//  <Millis> <Kind> <Peer> [<Text>]
+//
+//Millis is the event time in milliseconds since the Unix epoch. Kind is CONNECT, DISCONNECT or the
+//packet type, e.g. ALERT. Peer is the client's address, or with --anonymize-peers its pseudonym,
+//whether or not p has revealed the real ones on screen. Text, if the packet has any, is quoted
+//with quotes, backslashes and control characters escaped, so it never spans lines.
+//
+//When a client's sequence numbers skip some, Kind is GAP and how many packets are missing follows
+//Peer, unquoted.
+//
+//When the operator acknowledges a WARN or ALERT, so other dashboards and tools can follow along:
+//
+//  <Millis> ACK <Channel> <State> [<Operator>]
+//
+//Operator is quoted like Text, and left out if --operator wasn't given.
+//
+//With --event-history N, a consumer is caught up as it connects, so a tool attached late starts
+//with recent context rather than a blank:
+//
+//  <Millis> HISTORY <Count>
+//  ...the last Count lines, at most N, oldest first...
+//  <Millis> STATE <Channel> <State>
+//
+//with a STATE line for each channel, then the live events. Millis on HISTORY and STATE is when the
+//consumer connected. Tools that act on events, e.g. paging on ALERT, should skip the Count lines
+//after HISTORY, as those have already happened. Only the new consumer is sent them; nothing is
+//replayed on the dashboard itself.
+//
+//The main loop never waits on a consumer: each has its own thread and bounded buffer, and one that
+//falls too far behind is dropped. It can reconnect, but misses what happened in between.
+pub struct EventStream {
+    consumers: Arc<Mutex<Vec<SyncSender<String>>>>,
+    history: Arc<Mutex<History>>,
+    pseudonyms: Option<Arc<Pseudonyms>>,
+}
+
+//What a consumer is caught up with when it connects, with --event-history.
+struct History {
+    //The last len lines published, oldest first.
+    lines: VecDeque<String>,
+    len: usize,
+    //Each channel's name and warn state, for the STATE lines.
+    states: Vec<(String, WarnStates)>,
+}
+
+impl History {
+    //The lines a consumer connecting now is sent before any live event. Empty without
+    //--event-history.
+    fn catch_up(&self) -> Vec<String> {
+        if self.len == 0 {
+            return Vec::new();
+        }
+
+        let millis = now_millis();
+        let mut lines = Vec::with_capacity(self.lines.len() + self.states.len() + 1);
+        lines.push(format!("{} HISTORY {}\n", millis, self.lines.len()));
+        lines.extend(self.lines.iter().cloned());
+        for (name, warn_state) in self.states.iter() {
+            lines.push(format!("{} STATE {} {}\n", millis, name, warn_state.to_string()));
+        }
+        return lines;
+    }
+}
+
+impl EventStream {
+    //history_len is --event-history's N, 0 without it.
+    pub fn serve(path: &str, history_len: usize, pseudonyms: Option<Arc<Pseudonyms>>, log: Arc<Mutex<Log>>) -> io::Result<EventStream> {
+        //A socket file left behind by a previous run would make bind fail.
+        use std::os::unix::fs::FileTypeExt;
+        if std::fs::symlink_metadata(path).map_or(false, |m| m.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+
+        let consumers: Arc<Mutex<Vec<SyncSender<String>>>> = Arc::new(Mutex::new(Vec::new()));
+        let _consumers = Arc::clone(&consumers);
+        let history = Arc::new(Mutex::new(History { lines: VecDeque::with_capacity(history_len), len: history_len, states: Vec::new() }));
+        let _history = Arc::clone(&history);
+
+        let _event_listener = thread::spawn(move || {
+            for connection in listener.incoming() {
+                let mut connection = match connection {
+                    Ok(c) => c,
+                    Err(e) => {
+                        writeln!(log.lock().unwrap(), "ERROR: Event socket: {}", e).unwrap();
+                        continue;
+                    },
+                };
+
+                //Catch up and join while holding the list, which publish_line holds too, so no
+                //event is missed or sent twice in between. The buffer has room for the catch-up on
+                //top of the usual, so it can't get the consumer dropped.
+                let mut consumers = _consumers.lock().unwrap();
+                let catch_up = _history.lock().unwrap().catch_up();
+                let (tx, rx) = sync_channel::<String>(CONSUMER_BUFFER + catch_up.len());
+                for line in catch_up {
+                    let _ = tx.try_send(line);
+                }
+                consumers.push(tx);
+                drop(consumers);
+                writeln!(log.lock().unwrap(), "INFO: Event stream consumer connected.").unwrap();
+
+                //Ends when the consumer goes away, or when it's dropped from the list and the
+                //channel closes.
+                let _consumer_thread = thread::spawn(move || {
+                    for line in rx {
+                        if connection.write_all(line.as_bytes()).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+
+        return Ok(EventStream { consumers: consumers, history: history, pseudonyms: pseudonyms });
+    }
+
+    pub fn publish(&self, log_item: &LogItem) {
+        self.publish_line(format_event(log_item, self.pseudonyms.as_deref()));
+    }
+
+    pub fn publish_ack(&self, channel: &str, warn_state: &WarnStates, operator: Option<&str>) {
+        let millis = now_millis();
+
+        match operator {
+            Some(operator) => self.publish_line(format!("{} ACK {} {} {:?}\n", millis, channel, warn_state.to_string(), operator)),
+            None => self.publish_line(format!("{} ACK {} {}\n", millis, channel, warn_state.to_string())),
+        }
+    }
+
+    //Keep the channels' warn states for the STATE lines, with --event-history. Called every frame,
+    //as the states change in more places than are published.
+    pub fn update_states(&self, channels: &[Channel]) {
+        let mut history = self.history.lock().unwrap();
+        if history.len == 0 {
+            return;
+        }
+        history.states.clear();
+        history.states.extend(channels.iter().map(|channel| (channel.name.clone(), channel.warn_state)));
+    }
+
+    fn publish_line(&self, line: String) {
+        let mut consumers = self.consumers.lock().unwrap();
+        let mut history = self.history.lock().unwrap();
+        if history.len > 0 {
+            if history.lines.len() == history.len {
+                history.lines.pop_front();
+            }
+            history.lines.push_back(line.clone());
+        }
+        drop(history);
+
+        consumers.retain(|consumer| match consumer.try_send(line.clone()) {
+            Ok(()) => true,
+            //Full means the consumer has fallen too far behind; dropping its sender closes it.
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+fn now_millis() -> u128 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis());
+}
+
+fn format_event(log_item: &LogItem, pseudonyms: Option<&Pseudonyms>) -> String {
+    let millis = log_item
+        .timestamp()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis());
+
+    match log_item {
+        LogItem::ConnectLogItem { peer_addr, .. } => format!("{} CONNECT {}\n", millis, show_peer(peer_addr, pseudonyms)),
+        LogItem::DisconnectLogItem { peer_addr, .. } => format!("{} DISCONNECT {}\n", millis, show_peer(peer_addr, pseudonyms)),
+        LogItem::GapLogItem { peer_addr, missed, .. } => format!("{} GAP {} {}\n", millis, show_peer(peer_addr, pseudonyms), missed),
+        LogItem::PacketLogItem { peer_addr, packet, .. } => match &packet.text {
+            //Debug formatting quotes and escapes the text.
+            Some(text) => format!("{} {} {} {:?}\n", millis, packet.packet_type.to_string(), show_peer(peer_addr, pseudonyms), text),
+            None => format!("{} {} {}\n", millis, packet.packet_type.to_string(), show_peer(peer_addr, pseudonyms)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Disconnect, Packet, Peer};
+    use api::PacketType;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    fn peer() -> Peer {
+        return Peer::Tcp("192.168.1.7:51884".parse().unwrap());
+    }
+
+    fn timestamp() -> SystemTime {
+        return UNIX_EPOCH + Duration::from_millis(1_700_000_000_000);
+    }
+
+    fn alert(text: Option<&str>) -> LogItem {
+        return LogItem::PacketLogItem {
+            timestamp: timestamp(),
+            peer_addr: peer(),
+            packet: Packet {
+                packet_type: PacketType::Alert,
+                text: text.map(str::to_string),
+                will_type: None,
+                timestamp: None,
+                wants_ack: false,
+                sequence: None,
+                has_invalid_utf8: false,
+            },
+        };
+    }
+
+    #[test]
+    fn formats_every_event() {
+        assert_eq!(format_event(&LogItem::ConnectLogItem { timestamp: timestamp(), peer_addr: peer(), host_name: None }, None), "1700000000000 CONNECT 192.168.1.7:51884\n");
+        assert_eq!(format_event(&LogItem::DisconnectLogItem { timestamp: timestamp(), peer_addr: peer(), how: Disconnect::Clean }, None), "1700000000000 DISCONNECT 192.168.1.7:51884\n");
+        assert_eq!(format_event(&LogItem::GapLogItem { timestamp: timestamp(), peer_addr: peer(), missed: 3 }, None), "1700000000000 GAP 192.168.1.7:51884 3\n");
+        assert_eq!(format_event(&alert(None), None), "1700000000000 ALERT 192.168.1.7:51884\n");
+        assert_eq!(format_event(&alert(Some("db \"down\"\nagain")), None), "1700000000000 ALERT 192.168.1.7:51884 \"db \\\"down\\\"\\nagain\"\n");
+    }
+
+    #[test]
+    fn shows_peers_by_pseudonym_with_anonymize_peers() {
+        let pseudonyms = Pseudonyms::new();
+        let pseudonym = pseudonyms.show(&peer());
+        let line = format_event(&alert(Some("db down")), Some(&pseudonyms));
+        assert_eq!(line, format!("1700000000000 ALERT {} \"db down\"\n", pseudonym));
+        assert!(!line.contains("192.168.1.7"));
+
+        //The same host, the same pseudonym, so a tool can follow it.
+        let connect = format_event(&LogItem::ConnectLogItem { timestamp: timestamp(), peer_addr: peer(), host_name: None }, Some(&pseudonyms));
+        assert_eq!(connect, format!("1700000000000 CONNECT {}\n", pseudonym));
+    }
+
+    #[test]
+    fn catches_a_late_consumer_up_before_the_live_events() {
+        let path = std::env::temp_dir().join(format!("ww-events-test-{}", std::process::id()));
+        let stream = EventStream::serve(path.to_str().unwrap(), 2, None, Arc::new(Mutex::new(Log::new(None)))).unwrap();
+        for text in ["first", "second", "third"] {
+            stream.publish(&alert(Some(text)));
+        }
+        stream.update_states(&[Channel::new("default".to_string(), WarnStates::Alert), Channel::new("db".to_string(), WarnStates::None)]);
+
+        let connection = UnixStream::connect(&path).unwrap();
+        connection.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        //Joined once it's on the list; anything published before then would be history instead.
+        while stream.consumers.lock().unwrap().len() == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        stream.publish(&alert(Some("live")));
+
+        let lines: Vec<String> = BufReader::new(connection).lines().take(6).map(Result::unwrap).collect();
+        //Only the last two, and the STATE lines after them, are marked off as catching up.
+        assert!(lines[0].ends_with(" HISTORY 2"), "{:?}", lines);
+        assert_eq!(lines[1..3], ["1700000000000 ALERT 192.168.1.7:51884 \"second\"", "1700000000000 ALERT 192.168.1.7:51884 \"third\""]);
+        assert!(lines[3].ends_with(" STATE default ALERT"), "{:?}", lines);
+        assert!(lines[4].ends_with(" STATE db NONE"), "{:?}", lines);
+        assert_eq!(lines[5], "1700000000000 ALERT 192.168.1.7:51884 \"live\"");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+
+use crate::{disconnect_message, gap_message, show_peer, state_file::json_string, Channel, LogItem, State};
+
+//What the packet log is exported as, with --export-format.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+//One packet log entry, as exported.
+struct Row<'a> {
+    time: String,
+    channel: &'a str,
+    event: &'static str,
+    peer: String,
+    name: Option<&'a str>,
+    packet_type: Option<&'a str>,
+    text: Option<String>,
+}
+
+const CSV_HEADER: &str = "time,channel,event,peer,name,packet_type,text";
+
+//ww-export-<Time>.<csv|json>, in the working directory, for e.
+pub fn file_name(state: &State, format: ExportFormat) -> String {
+    let now = Local::now();
+    let file_time = if state.clock.is_utc {
+        now.with_timezone(&Utc).format("%Y%m%d-%H%M%S").to_string()
+    }
+    else {
+        now.format("%Y%m%d-%H%M%S").to_string()
+    };
+    return format!("ww-export-{}.{}", file_time, format.extension());
+}
+
+//Export every channel's packet log to path, for analysis after the fact, e.g. in a spreadsheet
+//during a postmortem. Unlike a snapshot, the entries are whole and unwrapped, oldest first, with
+//the time to the millisecond and a column per field:
+//
+//  time         RFC 3339, in UTC with --utc, else local time with its offset
+//  channel      the channel's name
+//  event        PACKET, CONNECT, DISCONNECT or GAP
+//  peer         the client's whole address, or with --anonymize-peers its pseudonym unless p
+//               has revealed the real ones
+//  name         the name the client sent, if it's still connected; as on screen, names go
+//               when their clients do
+//  packet_type  for PACKET, e.g. ALERT
+//  text         for PACKET, the text as sent; for the rest, the log's wording
+//
+//In CSV, fields with commas, quotes or line breaks are quoted, so text spanning lines stays one
+//row. In JSON, it's an array of objects, with null for the fields an entry doesn't have.
+pub fn save(state: &State, path: &str, format: ExportFormat) -> io::Result<()> {
+    let mut rows: Vec<(SystemTime, Row)> = Vec::new();
+    for channel in state.channels.iter() {
+        for log_item in channel.packet_log.iter().rev() {
+            rows.push((log_item.timestamp(), row(state, channel, log_item)));
+        }
+    }
+    //Each channel's log is in order already; this interleaves them. Stable, so entries with the
+    //same time keep their order.
+    rows.sort_by_key(|(timestamp, _)| *timestamp);
+
+    let mut out = BufWriter::new(File::create(path)?);
+    match format {
+        ExportFormat::Csv => write_csv(&mut out, rows.iter().map(|(_, row)| row))?,
+        ExportFormat::Json => write_json(&mut out, rows.iter().map(|(_, row)| row))?,
+    }
+    return out.flush();
+}
+
+fn row<'a>(state: &State, channel: &'a Channel, log_item: &'a LogItem) -> Row<'a> {
+    let time = if state.clock.is_utc {
+        DateTime::<Utc>::from(log_item.timestamp()).to_rfc3339_opts(SecondsFormat::Millis, true)
+    }
+    else {
+        DateTime::<Local>::from(log_item.timestamp()).to_rfc3339_opts(SecondsFormat::Millis, false)
+    };
+
+    let (event, peer_addr, packet_type, text) = match log_item {
+        LogItem::PacketLogItem { peer_addr, packet, .. } => ("PACKET", peer_addr, Some(packet.packet_type.to_string()), packet.text.clone()),
+        LogItem::ConnectLogItem { peer_addr, .. } => ("CONNECT", peer_addr, None, Some("has successfully associated.".to_string())),
+        LogItem::DisconnectLogItem { peer_addr, how, .. } => ("DISCONNECT", peer_addr, None, Some(disconnect_message(*how).to_string())),
+        LogItem::GapLogItem { peer_addr, missed, .. } => ("GAP", peer_addr, None, Some(gap_message(*missed))),
+    };
+
+    return Row {
+        time: time,
+        channel: &channel.name,
+        event: event,
+        peer: show_peer(peer_addr, state.shown_pseudonyms()),
+        name: channel.peer_names.get(peer_addr).map(String::as_str),
+        packet_type: packet_type,
+        text: text,
+    };
+}
+
+fn write_csv<'a>(out: &mut impl Write, rows: impl Iterator<Item = &'a Row<'a>>) -> io::Result<()> {
+    write!(out, "{}\r\n", CSV_HEADER)?;
+    for row in rows {
+        let fields = [
+            row.time.as_str(),
+            row.channel,
+            row.event,
+            row.peer.as_str(),
+            row.name.unwrap_or(""),
+            row.packet_type.unwrap_or(""),
+            row.text.as_deref().unwrap_or(""),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        //CRLF, as RFC 4180 has it, which spreadsheets expect.
+        write!(out, "{}\r\n", fields.join(","))?;
+    }
+    return Ok(());
+}
+
+fn write_json<'a>(out: &mut impl Write, rows: impl Iterator<Item = &'a Row<'a>>) -> io::Result<()> {
+    let json_or_null = |field: Option<&str>| field.map_or("null".to_string(), json_string);
+
+    writeln!(out, "[")?;
+    let mut is_first = true;
+    for row in rows {
+        if !is_first {
+            writeln!(out, ",")?;
+        }
+        is_first = false;
+        write!(out, "{{\"time\":{},\"channel\":{},\"event\":{},\"peer\":{},\"name\":{},\"packet_type\":{},\"text\":{}}}",
+            json_string(&row.time),
+            json_string(row.channel),
+            json_string(row.event),
+            json_string(&row.peer),
+            json_or_null(row.name),
+            json_or_null(row.packet_type),
+            json_or_null(row.text.as_deref()))?;
+    }
+    if !is_first {
+        writeln!(out)?;
+    }
+    writeln!(out, "]")?;
+    return Ok(());
+}
+
+//A field quoted for CSV if it needs to be, with its quotes doubled.
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| matches!(c, ',' | '"' | '\r' | '\n')) {
+        return format!("\"{}\"", field.replace('"', "\"\""));
+    }
+    return field.to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::test_state, Disconnect, Packet, Peer, WarnStates};
+    use api::PacketType;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const TRICKY_TEXT: &str = "disk \"sda\" full, again\nand again";
+
+    fn at(millis: u64) -> SystemTime {
+        return UNIX_EPOCH + Duration::from_millis(1_700_000_000_000 + millis);
+    }
+
+    fn packet(millis: u64, packet_type: PacketType, text: &str) -> LogItem {
+        return LogItem::PacketLogItem {
+            timestamp: at(millis),
+            peer_addr: Peer::Tcp("10.0.0.1:5000".parse().unwrap()),
+            packet: Packet { packet_type: packet_type, text: Some(text.to_string()), will_type: None, timestamp: None, wants_ack: false, sequence: None, has_invalid_utf8: false },
+        };
+    }
+
+    //Two channels, their entries interleaved in time, newest first as the packet log keeps them.
+    fn exported(format: ExportFormat) -> String {
+        let mut state = test_state();
+        let peer = Peer::Tcp("10.0.0.1:5000".parse().unwrap());
+        state.channels[0].peer_names.insert(peer, "db-monitor".to_string());
+        state.channels[0].packet_log.extend([
+            LogItem::DisconnectLogItem { timestamp: at(3), peer_addr: peer, how: Disconnect::Clean },
+            packet(1, PacketType::Warn, TRICKY_TEXT),
+            LogItem::ConnectLogItem { timestamp: at(0), peer_addr: peer, host_name: None },
+        ]);
+        state.channels.push(Channel::new("web".to_string(), WarnStates::None));
+        state.channels[1].packet_log.push_front(packet(2, PacketType::Alert, "down"));
+
+        let path = std::env::temp_dir().join(format!("ww-export-test-{}.{}", std::process::id(), format.extension()));
+        save(&state, path.to_str().unwrap(), format).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        return contents;
+    }
+
+    //Rows of fields, as RFC 4180 has them.
+    fn parse_csv(text: &str) -> Vec<Vec<String>> {
+        let (mut rows, mut row, mut field) = (Vec::new(), Vec::new(), String::new());
+        let mut is_quoted = false;
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (is_quoted, c) {
+                (true, '"') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                },
+                (true, '"') => is_quoted = false,
+                (false, '"') => is_quoted = true,
+                (false, ',') => row.push(std::mem::take(&mut field)),
+                (false, '\r') => (),
+                (false, '\n') => {
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                },
+                (_, c) => field.push(c),
+            }
+        }
+        return rows;
+    }
+
+    //The objects of a JSON array of flat objects, as (key, value) pairs with None for null. Only
+    //what write_json writes is understood.
+    fn parse_json(text: &str) -> Vec<Vec<(String, Option<String>)>> {
+        let (mut objects, mut object, mut key) = (Vec::new(), Vec::new(), None);
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '}' => objects.push(std::mem::take(&mut object)),
+                'n' => {
+                    assert_eq!(chars.by_ref().take(3).collect::<String>(), "ull");
+                    object.push((key.take().unwrap(), None));
+                },
+                '"' => {
+                    let mut string = String::new();
+                    loop {
+                        match chars.next().unwrap() {
+                            '"' => break,
+                            '\\' => match chars.next().unwrap() {
+                                'u' => string.push(char::from_u32(u32::from_str_radix(&chars.by_ref().take(4).collect::<String>(), 16).unwrap()).unwrap()),
+                                c => string.push(c),
+                            },
+                            c => string.push(c),
+                        }
+                    }
+                    match key.take() {
+                        None => key = Some(string),
+                        Some(key) => object.push((key, Some(string))),
+                    }
+                },
+                _ => (),
+            }
+        }
+        return objects;
+    }
+
+    #[test]
+    fn exports_csv_that_reads_back_whole() {
+        let rows = parse_csv(&exported(ExportFormat::Csv));
+        assert_eq!(rows[0].join(","), CSV_HEADER);
+        assert_eq!(rows[1..], [
+            ["2023-11-14T22:13:20.000Z", "default", "CONNECT", "10.0.0.1:5000", "db-monitor", "", "has successfully associated."],
+            ["2023-11-14T22:13:20.001Z", "default", "PACKET", "10.0.0.1:5000", "db-monitor", "WARN", TRICKY_TEXT],
+            ["2023-11-14T22:13:20.002Z", "web", "PACKET", "10.0.0.1:5000", "", "ALERT", "down"],
+            ["2023-11-14T22:13:20.003Z", "default", "DISCONNECT", "10.0.0.1:5000", "db-monitor", "", disconnect_message(Disconnect::Clean)],
+        ]);
+    }
+
+    #[test]
+    fn exports_json_that_reads_back_whole() {
+        let objects = parse_json(&exported(ExportFormat::Json));
+        assert_eq!(objects.len(), 4);
+        let field = |i: usize, key: &str| objects[i].iter().find(|(k, _)| k == key).unwrap().1.clone();
+        assert_eq!(field(0, "event").as_deref(), Some("CONNECT"));
+        assert_eq!(field(0, "packet_type"), None);
+        assert_eq!(field(1, "time").as_deref(), Some("2023-11-14T22:13:20.001Z"));
+        assert_eq!(field(1, "name").as_deref(), Some("db-monitor"));
+        assert_eq!(field(1, "text").as_deref(), Some(TRICKY_TEXT));
+        assert_eq!(field(2, "channel").as_deref(), Some("web"));
+        assert_eq!(field(2, "name"), None);
+        assert_eq!(field(3, "event").as_deref(), Some("DISCONNECT"));
+    }
+
+    #[test]
+    fn exports_an_empty_log_as_just_the_header_or_an_empty_array() {
+        let mut out: Vec<u8> = Vec::new();
+        write_csv(&mut out, std::iter::empty()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), format!("{}\r\n", CSV_HEADER));
+        let mut out: Vec<u8> = Vec::new();
+        write_json(&mut out, std::iter::empty()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "[\n]\n");
+    }
+}
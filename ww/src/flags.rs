@@ -0,0 +1,416 @@
+use api::{DEFAULT_PORT, MAX_MSG_LEN, MAX_NAME_LEN};
+
+use crate::{ALERT_FPS, CONFIRM_WINDOW, DEFAULT_FPS, HINTS_SHOWN_FOR, MAX_EVENT_HISTORY, MAX_FPS, MIN_FPS};
+
+//One command-line option. Both the parser and the usage are built from these, so an option can't
+//be accepted without being documented, or documented without being accepted.
+pub struct Flag {
+    pub name: &'static str,
+    //What the value is called in the usage, e.g. Path. None for switches, which take no value.
+    pub arg: Option<&'static str>,
+    pub description: String,
+    pub default: Option<String>,
+    pub example: Option<&'static str>,
+    //The environment variable used when the option isn't given, for deployments where those are
+    //easier to set than arguments, e.g. containers.
+    pub env: Option<&'static str>,
+}
+
+impl Flag {
+    fn switch(name: &'static str, description: String) -> Self {
+        return Flag { name: name, arg: None, description: description, default: None, example: None, env: None };
+    }
+
+    fn with_value(name: &'static str, arg: &'static str, description: String, default: Option<String>, example: &'static str) -> Self {
+        return Flag { name: name, arg: Some(arg), description: description, default: default, example: Some(example), env: None };
+    }
+
+    fn or_env(self, env: &'static str) -> Self {
+        return Flag { env: Some(env), ..self };
+    }
+}
+
+//Every option ww accepts, in the order they're listed in the usage.
+pub fn flags() -> Vec<Flag> {
+    return vec![
+        Flag::with_value("-p", "Port",
+            "Listen on Port.".to_string(),
+            Some(DEFAULT_PORT.to_string()), "-p 44445").or_env("WW_PORT"),
+        Flag::with_value("--bind", "Addr",
+            "Listen on the interface with address Addr, e.g. 0.0.0.0 for every interface.".to_string(),
+            Some("localhost".to_string()), "--bind 0.0.0.0").or_env("WW_BIND"),
+
+        Flag::with_value("--info-art", "Path",
+            "Change the info art with text found at Path. Ragged lines are padded with spaces.".to_string(),
+            None, "--info-art ./art/info.txt"),
+        Flag::with_value("--warn-art", "Path",
+            "Change the warn art with text found at Path. Ragged lines are padded with spaces.".to_string(),
+            None, "--warn-art ./art/warn.txt"),
+        Flag::with_value("--alert-art", "Path",
+            "Change the alert art with text found at Path. Ragged lines are padded with spaces.".to_string(),
+            None, "--alert-art ./art/alert.txt"),
+        Flag::with_value("--critical-art", "Path",
+            "Change the critical art with text found at Path. Ragged lines are padded with spaces.".to_string(),
+            None, "--critical-art ./art/critical.txt"),
+
+        Flag::with_value("--title", "Text",
+            "Show Text along the top of the dashboard, and as the terminal's window title where it supports one, to tell dashboards apart, e.g. in tmux panes or screenshots.".to_string(),
+            None, "--title \"prod cluster\""),
+        Flag::with_value("--art-position", "Position",
+            "Where the art goes: top, center, fifth (a fifth of the way down), or custom:<Row>. The packet log goes below it.".to_string(),
+            Some("fifth".to_string()), "--art-position custom:3"),
+        Flag::with_value("--fps", "N",
+            format!("Frames rendered per second, from {} to {}. Higher shows packets sooner but costs more CPU. An ALERT or CRITICAL on screen is always rendered at {} or more; its animation runs at the same speed whatever the rate.", MIN_FPS, MAX_FPS, ALERT_FPS),
+            Some(DEFAULT_FPS.to_string()), "--fps 30"),
+        Flag::with_value("--palette", "Colors",
+            "Colors for each state as info:#RRGGBB,warn:#RRGGBB,alert:#RRGGBB,critical:#RRGGBB, any subset. They color the art, the ALERT border, the packet log and the channel bar.".to_string(),
+            None, "--palette warn:#FFAA00,alert:#FF0000"),
+        Flag::with_value("--border-chars", "Chars",
+            "Glyphs for the ALERT border, or one of the presets: ascii, binary, matrix, blocks.".to_string(),
+            Some("ascii".to_string()), "--border-chars matrix"),
+        Flag::with_value("--screensaver-after", "Secs",
+            "Show only a drifting clock after Secs of no packets or keypresses while nothing is wrong.".to_string(),
+            None, "--screensaver-after 600"),
+        Flag::with_value("--seed", "N",
+            "Start the animation at frame N, so it is reproducible for screenshots.".to_string(),
+            None, "--seed 42"),
+        Flag::with_value("--min-state", "State",
+            "Never reset below State: none, warn, alert, or critical. Adjust at runtime with + and -.".to_string(),
+            Some("none".to_string()), "--min-state warn"),
+        Flag::with_value("--mute", "Addrs",
+            "Comma-separated IPs whose packets are logged, dimmed, but never raise the warn state. Toggle at runtime with m on the newest entry's peer.".to_string(),
+            None, "--mute 10.0.0.5,10.0.0.6"),
+        Flag::with_value("--log-density", "Density",
+            "full wraps long packet text; compact cuts each entry to one line. Toggle at runtime with d.".to_string(),
+            Some("full".to_string()), "--log-density compact"),
+        Flag::with_value("--max-display-len", "N",
+            "Cut packet text in the packet log to N characters, ending in an ellipsis, in either density. The log file keeps the full text.".to_string(),
+            None, "--max-display-len 80"),
+        Flag::with_value("--operator", "Name",
+            format!("Who acknowledges a WARN or ALERT with a, shown as \"ACK by Name\". Shorter than {} bytes, like client names.", MAX_NAME_LEN),
+            None, "--operator alice"),
+        Flag::with_value("--clock", "Hours",
+            "24 shows times in the packet log as 14:05; 12 shows them as 02:05 PM.".to_string(),
+            Some("24".to_string()), "--clock 12"),
+        Flag::switch("--utc",
+            "Show times in UTC rather than the local timezone.".to_string()),
+        Flag::with_value("--time-display", "Mode",
+            "absolute shows when each packet log entry happened, relative how long ago (5s ago, 2m ago), and both shows both.".to_string(),
+            Some("absolute".to_string()), "--time-display both"),
+        Flag::with_value("--log-limit", "N",
+            "Keep at most N entries in the packet log.".to_string(),
+            Some("unlimited".to_string()), "--log-limit 500"),
+        Flag::with_value("--retention", "Policy",
+            "Which entries to drop past --log-limit: fifo drops the oldest, severity drops the oldest of the least severe, keeping ALERTs longest.".to_string(),
+            Some("fifo".to_string()), "--retention severity"),
+        Flag::with_value("--replay", "Path",
+            "Play the scripted events in the file at Path as if a client sent them. See replay.rs for the format.".to_string(),
+            None, "--replay ./incident.txt"),
+        Flag::with_value("--expect", "Name:Secs",
+            "Raise an ALERT if the client named Name sends nothing for Secs, and clear it when it's heard from again. Repeat for more clients.".to_string(),
+            None, "--expect backup:3600"),
+        Flag::with_value("--escalate", "Count:Secs",
+            "Raise an ALERT when one host sends more than Count WARNs within Secs. Resetting starts the count over.".to_string(),
+            None, "--escalate 5:60"),
+        Flag::with_value("--on-alert", "Command",
+            "Run Command through the shell when a channel enters ALERT or goes up to CRITICAL, with the channel and the message as $1 and $2, also in WW_CHANNEL and WW_MESSAGE. Runs in the background; failures are logged.".to_string(),
+            None, "--on-alert 'paplay /usr/share/sounds/alarm.oga'"),
+        Flag::with_value("--on-reset", "Command",
+            "Run Command like --on-alert when a channel comes down from a WARN, ALERT or CRITICAL.".to_string(),
+            None, "--on-reset 'notify-send \"ww: $1 cleared\"'"),
+        Flag::with_value("--state-file", "Path",
+            "Keep Path up to date with the warn state as JSON, for status bars and other tools to read. Rewritten atomically on every change; says none once ww quits.".to_string(),
+            None, "--state-file /run/user/1000/ww.state"),
+        Flag::with_value("--export-on-exit", "Path",
+            "Write every channel's whole packet log to Path on quitting, in --export-format, for analysis after the fact. e exports at any time. See export.rs for the columns.".to_string(),
+            None, "--export-on-exit ./incident.csv"),
+        Flag::with_value("--export-format", "Format",
+            "csv or json, for --export-on-exit and e.".to_string(),
+            Some("csv".to_string()), "--export-format json"),
+        Flag::with_value("--redact", "Regex=Replacement",
+            "Replace every match of Regex in packet text before it is logged or shown. Split at the last =. Replacement may use $1 for capture groups. Repeatable; applied in order.".to_string(),
+            None, "--redact 'token=\\w+=[REDACTED]'"),
+        Flag::with_value("--max-payload", "N",
+            format!("Accept at most N bytes of text, up to {}, in INFO, WARN, ALERT, CRITICAL and LASTWILL packets. NAME packets keep their own limit of {} bytes.", MAX_MSG_LEN, MAX_NAME_LEN - 1),
+            Some(MAX_MSG_LEN.to_string()), "--max-payload 120"),
+        Flag::with_value("--oversize", "Policy",
+            "What to do with a packet over --max-payload: truncate keeps it, cut to N bytes; reject closes the connection.".to_string(),
+            Some("truncate".to_string()), "--max-payload 120 --oversize reject"),
+        Flag::with_value("--filter", "Key=Value",
+            "Only show packet log entries from clients that labeled themselves with Key=Value. Toggle at runtime with l.".to_string(),
+            None, "--filter env=prod"),
+        Flag::with_value("--idle-timeout", "Secs",
+            "Drop clients that send nothing for Secs. Quiet clients stay connected by sending any packet, e.g. their NAME again, more often. At least as long as every --expect interval.".to_string(),
+            None, "--idle-timeout 300"),
+        Flag::with_value("--channel", "Name:Port",
+            "Listen on Port as a separate channel called Name, with its own state and log. Repeat for more channels; Tab switches between them. Replaces -p.".to_string(),
+            None, "--channel prod:44444 --channel staging:44445"),
+        Flag::switch("--broadcast",
+            "Forward every WARN, ALERT and CRITICAL to all the other connected clients, e.g. so a whole team sees any alert.".to_string()),
+        Flag::with_value("--unix", "Path",
+            "Listen on a Unix domain socket at Path instead of on a TCP port. Unix only.".to_string(),
+            None, "--unix /tmp/ww.sock"),
+        Flag::with_value("--event-socket", "Path",
+            "Stream every event, one per line, to any consumer connected to a Unix domain socket at Path. Unix only.".to_string(),
+            None, "--event-socket /tmp/ww-events.sock"),
+        Flag::with_value("--event-history", "N",
+            format!("With --event-socket, send each consumer the last N events, up to {}, and every channel's warn state as it connects, marked off from the live events so they aren't acted on twice.", MAX_EVENT_HISTORY),
+            Some("0".to_string()), "--event-socket /tmp/ww-events.sock --event-history 50"),
+        Flag::with_value("--metrics-port", "Port",
+            "Serve Prometheus metrics at /metrics on Port. Requires the \"metrics\" feature.".to_string(),
+            None, "--metrics-port 9100"),
+        Flag::switch("--syslog",
+            "Also send the log to the system logger, with WARN, ALERT and CRITICAL packets at warning, err and crit. Requires the \"syslog\" feature.".to_string()),
+        Flag::with_value("--log", "Path",
+            "Write the log to Path.".to_string(),
+            Some("./warning_window.log".to_string()), "--log /var/log/ww.log").or_env("WW_LOG"),
+        Flag::switch("--no-log",
+            "Don't write the log file.".to_string()),
+        Flag::switch("--anonymize-peers",
+            "Show and log client IPs as stable pseudonyms, e.g. peer-3fa2c41e:51884, for shared screens. Names clients set are shown as usual. Reveal the real addresses at runtime with p.".to_string()),
+        Flag::switch("--resolve-peers",
+            "Show clients by the host name their IP has in reverse DNS, if any, until they send a NAME. Off by default, as every new host is a DNS query that can be slow and tells the DNS server who connected.".to_string()),
+        Flag::switch("--short-ipv6",
+            "Show long IPv6 client addresses cut down to their first two and last groups, e.g. [2001:db8…7334]:51884, and IPv4-mapped ones as IPv4. The log file keeps the whole address.".to_string()),
+        Flag::switch("--host-counts",
+            "Show how many hosts are at ALERT and at WARN, beside the art. A host is counted until the channel is reset.".to_string()),
+        Flag::switch("--no-hints",
+            format!("Don't show the keybinding hints along the bottom for the first {}s. Toggle them at runtime with ?.", HINTS_SHOWN_FOR.as_secs())),
+        Flag::switch("--confirm-quit-on-alert",
+            format!("While any channel is at ALERT, q and Esc ask first, and only a q within {}s quits; any other key cancels. Ctrl-C still quits at once.", CONFIRM_WINDOW.as_secs())),
+        Flag::switch("--no-color",
+            "Render without color. Also enabled by setting the NO_COLOR environment variable.".to_string()),
+        Flag::switch("--ascii-only",
+            "Render only ASCII, for dumb terminals. Non-ASCII in art is replaced, and the border uses the ascii preset.".to_string()),
+
+        Flag::switch("--check-art",
+            "Print the size of each art and any problems, e.g. ragged lines, then exit without starting. Exits with 1 if there were problems.".to_string()),
+        Flag::with_value("--check-size", "ColsxRows",
+            "With --check-art, also report art too large to render on a ColsxRows terminal.".to_string(),
+            None, "--check-art --check-size 80x24"),
+
+        Flag::switch("--list-colors",
+            "Print the states --palette can color with their default colors, and the --border-chars presets, each with a sample, then exit.".to_string()),
+
+        Flag::switch("--help",
+            "Show usage and exit.".to_string()),
+        Flag::switch("--version",
+            "Show the version and exit.".to_string()),
+    ];
+}
+
+pub fn print_usage() {
+    eprintln!("Usage: ww [Options]");
+    eprintln!("Accept networked notifications from client programs.");
+
+    for flag in flags() {
+        match flag.arg {
+            Some(arg) => eprint!("{} <{}>: {}", flag.name, arg, flag.description),
+            None => eprint!("{}: {}", flag.name, flag.description),
+        }
+        if let Some(env) = flag.env {
+            eprint!(" Or set {}.", env);
+        }
+        match flag.default {
+            Some(default) => eprintln!(" Default: {}.", default),
+            None => eprintln!(),
+        }
+        if let Some(example) = flag.example {
+            eprintln!("    e.g. ww {}", example);
+        }
+    }
+
+    eprintln!("An art Path of - reads that art from stdin.");
+    eprintln!("An option given wins over its environment variable, which wins over the default.");
+}
+
+//How many single-character insertions, deletions or substitutions turn a into b.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    return previous[b.len()];
+}
+
+//The known option an unknown one was most likely a typo of, if any is close enough.
+fn closest_flag(flags: &[Flag], unknown: &str) -> Option<&'static str> {
+    const MAX_TYPO_DISTANCE: usize = 2;
+    return flags.iter()
+        .map(|flag| (edit_distance(unknown, flag.name), flag.name))
+        .filter(|(distance, _)| *distance <= MAX_TYPO_DISTANCE)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name);
+}
+
+//The command line, checked against flags(): every option is known, and every option that takes a
+//value has one.
+pub struct Args {
+    args: Vec<String>,
+    flags: Vec<Flag>,
+}
+
+impl Args {
+    //args excludes the program name.
+    pub fn parse(args: Vec<String>) -> Result<Args, String> {
+        let flags = flags();
+
+        let mut i = 0;
+        while i < args.len() {
+            let arg = &args[i];
+            match flags.iter().find(|flag| flag.name == arg) {
+                Some(Flag { arg: Some(value_name), .. }) => {
+                    if i + 1 >= args.len() {
+                        return Err(format!("ww: {} needs a <{}>.", arg, value_name));
+                    }
+                    //Skip the value, whatever it looks like: --operator -x is a name.
+                    i += 1;
+                },
+                Some(_) => (),
+                //A lone - is a value, e.g. an art Path read from stdin.
+                None if arg.starts_with('-') && arg != "-" => {
+                    return match closest_flag(&flags, arg) {
+                        Some(name) => Err(format!("ww: unknown option {}. Did you mean {}?", arg, name)),
+                        None => Err(format!("ww: unknown option {}.", arg)),
+                    };
+                },
+                None => (),
+            }
+            i += 1;
+        }
+
+        return Ok(Args { args: args, flags: flags });
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        return self.positions(name).next();
+    }
+
+    //Where each occurrence of the option is, skipping anything that is another option's value.
+    fn positions<'a>(&'a self, name: &'a str) -> impl Iterator<Item = usize> + 'a {
+        let flag = self.flags.iter().find(|flag| flag.name == name).expect("Every option looked up must be in flags().");
+        let takes_value = flag.arg.is_some();
+
+        let mut is_value = false;
+        return self.args.iter().enumerate().filter_map(move |(i, arg)| {
+            if is_value {
+                is_value = false;
+                return None;
+            }
+            is_value = self.flags.iter().any(|flag| flag.name == arg && flag.arg.is_some());
+            if arg == name {
+                return Some(if takes_value { i + 1 } else { i });
+            }
+            return None;
+        });
+    }
+
+    //Whether a switch was given.
+    pub fn is_set(&self, name: &str) -> bool {
+        return self.find(name).is_some();
+    }
+
+    //The value of the first occurrence of an option.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        return self.find(name).map(|i| self.args[i].as_str());
+    }
+
+    //The value of an option, or else of its environment variable, if it has one and it's set.
+    pub fn value_or_env(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.value(name) {
+            return Some(value.to_string());
+        }
+        let flag = self.flags.iter().find(|flag| flag.name == name).expect("Every option looked up must be in flags().");
+        return flag.env.and_then(|env| std::env::var(env).ok()).filter(|value| value.len() > 0);
+    }
+
+    //The values of every occurrence of a repeatable option, in order.
+    pub fn values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        return self.positions(name).map(move |i| self.args[i].as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Args, String> {
+        return Args::parse(args.iter().map(|arg| arg.to_string()).collect());
+    }
+
+    #[test]
+    fn parses_switches_values_and_repeated_options() {
+        let args = args(&["-p", "44445", "--utc", "--expect", "backup:3600", "--no-log", "--expect", "db:60"]).unwrap();
+        assert_eq!(args.value("-p"), Some("44445"));
+        assert!(args.is_set("--utc"));
+        assert!(args.is_set("--no-log"));
+        assert!(!args.is_set("--broadcast"));
+        assert_eq!(args.value("--log"), None);
+        assert_eq!(args.values("--expect").collect::<Vec<_>>(), vec!["backup:3600", "db:60"]);
+    }
+
+    #[test]
+    fn suggests_the_option_a_typo_was_most_likely_of() {
+        assert_eq!(args(&["--utx"]).err().unwrap(), "ww: unknown option --utx. Did you mean --utc?");
+        assert_eq!(args(&["-p", "44445", "--titel", "prod"]).err().unwrap(), "ww: unknown option --titel. Did you mean --title?");
+        assert_eq!(args(&["--frobnicate"]).err().unwrap(), "ww: unknown option --frobnicate.");
+        assert_eq!(closest_flag(&flags(), "--help"), Some("--help"));
+    }
+
+    #[test]
+    fn refuses_an_option_missing_its_value() {
+        assert_eq!(args(&["--utc", "--operator"]).err().unwrap(), "ww: --operator needs a <Name>.");
+    }
+
+    #[test]
+    fn takes_whatever_follows_an_option_as_its_value() {
+        let args = args(&["--operator", "-x", "--utc"]).unwrap();
+        assert_eq!(args.value("--operator"), Some("-x"));
+        assert!(args.is_set("--utc"));
+
+        //An option's value is never taken for an option itself.
+        let args = self::args(&["--title", "--utc"]).unwrap();
+        assert_eq!(args.value("--title"), Some("--utc"));
+        assert!(!args.is_set("--utc"));
+    }
+
+    #[test]
+    fn accepts_a_lone_dash_as_a_value() {
+        let args = args(&["--info-art", "-", "-"]).unwrap();
+        assert_eq!(args.value("--info-art"), Some("-"));
+    }
+
+    //The only test that sets these variables, as tests run in parallel in one process.
+    #[test]
+    fn falls_back_to_the_environment_when_an_option_isnt_given() {
+        std::env::set_var("WW_PORT", "44446");
+        std::env::set_var("WW_BIND", "0.0.0.0");
+        std::env::set_var("WW_LOG", "/var/log/ww.log");
+
+        let from_env = args(&[]).unwrap();
+        assert_eq!(from_env.value_or_env("-p"), Some("44446".to_string()));
+        assert_eq!(from_env.value_or_env("--bind"), Some("0.0.0.0".to_string()));
+        assert_eq!(from_env.value_or_env("--log"), Some("/var/log/ww.log".to_string()));
+
+        let from_flags = args(&["-p", "44447", "--bind", "127.0.0.1", "--log", "./ww.log"]).unwrap();
+        assert_eq!(from_flags.value_or_env("-p"), Some("44447".to_string()));
+        assert_eq!(from_flags.value_or_env("--bind"), Some("127.0.0.1".to_string()));
+        assert_eq!(from_flags.value_or_env("--log"), Some("./ww.log".to_string()));
+
+        //Set but empty is the same as unset, and an option without a variable never reads one.
+        std::env::set_var("WW_PORT", "");
+        std::env::remove_var("WW_BIND");
+        std::env::remove_var("WW_LOG");
+        assert_eq!(from_env.value_or_env("-p"), None);
+        assert_eq!(from_env.value_or_env("--bind"), None);
+        assert_eq!(from_env.value_or_env("--log"), None);
+        assert_eq!(from_env.value_or_env("--title"), None);
+        std::env::remove_var("WW_PORT");
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crate::logger::Log;
+
+//Commands run when a channel enters ALERT, with --on-alert, or drops back from a WARN or ALERT,
+//with --on-reset: the escape hatch for integrations ww doesn't build in, e.g. a warning light, a
+//sound or a pager.
+//
+//Each runs through the shell, with the channel and the message of the newest WARN or ALERT in
+//WW_CHANNEL and WW_MESSAGE, and on Unix as $1 and $2 too, e.g.
+//
+//  ww --on-alert 'notify-send "ww: $1" "$2"'
+//
+//cmd has no $1 and $2, and would run the arguments as part of the command, so on Windows they're
+//only in the environment, e.g. %WW_CHANNEL%.
+pub struct Hooks {
+    pub on_alert: Option<String>,
+    pub on_reset: Option<String>,
+}
+
+impl Hooks {
+    pub fn alert(&self, channel: &str, message: &str, log: &Arc<Mutex<Log>>) {
+        if let Some(command) = &self.on_alert {
+            run(command, channel, message, Arc::clone(log));
+        }
+    }
+
+    pub fn reset(&self, channel: &str, message: &str, log: &Arc<Mutex<Log>>) {
+        if let Some(command) = &self.on_reset {
+            run(command, channel, message, Arc::clone(log));
+        }
+    }
+}
+
+//Start the command and leave it to its own thread, so a slow one never holds up the render loop.
+//Failures are only logged.
+fn run(command: &str, channel: &str, message: &str, log: Arc<Mutex<Log>>) {
+    //The terminal belongs to the dashboard: a command reading it would take the keypresses, and
+    //one writing to it would draw over the screen.
+    let mut process = shell_command(command, channel, message);
+    process.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    let mut child = match process.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            writeln!(log.lock().unwrap(), "ERROR: Could not run \"{}\": {}", command, e).unwrap();
+            return;
+        },
+    };
+
+    let command = command.to_string();
+    let _waiter = thread::spawn(move || {
+        match child.wait() {
+            Ok(status) if status.success() => (),
+            Ok(status) => writeln!(log.lock().unwrap(), "WARN: \"{}\" exited with {}.", command, status).unwrap(),
+            Err(e) => writeln!(log.lock().unwrap(), "ERROR: Could not wait on \"{}\": {}", command, e).unwrap(),
+        }
+    });
+}
+
+//The command as the shell runs it, with the channel and message passed along.
+fn shell_command(command: &str, channel: &str, message: &str) -> Command {
+    #[cfg(unix)]
+    let mut process = Command::new("sh");
+    #[cfg(unix)]
+    process.arg("-c").arg(command).arg("ww").arg(channel).arg(message);
+    #[cfg(windows)]
+    let mut process = Command::new("cmd");
+    #[cfg(windows)]
+    process.arg("/C").arg(command);
+
+    process.env("WW_CHANNEL", channel).env("WW_MESSAGE", message);
+    return process;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn passes_the_channel_and_message_as_arguments_and_in_the_environment() {
+        let output = shell_command("printf '%s|%s|%s|%s' \"$1\" \"$2\" \"$WW_CHANNEL\" \"$WW_MESSAGE\"", "db", "disk full; rm -rf /").output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "db|disk full; rm -rf /|db|disk full; rm -rf /");
+    }
+
+    //cmd would run a message like "& del *" as a command of its own.
+    #[cfg(windows)]
+    #[test]
+    fn passes_the_channel_and_message_only_in_the_environment() {
+        let process = shell_command("echo %WW_MESSAGE%", "db", "& del *");
+        let args: Vec<&std::ffi::OsStr> = process.get_args().collect();
+        assert_eq!(args, ["/C", "echo %WW_MESSAGE%"]);
+
+        let envs: Vec<_> = process.get_envs().collect();
+        assert!(envs.contains(&("WW_CHANNEL".as_ref(), Some("db".as_ref()))));
+        assert!(envs.contains(&("WW_MESSAGE".as_ref(), Some("& del *".as_ref()))));
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+};
+
+//Where the server's log lines go: the --log file unless --no-log, and with --syslog the
+//system logger as well. Everything logs with writeln!, so lines are split back out here to send
+//each to syslog as its own message.
+pub struct Log {
+    file: Option<File>,
+    #[cfg(feature = "syslog")]
+    syslog: Option<syslog::Logger<syslog::LoggerBackend, syslog::Formatter3164>>,
+    //What has been written of the current line, until its newline arrives.
+    #[cfg(feature = "syslog")]
+    line: Vec<u8>,
+}
+
+impl Log {
+    pub fn new(file: Option<File>) -> Self {
+        return Log {
+            file: file,
+            #[cfg(feature = "syslog")]
+            syslog: None,
+            #[cfg(feature = "syslog")]
+            line: Vec::new(),
+        };
+    }
+
+    #[cfg(feature = "syslog")]
+    pub fn connect_syslog(&mut self) -> Result<(), String> {
+        let formatter = syslog::Formatter3164 {
+            facility: syslog::Facility::LOG_USER,
+            hostname: None,
+            process: "ww".to_string(),
+            pid: std::process::id(),
+        };
+        let logger = syslog::unix(formatter).map_err(|e| e.to_string())?;
+        self.syslog = Some(logger);
+        return Ok(());
+    }
+}
+
+impl Write for Log {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(file) = &mut self.file {
+            file.write_all(buf)?;
+        }
+
+        #[cfg(feature = "syslog")]
+        if let Some(syslog) = &mut self.syslog {
+            self.line.extend_from_slice(buf);
+            while let Some(end) = self.line.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.line.drain(..=end).collect();
+                //The system logger going away mustn't take the dashboard with it; the file still
+                //has the line.
+                let _ = send_to_syslog(syslog, &String::from_utf8_lossy(&line[..end]));
+            }
+        }
+
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(file) = &mut self.file {
+            file.flush()?;
+        }
+        return Ok(());
+    }
+}
+
+//Every packet is logged as INFO, so packets are mapped by their type instead: WARN packets to
+//warning, ALERT packets to err and CRITICAL packets to crit. Connects and disconnects are notices. Anything else goes by the
+//line's own level.
+#[cfg(feature = "syslog")]
+fn send_to_syslog(syslog: &mut syslog::Logger<syslog::LoggerBackend, syslog::Formatter3164>, line: &str) -> syslog::Result<()> {
+    if line.starts_with("INFO: Received CRITICAL packet") {
+        return syslog.crit(line);
+    }
+    if line.starts_with("INFO: Received ALERT packet") || line.starts_with("ERROR:") {
+        return syslog.err(line);
+    }
+    if line.starts_with("INFO: Received WARN packet") || line.starts_with("WARN:") {
+        return syslog.warning(line);
+    }
+    if line.starts_with("INFO: Received connection") || line.starts_with("INFO: Closed connection") {
+        return syslog.notice(line);
+    }
+    return syslog.info(line);
+}
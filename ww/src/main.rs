@@ -1,9 +1,36 @@
 use std::io::{self, stdout};
 
+mod broadcast;
+use broadcast::{Broadcast, ClientWriter};
+mod logger;
+use logger::Log;
+mod metrics;
+use metrics::Metrics;
+mod replay;
+mod redact;
+use redact::Redactions;
+mod flags;
+use flags::{Args, print_usage};
+mod hooks;
+use hooks::Hooks;
+mod snapshot;
+mod export;
+use export::ExportFormat;
+mod pseudonyms;
+use pseudonyms::Pseudonyms;
+mod resolve;
+use resolve::Resolver;
+mod state_file;
+use state_file::StateFile;
+#[cfg(unix)]
+mod events;
+
+use api::{PacketType, PacketBuffer, TIMESTAMP_FLAG, ACK_FLAG, SEQUENCE_FLAG, COMPRESSED_FLAG, ACK, ASSOC_REQUEST, ASSOC_ACCEPT, VERSIONED_ASSOC_REQUEST, VERSIONED_ASSOC_ACCEPT, DEFAULT_PORT, MAX_NAME_LEN, MAX_MSG_LEN, parse_labels};
+
 use crossterm::{
-    event::{poll, read, Event, KeyCode, KeyModifiers},
+    event::{poll, read, Event, KeyCode, KeyEvent, KeyModifiers},
     execute,
-    style::{self, Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+    style::{self, Attribute, Color, Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor},
     terminal,
     cursor,
     QueueableCommand,
@@ -11,11 +38,14 @@ use crossterm::{
     queue,
 };
 
-#[derive(Copy, Clone, PartialEq)]
+//Ordered by severity.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 enum WarnStates {
     None,
     Warn,
     Alert,
+    //For catastrophic events, only ever raised by a CRITICAL packet.
+    Critical,
 }
 
 impl WarnStates {
@@ -24,6 +54,23 @@ impl WarnStates {
             Self::None => "NONE",
             Self::Warn => "WARN",
             Self::Alert => "ALERT",
+            Self::Critical => "CRITICAL",
+        }
+    }
+
+    fn raised(&self) -> Self {
+        match self {
+            Self::None => Self::Warn,
+            Self::Warn => Self::Alert,
+            Self::Alert | Self::Critical => Self::Critical,
+        }
+    }
+
+    fn lowered(&self) -> Self {
+        match self {
+            Self::None | Self::Warn => Self::None,
+            Self::Alert => Self::Warn,
+            Self::Critical => Self::Alert,
         }
     }
 }
@@ -32,10 +79,12 @@ struct WarnStateAsciiArt {
     info_art: String,
     warn_art: String,
     alert_art: String,
+    critical_art: String,
 
     info_color: style::Color,
     warn_color: style::Color,
     alert_color: style::Color,
+    critical_color: style::Color,
 }
 
 impl WarnStateAsciiArt {
@@ -66,19 +115,47 @@ impl WarnStateAsciiArt {
         ).to_string();
     }
 
+    fn default_critical_art() -> String {
+        return concat!(
+            "  _____  \n",
+            " /     \\ \n",
+            "| () () |\n",
+            " \\  ^  / \n",
+            "  |||||  \n",
+        ).to_string();
+    }
+
     fn new() -> Self {
         return WarnStateAsciiArt {
             info_art: Self::default_info_art(),
             warn_art: Self::default_warn_art(),
             alert_art: Self::default_alert_art(),
+            critical_art: Self::default_critical_art(),
 
             info_color: Color::Rgb { r: 24, g: 24, b: 24, },
             warn_color: Color::Rgb { r: 244, g: 131, b: 37, }, //Also try #FF9F43.
             alert_color: Color::Rgb { r: 179, g: 0, b: 0, },
+            critical_color: Color::Rgb { r: 128, g: 0, b: 96, },
         };
     }
 
-    fn build(mut info_art: String, mut warn_art: String, mut alert_art: String) -> Self {
+    //Pad every line with spaces to the width of the longest, so the art is always rectangular.
+    //Hand-written art often has its trailing whitespace trimmed by editors, which otherwise
+    //leaves stray characters behind when the art is blanked and redrawn.
+    fn pad_art(art: String) -> String {
+        let max_width = art.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+        let mut padded = String::with_capacity(art.len());
+        for line in art.lines() {
+            padded.push_str(line);
+            for _ in line.chars().count()..max_width {
+                padded.push(' ');
+            }
+            padded.push('\n');
+        }
+        return padded;
+    }
+
+    fn build(mut info_art: String, mut warn_art: String, mut alert_art: String, mut critical_art: String) -> Self {
         if info_art == "" {
             info_art = Self::default_info_art();
         }
@@ -88,18 +165,23 @@ impl WarnStateAsciiArt {
         if alert_art == "" {
             alert_art = Self::default_alert_art();
         }
+        if critical_art == "" {
+            critical_art = Self::default_critical_art();
+        }
         return WarnStateAsciiArt {
-            info_art: info_art,
-            warn_art: warn_art,
-            alert_art: alert_art,
+            info_art: Self::pad_art(info_art),
+            warn_art: Self::pad_art(warn_art),
+            alert_art: Self::pad_art(alert_art),
+            critical_art: Self::pad_art(critical_art),
 
             info_color: Color::Rgb { r: 24, g: 24, b: 24, },
             warn_color: Color::Rgb { r: 244, g: 131, b: 37, }, //Also try #FF9F43.
             alert_color: Color::Rgb { r: 179, g: 0, b: 0, },
+            critical_color: Color::Rgb { r: 128, g: 0, b: 96, },
         };
     }
 
-    fn build_with_color(mut info_art: String, mut warn_art: String, mut alert_art: String, info_color: style::Color, warn_color: style::Color, alert_color: style::Color) -> Self {
+    fn build_with_color(mut info_art: String, mut warn_art: String, mut alert_art: String, mut critical_art: String, info_color: style::Color, warn_color: style::Color, alert_color: style::Color, critical_color: style::Color) -> Self {
         if info_art == "" {
             info_art = Self::default_info_art();
         }
@@ -109,14 +191,19 @@ impl WarnStateAsciiArt {
         if alert_art == "" {
             alert_art = Self::default_alert_art();
         }
+        if critical_art == "" {
+            critical_art = Self::default_critical_art();
+        }
         return WarnStateAsciiArt {
-            info_art: info_art,
-            warn_art: warn_art,
-            alert_art: alert_art,
+            info_art: Self::pad_art(info_art),
+            warn_art: Self::pad_art(warn_art),
+            alert_art: Self::pad_art(alert_art),
+            critical_art: Self::pad_art(critical_art),
 
             info_color,
             warn_color,
             alert_color,
+            critical_color,
         };
     }
 
@@ -125,28 +212,14 @@ impl WarnStateAsciiArt {
             WarnStates::None => &self.info_art,
             WarnStates::Warn => &self.warn_art,
             WarnStates::Alert => &self.alert_art,
+            WarnStates::Critical => &self.critical_art,
         };
     }
 
+    //The art is padded to be rectangular, so its first line is as wide as any. Counted in chars
+    //like pad_art, not bytes, so box-drawing and block glyphs don't make art seem wider than it is.
     fn width(&self, warn_state: &WarnStates) -> usize {
-        match warn_state {
-            WarnStates::None => {
-                //These return the index of the first \n, or the len().
-                return self.info_art.char_indices()                 //        ignore this, just for returning a tuple.
-                                    .find(|c| { c.1 == '\n' })      //        v
-                                    .unwrap_or_else(|| (self.info_art.len(), 'i')).0;
-            },
-            WarnStates::Warn => {
-                return self.warn_art.char_indices()
-                                    .find(|c| { c.1 == '\n' })
-                                    .unwrap_or_else(|| (self.warn_art.len(), 'i')).0;
-            },
-            WarnStates::Alert => {
-                return self.alert_art.char_indices()
-                                    .find(|c| { c.1 == '\n' })
-                                    .unwrap_or_else(|| (self.alert_art.len(), 'i')).0;
-            },
-        };
+        return self.to_ascii_art(warn_state).lines().next().map_or(0, |line| line.chars().count());
     }
 
     fn height(&self, warn_state: &WarnStates) -> usize {
@@ -160,6 +233,9 @@ impl WarnStateAsciiArt {
             WarnStates::Alert => {
                 return self.alert_art.lines().count();
             },
+            WarnStates::Critical => {
+                return self.critical_art.lines().count();
+            },
         }
     }
 
@@ -168,7 +244,10 @@ impl WarnStateAsciiArt {
             self.width(&WarnStates::None),
             std::cmp::max(
                 self.width(&WarnStates::Warn),
-                self.width(&WarnStates::Alert)
+                std::cmp::max(
+                    self.width(&WarnStates::Alert),
+                    self.width(&WarnStates::Critical)
+                )
             )
         );
     }
@@ -178,7 +257,10 @@ impl WarnStateAsciiArt {
             self.height(&WarnStates::None),
             std::cmp::max(
                 self.height(&WarnStates::Warn),
-                self.height(&WarnStates::Alert)
+                std::cmp::max(
+                    self.height(&WarnStates::Alert),
+                    self.height(&WarnStates::Critical)
+                )
             )
         );
     }
@@ -194,6 +276,9 @@ impl WarnStateAsciiArt {
             WarnStates::Alert => {
                 return self.alert_color;
             },
+            WarnStates::Critical => {
+                return self.critical_color;
+            },
         }
     }
 }
@@ -205,403 +290,1418 @@ use std::net::{TcpListener, TcpStream, IpAddr, SocketAddr};
 
 use std::sync::mpsc::Receiver;
 
-fn update(state: &mut State, render_state: &mut RenderState, rx: &Receiver<LogItem>, log: Arc<Mutex<File>>) -> io::Result<()> {
-    //We have a received a packet when log_item is Some, or otherwise a connection notification
-    //from the connecting/disconnecting client.
-    //I initially went with a packet_received variable, but the borrow checker complained
-    //about borrowing a variable that moved between loops *despite being assigned*.
-    let mut log_item: Option<LogItem> = None;
-    match rx.try_recv() {
-        Ok(l) => {
-            log_item = Some(l);
-        }
-        Err(e) => match e {
-            TryRecvError::Empty => (),
-            TryRecvError::Disconnected => {
-                panic!("Reached an impossible state: connection_manager was closed before main loop finished.");
-            }
-        },
+//Keys in InputMode::Normal: the keybindings.
+fn handle_normal_key(state: &mut State, render_state: &mut RenderState, event: KeyEvent, log: Arc<Mutex<Log>>) -> io::Result<()> {
+    //[q]uit.
+    if event.code == KeyCode::Char('q') || event.code == KeyCode::Esc {
+        request_quit(state, render_state);
     }
 
-    //Every 500 ms, we render. If a keypress is received, render immediately.
-    if poll(Duration::from_millis(500))? {
-        // It's guaranteed that the `read()` won't block when the `poll()`
-        // function returns `true`
-        match read()? {
-            Event::Key(event) => {
-                //[q]uit.
-                if let KeyCode::Char(c) = event.code {
-                    if c == 'q' {
-                        state.window_should_close = true;
-                    }
-                    if c == 'c' && event.modifiers == KeyModifiers::CONTROL {
-                        state.window_should_close = true;
+    //Regular keybindings.
+    if let KeyCode::Char(c) = event.code {
+        match c {
+            //[r]eset warn state of the channel on screen.
+            'r' => {
+                let min_state = state.min_state;
+                reset_warn_state(state.active_mut(), min_state);
+                render_state.warn_state_changed = true;
+                render_state.channels_changed = true;
+            },
+            //[a]cknowledge the WARN or ALERT on screen. Without an --operator, ask who is on it first.
+            'a' => {
+                let channel = state.active();
+                if channel.warn_state != WarnStates::None && !channel.is_acknowledged {
+                    match state.operator.clone() {
+                        Some(operator) => acknowledge(state, render_state, Some(operator), log.clone()),
+                        None => {
+                            state.input_mode = InputMode::AckName(String::new());
+                            render_state.prompt_changed = true;
+                        },
                     }
                 }
-                if event.code == KeyCode::Esc {
-                    state.window_should_close = true;
+            },
+            //[/] Search the packet log, starting from the current search.
+            '/' => {
+                state.input_mode = InputMode::Search(state.search.clone().unwrap_or_default());
+                render_state.prompt_changed = true;
+            },
+            //Raise[+] or lower[-] the --min-state floor.
+            '+' | '-' => {
+                state.min_state = if c == '+' { state.min_state.raised() } else { state.min_state.lowered() };
+                writeln!(log.lock().unwrap(), "INFO: Minimum state set to {}.", state.min_state.to_string()).unwrap();
+                render_state.min_state_changed = true;
+                //The floor applies to every channel.
+                for channel in state.channels.iter_mut() {
+                    if channel.warn_state < state.min_state {
+                        channel.warn_state = state.min_state;
+                        clear_acknowledgement(channel);
+                        render_state.warn_state_changed = true;
+                        render_state.channels_changed = true;
+                    }
                 }
-
-                //Regular keybindings.
-                if let KeyCode::Char(c) = event.code {
-                    match c {
-                        //[r]eset warn state.
-                        'r' => {
-                            state.warn_state = WarnStates::None;
-                            render_state.warn_state_changed = true;
-                        },
-                        //[f]ocus mode toggle.
-                        'f' => {
-                            state.is_focused_mode = !state.is_focused_mode;
-                            render_state.focused_mode_changed = true;
-                        },
-                        _ => (),
+            },
+            //[f]ocus mode toggle.
+            'f' => {
+                state.is_focused_mode = !state.is_focused_mode;
+                render_state.focused_mode_changed = true;
+            },
+            //[m]ute or unmute the peer of the newest log entry.
+            'm' => {
+                let ip = state.active().packet_log.front().and_then(|log_item| log_item.peer_addr().ip());
+                if let Some(ip) = ip {
+                    let shown_ip = state.pseudonyms.as_ref().map_or(ip.to_string(), |pseudonyms| pseudonyms.show_ip(&ip));
+                    if state.muted.remove(&ip) {
+                        writeln!(log.lock().unwrap(), "INFO: Unmuted {}.", shown_ip).unwrap();
+                    }
+                    else {
+                        state.muted.insert(ip);
+                        writeln!(log.lock().unwrap(), "INFO: Muted {}.", shown_ip).unwrap();
                     }
+                    render_state.packet_log_changed = true;
                 }
             },
-            Event::Resize(_width, _height) => {
-                // writeln!(log.lock().unwrap(), "New size {}x{}", width, height)?
-                *render_state = RenderState::rerender_all();
+            //Toggle the --filter on the packet [l]og.
+            'l' => {
+                if state.filter.is_some() {
+                    state.is_filtering = !state.is_filtering;
+                    render_state.filter_changed = true;
+                    render_state.packet_log_changed = true;
+                }
             },
-            _ => (),
-        }
-    } else {
-        // Timeout expired and no `Event` is available
-    }
-
-    if log_item.is_some() {
-        let log_item = log_item.unwrap();
-
-        match &log_item {
-            LogItem::PacketLogItem { peer_addr, packet, .. } => {
-                match packet.packet_type {
-                    PacketType::Warn => {
-                        if state.warn_state != WarnStates::Alert {
-                            state.warn_state = WarnStates::Warn;
-                            render_state.warn_state_changed = true;
-                        }
+            //[C]lear the packet log of the channel on screen, asking first during an ALERT there.
+            'C' => {
+                if state.active().warn_state >= WarnStates::Alert {
+                    state.input_mode = InputMode::ConfirmClear(Instant::now());
+                    render_state.prompt_changed = true;
+                }
+                else {
+                    clear_packet_log(state, render_state, log.clone());
+                }
+            },
+            //Reveal the real addresses behind the --anonymize-[p]eers pseudonyms, or hide them again.
+            'p' => {
+                if state.pseudonyms.is_some() {
+                    state.is_revealing_peers = !state.is_revealing_peers;
+                    render_state.packet_log_changed = true;
+                }
+            },
+            //[s]napshot the channel on screen to a file.
+            's' => {
+                let notice = match snapshot::save(state, Arc::clone(&log)) {
+                    Ok(file_name) => {
+                        writeln!(log.lock().unwrap(), "INFO: Saved a snapshot to {}.", file_name).unwrap();
+                        format!("Saved {}", file_name)
                     },
-                    PacketType::Alert => {
-                        state.warn_state = WarnStates::Alert;
-                        render_state.warn_state_changed = true;
+                    Err(e) => {
+                        writeln!(log.lock().unwrap(), "ERROR: Could not save a snapshot: {}", e).unwrap();
+                        format!("Could not save snapshot: {}", e)
                     },
-                    PacketType::Name => {
-                        if packet.text.is_some() {
-                            let name = packet.text.as_ref().unwrap();
-                            if name.len() < 25 {
-                                state.peer_names.insert(*peer_addr, name.clone());
-                            }
-                        }
+                };
+                state.notice = Some((notice, Instant::now()));
+                render_state.prompt_changed = true;
+            },
+            //[e]xport every channel's packet log to a file.
+            'e' => {
+                let file_name = export::file_name(state, state.export_format);
+                let notice = match export::save(state, &file_name, state.export_format) {
+                    Ok(()) => {
+                        writeln!(log.lock().unwrap(), "INFO: Exported the packet log to {}.", file_name).unwrap();
+                        format!("Exported {}", file_name)
+                    },
+                    Err(e) => {
+                        writeln!(log.lock().unwrap(), "ERROR: Could not export the packet log: {}", e).unwrap();
+                        format!("Could not export: {}", e)
                     },
-                    _ => (),
                 };
+                state.notice = Some((notice, Instant::now()));
+                render_state.prompt_changed = true;
+            },
+            //Toggle the packet log [d]ensity.
+            'd' => {
+                state.is_compact_log = !state.is_compact_log;
+                render_state.packet_log_changed = true;
             },
-            LogItem::DisconnectLogItem { peer_addr, .. } => {
-                state.peer_names.remove(peer_addr);
+            //[R]eload the art files.
+            'R' => {
+                reload_art(state, render_state, Arc::clone(&log))?;
+            },
+            //Show or hide the keybinding hints[?]. Either way, they stay that way.
+            '?' => {
+                state.is_showing_hints = !state.is_showing_hints;
+                state.hints_hide_at = None;
+                render_state.hints_changed = true;
             },
             _ => (),
         }
+    }
 
-        state.packet_log.push_front(log_item);
-        render_state.packet_log_changed = true;
+    //Tab to the next channel, Shift-Tab (BackTab) to the previous.
+    if event.code == KeyCode::Tab || event.code == KeyCode::BackTab {
+        let num_channels = state.channels.len();
+        if event.code == KeyCode::Tab {
+            state.active_channel = (state.active_channel + 1) % num_channels;
+        }
+        else {
+            state.active_channel = (state.active_channel + num_channels - 1) % num_channels;
+        }
+        *render_state = RenderState::rerender_all();
     }
 
     return Ok(());
 }
 
-fn get_rand_char(rand: usize) -> char {
-    return match rand {
-        0 => '#',
-        1 => '&',
-        2 => '+',
-        3 => '=',
-        4 => '*',
-        5 => '-',
-        _ => ' ',
-    };
+//What keys are for right now. Anything but Normal is a partly entered command, which Esc always
+//abandons, throwing away whatever was typed.
+#[derive(PartialEq)]
+enum InputMode {
+    Normal,
+    //Typing a search of the packet log, after /.
+    Search(String),
+    //Typing who is acknowledging, after a without an --operator.
+    AckName(String),
+    //With --confirm-quit-on-alert, waiting for a second q since then.
+    ConfirmQuit(Instant),
+    //Waiting for a second C since then, to clear the packet log during an ALERT.
+    ConfirmClear(Instant),
 }
 
-fn render_alert_border(frame_number: usize, warn_art: &WarnStateAsciiArt) -> io::Result<()> {
-    let mut stdout = stdout();
-    let (cols, rows) = terminal::size()?;
-
-    //Blank out the border every frame.
-    for y in 0..rows {
-        let xs: [u16; 8] = [0, 1, 2, 3, cols-4, cols-3, cols-2, cols-1];
-        for x in xs {
-            queue!(stdout, cursor::MoveTo(x, y), style::Print(' '))?;
-        }
+//Keys while a command is partly entered.
+fn handle_mode_key(state: &mut State, render_state: &mut RenderState, event: KeyEvent, log: Arc<Mutex<Log>>) {
+    render_state.prompt_changed = true;
+    if event.code == KeyCode::Esc {
+        state.input_mode = InputMode::Normal;
+        return;
     }
 
-    // queue!(stdout, style::SetForegroundColor(warn_art.color(&WarnStates::Alert)))?;
-    for y in 0..rows {
-        let i = y as usize;
-
-        //Print the streams of characters that appear on the left.
-        if true {
-            let mut c = get_rand_char((frame_number - i) % 11);
-            if (frame_number - i) % 143 <= 80 {
-                queue!(stdout, cursor::MoveTo(0, y), style::Print(c))?;
+    //Names are held to the same limit as client names.
+    let max_len = if let InputMode::AckName(_) = state.input_mode { MAX_NAME_LEN - 1 } else { MAX_MSG_LEN };
+    match &mut state.input_mode {
+        InputMode::Normal => (),
+        //Anything but q is a change of mind.
+        InputMode::ConfirmQuit(_) => {
+            if event.code == KeyCode::Char('q') {
+                state.window_should_close = true;
             }
-            c = get_rand_char((frame_number - i) % 9);
-            if (frame_number - i) % 223 <= 100 {
-                queue!(stdout, cursor::MoveTo(1, y), style::Print(c))?;
-            }
-            c = get_rand_char((frame_number - i) % 7);
-            if (frame_number - i) % 349 <= 180 {
-                queue!(stdout, cursor::MoveTo(2, y), style::Print(c))?;
+            state.input_mode = InputMode::Normal;
+        },
+        InputMode::ConfirmClear(_) => {
+            state.input_mode = InputMode::Normal;
+            if event.code == KeyCode::Char('C') {
+                clear_packet_log(state, render_state, log);
             }
-            c = get_rand_char((frame_number - i) % 12);
-            if (frame_number - i) % 943 <= 200 {
-                queue!(stdout, cursor::MoveTo(3, y), style::Print(c))?;
+        },
+        InputMode::Search(text) | InputMode::AckName(text) => match event.code {
+            KeyCode::Char(c) if !c.is_control() && text.len() + c.len_utf8() <= max_len => text.push(c),
+            KeyCode::Backspace => {
+                text.pop();
+            },
+            KeyCode::Enter => {
+                let text = std::mem::take(text);
+                let text = if text.len() > 0 { Some(text) } else { None };
+                match std::mem::replace(&mut state.input_mode, InputMode::Normal) {
+                    InputMode::Search(_) => {
+                        state.search = text;
+                        render_state.search_changed = true;
+                        render_state.packet_log_changed = true;
+                    },
+                    InputMode::AckName(_) => acknowledge(state, render_state, text, log),
+                    _ => (),
+                }
+            },
+            _ => (),
+        },
+    }
+}
+
+//How many hosts are at ALERT and at WARN. CRITICAL hosts count as ALERT.
+fn host_counts(host_states: &HashMap<Peer, WarnStates>) -> (usize, usize) {
+    let num_alert = host_states.values().filter(|state| **state >= WarnStates::Alert).count();
+    let num_warn = host_states.values().filter(|state| **state == WarnStates::Warn).count();
+    return (num_alert, num_warn);
+}
+
+//Wipe the packet log on screen, e.g. once an incident is over. The log file keeps everything.
+fn clear_packet_log(state: &mut State, render_state: &mut RenderState, log: Arc<Mutex<Log>>) {
+    let channel = state.active_mut();
+    channel.packet_log.clear();
+    writeln!(log.lock().unwrap(), "INFO: Cleared the packet log of channel {}.", channel.name).unwrap();
+    render_state.packet_log_changed = true;
+}
+
+//Acknowledge the WARN or ALERT on screen: someone is on it. It stays raised, only calmer, until the
+//next WARN or ALERT or a reset.
+fn acknowledge(state: &mut State, render_state: &mut RenderState, operator: Option<String>, log: Arc<Mutex<Log>>) {
+    let channel = state.active_mut();
+    //It may have been reset while the name was typed.
+    if channel.warn_state == WarnStates::None || channel.is_acknowledged {
+        return;
+    }
+
+    channel.is_acknowledged = true;
+    channel.acknowledged_by = operator;
+    match &channel.acknowledged_by {
+        Some(operator) => writeln!(log.lock().unwrap(), "INFO: {} acknowledged by {} on channel {}.", channel.warn_state.to_string(), operator, channel.name).unwrap(),
+        None => writeln!(log.lock().unwrap(), "INFO: {} acknowledged on channel {}.", channel.warn_state.to_string(), channel.name).unwrap(),
+    }
+    #[cfg(unix)]
+    if let Some(event_stream) = &state.event_stream {
+        let channel = state.active();
+        event_stream.publish_ack(&channel.name, &channel.warn_state, channel.acknowledged_by.as_deref());
+    }
+    render_state.warn_state_changed = true;
+}
+
+//SIGTERM, e.g. from systemd or docker stop, and SIGINT from outside the terminal quit like q
+//does, so the terminal is restored on the way out rather than left in raw mode. update() notices
+//the signal stored in caught_signal.
+#[cfg(unix)]
+fn catch_quit_signals(caught_signal: &Arc<AtomicUsize>) -> io::Result<()> {
+    for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        signal_hook::flag::register_usize(signal, Arc::clone(caught_signal), signal as usize)?;
+    }
+    return Ok(());
+}
+
+//Each channel has its own receiver, at the same index as the channel in state.channels.
+fn update(state: &mut State, render_state: &mut RenderState, receivers: &[Receiver<LogItem>], log: Arc<Mutex<Log>>) -> io::Result<()> {
+    #[cfg(unix)]
+    match state.caught_signal.load(Ordering::Relaxed) {
+        0 => (),
+        signal => {
+            writeln!(log.lock().unwrap(), "INFO: Caught signal {}; closing.", signal).unwrap();
+            state.window_should_close = true;
+            return Ok(());
+        },
+    }
+
+    //We have a received a packet, or otherwise a connection notification from the
+    //connecting/disconnecting client, for every channel with an item in log_items.
+    //At most one per channel per frame.
+    let mut log_items: Vec<(usize, LogItem)> = Vec::new();
+    //Whether anything happened this frame, for the screensaver.
+    let mut had_activity = false;
+    for (channel_index, rx) in receivers.iter().enumerate() {
+        match rx.try_recv() {
+            Ok(l) => {
+                log_items.push((channel_index, l));
+                had_activity = true;
             }
+            Err(e) => match e {
+                TryRecvError::Empty => (),
+                TryRecvError::Disconnected => {
+                    //Every sender for the channel is gone, so nothing more can arrive on it. That
+                    //only happens when the listener is shutting down, so the window should too.
+                    writeln!(log.lock().unwrap(), "INFO: Channel {} stopped receiving; closing.", state.channels[channel_index].name).unwrap();
+                    state.window_should_close = true;
+                    return Ok(());
+                }
+            },
         }
+    }
 
-        //Print the streams of characters that appear on the right.
-        if true {
-            let mut c = get_rand_char((frame_number - i) % 11);
-            if (frame_number - i) % 139 <= 90 {
-                queue!(stdout, cursor::MoveTo(cols, y), style::Print(c))?;
-            }
-            c = get_rand_char((frame_number - i) % 9);
-            if (frame_number - i) % 226 <= 130 {
-                queue!(stdout, cursor::MoveTo(cols - 1, y), style::Print(c))?;
-            }
-            c = get_rand_char((frame_number - i) % 7);
-            if (frame_number - i) % 363 <= 200 {
-                queue!(stdout, cursor::MoveTo(cols - 2, y), style::Print(c))?;
-            }
-            c = get_rand_char((frame_number - i) % 12);
-            if (frame_number - i) % 927 <= 200 {
-                queue!(stdout, cursor::MoveTo(cols - 3, y), style::Print(c))?;
-            }
+    //Every poll_interval (500 ms by default), we render. If a keypress is received, render immediately.
+    //While a resize is settling, wake up in time to notice it has.
+    let mut poll_timeout = state.poll_interval;
+    if state.resize_settling_since.is_some() {
+        poll_timeout = std::cmp::min(poll_timeout, RESIZE_DEBOUNCE);
+    }
+    //Render an ALERT or CRITICAL at ALERT_FPS, so no animation step is late. Packets are taken once
+    //per frame too, so they only arrive sooner.
+    if state.active().shown_warn_state >= WarnStates::Alert && !state.is_screensaver {
+        poll_timeout = std::cmp::min(poll_timeout, Duration::from_millis(1000 / ALERT_FPS));
+    }
+    if poll(poll_timeout)? {
+        // It's guaranteed that the `read()` won't block when the `poll()`
+        // function returns `true`
+        match read()? {
+            Event::Key(event) => {
+                had_activity = true;
+
+                //Ctrl-C always quits at once, whatever is being typed, even past --confirm-quit-on-alert.
+                if event.code == KeyCode::Char('c') && event.modifiers == KeyModifiers::CONTROL {
+                    state.window_should_close = true;
+                }
+                else if state.input_mode == InputMode::Normal {
+                    handle_normal_key(state, render_state, event, Arc::clone(&log))?;
+                }
+                else {
+                    handle_mode_key(state, render_state, event, Arc::clone(&log));
+                }
+            },
+            Event::Resize(_width, _height) => {
+                // writeln!(log.lock().unwrap(), "New size {}x{}", width, height)?
+                //Dragging a window edge sends a stream of these; redraw once it stops.
+                state.resize_settling_since = Some(Instant::now());
+            },
+            _ => (),
         }
+    } else {
+        // Timeout expired and no `Event` is available
+    }
 
-        //Print the bordering '|' characters on the left and right.
-        if (frame_number + i) % 6 < 3 {
-            queue!(stdout, cursor::MoveTo(0, y), style::Print("|"))?;
-            queue!(stdout, cursor::MoveTo(cols, y), style::Print("|"))?;
+    if let Some(hide_at) = state.hints_hide_at {
+        if Instant::now() >= hide_at {
+            state.is_showing_hints = false;
+            state.hints_hide_at = None;
+            render_state.hints_changed = true;
         }
-        if frame_number % 13 + i % 5 <= 3 {
-            queue!(stdout, cursor::MoveTo(0, y), style::Print(":"))?;
-            queue!(stdout, cursor::MoveTo(cols, y), style::Print(":"))?;
+    }
+
+    if let Some((_, shown_at)) = &state.notice {
+        if shown_at.elapsed() >= NOTICE_SHOWN_FOR {
+            state.notice = None;
+            render_state.prompt_changed = true;
         }
     }
-    // queue!(stdout, style::ResetColor)?;
 
-    return Ok(());
-}
+    if let InputMode::ConfirmQuit(requested_at) | InputMode::ConfirmClear(requested_at) = state.input_mode {
+        if requested_at.elapsed() >= CONFIRM_WINDOW {
+            state.input_mode = InputMode::Normal;
+            render_state.prompt_changed = true;
+        }
+    }
 
-fn render_warn_state(warn_art: &WarnStateAsciiArt, warn_state: &WarnStates, is_centered: bool, frame_number: usize) -> io::Result<()> {
-    let mut stdout = stdout();
-    let ascii_width = warn_art.width(warn_state);
-    let ascii_height = warn_art.height(warn_state);
+    for (channel_index, log_item) in log_items.iter() {
+        check_in(state, render_state, *channel_index, log_item, Arc::clone(&log));
+    }
+    log_items.append(&mut check_heartbeats(state, Arc::clone(&log)));
 
-    let (cols, rows) = terminal::size()?;
+    for (channel_index, log_item) in log_items {
+        take_log_item(state, render_state, channel_index, log_item, &log);
+    }
 
-    let ascii_x;
-    let ascii_y;
-    if is_centered {
-        ascii_x = (cols / 2) - (ascii_width / 2) as u16;
-        ascii_y = (rows / 2) - (ascii_height / 2) as u16;
+    run_hooks(state, &log);
+
+    //Show a channel's new warn state straight away, unless its shown state changed less than
+    //WARN_STATE_SETTLE_FRAMES ago; then wait out the rest of that and show whatever it is by then.
+    let settle = state.poll_interval * WARN_STATE_SETTLE_FRAMES;
+    let active_channel = state.active_channel;
+    for (channel_index, channel) in state.channels.iter_mut().enumerate() {
+        if channel.shown_warn_state != channel.warn_state && channel.shown_since.map_or(true, |since| since.elapsed() >= settle) {
+            channel.shown_warn_state = channel.warn_state;
+            channel.shown_since = Some(Instant::now());
+            if channel_index == active_channel {
+                render_state.warn_state_changed = true;
+            }
+        }
     }
-    else {
-        ascii_x = (cols / 2) - (ascii_width / 2) as u16;
-        ascii_y = rows / 5;
+
+    //Buckets change as packets arrive and as time moves them along; redraw only when one does.
+    for channel in state.channels.iter_mut() {
+        while channel.packet_times.front().map_or(false, |time| time.elapsed() >= Duration::from_secs(SPARKLINE_SECS)) {
+            channel.packet_times.pop_front();
+        }
+    }
+    if state.is_showing_host_counts {
+        let host_counts = host_counts(&state.active().host_states);
+        if host_counts != state.host_counts {
+            state.host_counts = host_counts;
+            render_state.host_counts_changed = true;
+        }
     }
 
-    let ascii_min_x;
-    let ascii_min_y;
-    if is_centered {
-        ascii_min_x = (cols / 2) - (warn_art.max_width() / 2) as u16;
-        ascii_min_y = (rows / 2) - (warn_art.max_height() / 2) as u16;
+    let packet_rate = packet_rate(&state.active().packet_times);
+    if packet_rate != state.packet_rate {
+        state.packet_rate = packet_rate;
+        render_state.sparkline_changed = true;
     }
-    else {
-        ascii_min_x = (cols / 2) - (warn_art.max_width() / 2) as u16;
-        ascii_min_y = rows / 5;
+
+    //Keep the ages in the packet log current.
+    if state.clock.time_display != TimeDisplay::Absolute {
+        let step = secs_since_epoch(SystemTime::now(), Arc::clone(&log));
+        if step != state.relative_time_step {
+            state.relative_time_step = step;
+            render_state.packet_log_changed = true;
+        }
     }
 
-    //Blank the previous warn_state.
-    //These max_glitch variables actually denote the max + 1 due to being used in a modulo.
-    //Apologies for any confusion this may cause.
-    let max_horizontal_glitch: u16 = 4;
-    let max_vertical_glitch: u16 = 3;
-    queue!(stdout, cursor::MoveTo(ascii_min_x - max_horizontal_glitch, ascii_min_y - max_vertical_glitch))?;
-    for _y in 0..(warn_art.max_height() + (2 * max_vertical_glitch) as usize - 1) {
-        for _x in 0..(warn_art.max_width() + (2 * max_horizontal_glitch) as usize - 1) {
-            queue!(stdout, style::Print(' '))?;
+    if let Some(since) = state.resize_settling_since {
+        if since.elapsed() >= RESIZE_DEBOUNCE {
+            state.resize_settling_since = None;
+            *render_state = RenderState::rerender_all();
         }
-        queue!(stdout, cursor::MoveDown(1), cursor::MoveToColumn(ascii_min_x - max_horizontal_glitch))?;
     }
-    let max_horizontal_glitch: usize = max_horizontal_glitch as usize;
-    let max_vertical_glitch: usize = max_vertical_glitch as usize;
 
-    //Print the current warn_state.
-    queue!(stdout, cursor::MoveTo(ascii_x, ascii_y), style::SetBackgroundColor(warn_art.color(warn_state)))?;
-    let ascii_art = warn_art.to_ascii_art(warn_state);
-    for (i, line) in ascii_art.lines().enumerate() {
-        //Compute the horizontal and vertical shift applied to the frame every so often.
-        let mut horizontal_glitch: i32 = 0;
-        if (frame_number + ((i << 3) % 5)) % 284 <= 37 {
-            horizontal_glitch = ((((frame_number + i) << 3) % 213) % max_horizontal_glitch) as i32;
-            if ((frame_number << 4) + ((i << 5) % 23)) % 2 == 0 {
-                horizontal_glitch *= -1;
-            }
+    if let Some(screensaver_after) = state.screensaver_after {
+        if had_activity {
+            state.last_activity = Instant::now();
         }
-        let mut vertical_glitch: i32 = 0;
-        if (frame_number + ((i << 2) % 7)) % 361 <= 25 {
-            vertical_glitch = ((((frame_number + i) << 3) % 213) % max_vertical_glitch) as i32;
-            if ((frame_number << 5) + ((i << 4) % 49)) % 2 == 0 {
-                vertical_glitch *= -1;
+
+        //Only idle while everything is nominal, so the saver can never hide a WARN or ALERT.
+        let is_nominal = state.channels.iter().all(|channel| channel.warn_state == WarnStates::None);
+        if state.is_screensaver {
+            if had_activity || !is_nominal {
+                state.is_screensaver = false;
+                *render_state = RenderState::rerender_all();
             }
         }
-        let x = ascii_x as i32 + horizontal_glitch;
-        let y = ascii_y as i32 + i as i32 + vertical_glitch;
-        queue!(stdout, cursor::MoveTo(x as u16, y as u16), style::Print(line))?;
+        else if is_nominal && state.last_activity.elapsed() >= screensaver_after {
+            state.is_screensaver = true;
+            render_state.clear_background = true;
+        }
 
-        //Original code to print without glitching.
-        // queue!(
-        //     stdout,
-        //     style::Print(line),
-        //     cursor::MoveDown(1),
-        //     cursor::MoveToColumn(ascii_x),
-        // )?;
+        //Move the clock every so often.
+        if state.is_screensaver {
+            let step = secs_since_epoch(SystemTime::now(), Arc::clone(&log)) / SCREENSAVER_STEP_SECS;
+            if step != state.screensaver_step {
+                state.screensaver_step = step;
+                render_state.clear_background = true;
+            }
+        }
     }
-    queue!(stdout, style::ResetColor)?;
+
+    //Report the worst state of any channel.
+    let worst_warn_state = state.channels.iter().map(|channel| channel.warn_state).max().unwrap();
+    state.metrics.set_warn_state(&worst_warn_state);
 
     return Ok(());
 }
 
-fn render_packet_log(packet_log: &VecDeque<LogItem>, warn_art_max_height: usize, peer_names: &HashMap<SocketAddr, String>) -> io::Result<()> {
-    let mut stdout = stdout();
-
-    let (cols, rows) = terminal::size()?;
-
-    let margin_x = 4;
-    let start_x = margin_x as u16;
-    let start_y = 2 + warn_art_max_height as u16 + rows / 5;
+//Take one packet, connection notice or alarm into a channel: raise or reset its warn state, keep
+//names and labels up to date, and add it to the packet log.
+fn take_log_item(state: &mut State, render_state: &mut RenderState, channel_index: usize, log_item: LogItem, log: &Arc<Mutex<Log>>) {
+    let is_active = channel_index == state.active_channel;
+    let channel = &mut state.channels[channel_index];
+    let previous_warn_state = channel.warn_state;
+    let previous_is_acknowledged = channel.is_acknowledged;
+
+    match &log_item {
+        LogItem::PacketLogItem { peer_addr, packet, .. } => {
+            //Muted peers are still logged, but can't raise the state.
+            let is_muted = peer_addr.ip().map_or(false, |ip| state.muted.contains(&ip));
+            match packet.packet_type {
+                PacketType::Warn | PacketType::Alert | PacketType::Critical if is_muted => (),
+                PacketType::Warn => {
+                    let host_state = channel.host_states.entry(peer_addr.host()).or_insert(WarnStates::Warn);
+                    *host_state = std::cmp::max(*host_state, WarnStates::Warn);
+                    if channel.warn_state < WarnStates::Alert {
+                        channel.warn_state = WarnStates::Warn;
+                        if let Some(escalation) = state.escalation {
+                            //Ports change every time a client reconnects, so count by host.
+                            let warn_times = channel.warn_times.entry(peer_addr.host()).or_default();
+                            if escalation.is_crossed(warn_times, log_item.received_at()) {
+                                writeln!(log.lock().unwrap(), "INFO: Escalated to ALERT on channel {}: more than {} WARNs from {} within {}s.", channel.name, escalation.count, show_peer(peer_addr, state.pseudonyms.as_deref()), escalation.window.as_secs()).unwrap();
+                                channel.warn_state = WarnStates::Alert;
+                                channel.host_states.insert(peer_addr.host(), WarnStates::Alert);
+                                channel.warn_times.clear();
+                            }
+                        }
+                    }
+                    clear_acknowledgement(channel);
+                },
+                //An ALERT doesn't bring a CRITICAL down.
+                PacketType::Alert => {
+                    channel.warn_state = std::cmp::max(channel.warn_state, WarnStates::Alert);
+                    let host_state = channel.host_states.entry(peer_addr.host()).or_insert(WarnStates::Alert);
+                    *host_state = std::cmp::max(*host_state, WarnStates::Alert);
+                    clear_acknowledgement(channel);
+                },
+                PacketType::Critical => {
+                    channel.warn_state = WarnStates::Critical;
+                    channel.host_states.insert(peer_addr.host(), WarnStates::Critical);
+                    clear_acknowledgement(channel);
+                },
+                PacketType::Name => {
+                    if packet.text.is_some() {
+                        let name = packet.text.as_ref().unwrap();
+                        if name.len() < MAX_NAME_LEN {
+                            //A name identifies a client across connections. When one reconnects
+                            //(same host, new port) before its old connection is noticed as
+                            //dropped, the new connection takes the name over so it isn't
+                            //listed twice.
+                            let previous_peers: Vec<Peer> = channel.peer_names.iter()
+                                .filter(|(peer, existing)| *peer != peer_addr && peer.host() == peer_addr.host() && *existing == name)
+                                .map(|(peer, _)| *peer)
+                                .collect();
+                            for previous_peer in previous_peers {
+                                channel.peer_names.remove(&previous_peer);
+                                writeln!(log.lock().unwrap(), "INFO: {} took the name \"{}\" over from {}.", show_peer(peer_addr, state.pseudonyms.as_deref()), name, show_peer(&previous_peer, state.pseudonyms.as_deref())).unwrap();
+                            }
+                            channel.peer_names.insert(*peer_addr, name.clone());
+                        }
+                    }
+                },
+                PacketType::Reset => {
+                    reset_warn_state(channel, state.min_state);
+                },
+                PacketType::Metadata => {
+                    //handle_packet already refused malformed labels.
+                    let labels = parse_labels(packet.text.as_deref().unwrap_or("")).unwrap_or_default();
+                    if labels.len() > 0 {
+                        channel.peer_labels.insert(*peer_addr, labels);
+                    }
+                    else {
+                        channel.peer_labels.remove(peer_addr);
+                    }
+                    if is_active && state.filter.is_some() {
+                        render_state.packet_log_changed = true;
+                    }
+                },
+                _ => (),
+            };
+        },
+        //Host names are kept too, as they go by IP, not by connection.
+        LogItem::ConnectLogItem { peer_addr, host_name: Some(host_name), .. } => {
+            if let Some(ip) = peer_addr.ip() {
+                state.host_names.insert(ip, host_name.clone());
+                if is_active {
+                    render_state.packet_log_changed = true;
+                }
+            }
+        },
+        //Labels are kept, unlike names, so --filter still shows what a client sent once it's
+        //gone.
+        LogItem::DisconnectLogItem { peer_addr, .. } => {
+            channel.peer_names.remove(peer_addr);
+        },
+        _ => (),
+    }
 
-    //Blank the packet log.
-    queue!(stdout, cursor::MoveTo(start_x, start_y))?;
-    for _y in start_y..=(rows - 3) {
-        for _x in margin_x..=(cols - margin_x) {
-            queue!(stdout, style::Print(' '))?;
+    //The art and border follow the warn state once it settles, below.
+    if channel.warn_state != previous_warn_state {
+        render_state.channels_changed = true;
+    }
+    if channel.is_acknowledged != previous_is_acknowledged {
+        render_state.channels_changed = true;
+        if is_active {
+            render_state.warn_state_changed = true;
         }
-        queue!(stdout, cursor::MoveDown(1), cursor::MoveToColumn(start_x))?;
     }
 
-    // println!("packet_log len: {}", packet_log.len());
-    queue!(stdout, cursor::MoveTo(start_x, start_y))?;
-    for log_item in packet_log {
-        let timestamp_in_secs = log_item.timestamp().duration_since(UNIX_EPOCH).expect("Time went backwards.").as_secs();
+    #[cfg(unix)]
+    if let Some(event_stream) = &state.event_stream {
+        event_stream.publish(&log_item);
+    }
 
-        let secs_per_day  =  24 * 60 * 60;
-        let secs_per_hour =  60 * 60;
-        let secs_per_min  =  60;
+    if let LogItem::PacketLogItem { .. } = log_item {
+        channel.packet_times.push_back(Instant::now());
+    }
 
-        let hour = (timestamp_in_secs % secs_per_day) / secs_per_hour;
-        let min = (timestamp_in_secs % secs_per_hour) / secs_per_min;
+    channel.packet_log.push_front(log_item);
+    if let Some(log_limit) = state.log_limit {
+        trim_packet_log(&mut channel.packet_log, log_limit, state.retention);
+    }
+    if is_active {
+        render_state.packet_log_changed = true;
+    }
+}
 
-        //Print the time.
-        queue!(stdout,
-            style::Print(
-                format!("[{:0>2}:{:0>2}] ", hour, min)
-            )
-        )?;
+//How long the keybinding hints show at startup, unless --no-hints.
+const HINTS_SHOWN_FOR: Duration = Duration::from_secs(8);
 
-        let mut y;
+//The keys that do something right now: filtering needs a --filter, and switching channels more
+//than one channel.
+fn keybinding_hints(state: &State) -> String {
+    let mut hints = vec!["[q]uit", "[r]eset", "[a]ck", "[f]ocus", "[m]ute", "[d]ensity", "[C]lear", "[s]napshot", "[e]xport"];
+    if state.filter.is_some() {
+        hints.push("[l] filter");
+    }
+    if state.pseudonyms.is_some() {
+        hints.push("[p]eers");
+    }
+    hints.extend(["[+/-] floor", "[R]eload art"]);
+    if state.channels.len() > 1 {
+        hints.push("[Tab] channel");
+    }
+    hints.extend(["[/] search", "[?] hide"]);
+    return hints.join(" ");
+}
 
-        //Depending on the packet, print different things.
+//The hints go on the row between the packet log, which stops at rows - 3, and the quit prompt.
+fn render_hints(out: &mut impl Write, state: &State, cols: u16, rows: u16) -> io::Result<()> {
+    //Just inside the ALERT border.
+    let width = cols.saturating_sub(8) as usize;
+    let ellipsis = if state.ascii_only { "..." } else { "…" };
+    let hints = if state.is_showing_hints { truncate_with_ellipsis(keybinding_hints(state), width, ellipsis) } else { String::new() };
+
+    queue!(out, cursor::MoveTo(4, rows - 2))?;
+    if state.use_color {
+        queue!(out, SetForegroundColor(Color::DarkGrey), style::Print(format!("{:<width$}", hints, width = width)), ResetColor)?;
+    }
+    else {
+        queue!(out, style::Print(format!("{:<width$}", hints, width = width)))?;
+    }
+    return Ok(());
+}
+
+//How long after a first q or Esc during an ALERT a q quits, with --confirm-quit-on-alert, and a
+//second C clears the packet log.
+const CONFIRM_WINDOW: Duration = Duration::from_secs(2);
+const QUIT_PROMPT: &str = "Press q again to quit during ALERT, Esc to stay";
+const CLEAR_PROMPT: &str = "Press C again to clear the log during ALERT, Esc to keep it";
+//How long a notice, e.g. where a snapshot was saved, stays on the prompt row.
+const NOTICE_SHOWN_FOR: Duration = Duration::from_secs(3);
+
+//Quit, unless --confirm-quit-on-alert is on and some channel is at ALERT; then only a q within
+//CONFIRM_WINDOW quits, so a stray keypress can't close the dashboard mid-incident.
+fn request_quit(state: &mut State, render_state: &mut RenderState) {
+    let is_alert = state.channels.iter().any(|channel| channel.warn_state >= WarnStates::Alert);
+    if !state.confirm_quit_on_alert || !is_alert {
+        state.window_should_close = true;
+        return;
+    }
+
+    state.input_mode = InputMode::ConfirmQuit(Instant::now());
+    render_state.prompt_changed = true;
+}
+
+//The prompt for a partly entered command, just inside the ALERT border on the bottom row, with a
+//cursor after what's been typed so far. Outside of a prompt, the row shows any notice.
+fn render_prompt(out: &mut impl Write, state: &State, cols: u16, rows: u16) -> io::Result<()> {
+    let width = cols.saturating_sub(8) as usize;
+    let prompt = match &state.input_mode {
+        InputMode::Normal => state.notice.as_ref().map(|(notice, _)| notice.clone()).unwrap_or_default(),
+        InputMode::Search(text) => format!("/{}_", sanitize_for_terminal(text)),
+        InputMode::AckName(text) => format!("ACK as: {}_", sanitize_for_terminal(text)),
+        InputMode::ConfirmQuit(_) => QUIT_PROMPT.to_string(),
+        InputMode::ConfirmClear(_) => CLEAR_PROMPT.to_string(),
+    };
+    //Keep the end of a long search in view, where the typing is.
+    let prompt = if prompt.chars().count() > width { prompt.chars().skip(prompt.chars().count() - width).collect() } else { prompt };
+
+    queue!(out, cursor::MoveTo(4, rows - 1), style::Print(format!("{:<width$}", prompt, width = width)))?;
+    return Ok(());
+}
+
+//Alarms from the watchdog come from this address, as there's no connection behind them.
+const WATCHDOG_ADDR: ([u8; 4], u16) = ([0, 0, 0, 0], 0);
+
+//Count a received packet as the heartbeat of the --expect'ed client that sent it, if any, and take
+//down the alarm for it if it had gone missing.
+fn check_in(state: &mut State, render_state: &mut RenderState, channel_index: usize, log_item: &LogItem, log: Arc<Mutex<Log>>) {
+    let (peer_addr, packet) = match log_item {
+        LogItem::PacketLogItem { peer_addr, packet, .. } => (peer_addr, packet),
+        _ => return,
+    };
+
+    //Once something else raises an ALERT, a client coming back mustn't clear it.
+    let is_muted = peer_addr.ip().map_or(false, |ip| state.muted.contains(&ip));
+    if (packet.packet_type == PacketType::Alert || packet.packet_type == PacketType::Critical) && !is_muted {
+        for expectation in state.expected.iter_mut() {
+            if expectation.channel_index == channel_index {
+                expectation.restore_state = None;
+            }
+        }
+    }
+
+    //A NAME counts under the name it gives.
+    let name = match packet.packet_type {
+        PacketType::Name => packet.text.clone(),
+        _ => state.channels[channel_index].peer_names.get(peer_addr).cloned(),
+    };
+    let name = match name {
+        Some(name) => name,
+        None => return,
+    };
+
+    let mut recoveries: Vec<(usize, Option<WarnStates>)> = Vec::new();
+    for expectation in state.expected.iter_mut() {
+        if expectation.name != name {
+            continue;
+        }
+        if expectation.is_missing {
+            expectation.is_missing = false;
+            recoveries.push((expectation.channel_index, expectation.restore_state.take()));
+            writeln!(log.lock().unwrap(), "INFO: {} is sending heartbeats again.", name).unwrap();
+        }
+        expectation.last_seen = Instant::now();
+        expectation.channel_index = channel_index;
+    }
+
+    for (alarm_channel, restore_state) in recoveries {
+        //The alarm stays up while any other client on the channel is still missing.
+        let is_still_missing = state.expected.iter()
+            .any(|expectation| expectation.is_missing && expectation.channel_index == alarm_channel);
+        let channel = &mut state.channels[alarm_channel];
+        if !is_still_missing {
+            channel.host_states.remove(&Peer::Tcp(SocketAddr::from(WATCHDOG_ADDR)));
+        }
+        if let Some(restore_state) = restore_state {
+            if !is_still_missing && channel.warn_state == WarnStates::Alert {
+                channel.warn_state = std::cmp::max(restore_state, state.min_state);
+                render_state.channels_changed = true;
+                if alarm_channel == state.active_channel {
+                    render_state.warn_state_changed = true;
+                }
+            }
+        }
+    }
+}
+
+//Raise an alarm for every --expect'ed client that has just gone quiet for longer than its
+//interval. The alarms are ALERTs, handled like any other packet.
+fn check_heartbeats(state: &mut State, log: Arc<Mutex<Log>>) -> Vec<(usize, LogItem)> {
+    let mut alarms: Vec<(usize, LogItem)> = Vec::new();
+
+    for i in 0..state.expected.len() {
+        let expectation = &state.expected[i];
+        if expectation.is_missing || expectation.last_seen.elapsed() < expectation.interval {
+            continue;
+        }
+
+        //Clients missing on the same channel share the state from before the first alarm.
+        let channel_index = expectation.channel_index;
+        let restore_state = match state.expected.iter().find(|other| other.is_missing && other.channel_index == channel_index) {
+            Some(other) => other.restore_state,
+            None => Some(state.channels[channel_index].warn_state),
+        };
+
+        let expectation = &mut state.expected[i];
+        expectation.is_missing = true;
+        expectation.restore_state = restore_state;
+        writeln!(log.lock().unwrap(), "WARN: {} missed its heartbeat: not heard from in {}s.", expectation.name, expectation.interval.as_secs()).unwrap();
+
+        alarms.push((channel_index, LogItem::PacketLogItem {
+            timestamp: SystemTime::now(),
+            peer_addr: Peer::Tcp(SocketAddr::from(WATCHDOG_ADDR)),
+            packet: Packet {
+                packet_type: PacketType::Alert,
+                text: Some(format!("{} missed its heartbeat", expectation.name)),
+                will_type: None,
+                timestamp: None,
+                wants_ack: false,
+                sequence: None,
+                has_invalid_utf8: false,
+            },
+        }));
+    }
+
+    return alarms;
+}
+
+//Run --on-alert for every channel that has entered ALERT or gone up to CRITICAL since the last
+//frame, and --on-reset for every one whose WARN, ALERT or CRITICAL has come down. A channel staying
+//at ALERT runs nothing more.
+fn run_hooks(state: &mut State, log: &Arc<Mutex<Log>>) {
+    for channel in state.channels.iter_mut() {
+        let previous_state = channel.hooked_state;
+        channel.hooked_state = channel.warn_state;
+        if channel.warn_state == previous_state {
+            continue;
+        }
+
+        let message = newest_warning(&channel.packet_log).unwrap_or("");
+        if channel.warn_state >= WarnStates::Alert && channel.warn_state > previous_state {
+            state.hooks.alert(&channel.name, message, log);
+        }
+        else if channel.warn_state < previous_state && previous_state >= WarnStates::Warn {
+            state.hooks.reset(&channel.name, message, log);
+        }
+    }
+}
+
+//The text of the newest WARN, ALERT or CRITICAL in a packet log, if it had any.
+fn newest_warning(packet_log: &VecDeque<LogItem>) -> Option<&str> {
+    return packet_log.iter().find_map(|log_item| match log_item {
+        LogItem::PacketLogItem { packet, .. } if matches!(packet.packet_type, PacketType::Warn | PacketType::Alert | PacketType::Critical) => Some(packet.text.as_deref().unwrap_or("")),
+        _ => None,
+    });
+}
+
+//Drop a channel's warn state back down, but no lower than the --min-state floor. Everything that
+//resets the state goes through here so none of it can undercut the floor.
+fn reset_warn_state(channel: &mut Channel, min_state: WarnStates) {
+    channel.warn_state = min_state;
+    channel.warn_times.clear();
+    channel.host_states.clear();
+    clear_acknowledgement(channel);
+}
+
+fn clear_acknowledgement(channel: &mut Channel) {
+    channel.is_acknowledged = false;
+    channel.acknowledged_by = None;
+}
+
+//Border glyph presets, selectable with --border-chars <preset>.
+const BORDER_PRESETS: [(&str, &str); 4] = [
+    ("ascii", "#&+=*-"),
+    ("binary", "01"),
+    ("matrix", "ｱｲｳｴｵｶｷｸｹｺ0123456789"),
+    ("blocks", "█▓▒░"),
+];
+
+//The states --palette can color, by the name they're given there.
+const PALETTE_STATES: [(&str, WarnStates); 4] = [
+    ("info", WarnStates::None),
+    ("warn", WarnStates::Warn),
+    ("alert", WarnStates::Alert),
+    ("critical", WarnStates::Critical),
+];
+
+//Parse a --palette like info:#1e90ff,warn:#ffd700,alert:#dc143c,critical:#8b008b. States left out
+//keep their default colors.
+fn parse_palette(arg: &str) -> Option<Vec<(WarnStates, Color)>> {
+    let mut palette = Vec::new();
+    for entry in arg.split(',') {
+        let (state, hex) = entry.split_once(':')?;
+        let (_, warn_state) = PALETTE_STATES.iter().find(|(name, _)| *name == state)?;
+        let warn_state = *warn_state;
+        let hex = hex.strip_prefix('#')?;
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let rgb = u32::from_str_radix(hex, 16).ok()?;
+        palette.push((warn_state, Color::Rgb { r: (rgb >> 16) as u8, g: (rgb >> 8) as u8, b: rgb as u8 }));
+    }
+    return Some(palette);
+}
+
+fn parse_border_chars(arg: &str) -> Vec<char> {
+    for (name, glyphs) in BORDER_PRESETS {
+        if arg == name {
+            return glyphs.chars().collect();
+        }
+    }
+    if arg == "" {
+        return BORDER_PRESETS[0].1.chars().collect();
+    }
+    return arg.chars().collect();
+}
+
+//A glyph for one of the border's eight streams, or a space. Only the first six values of rand
+//produce a glyph, whatever the size of the set, so that the density of the streams doesn't change
+//with the theme. Which glyph is picked separately, over the whole set, from the stream and where
+//in it the glyph is: the same position always gets the same glyph, so it falls down the screen a
+//row per frame.
+fn get_rand_char(rand: usize, position: usize, stream: usize, glyphs: &[char]) -> char {
+    if rand < 6 {
+        //Multiplying by large odd constants scatters neighbouring positions across the set.
+        let scrambled = position.wrapping_mul(0x9E37_79B9).wrapping_add((stream + 1).wrapping_mul(0x85EB_CA6B));
+        return glyphs[(scrambled >> 7) % glyphs.len()];
+    }
+    return ' ';
+}
+
+fn render_alert_border(out: &mut impl Write, cols: u16, rows: u16, frame_number: usize, warn_art: &WarnStateAsciiArt, warn_state: &WarnStates, glyphs: &[char], use_color: bool) -> io::Result<()> {
+    //A CRITICAL border streams twice as fast and flashes solid, so it can't be taken for an ALERT.
+    let is_critical = *warn_state == WarnStates::Critical;
+    let frame_number = if is_critical { frame_number.wrapping_mul(2) } else { frame_number };
+
+    //Blank out the border every frame.
+    for y in 0..rows {
+        let xs: [u16; 8] = [0, 1, 2, 3, cols-4, cols-3, cols-2, cols-1];
+        for x in xs {
+            queue!(out, cursor::MoveTo(x, y), style::Print(' '))?;
+        }
+    }
+
+    if use_color {
+        queue!(out, style::SetForegroundColor(warn_art.color(warn_state)))?;
+    }
+    for y in 0..rows {
+        //Subtractions from frame_number wrap, as a small --seed can put it below the row count.
+        let i = y as usize;
+        let position = frame_number.wrapping_sub(i);
+
+        //Print the streams of characters that appear on the left.
+        if true {
+            let mut c = get_rand_char(position % 11, position, 0, glyphs);
+            if position % 143 <= 80 {
+                queue!(out, cursor::MoveTo(0, y), style::Print(c))?;
+            }
+            c = get_rand_char(position % 9, position, 1, glyphs);
+            if position % 223 <= 100 {
+                queue!(out, cursor::MoveTo(1, y), style::Print(c))?;
+            }
+            c = get_rand_char(position % 7, position, 2, glyphs);
+            if position % 349 <= 180 {
+                queue!(out, cursor::MoveTo(2, y), style::Print(c))?;
+            }
+            c = get_rand_char(position % 12, position, 3, glyphs);
+            if position % 943 <= 200 {
+                queue!(out, cursor::MoveTo(3, y), style::Print(c))?;
+            }
+        }
+
+        //Print the streams of characters that appear on the right.
+        if true {
+            let mut c = get_rand_char(position % 11, position, 4, glyphs);
+            if position % 139 <= 90 {
+                queue!(out, cursor::MoveTo(cols - 1, y), style::Print(c))?;
+            }
+            c = get_rand_char(position % 9, position, 5, glyphs);
+            if position % 226 <= 130 {
+                queue!(out, cursor::MoveTo(cols - 2, y), style::Print(c))?;
+            }
+            c = get_rand_char(position % 7, position, 6, glyphs);
+            if position % 363 <= 200 {
+                queue!(out, cursor::MoveTo(cols - 3, y), style::Print(c))?;
+            }
+            c = get_rand_char(position % 12, position, 7, glyphs);
+            if position % 927 <= 200 {
+                queue!(out, cursor::MoveTo(cols - 4, y), style::Print(c))?;
+            }
+        }
+
+        //Print the bordering '|' characters on the left and right.
+        if (frame_number + i) % 6 < 3 {
+            queue!(out, cursor::MoveTo(0, y), style::Print("|"))?;
+            queue!(out, cursor::MoveTo(cols - 1, y), style::Print("|"))?;
+        }
+        if frame_number % 13 + i % 5 <= 3 {
+            queue!(out, cursor::MoveTo(0, y), style::Print(":"))?;
+            queue!(out, cursor::MoveTo(cols - 1, y), style::Print(":"))?;
+        }
+
+        if is_critical && frame_number % 16 < 4 {
+            queue!(out, cursor::MoveTo(0, y), style::Print("|"))?;
+            queue!(out, cursor::MoveTo(cols - 1, y), style::Print("|"))?;
+        }
+    }
+    if use_color {
+        queue!(out, style::ResetColor)?;
+    }
+
+    return Ok(());
+}
+
+//Where something size cells long starts when centered in total cells. Any odd cell left over goes
+//after it, whatever the parity of either, and something larger than total starts at 0 rather than
+//underflowing.
+fn center_start(total: u16, size: usize) -> u16 {
+    let size = u16::try_from(size).unwrap_or(u16::MAX);
+    return total.saturating_sub(size) / 2;
+}
+
+//Where the art goes vertically, from --art-position. The packet log goes below it.
+#[derive(Copy, Clone)]
+enum ArtPosition {
+    //Just clear of the channel bar and the glitch effect.
+    Top,
+    Center,
+    //A fifth of the way down.
+    Fifth,
+    Row(u16),
+}
+
+impl ArtPosition {
+    //The row the top of art of the given height goes on.
+    fn art_y(&self, rows: u16, height: usize) -> u16 {
+        let y = match self {
+            ArtPosition::Top => 3,
+            ArtPosition::Center => center_start(rows, height),
+            ArtPosition::Fifth => rows / 5,
+            ArtPosition::Row(row) => *row,
+        };
+        //Never so low the art runs off the bottom, wherever it was asked for.
+        let height = u16::try_from(height).unwrap_or(u16::MAX);
+        return std::cmp::min(y, rows.saturating_sub(height));
+    }
+}
+
+//Acknowledged art is drawn dim and holds still, so it reads as being handled at a glance.
+fn render_warn_state(out: &mut impl Write, cols: u16, rows: u16, warn_art: &WarnStateAsciiArt, warn_state: &WarnStates, art_position: ArtPosition, frame_number: usize, is_acknowledged: bool, use_color: bool) -> io::Result<()> {
+    let ascii_width = warn_art.width(warn_state);
+    let ascii_height = warn_art.height(warn_state);
+
+    //render() refuses art that doesn't fit with a margin, but don't rely on it to keep the
+    //arithmetic below from going out of the terminal.
+    if warn_art.max_width() > cols as usize || warn_art.max_height() > rows as usize {
+        return Err(Error::new(ErrorKind::Other, "ascii art is larger than the terminal."));
+    }
+
+    let ascii_x = center_start(cols, ascii_width);
+    let ascii_y = art_position.art_y(rows, ascii_height);
+
+    let ascii_min_x = center_start(cols, warn_art.max_width());
+    let ascii_min_y = art_position.art_y(rows, warn_art.max_height());
+
+    //Blank the previous warn_state.
+    //These max_glitch variables actually denote the max + 1 due to being used in a modulo.
+    //Apologies for any confusion this may cause.
+    let max_horizontal_glitch: u16 = 4;
+    let max_vertical_glitch: u16 = 3;
+    //Kept inside the terminal, as art about its size leaves no room for the glitch margin: a space
+    //past the last column would wrap onto the next row, and on the bottom row scroll the screen.
+    let blank_x = ascii_min_x.saturating_sub(max_horizontal_glitch);
+    let blank_y = ascii_min_y.saturating_sub(max_vertical_glitch);
+    let blank_width = std::cmp::min(warn_art.max_width() + (2 * max_horizontal_glitch) as usize - 1, cols.saturating_sub(blank_x) as usize);
+    let blank_height = std::cmp::min(warn_art.max_height() + (2 * max_vertical_glitch) as usize - 1, rows.saturating_sub(blank_y) as usize);
+    for y in 0..blank_height {
+        queue!(out, cursor::MoveTo(blank_x, blank_y + y as u16))?;
+        for _x in 0..blank_width {
+            queue!(out, style::Print(' '))?;
+        }
+    }
+    let max_horizontal_glitch: usize = max_horizontal_glitch as usize;
+    let max_vertical_glitch: usize = max_vertical_glitch as usize;
+
+    //Print the current warn_state.
+    //Without color the art's shape alone has to carry the meaning, so the defaults are kept distinct.
+    queue!(out, cursor::MoveTo(ascii_x, ascii_y))?;
+    if use_color {
+        queue!(out, style::SetBackgroundColor(warn_art.color(warn_state)))?;
+    }
+    if is_acknowledged {
+        queue!(out, SetAttribute(Attribute::Dim))?;
+    }
+    let ascii_art = warn_art.to_ascii_art(warn_state);
+    for (i, line) in ascii_art.lines().enumerate() {
+        if is_acknowledged {
+            queue!(out, cursor::MoveTo(ascii_x, ascii_y + i as u16), style::Print(line))?;
+            continue;
+        }
+
+        //Compute the horizontal and vertical shift applied to the frame every so often.
+        let mut horizontal_glitch: i32 = 0;
+        if (frame_number + ((i << 3) % 5)) % 284 <= 37 {
+            horizontal_glitch = ((((frame_number + i) << 3) % 213) % max_horizontal_glitch) as i32;
+            if ((frame_number << 4) + ((i << 5) % 23)) % 2 == 0 {
+                horizontal_glitch *= -1;
+            }
+        }
+        let mut vertical_glitch: i32 = 0;
+        if (frame_number + ((i << 2) % 7)) % 361 <= 25 {
+            vertical_glitch = ((((frame_number + i) << 3) % 213) % max_vertical_glitch) as i32;
+            if ((frame_number << 5) + ((i << 4) % 49)) % 2 == 0 {
+                vertical_glitch *= -1;
+            }
+        }
+        //A glitch can't push the art off any edge of the terminal. The art fits, as checked above,
+        //so there's always room for a whole line.
+        let x = (ascii_x as i32 + horizontal_glitch).min(cols as i32 - ascii_width as i32).max(0);
+        let y = (ascii_y as i32 + i as i32 + vertical_glitch).min(rows as i32 - 1).max(0);
+        queue!(out, cursor::MoveTo(x as u16, y as u16), style::Print(line))?;
+
+        //Original code to print without glitching.
+        // queue!(
+        //     out,
+        //     style::Print(line),
+        //     cursor::MoveDown(1),
+        //     cursor::MoveToColumn(ascii_x),
+        // )?;
+    }
+    if is_acknowledged {
+        queue!(out, SetAttribute(Attribute::NormalIntensity))?;
+    }
+    if use_color {
+        queue!(out, style::ResetColor)?;
+    }
+
+    return Ok(());
+}
+
+//A one-column glyph and color marking the kind of a packet log entry, so connects, chatter and
+//alerts can be told apart at a glance.
+//With a palette, INFO, WARN, ALERT and CRITICAL take their color from it rather than the defaults here.
+fn log_item_glyph(log_item: &LogItem, ascii_only: bool, palette: Option<&WarnStateAsciiArt>) -> (char, Color) {
+    let state_color = |warn_state: WarnStates, default: Color| palette.map_or(default, |palette| palette.color(&warn_state));
+    match log_item {
+        LogItem::ConnectLogItem { .. } => (if ascii_only { '>' } else { '→' }, Color::Green),
+        LogItem::DisconnectLogItem { how: Disconnect::Lost, .. } => (if ascii_only { '<' } else { '←' }, Color::Yellow),
+        LogItem::DisconnectLogItem { .. } => (if ascii_only { '<' } else { '←' }, Color::DarkGrey),
+        LogItem::GapLogItem { .. } => ('?', Color::Magenta),
+        LogItem::PacketLogItem { packet, .. } => match packet.packet_type {
+            PacketType::Info => ('i', state_color(WarnStates::None, Color::Cyan)),
+            PacketType::Warn => ('!', state_color(WarnStates::Warn, Color::Yellow)),
+            PacketType::Alert => (if ascii_only { 'X' } else { '‼' }, state_color(WarnStates::Alert, Color::Red)),
+            PacketType::Critical => (if ascii_only { '*' } else { '✖' }, state_color(WarnStates::Critical, Color::Magenta)),
+            PacketType::Name => ('@', Color::Cyan),
+            PacketType::Reset => ('=', Color::Green),
+            PacketType::LastWill => ('~', Color::DarkGrey),
+            PacketType::Metadata => ('#', Color::Cyan),
+        },
+    }
+}
+
+//Text from clients goes straight to the operator's terminal, so an escape sequence in it could
+//move the cursor, recolor or clear the screen. Show control characters visibly instead, in caret
+//notation (ESC as ^[, DEL as ^?), or as \u{..} for the C1 controls, which have none.
+fn sanitize_for_terminal(text: &str) -> String {
+    let mut sanitized = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\u{0}'..='\u{1f}' => {
+                sanitized.push('^');
+                sanitized.push((c as u8 + 0x40) as char);
+            },
+            '\u{7f}' => sanitized.push_str("^?"),
+            '\u{80}'..='\u{9f}' => sanitized.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            _ => sanitized.push(c),
+        }
+    }
+    return sanitized;
+}
+
+//The peer as shown and logged: with --anonymize-peers, its pseudonym.
+fn show_peer(peer_addr: &Peer, pseudonyms: Option<&Pseudonyms>) -> String {
+    match pseudonyms {
+        Some(pseudonyms) => return pseudonyms.show(peer_addr),
+        None => return peer_addr.to_string(),
+    }
+}
+
+//IPv6 addresses longer than this are cut short with --short-ipv6. Any IPv4 address fits.
+const SHORT_IPV6_LEN: usize = 15;
+
+//How peers are shown on screen and in snapshots, where space is short: as pseudonyms with
+//--anonymize-peers, else by host name with --resolve-peers, and with --short-ipv6, long IPv6
+//addresses cut down to their first two and last groups. Log lines go through show_peer and keep
+//the whole address.
+#[derive(Copy, Clone)]
+struct PeerDisplay<'a> {
+    pseudonyms: Option<&'a Pseudonyms>,
+    host_names: &'a HashMap<IpAddr, String>,
+    is_short_ipv6: bool,
+    ascii_only: bool,
+}
+
+impl PeerDisplay<'_> {
+    //The port stays, so clients on the same host can still be told apart.
+    fn show(&self, peer_addr: &Peer) -> String {
+        if self.pseudonyms.is_some() {
+            return show_peer(peer_addr, self.pseudonyms);
+        }
+        let addr = match peer_addr {
+            Peer::Tcp(addr) => addr,
+            #[cfg(unix)]
+            Peer::Unix(_) => return peer_addr.to_string(),
+        };
+        //Names come from whoever runs the reverse zone, so they're as untrusted as packet text.
+        if let Some(name) = self.host_names.get(&addr.ip()) {
+            return format!("{}:{}", sanitize_for_terminal(name), addr.port());
+        }
+        match addr.ip() {
+            IpAddr::V6(ip) if self.is_short_ipv6 => {
+                if let Some(ip) = ip.to_ipv4_mapped() {
+                    return format!("{}:{}", ip, addr.port());
+                }
+                let full = ip.to_string();
+                if full.len() <= SHORT_IPV6_LEN {
+                    return peer_addr.to_string();
+                }
+                let segments = ip.segments();
+                let ellipsis = if self.ascii_only { "..." } else { "…" };
+                return format!("[{:x}:{:x}{}{:x}]:{}", segments[0], segments[1], ellipsis, segments[7], addr.port());
+            },
+            _ => return peer_addr.to_string(),
+        }
+    }
+}
+
+//NAME packets always show the IP, so it's clear which client took the name. Everything else
+//shows the client's name if it has one.
+fn peer_label(packet_type: &PacketType, peer_addr: &Peer, peer_names: &HashMap<Peer, String>, peers: PeerDisplay) -> String {
+    if *packet_type != PacketType::Name {
+        if let Some(name) = peer_names.get(peer_addr) {
+            return sanitize_for_terminal(name);
+        }
+    }
+    return peers.show(peer_addr);
+}
+
+//Marks text that arrived as invalid UTF-8, so the replacement characters in it aren't taken for
+//what the client meant to send.
+fn invalid_utf8_marker(ascii_only: bool) -> &'static str {
+    return if ascii_only { "[!]" } else { "⚠" };
+}
+
+//Labels as key=value, space-separated, safe to print.
+fn format_labels(labels: &[(String, String)]) -> String {
+    let labels: Vec<String> = labels.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+    return sanitize_for_terminal(&labels.join(" "));
+}
+
+//How much of the search the label beside the art shows.
+const SEARCH_LABEL_LEN: usize = 20;
+
+//Whether an entry mentions the search in its text or who it's from, ignoring case.
+fn matches_search(log_item: &LogItem, search: &str, peer_names: &HashMap<Peer, String>, peers: PeerDisplay) -> bool {
+    let search = search.to_lowercase();
+    let (peer, text) = match log_item {
+        LogItem::PacketLogItem { peer_addr, packet, .. } => (peer_label(&packet.packet_type, peer_addr, peer_names, peers), packet.text.as_deref().unwrap_or("")),
+        LogItem::ConnectLogItem { peer_addr, .. } | LogItem::DisconnectLogItem { peer_addr, .. } | LogItem::GapLogItem { peer_addr, .. } => (peers.show(peer_addr), ""),
+    };
+    return peer.to_lowercase().contains(&search) || text.to_lowercase().contains(&search);
+}
+
+//How the packet log is drawn, from State: where it goes, which entries it shows, and how.
+#[derive(Copy, Clone)]
+struct LogView<'a> {
+    //The log starts below the tallest art.
+    warn_art_max_height: usize,
+    art_position: ArtPosition,
+    //With a filter, only entries from peers labeled with it are shown, and with a search, only
+    //those mentioning it.
+    filter: Option<&'a (String, String)>,
+    search: Option<&'a str>,
+    muted: &'a HashSet<IpAddr>,
+    peers: PeerDisplay<'a>,
+    is_compact: bool,
+    max_display_len: Option<usize>,
+    use_color: bool,
+    ascii_only: bool,
+    palette: Option<&'a WarnStateAsciiArt>,
+    clock: Clock,
+}
+
+fn render_packet_log(out: &mut impl Write, cols: u16, rows: u16, channel: &Channel, view: LogView, previous_end: u16, log: Arc<Mutex<Log>>) -> io::Result<u16> {
+    let LogView { warn_art_max_height, art_position, filter, search, muted, peers, is_compact, max_display_len, use_color, ascii_only, palette, clock } = view;
+    let (packet_log, peer_names, peer_labels) = (&channel.packet_log, &channel.peer_names, &channel.peer_labels);
+
+    let margin_x = 4;
+    let start_x = margin_x as u16;
+    //Start below where the tallest art would end, with room for its glitching.
+    let start_y = art_position.art_y(rows, warn_art_max_height) + warn_art_max_height as u16 + 2;
+    if start_y > rows.saturating_sub(3) {
+        //No room for the log with the art this low.
+        return Ok(start_y);
+    }
+
+    //Blank only the rows the previous render drew on, up to previous_end; the rest of the log area
+    //is blank already. On a busy dashboard this runs every frame, and most of the area is often empty.
+    let blank_row = " ".repeat((cols - 2 * margin_x + 1) as usize);
+    for y in start_y..std::cmp::min(previous_end, rows - 2) {
+        queue!(out, cursor::MoveTo(start_x, y), style::Print(&blank_row))?;
+    }
+
+    // println!("packet_log len: {}", packet_log.len());
+    let now = SystemTime::now();
+    //Where the cursor is, tracked here rather than asked of the terminal, so the log can be drawn
+    //to any writer.
+    let mut y = start_y;
+    queue!(out, cursor::MoveTo(start_x, start_y))?;
+    for log_item in packet_log {
+        if let Some(filter) = filter {
+            if !peer_labels.get(log_item.peer_addr()).map_or(false, |labels| labels.contains(filter)) {
+                continue;
+            }
+        }
+        if let Some(search) = search {
+            if !matches_search(log_item, search, peer_names, peers) {
+                continue;
+            }
+        }
+
+        let timestamp_in_secs = secs_since_epoch(log_item.timestamp(), Arc::clone(&log));
+
+        //Dim everything from muted peers.
+        if log_item.peer_addr().ip().map_or(false, |ip| muted.contains(&ip)) {
+            queue!(out, SetAttribute(Attribute::Dim))?;
+        }
+
+        //Print the time.
+        let time = format!("[{}] ", clock.format_log_time(log_item.timestamp(), timestamp_in_secs, now));
+        queue!(out, style::Print(&time))?;
+
+        //Print the glyph for the kind of entry.
+        let (glyph, color) = log_item_glyph(log_item, ascii_only, palette);
+        if use_color {
+            queue!(out, SetForegroundColor(color), style::Print(glyph), ResetColor)?;
+        }
+        else {
+            queue!(out, style::Print(glyph))?;
+        }
+        queue!(out, style::Print(' '))?;
+        let mut x = start_x + time.chars().count() as u16 + 2;
+
+        //Depending on the packet, print different things.
         match &log_item {
             LogItem::ConnectLogItem { peer_addr, .. } => {
-                queue!(stdout,
+                queue!(out,
                     style::Print(
-                        format!("{} has successfully associated.", peer_addr.to_string())
+                        format!("{} has successfully associated.", peers.show(peer_addr))
                     )
                 )?;
                 queue!(
-                    stdout,
+                    out,
                     cursor::MoveDown(1),
                     cursor::MoveToColumn(start_x),
                 )?;
-
-                (_, y) = cursor::position().unwrap();
+                y += 1;
             },
-            LogItem::DisconnectLogItem { peer_addr, .. } => {
-                queue!(stdout,
+            LogItem::DisconnectLogItem { peer_addr, how, .. } => {
+                queue!(out,
                     style::Print(
-                        format!("{} has disconnected.", peer_addr.to_string())
+                        format!("{} {}", peers.show(peer_addr), disconnect_message(*how))
                     )
                 )?;
                 queue!(
-                    stdout,
+                    out,
                     cursor::MoveDown(1),
                     cursor::MoveToColumn(start_x),
                 )?;
-
-                (_, y) = cursor::position().unwrap();
+                y += 1;
             },
-            LogItem::PacketLogItem { peer_addr, packet, .. } => {
-                //Print the packet type.
-                queue!(stdout,
-                    style::Print(
-                        format!("{} | ", packet.packet_type.to_string())
-                    )
-                )?;
-
-                //Print the peer address/name.
-                //Look at this abomination. Rust, please.
-
-                let mut use_name = false;
-                let peer_name: &str;
-                //NAME packets always print the IP.
-                if let PacketType::Name = packet.packet_type {
-                    //Negation of if let statements not implemented yet.
+            //In the glyph's color too, as it's a warning about the log itself.
+            LogItem::GapLogItem { peer_addr, missed, .. } => {
+                let msg = format!("{} {}", peers.show(peer_addr), gap_message(*missed));
+                if use_color {
+                    queue!(out, SetForegroundColor(color), style::Print(msg), ResetColor)?;
                 }
                 else {
-                    let peer_name_option = peer_names.get(peer_addr);
-                    if peer_name_option.is_some() {
-                        use_name = true;
-                        peer_name = peer_name_option.unwrap();
-                        queue!(stdout,
-                            style::Print(
-                                format!("{} | ", peer_name)
-                            )
-                        )?;
-                    }
+                    queue!(out, style::Print(msg))?;
                 }
-
-                if !use_name {
-                    queue!(stdout,
-                        style::Print(
-                            format!("{} | ", peer_addr.to_string())
-                        )
-                    )?;
-                }
-
-                //Print the message text.
-                let default = "".to_string();
-                let msg = packet.text.as_ref().unwrap_or(&default).as_str();
-                let mut x;
-                (x, y) = cursor::position().unwrap();
-                for c in msg.chars() {
+                queue!(
+                    out,
+                    cursor::MoveDown(1),
+                    cursor::MoveToColumn(start_x),
+                )?;
+                y += 1;
+            },
+            LogItem::PacketLogItem { peer_addr, packet, .. } => {
+                //Print the packet type.
+                let packet_type = format!("{} | ", packet.packet_type.to_string());
+                queue!(out, style::Print(&packet_type))?;
+
+                //Print the peer address/name, and its labels if it has any.
+                let mut peer = peer_label(&packet.packet_type, peer_addr, peer_names, peers);
+                if let Some(labels) = peer_labels.get(peer_addr) {
+                    if packet.packet_type != PacketType::Metadata {
+                        peer.push_str(&format!(" [{}]", format_labels(labels)));
+                    }
+                }
+                let peer = format!("{} | ", peer);
+                queue!(out, style::Print(&peer))?;
+                x += (packet_type.chars().count() + peer.chars().count()) as u16;
+
+                //Print the message text. A METADATA packet's labels go on one line.
+                let mut msg = match packet.packet_type {
+                    PacketType::Metadata => format_labels(&parse_labels(packet.text.as_deref().unwrap_or("")).unwrap_or_default()),
+                    _ => sanitize_for_terminal(packet.text.as_deref().unwrap_or("")),
+                };
+                if packet.has_invalid_utf8 {
+                    msg = format!("{} {}", invalid_utf8_marker(ascii_only), msg);
+                }
+                //--max-display-len caps every entry; the packet keeps the full text.
+                let ellipsis = if ascii_only { "..." } else { "…" };
+                if let Some(max_display_len) = max_display_len {
+                    msg = truncate_with_ellipsis(msg, max_display_len, ellipsis);
+                }
+
+                //Compact density cuts the text off at the end of the line instead of wrapping.
+                let line_space = (cols - margin_x).saturating_sub(x) as usize;
+                if is_compact {
+                    msg = truncate_with_ellipsis(msg, line_space, ellipsis);
+                }
+                //Wrapped text stops at the bottom of the log area; say so rather than cutting it off
+                //mid-word with nothing to show there's more.
+                else {
+                    let lines_below = (rows - 3).saturating_sub(y) as usize;
+                    let space = line_space + lines_below * (cols - margin_x).saturating_sub(start_x) as usize;
+                    let marker = if ascii_only { "...[truncated]" } else { "…[truncated]" };
+                    let marker = if space >= marker.chars().count() { marker } else { ellipsis };
+                    msg = truncate_with_ellipsis(msg, space, marker);
+                }
+
+                for c in msg.chars() {
                     if x >= cols - margin_x {
                         if y > rows - 4 {
                             break;
                         }
                         queue!(
-                            stdout,
+                            out,
                             cursor::MoveDown(1),
                             cursor::MoveToColumn(start_x),
                         )?;
                         x = start_x;
                         y += 1;
                     }
-                    queue!(stdout, style::Print(c))?;
+                    queue!(out, style::Print(c))?;
                     x += 1;
                 }
                 queue!(
-                    stdout,
+                    out,
                     cursor::MoveDown(1),
                     cursor::MoveToColumn(start_x),
                 )?;
@@ -609,630 +1709,4204 @@ fn render_packet_log(packet_log: &VecDeque<LogItem>, warn_art_max_height: usize,
             },
         }
 
-        //Stop near the bottom of the screen.
-        if y > rows - 3 {
-            break;
+        queue!(out, SetAttribute(Attribute::NormalIntensity))?;
+
+        //Stop near the bottom of the screen.
+        if y > rows - 3 {
+            break;
+        }
+    }
+    queue!(out, style::ResetColor)?;
+
+    return Ok(y);
+}
+
+//Cut text down to at most max_chars characters, ending in the ellipsis if anything was cut. Counts
+//chars, not bytes, so a multibyte character is never split.
+fn truncate_with_ellipsis(text: String, max_chars: usize, ellipsis: &str) -> String {
+    if text.chars().count() <= max_chars {
+        return text;
+    }
+
+    let mut truncated: String = text.chars().take(max_chars.saturating_sub(ellipsis.chars().count())).collect();
+    truncated.push_str(ellipsis);
+    return truncated;
+}
+
+//Cut text down to at most max_len bytes, for what has to fit in a packet. Stops short of a multibyte
+//character rather than splitting it.
+fn truncate_to_bytes(text: &mut String, max_len: usize) {
+    if text.len() > max_len {
+        let mut end = max_len;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+}
+
+//How often, in seconds, the screensaver clock moves to a new spot.
+const SCREENSAVER_STEP_SECS: u64 = 10;
+
+//A small clock drifting around an otherwise blank screen: shows the display is alive without
+//burning anything in.
+fn render_screensaver(out: &mut impl Write, state: &State, cols: u16, rows: u16, log: Arc<Mutex<Log>>) -> io::Result<()> {
+    let timestamp_in_secs = secs_since_epoch(SystemTime::now(), log);
+    let clock = state.clock.format(timestamp_in_secs);
+
+    //Scatter the positions by multiplying the step with some large primes.
+    let step = state.screensaver_step;
+    let x = (step.wrapping_mul(7919) % (cols.saturating_sub(clock.len() as u16).max(1) as u64)) as u16;
+    let y = (step.wrapping_mul(104729) % (rows.max(1) as u64)) as u16;
+
+    queue!(out, cursor::MoveTo(x, y))?;
+    if state.use_color {
+        queue!(out, SetForegroundColor(Color::DarkGrey), style::Print(clock), ResetColor)?;
+    }
+    else {
+        queue!(out, style::Print(clock))?;
+    }
+
+    return Ok(());
+}
+
+//How many seconds the sparkline covers, one character each.
+const SPARKLINE_SECS: u64 = 30;
+
+//Count packets into one bucket per second, oldest first, ending with the current second.
+fn packet_rate(packet_times: &VecDeque<Instant>) -> Vec<u32> {
+    let mut buckets = vec![0; SPARKLINE_SECS as usize];
+    for time in packet_times {
+        let age = time.elapsed().as_secs();
+        if age < SPARKLINE_SECS {
+            buckets[(SPARKLINE_SECS - 1 - age) as usize] += 1;
+        }
+    }
+    return buckets;
+}
+
+//Draw the packet rate at the top right, clear of the ALERT border, scaled so the busiest second
+//is a full block. Seconds without packets are left blank.
+fn render_sparkline(out: &mut impl Write, packet_rate: &[u32], cols: u16, use_color: bool, ascii_only: bool) -> io::Result<()> {
+    let levels: Vec<char> = if ascii_only { ".:|".chars().collect() } else { "▁▂▃▄▅▆▇█".chars().collect() };
+    let max = packet_rate.iter().copied().max().unwrap_or(0);
+    let sparkline: String = packet_rate
+        .iter()
+        .map(|&count| {
+            if count == 0 {
+                return ' ';
+            }
+            //Round up, so a single packet still shows next to a busy second.
+            let level = (count as usize * levels.len()).div_ceil(max as usize);
+            return levels[level - 1];
+        })
+        .collect();
+
+    let x = cols.saturating_sub(5 + packet_rate.len() as u16);
+    queue!(out, cursor::MoveTo(x, 0))?;
+    if use_color {
+        queue!(out, SetForegroundColor(Color::Cyan), style::Print(sparkline), ResetColor)?;
+    }
+    else {
+        queue!(out, style::Print(sparkline))?;
+    }
+
+    return Ok(());
+}
+
+//With --host-counts, how many hosts are at ALERT and WARN on the channel on screen, in their
+//colors, below the other labels: one host's ALERT among many otherwise quiet ones reads differently
+//from half of them in trouble.
+fn render_host_counts(out: &mut impl Write, state: &State) -> io::Result<()> {
+    let (num_alert, num_warn) = state.host_counts;
+    //Wide enough for the longest label there can be, so it always covers the previous one.
+    queue!(out, cursor::MoveTo(0, 10), style::Print(" ".repeat("Hosts: 99999 ALERT 99999 WARN".len())), cursor::MoveTo(0, 10))?;
+    if num_alert == 0 && num_warn == 0 {
+        return Ok(());
+    }
+
+    queue!(out, style::Print("Hosts:"))?;
+    for (count, warn_state, default) in [(num_alert, WarnStates::Alert, Color::Red), (num_warn, WarnStates::Warn, Color::Yellow)] {
+        if count == 0 {
+            continue;
+        }
+        let label = format!(" {} {}", count, warn_state.to_string());
+        if state.use_color {
+            let color = state.palette().map_or(default, |palette| palette.color(&warn_state));
+            queue!(out, SetForegroundColor(color), style::Print(label), ResetColor)?;
+        }
+        else {
+            queue!(out, style::Print(label))?;
+        }
+    }
+    return Ok(());
+}
+
+//With --title, the dashboard's title along the top, between the ALERT border and the sparkline,
+//so dashboards side by side, or in screenshots, can be told apart. It never changes, so it's only
+//drawn on a full redraw.
+fn render_title(out: &mut impl Write, title: &str, cols: u16, use_color: bool, ascii_only: bool) -> io::Result<()> {
+    let start_x = 5;
+    let sparkline_x = cols.saturating_sub(5 + SPARKLINE_SECS as u16);
+    let ellipsis = if ascii_only { "..." } else { "…" };
+    let title = truncate_with_ellipsis(sanitize_for_terminal(title), sparkline_x.saturating_sub(start_x + 1) as usize, ellipsis);
+
+    queue!(out, cursor::MoveTo(start_x, 0))?;
+    if use_color {
+        queue!(out, SetAttribute(Attribute::Bold), style::Print(title), SetAttribute(Attribute::Reset))?;
+    }
+    else {
+        queue!(out, style::Print(title))?;
+    }
+    return Ok(());
+}
+
+//List the channels along the top, the one on screen in brackets, so an alert on another channel
+//isn't missed.
+fn render_channel_bar(out: &mut impl Write, state: &State, cols: u16) -> io::Result<()> {
+    //Clear of the ALERT border.
+    let start_x = 5;
+    queue!(out, cursor::MoveTo(start_x, 1))?;
+    for _x in start_x..cols.saturating_sub(start_x) {
+        queue!(out, style::Print(' '))?;
+    }
+    queue!(out, cursor::MoveTo(start_x, 1))?;
+
+    for (i, channel) in state.channels.iter().enumerate() {
+        let label;
+        if i == state.active_channel {
+            label = format!("[{}]", channel.name);
+        }
+        else {
+            label = format!(" {} ", channel.name);
+        }
+
+        if state.use_color && channel.warn_state != WarnStates::None {
+            let color = match state.palette() {
+                Some(palette) => palette.color(&channel.warn_state),
+                None if channel.warn_state == WarnStates::Critical => Color::Magenta,
+                None if channel.warn_state == WarnStates::Alert => Color::Red,
+                None => Color::Yellow,
+            };
+            queue!(out, SetForegroundColor(color), style::Print(label), ResetColor)?;
+        }
+        else {
+            queue!(out, style::Print(label))?;
+        }
+        if channel.warn_state != WarnStates::None {
+            queue!(out, style::Print(format!("({})", channel.warn_state.to_string())))?;
+        }
+        queue!(out, style::Print(' '))?;
+    }
+
+    return Ok(());
+}
+
+//Everything is drawn to out, which main points at stdout, at the size main gets from the terminal.
+//Any writer and size will do, e.g. a Vec<u8> to inspect the escape sequences a frame produces,
+//as nothing here queries the terminal.
+fn render(out: &mut impl Write, state: &State, render_state: &mut RenderState, cols: u16, rows: u16, log: Arc<Mutex<Log>>, frame_number: usize) -> io::Result<()> {
+    //Drawing mid-resize would only leave glyphs behind at positions computed for a stale size.
+    //update() schedules a full redraw once the size settles.
+    if state.resize_settling_since.is_some() {
+        return Ok(());
+    }
+
+    let channel = state.active();
+    let min_cols = state.warn_state_ascii_art.width(&channel.shown_warn_state) as u16 + 10;
+    let min_rows = state.warn_state_ascii_art.height(&channel.shown_warn_state) as u16 + 10;
+    if cols < min_cols || rows < min_rows {
+        writeln!(log.lock().unwrap(), "ERROR: ascii art is too large to render on terminal.").unwrap();
+        return Err(Error::new(
+            ErrorKind::Other,
+            "ERROR: ascii art is too large to render on terminal.",
+        ));
+    }
+
+    if render_state.clear_background {
+        queue!(
+            out,
+            terminal::Clear(terminal::ClearType::All),
+        )?;
+    }
+
+    //The saver replaces everything else, and is only redrawn when the clock moves.
+    if state.is_screensaver {
+        if render_state.clear_background {
+            render_screensaver(out, state, cols, rows, Arc::clone(&log))?;
+        }
+        out.flush()?;
+        *render_state = RenderState::new();
+        return Ok(());
+    }
+
+    //Print the ascii art representing the warn state.
+    render_warn_state(out, cols, rows, &state.warn_state_ascii_art, &channel.shown_warn_state, state.art_position, frame_number, channel.is_acknowledged, state.use_color)?;
+
+    //Print the border art when alert.
+    if channel.shown_warn_state >= WarnStates::Alert {
+        render_alert_border(out, cols, rows, frame_number, &state.warn_state_ascii_art, &channel.shown_warn_state, &state.border_chars, state.use_color)?;
+    }
+    else {
+        //Blank out the border if we have changed away from alert state.
+        //Unfortunately, this code also triggers when switching from NONE to WARN.
+        if render_state.warn_state_changed {
+            //Blank out the border.
+            for y in 0..rows {
+                let xs: [u16; 8] = [0, 1, 2, 3, cols-4, cols-3, cols-2, cols-1];
+                for x in xs {
+                    queue!(out, cursor::MoveTo(x, y), style::Print(' '))?;
+                }
+            }
+        }
+    }
+
+    if render_state.focused_mode_changed {
+        if state.is_focused_mode {
+            queue!(out, cursor::MoveTo(0, 5), style::Print("Focus!"))?;
+        }
+        else {
+            queue!(out, cursor::MoveTo(0, 5), style::Print("      "))?;
+        }
+    }
+
+    if render_state.min_state_changed {
+        if state.min_state != WarnStates::None {
+            queue!(out, cursor::MoveTo(0, 6), style::Print(format!("Floor: {:<8}", state.min_state.to_string())))?;
+        }
+        else {
+            queue!(out, cursor::MoveTo(0, 6), style::Print("               "))?;
+        }
+    }
+
+    if render_state.hints_changed {
+        render_hints(out, state, cols, rows)?;
+    }
+
+    if render_state.prompt_changed {
+        render_prompt(out, state, cols, rows)?;
+    }
+
+    if render_state.search_changed {
+        //Cut to SEARCH_LABEL_LEN, so blanking that much always covers the previous label.
+        let ellipsis = if state.ascii_only { "..." } else { "…" };
+        let label = match &state.search {
+            Some(search) => format!("Search: {}", truncate_with_ellipsis(sanitize_for_terminal(search), SEARCH_LABEL_LEN, ellipsis)),
+            None => String::new(),
+        };
+        queue!(out, cursor::MoveTo(0, 9), style::Print(format!("{:<width$}", label, width = "Search: ".len() + SEARCH_LABEL_LEN)))?;
+    }
+
+    if render_state.filter_changed {
+        if let Some((key, value)) = &state.filter {
+            let label = sanitize_for_terminal(&format!("Filter: {}={}", key, value));
+            if state.is_filtering {
+                queue!(out, cursor::MoveTo(0, 8), style::Print(label))?;
+            }
+            else {
+                queue!(out, cursor::MoveTo(0, 8), style::Print(" ".repeat(label.chars().count())))?;
+            }
+        }
+    }
+
+    //An acknowledged ALERT keeps its border, which blanks the start of the label every frame.
+    if render_state.warn_state_changed || channel.shown_warn_state >= WarnStates::Alert {
+        //Operator names are capped at MAX_NAME_LEN, so this always covers the previous label.
+        let label = match (channel.is_acknowledged, &channel.acknowledged_by) {
+            (true, Some(operator)) => format!("ACK by {}", operator),
+            (true, None) => "ACK".to_string(),
+            (false, _) => String::new(),
+        };
+        queue!(out, cursor::MoveTo(0, 7), style::Print(format!("{:<width$}", label, width = "ACK by ".len() + MAX_NAME_LEN)))?;
+    }
+
+    if let (Some(title), true) = (&state.title, render_state.clear_background) {
+        render_title(out, title, cols, state.use_color, state.ascii_only)?;
+    }
+
+    if render_state.channels_changed && state.channels.len() > 1 {
+        render_channel_bar(out, state, cols)?;
+    }
+
+    //The ALERT border blanks the columns the counts start in every frame, so they're redrawn over it
+    //for as long as it's up.
+    if (render_state.host_counts_changed || channel.shown_warn_state >= WarnStates::Alert) && state.is_showing_host_counts {
+        render_host_counts(out, state)?;
+    }
+
+    if render_state.sparkline_changed {
+        render_sparkline(out, &state.packet_rate, cols, state.use_color, state.ascii_only)?;
+    }
+
+    if render_state.packet_log_changed {
+        //A cleared screen has nothing left over to blank.
+        let previous_end = if render_state.clear_background { 0 } else { render_state.packet_log_end };
+        render_state.packet_log_end = render_packet_log(out, cols, rows, channel, state.log_view(), previous_end, Arc::clone(&log))?;
+    }
+
+    out.flush()?;
+
+    //It is implicit that render() will deal with every field in render_state if true,
+    //so to avoid manually tracking that we have dealt with everything, we simply create
+    //a new render_state where everything is false. Only packet_log_end carries over.
+    let packet_log_end = render_state.packet_log_end;
+    *render_state = RenderState::new();
+    render_state.packet_log_end = packet_log_end;
+
+    return Ok(());
+}
+
+use std::io::{Error, ErrorKind, Read, Write}; //Import the Read, Write traits for TcpStream.
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+//Packets can flip a warn state back and forth faster than it's worth drawing, each flip blanking or
+//redrawing the ALERT border. The art and border change at most once per this many frames, always
+//ending on the latest state.
+const WARN_STATE_SETTLE_FRAMES: u32 = 2;
+
+//How long the terminal size has to stay put before a resize is redrawn.
+const RESIZE_DEBOUNCE: Duration = Duration::from_millis(150);
+
+//Anything the protocol can run over. Only the timeouts and nodelay are needed beyond
+//Read + Write; streams without a notion of them (e.g. an in-memory pipe) can treat them as no-ops.
+trait Stream: Read + Write {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()>;
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()>;
+    //Another handle to write to the same connection, e.g. from another thread.
+    fn try_clone_writer(&self) -> io::Result<Box<dyn Write + Send>>;
+}
+
+impl Stream for TcpStream {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        return TcpStream::set_read_timeout(self, dur);
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        return TcpStream::set_write_timeout(self, dur);
+    }
+
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        return TcpStream::set_nodelay(self, nodelay);
+    }
+
+    fn try_clone_writer(&self) -> io::Result<Box<dyn Write + Send>> {
+        return Ok(Box::new(TcpStream::try_clone(self)?));
+    }
+}
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        return UnixStream::set_read_timeout(self, dur);
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        return UnixStream::set_write_timeout(self, dur);
+    }
+
+    //Unix domain sockets never batch writes.
+    fn set_nodelay(&self, _nodelay: bool) -> io::Result<()> {
+        return Ok(());
+    }
+
+    fn try_clone_writer(&self) -> io::Result<Box<dyn Write + Send>> {
+        return Ok(Box::new(UnixStream::try_clone(self)?));
+    }
+}
+
+fn handle_association<S: Stream>(connection: &mut S) -> Result<(), Error> {
+    //Connections must associate within the timeout or be dropped. The two bytes may arrive in
+    //separate reads, so the timeout is a deadline for both rather than for each read; otherwise a
+    //client could hold the connection open by trickling bytes.
+    let deadline = Instant::now() + Duration::from_millis(200);
+
+    let mut buf: [u8; 2] = [0; 2];
+    let mut num_bytes_read = 0;
+    while num_bytes_read < buf.len() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::new(ErrorKind::TimedOut, "Could not associate: client did not send an association request in time."));
+        }
+        connection
+            .set_read_timeout(Some(remaining))
+            .expect("No errors unless duration is 0.");
+
+        match connection.read(&mut buf[num_bytes_read..]) {
+            Ok(0) => {
+                //Drop the connection without logging anything - client disconnected for some reason.
+                return Err(Error::from(ErrorKind::UnexpectedEof));
+            }
+            Ok(n) => num_bytes_read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                //In the case of any other error - whether TimedOut or WouldBlock - drop the
+                //connection. Association is not expensive.
+                return Err(e);
+            }
+        }
+    }
+
+    //Okay, we got the two bytes of an association request from the client.
+    //If they sent more straight after, it's read as their first packet.
+
+    //Check that it *is* an association request.
+    if buf != ASSOC_REQUEST && buf != VERSIONED_ASSOC_REQUEST {
+        return Err(Error::new(
+            ErrorKind::Other,
+            "Could not associate: packet received from client was not an association request.",
+        ));
+    }
+
+    //Must send association accept, but timeout if the client suddenly decides to stop ACKing.
+    connection
+        .set_write_timeout(Some(Duration::from_millis(200)))
+        .expect("No errors unless duration is 0.");
+
+    //A versioned request gets the server version along with the accept.
+    let buf: Vec<u8> = if buf == VERSIONED_ASSOC_REQUEST {
+        let mut accept = vec![SERVER_VERSION.len() as u8 + 1, VERSIONED_ASSOC_ACCEPT];
+        accept.extend_from_slice(SERVER_VERSION.as_bytes());
+        accept
+    }
+    else {
+        ASSOC_ACCEPT.to_vec()
+    };
+    let num_bytes_wrote = match connection.write(&buf) {
+        Ok(0) => {
+            //Drop the connection without logging anything - socket is broken for some reason.
+            return Err(Error::from(ErrorKind::UnexpectedEof));
+        }
+        Ok(n) => n,
+        Err(e) => {
+            //In the case of any error - whether TimedOut, WouldBlock, even Interrupted - drop the
+            //connection. Association is not expensive.
+            return Err(e);
+        }
+    };
+
+    if num_bytes_wrote != buf.len() {
+        //If the server only manages to send one byte it should simply drop the connection and
+        //let the client retry association.
+        return Err(Error::new(
+            ErrorKind::Other,
+            "Could not associate: wrote incorrect num of bytes to client.",
+        ));
+    }
+
+    //Set back to blocking - associated clients CAN slow loris, unless handle_connection is given
+    //an --idle-timeout.
+    connection
+        .set_read_timeout(None)
+        .expect("No errors unless duration is 0.");
+    connection
+        .set_write_timeout(None)
+        .expect("No errors unless duration is 0.");
+
+    //We are associated! We can start receiving data!
+    return Ok(());
+}
+
+#[derive(Debug, Clone)]
+struct Packet {
+    packet_type: PacketType,
+    text: Option<String>,
+    //Only for LASTWILL packets: the type of packet to fire when the client drops.
+    //None on a LASTWILL cancels the will.
+    will_type: Option<PacketType>,
+    //When the client says the event happened, if it sent a timestamp.
+    timestamp: Option<SystemTime>,
+    //Whether the client wants an ACK once the packet is passed on.
+    wants_ack: bool,
+    //The packet's number, if the client numbers them.
+    sequence: Option<u32>,
+    //Whether the text had invalid UTF-8, replaced for display.
+    has_invalid_utf8: bool,
+}
+
+//Bytes as space-separated hex, e.g. 68 69 ff.
+fn hex_dump(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    return hex.join(" ");
+}
+
+fn handle_packet<S: Read>(connection: &mut S, peer_addr: &str, log: Arc<Mutex<Log>>, metrics: &Metrics, redactions: &Redactions, max_payload: Option<MaxPayload>) -> Result<Packet, Error> {
+    //Read exactly one byte from the kernel's read queue. The first byte of every packet is the
+    //length of the packet in total bytes. This prevents us from reading multiple packets from the
+    //queue at once.
+    let mut buf = PacketBuffer::new();
+    let num_bytes_read = match connection.read(buf.length_byte_mut()) {
+        Ok(0) => 0,
+        Ok(n) => n,
+        Err(e) => {
+            //In the case of any error - whether TimedOut, WouldBlock, even Interrupted - drop the
+            //connection.
+            //TODO: Make reading packets error-tolerant.
+            return Err(e);
+        }
+    };
+
+    // writeln!(log, "DEBUG: Received packet from {}.", peer_addr);
+
+    if num_bytes_read == 0 {
+        //The other side has closed the connection; terminate the thread.
+        writeln!(log.lock().unwrap(), "INFO: Closed connection to {peer_addr}: client disconnected.").unwrap();
+        return Err(Error::new(ErrorKind::Other, ClientClosed));
+    }
+
+    //                                          Add one back into num_bytes to get the true number.
+    //                                          v
+    //So the boundaries are:
+    //  [0]              -> 1 byte, no packet type: rejected below.
+    //  [1, 3]           -> 2 bytes, a bare WARN: the smallest valid packet, text is None.
+    //  [2, 3, b'x']     -> 3 bytes, a WARN with one byte of text.
+    //  [255, 3, ...]    -> 256 bytes, a WARN with 254 bytes of text: the most a packet can hold.
+    let num_bytes_in_packet = buf.len();
+    if num_bytes_in_packet == 1 {
+        //Ill-formed packet! The client is sending junk! Close the connection.
+        //Protocol does not handle single-byte packets.
+        //num_bytes_in_packet will never exceed MAX_PACKET_BYTES, as the length byte is only a u8.
+        writeln!(log.lock().unwrap(), "INFO: Closed connection to {peer_addr}: num_bytes_in_packet invalid, ({num_bytes_in_packet}).").unwrap();
+        metrics.count_rejected();
+        return Err(Error::new(
+            ErrorKind::Other,
+            "Invalid number of bytes declared by packet header.",
+        ));
+    }
+
+    // writeln!(log, "DEBUG: Packet reports it is {} bytes long.", num_bytes_in_packet);
+
+    //Good. We know how large the packet will be. Let's try to read the rest of it.
+    //The rest may arrive split across several reads (a 256-byte packet easily can be), so keep
+    //reading until it's all here or the client stops sending.
+    let mut num_bytes_read = 0;
+    while num_bytes_read < num_bytes_in_packet - 1 {
+        match connection.read(buf.rest_mut(num_bytes_read)) {
+            Ok(0) => break,
+            Ok(n) => num_bytes_read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                //In the case of any other error - whether TimedOut or WouldBlock - drop the
+                //connection.
+                //TODO: Make reading packets error-tolerant.
+                return Err(e);
+            }
+        }
+    }
+
+    // writeln!(log, "DEBUG: Successfully read {} more bytes of the packet.", num_bytes_read);
+
+    //                                 Plus one for the initial byte.
+    //                                         v
+    if num_bytes_in_packet != num_bytes_read + 1 {
+        //The client closed the connection partway through the packet.
+        writeln!(log.lock().unwrap(),
+            "INFO: Closed connection to {}: num_bytes_in_packet != total_num_bytes_read, ({} != {}).",
+            peer_addr,
+            num_bytes_in_packet,
+            num_bytes_read + 1
+        ).unwrap();
+        metrics.count_rejected();
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Num of bytes read does not match num of bytes declared in header by client."));
+    }
+
+    //The high bit of the type byte flags a timestamp following it, the next one a request for an
+    //ACK, the next a sequence number, and the next compressed text.
+    //Single-byte packets were rejected above, so there is always a type byte.
+    let type_byte = buf.byte(1).unwrap();
+    let has_timestamp = type_byte & TIMESTAMP_FLAG != 0;
+    let wants_ack = type_byte & ACK_FLAG != 0;
+    let has_sequence = type_byte & SEQUENCE_FLAG != 0;
+    let is_compressed = type_byte & COMPRESSED_FLAG != 0;
+    let packet_type_number = type_byte & !(TIMESTAMP_FLAG | ACK_FLAG | SEQUENCE_FLAG | COMPRESSED_FLAG);
+    let packet_type = match PacketType::from_type_number(packet_type_number) {
+        Ok(t) => t,
+        Err(e) => {
+            metrics.count_rejected();
+            return Err(e);
+        },
+    };
+
+    let mut fields_start = 2;
+    let mut timestamp: Option<SystemTime> = None;
+    if has_timestamp {
+        let timestamp_bytes = match buf.bytes(fields_start, 8) {
+            Some(b) => b,
+            None => {
+                writeln!(log.lock().unwrap(), "INFO: Closed connection to {peer_addr}: packet flagged a timestamp but is too short to hold one.").unwrap();
+                metrics.count_rejected();
+                return Err(Error::new(ErrorKind::Other, "Client sent a packet too short for its timestamp."));
+            },
+        };
+        let millis = u64::from_be_bytes(timestamp_bytes.try_into().unwrap());
+        //A timestamp too far out to represent is as good as none.
+        timestamp = UNIX_EPOCH.checked_add(Duration::from_millis(millis));
+        fields_start += 8;
+    }
+
+    let mut sequence: Option<u32> = None;
+    if has_sequence {
+        let sequence_bytes = match buf.bytes(fields_start, 4) {
+            Some(b) => b,
+            None => {
+                writeln!(log.lock().unwrap(), "INFO: Closed connection to {peer_addr}: packet flagged a sequence number but is too short to hold one.").unwrap();
+                metrics.count_rejected();
+                return Err(Error::new(ErrorKind::Other, "Client sent a packet too short for its sequence number."));
+            },
+        };
+        sequence = Some(u32::from_be_bytes(sequence_bytes.try_into().unwrap()));
+        fields_start += 4;
+    }
+
+    //Most packets are the header followed by optional text, but a LASTWILL has the type of its will
+    //packet before the text.
+    let mut will_type: Option<PacketType> = None;
+    let text_start;
+    match (packet_type, buf.byte(fields_start)) {
+        (PacketType::LastWill, Some(will_type_number)) => {
+            will_type = match PacketType::from_type_number(will_type_number) {
+                Ok(t @ (PacketType::Info | PacketType::Warn | PacketType::Alert | PacketType::Critical)) => Some(t),
+                _ => {
+                    writeln!(log.lock().unwrap(), "INFO: Closed connection to {peer_addr}: sent LASTWILL packet with invalid will type ({will_type_number}).").unwrap();
+                    metrics.count_rejected();
+                    return Err(Error::new(ErrorKind::Other, "Client sent LASTWILL packet with invalid will type."));
+                },
+            };
+            text_start = fields_start + 1;
+        },
+        _ => text_start = fields_start,
+    }
+
+    let packet_text: Option<String>;
+    //If the packet is longer than the fixed fields there is optional text. Compressed text is
+    //inflated before anything looks at it, --max-payload included.
+    let inflated: Vec<u8>;
+    let text_bytes = if is_compressed {
+        if !matches!(packet_type, PacketType::Info | PacketType::Warn | PacketType::Alert | PacketType::Critical) {
+            writeln!(log.lock().unwrap(), "INFO: Closed connection to {peer_addr}: sent {} packet flagged as compressed, which only INFO, WARN, ALERT and CRITICAL can be.", packet_type.to_string()).unwrap();
+            metrics.count_rejected();
+            return Err(Error::new(ErrorKind::Other, "Client sent a compressed packet of a type that can't be."));
+        }
+        inflated = match api::inflate_text(buf.bytes_from(text_start)) {
+            Some(text) => text,
+            None => {
+                writeln!(log.lock().unwrap(), "INFO: Closed connection to {peer_addr}: sent {} packet with compressed text that is malformed or inflates to over {} bytes.", packet_type.to_string(), api::MAX_COMPRESSED_MSG_LEN).unwrap();
+                metrics.count_rejected();
+                return Err(Error::new(ErrorKind::Other, "Client sent a packet with bad compressed text."));
+            },
+        };
+        &inflated[..]
+    }
+    else {
+        buf.bytes_from(text_start)
+    };
+    let max_payload = max_payload.filter(|_| packet_type != PacketType::Name && packet_type != PacketType::Metadata);
+    if let Some(MaxPayload { len, oversize: Oversize::Reject }) = max_payload {
+        if text_bytes.len() > len {
+            writeln!(log.lock().unwrap(), "INFO: Closed connection to {peer_addr}: sent {} packet with {} bytes of text, over --max-payload ({len}).", packet_type.to_string(), text_bytes.len()).unwrap();
+            metrics.count_rejected();
+            return Err(Error::new(ErrorKind::Other, "Client sent a packet over --max-payload."));
+        }
+    }
+    let mut has_invalid_utf8 = false;
+    if text_bytes.len() > 0 {
+        //Invalid UTF-8 is shown with replacement characters, but it may be a buggy or malicious
+        //client, so log what was actually sent.
+        let text = match std::str::from_utf8(text_bytes) {
+            Ok(text) => text.to_string(),
+            Err(_) => {
+                writeln!(log.lock().unwrap(), "WARN: {peer_addr} sent {} packet with invalid UTF-8 text, shown with replacement characters. The bytes were: {}", packet_type.to_string(), hex_dump(text_bytes)).unwrap();
+                has_invalid_utf8 = true;
+                String::from_utf8_lossy(text_bytes).into_owned()
+            },
+        };
+        //Labels are left alone, as a replacement could break their format.
+        if packet_type == PacketType::Metadata {
+            packet_text = Some(text);
+        }
+        else {
+            let mut text = redactions.apply(text);
+            //Cut after redacting, as a replacement can lengthen the text.
+            if let Some(MaxPayload { len, .. }) = max_payload {
+                if text.len() > len {
+                    writeln!(log.lock().unwrap(), "INFO: Truncated {} packet from {peer_addr} from {} to at most {len} bytes of text, per --max-payload.", packet_type.to_string(), text.len()).unwrap();
+                    truncate_to_bytes(&mut text, len);
+                }
+            }
+            packet_text = Some(text);
+        }
+        // writeln!(log, "DEBUG: Received text: {} of {} bytes.", packet_text.clone().unwrap(), packet_text.clone().unwrap().len();
+    } else {
+        packet_text = None;
+    }
+
+    let mut _log = log.lock().unwrap();
+    match packet_type {
+        PacketType::Info => {
+            if packet_text == None {
+                writeln!(_log, "INFO: Closed connection to {peer_addr}: sent INFO packet without text.").unwrap();
+                metrics.count_rejected();
+                return Err(Error::new(ErrorKind::Other, "Client sent INFO packet without text."));
+            }
+            write!(_log, "INFO: Received INFO packet from {peer_addr}").unwrap();
+        }
+        PacketType::Warn => {
+            write!(_log, "INFO: Received WARN packet from {peer_addr}").unwrap();
+        }
+        PacketType::Alert => {
+            write!(_log, "INFO: Received ALERT packet from {peer_addr}").unwrap();
+        }
+        PacketType::Critical => {
+            write!(_log, "INFO: Received CRITICAL packet from {peer_addr}").unwrap();
+        }
+        PacketType::Name => {
+            if packet_text == None {
+                writeln!(_log, "INFO: Closed connection to {peer_addr}: sent NAME packet without text.").unwrap();
+                metrics.count_rejected();
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    "Client sent NAME packet without text.",
+                ));
+            }
+            write!(_log, "INFO: Recieved NAME packet from {peer_addr}").unwrap();
+        }
+        PacketType::Reset => {
+            write!(_log, "INFO: Received RESET packet from {peer_addr}").unwrap();
+        }
+        PacketType::LastWill => {
+            if will_type == Some(PacketType::Info) && packet_text == None {
+                writeln!(_log, "INFO: Closed connection to {peer_addr}: sent LASTWILL packet with an INFO will without text.").unwrap();
+                metrics.count_rejected();
+                return Err(Error::new(ErrorKind::Other, "Client sent LASTWILL packet with an INFO will without text."));
+            }
+            match will_type {
+                Some(t) => write!(_log, "INFO: Received LASTWILL packet from {peer_addr} with a {} will", t.to_string()).unwrap(),
+                None => write!(_log, "INFO: Received LASTWILL packet from {peer_addr} cancelling its will").unwrap(),
+            }
+        }
+        PacketType::Metadata => {
+            if parse_labels(packet_text.as_deref().unwrap_or("")).is_none() {
+                writeln!(_log, "INFO: Closed connection to {peer_addr}: sent METADATA packet with malformed labels.").unwrap();
+                metrics.count_rejected();
+                return Err(Error::new(ErrorKind::Other, "Client sent METADATA packet with malformed labels."));
+            }
+            write!(_log, "INFO: Received METADATA packet from {peer_addr}").unwrap();
+        }
+    }
+
+    if packet_type == PacketType::Metadata && packet_text.is_some() {
+        //Keep the labels, separated by newlines, on one line of the log.
+        writeln!(_log, " with labels: {}.", packet_text.as_deref().unwrap().replace(api::LABEL_SEPARATOR, " ")).unwrap();
+    }
+    else if packet_text.is_some() {
+        writeln!(_log, " with text: \"{}\".", packet_text.as_deref().unwrap()).unwrap();
+    } else {
+        writeln!(_log, ".").unwrap();
+    }
+
+    metrics.count_packet(&packet_type);
+
+    return Ok(Packet {
+        packet_type: packet_type,
+        text: packet_text,
+        will_type: will_type,
+        timestamp: timestamp,
+        wants_ack: wants_ack,
+        sequence: sequence,
+        has_invalid_utf8: has_invalid_utf8,
+    });
+}
+
+//How long a broadcast waits on a client that has stopped reading before dropping it.
+const BROADCAST_WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+
+fn handle_connection<S: Stream + Send + 'static>(mut connection: S, peer_addr: Peer, tx: Sender<LogItem>, log: Arc<Mutex<Log>>, metrics: Arc<Metrics>, broadcast: Option<Arc<Broadcast>>, idle_timeout: Option<Duration>, redactions: Arc<Redactions>, max_payload: Option<MaxPayload>, pseudonyms: Option<Arc<Pseudonyms>>, resolver: Option<Arc<Resolver>>) {
+    //connection_thread handles the particulars of each connection,
+    //before sending out data through the channel to the main thread.
+    let _connection_thread = thread::spawn(move || {
+        let peer_addr_str = show_peer(&peer_addr, pseudonyms.as_deref());
+
+        //First, associate with the client without allocating state. A client that doesn't is
+        //dropped; one that hung up first isn't worth logging.
+        if let Err(e) = handle_association(&mut connection) {
+            if e.kind() != ErrorKind::UnexpectedEof {
+                writeln!(log.lock().unwrap(), "INFO: Dropped connection from {peer_addr_str}: {e}").unwrap();
+            }
+            return;
+        }
+
+        //ACKs and broadcasts are a couple of bytes each, and a client waiting on one shouldn't also
+        //wait for Nagle's algorithm to give up on batching it.
+        if let Err(e) = connection.set_nodelay(true) {
+            writeln!(log.lock().unwrap(), "WARN: Could not set TCP_NODELAY for {peer_addr_str}: {e}.").unwrap();
+        }
+
+        //Send a connection notice to the packet_log.
+        writeln!(log.lock().unwrap(), "INFO: Received connection from {peer_addr_str}.").unwrap();
+        metrics.client_connected();
+        //Before the connection shows up, so it's shown by name from the start. Its packets wait in
+        //the socket meanwhile.
+        let host_name = match (&resolver, peer_addr.ip()) {
+            (Some(resolver), Some(ip)) => resolver.resolve(ip, &peer_addr_str, &log),
+            _ => None,
+        };
+        let log_item = LogItem::ConnectLogItem {
+            timestamp: SystemTime::now(),
+            peer_addr: peer_addr,
+            host_name: host_name,
+        };
+        tx.send(log_item).expect("Unable to send on channel.");
+
+        //With --broadcast, the client also receives everyone else's WARNs and ALERTs. Those are
+        //written from other threads, so ACKs go through the same writer rather than connection.
+        let mut writer: Option<ClientWriter> = None;
+        if let Some(broadcast) = &broadcast {
+            connection
+                .set_write_timeout(Some(BROADCAST_WRITE_TIMEOUT))
+                .expect("No errors unless duration is 0.");
+            match connection.try_clone_writer() {
+                Ok(w) => {
+                    let w: ClientWriter = Arc::new(Mutex::new(w));
+                    broadcast.join(peer_addr, Arc::clone(&w));
+                    writer = Some(w);
+                },
+                Err(e) => {
+                    writeln!(log.lock().unwrap(), "ERROR: Could not broadcast to {peer_addr_str}: {}", e).unwrap();
+                },
+            }
+        }
+
+        //With --idle-timeout, a client that goes quiet for that long is dropped rather than holding
+        //its thread and socket forever. Any packet resets the clock.
+        if idle_timeout.is_some() {
+            connection
+                .set_read_timeout(idle_timeout)
+                .expect("No errors unless duration is 0.");
+        }
+
+        //Fired as if sent by the client when the connection drops, unless the client cancels it.
+        let mut last_will: Option<Packet> = None;
+        //The last sequence number the client sent, if it numbers its packets.
+        let mut last_sequence: Option<u32> = None;
+        //How the connection ended, once it has.
+        let mut how = Disconnect::Clean;
+
+        loop {
+            //Read exactly one packet from kernel's internal buffer and return it.
+            let packet = match handle_packet(&mut connection, &peer_addr_str, Arc::clone(&log), &metrics, &redactions, max_payload) {
+                Ok(p) => Some(p),
+                Err(e) => {
+                    how = Disconnect::from_error(&e);
+                    if idle_timeout.is_some() && (e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut) {
+                        writeln!(log.lock().unwrap(), "INFO: Closed connection to {peer_addr_str}: idle for {}s.", idle_timeout.unwrap().as_secs()).unwrap();
+                    }
+                    //A packet cut short is logged by handle_packet.
+                    else if how == Disconnect::Lost && e.kind() != ErrorKind::UnexpectedEof {
+                        writeln!(log.lock().unwrap(), "INFO: Lost connection to {peer_addr_str}: {}", e).unwrap();
+                    }
+                    None
+                },
+            };
+
+            //Send structured data from packet to main thread.
+            if packet.is_some() {
+                let packet = packet.unwrap();
+                if let Some(sequence) = packet.sequence {
+                    //The gap goes in the log before the packet that revealed it.
+                    if let Some(missed) = sequence_gap(last_sequence, sequence, &peer_addr_str, &log) {
+                        let log_item = LogItem::GapLogItem {
+                            timestamp: SystemTime::now(),
+                            peer_addr: peer_addr,
+                            missed: missed,
+                        };
+                        tx.send(log_item).expect("Unable to send on channel.");
+                    }
+                    last_sequence = Some(sequence);
+                }
+                if let PacketType::LastWill = packet.packet_type {
+                    last_will = packet.will_type.map(|will_type| Packet {
+                        packet_type: will_type,
+                        text: packet.text.clone(),
+                        will_type: None,
+                        timestamp: None,
+                        wants_ack: false,
+                        sequence: None,
+                        has_invalid_utf8: packet.has_invalid_utf8,
+                    });
+                }
+
+                if let Some(broadcast) = &broadcast {
+                    if let PacketType::Warn | PacketType::Alert | PacketType::Critical = packet.packet_type {
+                        broadcast.forward(peer_addr, packet.packet_type, packet.text.as_deref(), &log);
+                    }
+                }
+
+                let wants_ack = packet.wants_ack;
+                let log_item = LogItem::PacketLogItem {
+                    timestamp: SystemTime::now(),
+                    peer_addr: peer_addr,
+                    packet: packet,
+                };
+
+                tx.send(log_item).expect("Unable to send on channel.");
+
+                //Acknowledge once the packet is on its way to the screen. If the client has gone,
+                //the next read notices.
+                if wants_ack {
+                    let _ = match &writer {
+                        Some(writer) => writer.lock().unwrap().write_all(&ACK),
+                        None => connection.write_all(&ACK),
+                    };
+                }
+            } else {
+                if let Some(broadcast) = &broadcast {
+                    broadcast.leave(peer_addr);
+                }
+
+                if let Some(will) = last_will {
+                    writeln!(log.lock().unwrap(), "INFO: Firing the last will of {peer_addr_str}.").unwrap();
+                    if let Some(broadcast) = &broadcast {
+                        if let PacketType::Warn | PacketType::Alert | PacketType::Critical = will.packet_type {
+                            broadcast.forward(peer_addr, will.packet_type, will.text.as_deref(), &log);
+                        }
+                    }
+                    let log_item = LogItem::PacketLogItem {
+                        timestamp: SystemTime::now(),
+                        peer_addr: peer_addr,
+                        packet: will,
+                    };
+                    tx.send(log_item).expect("Unable to send on channel.");
+                }
+
+                //Send a disconnect notice to packet_log before exiting.
+                let log_item = LogItem::DisconnectLogItem {
+                    timestamp: SystemTime::now(),
+                    peer_addr: peer_addr,
+                    how: how,
+                };
+                tx.send(log_item).expect("Unable to send on channel.");
+                metrics.client_disconnected();
+                return;
+            }
+        }
+    });
+}
+
+//How many packets are missing between a client's last sequence number and this one. A number at
+//or below the last isn't a gap: the client started counting again, e.g. after a restart.
+fn sequence_gap(last_sequence: Option<u32>, sequence: u32, peer_addr: &str, log: &Arc<Mutex<Log>>) -> Option<u32> {
+    let last_sequence = last_sequence?;
+    let expected = last_sequence.wrapping_add(1);
+    if sequence == expected {
+        return None;
+    }
+    else if sequence > expected {
+        let missed = sequence - expected;
+        writeln!(log.lock().unwrap(), "WARN: {peer_addr} is missing {missed} packet(s): expected sequence number {expected}, got {sequence}.").unwrap();
+        return Some(missed);
+    }
+    else {
+        writeln!(log.lock().unwrap(), "INFO: {peer_addr} restarted its sequence numbers at {sequence}, after {last_sequence}.").unwrap();
+        return None;
+    }
+}
+
+//The protocol:
+//
+//HEADER:
+//[u8][u8]
+//  ^   ^----------------------------\
+//  |                                |
+//num_bytes (inclusive)          packet type
+//
+//NOTE: num_bytes is mapped to be one less than it actually is, i.e.
+//if num_bytes is 00000000, the true num_bytes is 1.
+//This is so that 11111111 represents 256 instead of 255.
+//You may conceptualize it as the number of bytes that follow the initial one.
+//A num_bytes of 00000000 is invalid, as there must be a packet type.
+//
+//The payload is optional, and depends on the packet type.
+//
+//If the high bit of the packet type is set, the payload starts with a timestamp: the time the
+//event happened, as a big-endian u64 of milliseconds since the Unix epoch. The rest of the payload
+//follows as usual. Without it, the time the packet is received is used.
+//
+//If the next bit is set, the server replies with an ACK once it has passed the packet on. Only
+//servers that answer a VERSIONED ASSOCIATION REQUEST with a version do this.
+//
+//If the bit after that is set, the payload carries a sequence number next, after any timestamp: a
+//big-endian u32 the client counts up by one with each packet. A jump in it means packets went
+//missing on the client's side, and is logged. Also only for servers that report a version.
+//
+//If the bit after that is set, the text, everything after the timestamp and sequence number, is
+//compressed with raw deflate, and may inflate to more than fits in a packet, up to 4096 bytes.
+//Only for INFO, WARN, ALERT and CRITICAL, and only servers from version 0.2.0 understand it.
+//
+//PACKET TYPES:
+//00000000 - ASSOCIATION REQUEST
+//00000001 - ASSOCIATION ACCEPT
+//00000010 - CLIENT INFO - text payload
+//00000011 - CLIENT WARN - optional text payload
+//00000100 - CLIENT ALERT - optional text payload
+//00000101 - CLIENT NAME CHANGE - text payload
+//00000110 - CLIENT RESET - no payload, any text is ignored
+//00000111 - CLIENT LAST WILL - optional payload of [will packet type][optional text]
+//  The server keeps the will (an INFO, WARN, ALERT or CRITICAL) and acts on it as if the client
+//  had sent it once the connection drops, for whatever reason. Sending a LAST WILL without payload
+//  cancels it.
+//00001000 - VERSIONED ASSOCIATION REQUEST - no payload
+//  Sent instead of an ASSOCIATION REQUEST by clients that want the server's version.
+//00001001 - VERSIONED ASSOCIATION ACCEPT - text payload of the server version, e.g. 0.1.0
+//  The reply to a VERSIONED ASSOCIATION REQUEST. Servers that predate it reply with a plain
+//  ASSOCIATION ACCEPT, so clients must handle both.
+//00001010 - ACK - no payload, sent by the server for packets flagged for one.
+//00001011 - CLIENT METADATA - optional payload of labels, key=value pairs separated by newlines
+//  e.g. env=prod\nservice=api. Keys are split off at the first = and can't be empty. Replaces
+//  the labels the client sent before; without payload it clears them.
+//00001100 - CLIENT CRITICAL - optional text payload
+//  Above ALERT, for catastrophic events. Only servers that report a version understand it.
+
+// use std::env;
+
+//Push the terminal's window title onto its title stack, and pop it back off. xterm and most
+//terminals that let a window title be set support these; the rest ignore them.
+const PUSH_WINDOW_TITLE: &str = "\x1b[22;0t";
+const POP_WINDOW_TITLE: &str = "\x1b[23;0t";
+
+struct WindowContext {
+    //Whether the window title was set, to be restored.
+    has_title: bool,
+}
+
+impl WindowContext {
+    fn new(title: Option<&str>) -> WindowContext {
+        terminal::enable_raw_mode().unwrap();
+        execute!(stdout(), terminal::EnterAlternateScreen).unwrap();
+        execute!(stdout(), terminal::Clear(terminal::ClearType::All)).unwrap();
+        execute!(stdout(), cursor::Hide).unwrap();
+        if let Some(title) = title {
+            execute!(stdout(), Print(PUSH_WINDOW_TITLE), terminal::SetTitle(sanitize_for_terminal(title))).unwrap();
+        }
+        return WindowContext { has_title: title.is_some() };
+    }
+}
+
+impl Drop for WindowContext {
+    fn drop(&mut self) {
+        terminal::disable_raw_mode().unwrap();
+        execute!(stdout(), terminal::LeaveAlternateScreen).unwrap();
+        execute!(stdout(), cursor::Show).unwrap();
+        if self.has_title {
+            execute!(stdout(), Print(POP_WINDOW_TITLE)).unwrap();
+        }
+    }
+}
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use chrono::{DateTime, Local};
+
+//The system clock can be stepped backwards (NTP, VM resume) to before the epoch. An alerting
+//dashboard shouldn't crash over it, so treat such a time as zero and note it in the log.
+fn secs_since_epoch(time: SystemTime, log: Arc<Mutex<Log>>) -> u64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => {
+            writeln!(log.lock().unwrap(), "WARN: System clock is set before the Unix epoch; using 0 as the timestamp.").unwrap();
+            0
+        },
+    }
+}
+
+//Whether packet log entries show when they happened, how long ago, or both; set by --time-display.
+#[derive(Copy, Clone, PartialEq)]
+enum TimeDisplay {
+    Absolute,
+    Relative,
+    Both,
+}
+
+//How times are shown in the packet log and on the screensaver: in the local timezone unless
+//--utc, and 24-hour unless --clock 12.
+#[derive(Copy, Clone)]
+struct Clock {
+    is_utc: bool,
+    is_12_hour: bool,
+    //Only the packet log follows this; the screensaver always shows the time.
+    time_display: TimeDisplay,
+}
+
+//How long ago something happened, to the largest whole unit: "5s ago", "2m ago", "3h ago", "4d ago".
+//Anything under a second ago, or in the future from a client with a fast clock, is "just now".
+fn format_age(age: Option<Duration>) -> String {
+    let secs = match age {
+        Some(age) => age.as_secs(),
+        None => 0,
+    };
+
+    if secs == 0 {
+        return "just now".to_string();
+    }
+    if secs < 60 {
+        return format!("{}s ago", secs);
+    }
+    if secs < 60 * 60 {
+        return format!("{}m ago", secs / 60);
+    }
+    if secs < 60 * 60 * 24 {
+        return format!("{}h ago", secs / (60 * 60));
+    }
+    return format!("{}d ago", secs / (60 * 60 * 24));
+}
+
+impl Clock {
+    //A packet log entry's time, as --time-display asks. `now` is taken once per render, so every
+    //entry's age is measured from the same instant.
+    fn format_log_time(&self, timestamp: SystemTime, secs_since_epoch: u64, now: SystemTime) -> String {
+        let age = now.duration_since(timestamp).ok();
+        match self.time_display {
+            TimeDisplay::Absolute => return self.format(secs_since_epoch),
+            TimeDisplay::Relative => return format_age(age),
+            TimeDisplay::Both => return format!("{}, {}", self.format(secs_since_epoch), format_age(age)),
+        }
+    }
+
+    fn format(&self, secs_since_epoch: u64) -> String {
+        //A client can send a timestamp far past anything chrono can represent; show the epoch.
+        let utc: DateTime<chrono::Utc> = i64::try_from(secs_since_epoch).ok()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .unwrap_or_default();
+        let format = if self.is_12_hour { "%I:%M %p" } else { "%H:%M" };
+
+        if self.is_utc {
+            return utc.format(format).to_string();
+        }
+        return utc.with_timezone(&Local).format(format).to_string();
+    }
+}
+
+//Who a connection is from. Unix domain socket clients have no address of their own, so they are
+//numbered in the order they connected.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum Peer {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(u64),
+}
+
+use std::fmt;
+
+impl Peer {
+    //Mutes go by IP, as a client's port changes every time it reconnects.
+    fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Peer::Tcp(addr) => Some(addr.ip()),
+            #[cfg(unix)]
+            Peer::Unix(_) => None,
+        }
+    }
+
+    //The peer without its port, which stays the same as a client reconnects.
+    fn host(&self) -> Peer {
+        match self {
+            Peer::Tcp(addr) => Peer::Tcp(SocketAddr::new(addr.ip(), 0)),
+            #[cfg(unix)]
+            Peer::Unix(n) => Peer::Unix(*n),
+        }
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Peer::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Peer::Unix(n) => write!(f, "unix#{}", n),
+        }
+    }
+}
+
+enum LogItem {
+    PacketLogItem {
+        //When the packet arrived. timestamp() prefers when the client says it was sent.
+        timestamp: SystemTime,
+        peer_addr: Peer,
+        packet: Packet,
+    },
+    ConnectLogItem {
+        timestamp: SystemTime,
+        peer_addr: Peer,
+        //With --resolve-peers, the client's host name, if it has one.
+        host_name: Option<String>,
+    },
+    DisconnectLogItem {
+        timestamp: SystemTime,
+        peer_addr: Peer,
+        how: Disconnect,
+    },
+    //Packets a client numbered but never sent us, going by the gap in its sequence numbers.
+    GapLogItem {
+        timestamp: SystemTime,
+        peer_addr: Peer,
+        missed: u32,
+    },
+}
+
+//How a connection ended. A monitored host dropping off without closing its connection is news in
+//itself, e.g. it crashed or lost its network, so it's shown apart from one that said goodbye.
+#[derive(Clone, Copy, PartialEq)]
+enum Disconnect {
+    //The client closed the connection between packets.
+    Clean,
+    //The connection was reset or failed under us.
+    Lost,
+    //We closed it: the client sent junk, or went quiet past --idle-timeout.
+    Dropped,
+}
+
+//How the packet log words a disconnect, after the peer.
+fn disconnect_message(how: Disconnect) -> &'static str {
+    return match how {
+        Disconnect::Clean => "has disconnected.",
+        Disconnect::Lost => "lost its connection.",
+        Disconnect::Dropped => "was disconnected.",
+    };
+}
+
+//How the packet log words a gap in a client's sequence numbers, after the peer.
+fn gap_message(missed: u32) -> String {
+    if missed == 1 {
+        return "is missing a packet: its sequence numbers skipped one.".to_string();
+    }
+    return format!("is missing {} packets: its sequence numbers skipped them.", missed);
+}
+
+//Marks the error handle_packet returns when the client closes the connection cleanly, so it can be
+//told from the errors of a connection that failed.
+#[derive(Debug)]
+struct ClientClosed;
+
+impl fmt::Display for ClientClosed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Client closed the connection.")
+    }
+}
+
+impl std::error::Error for ClientClosed {}
+
+impl Disconnect {
+    //What an error from handle_packet says about how the connection ended. handle_packet's own
+    //rejections are ErrorKind::Other; the rest come from the socket.
+    fn from_error(e: &Error) -> Self {
+        if e.get_ref().map_or(false, |inner| inner.is::<ClientClosed>()) {
+            return Disconnect::Clean;
+        }
+        match e.kind() {
+            ErrorKind::Other | ErrorKind::WouldBlock | ErrorKind::TimedOut => return Disconnect::Dropped,
+            _ => return Disconnect::Lost,
+        }
+    }
+}
+
+impl LogItem {
+    fn peer_addr(&self) -> &Peer {
+        match self {
+            LogItem::PacketLogItem { peer_addr, .. } => peer_addr,
+            LogItem::ConnectLogItem { peer_addr, .. } => peer_addr,
+            LogItem::DisconnectLogItem { peer_addr, .. } => peer_addr,
+            LogItem::GapLogItem { peer_addr, .. } => peer_addr,
+        }
+    }
+
+    //When the entry happened, as shown and exported. Prefers the time the client says a packet was
+    //sent, e.g. for alerts it held on to while it couldn't reach us.
+    fn timestamp(&self) -> SystemTime {
+        match self {
+            LogItem::PacketLogItem { timestamp, packet, .. } => packet.timestamp.unwrap_or(*timestamp),
+            LogItem::ConnectLogItem { timestamp, .. } => *timestamp,
+            LogItem::DisconnectLogItem { timestamp, .. } => *timestamp,
+            LogItem::GapLogItem { timestamp, .. } => *timestamp,
+        }
+    }
+
+    //When the entry reached us, whatever time a client put on it.
+    fn received_at(&self) -> SystemTime {
+        match self {
+            LogItem::PacketLogItem { timestamp, .. } => *timestamp,
+            LogItem::ConnectLogItem { timestamp, .. } => *timestamp,
+            LogItem::DisconnectLogItem { timestamp, .. } => *timestamp,
+            LogItem::GapLogItem { timestamp, .. } => *timestamp,
+        }
+    }
+
+    //How important the entry is to keep around: CRITICAL over ALERT over WARN over everything else.
+    fn severity(&self) -> u8 {
+        match self {
+            LogItem::PacketLogItem { packet, .. } => match packet.packet_type {
+                PacketType::Critical => 3,
+                PacketType::Alert => 2,
+                PacketType::Warn => 1,
+                _ => 0,
+            },
+            //A lost packet may have been a WARN or ALERT itself.
+            LogItem::GapLogItem { .. } => 1,
+            _ => 0,
+        }
+    }
+}
+
+//With --max-payload, the most bytes of text a client may send in INFO, WARN, ALERT and LASTWILL
+//packets, and what --oversize says to do with a packet over it. NAME and METADATA packets have
+//limits of their own.
+#[derive(Copy, Clone)]
+struct MaxPayload {
+    len: usize,
+    oversize: Oversize,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Oversize {
+    //Keep the packet, cut to the limit.
+    Truncate,
+    //Refuse the packet and close the connection, as for any other malformed packet.
+    Reject,
+}
+
+//Which entries to drop once the packet log is over --log-limit.
+#[derive(Copy, Clone, PartialEq)]
+enum Retention {
+    //The oldest entry, whatever it is.
+    Fifo,
+    //The oldest entry of the lowest severity in the log, so a flood of INFO can't push out an
+    //older ALERT.
+    Severity,
+}
+
+//The packet log is newest first, so the oldest entries are at the back.
+fn trim_packet_log(packet_log: &mut VecDeque<LogItem>, log_limit: usize, retention: Retention) {
+    while packet_log.len() > log_limit {
+        let evict = match retention {
+            Retention::Fifo => packet_log.len() - 1,
+            Retention::Severity => {
+                let lowest = packet_log.iter().map(|item| item.severity()).min().unwrap();
+                packet_log.iter().rposition(|item| item.severity() == lowest).unwrap()
+            },
+        };
+        packet_log.remove(evict);
+    }
+}
+
+//An independent warn state and packet log, fed by its own listener. Without --channel there is
+//just the one.
+struct Channel {
+    name: String,
+    warn_state: WarnStates,
+    packet_log: VecDeque<LogItem>,
+    peer_names: HashMap<Peer, String>,
+    //The labels each client last sent in a METADATA packet, in the order it sent them.
+    peer_labels: HashMap<Peer, Vec<(String, String)>>,
+    //When each packet of the last SPARKLINE_SECS arrived, oldest first.
+    packet_times: VecDeque<Instant>,
+    //The warn state on screen, which trails warn_state while it's changing quickly, and when it
+    //last changed. See WARN_STATE_SETTLE_FRAMES.
+    shown_warn_state: WarnStates,
+    shown_since: Option<Instant>,
+    //Whether the operator has acknowledged the current WARN or ALERT with a, and who they said
+    //they were. Any new WARN or ALERT, or a change of state, clears it.
+    is_acknowledged: bool,
+    acknowledged_by: Option<String>,
+    //With --escalate, when each host's recent WARNs arrived, oldest first, at most one more than
+    //the count. Reset clears them.
+    warn_times: HashMap<Peer, VecDeque<SystemTime>>,
+    //The warn state --on-alert and --on-reset last saw, to run them only on a change.
+    hooked_state: WarnStates,
+    //The most severe state each host has raised, by Peer::host, for --host-counts. warn_state is the
+    //highest of these, or higher through --min-state. A host stays counted until the channel is
+    //reset, as there's no telling when its problem went away, except the watchdog, which stops
+    //counting once every missing client is back.
+    host_states: HashMap<Peer, WarnStates>,
+}
+
+impl Channel {
+    fn new(name: String, warn_state: WarnStates) -> Self {
+        return Channel {
+            name: name,
+            warn_state: warn_state,
+            packet_log: VecDeque::new(),
+            peer_names: HashMap::new(),
+            peer_labels: HashMap::new(),
+            shown_warn_state: warn_state,
+            shown_since: None,
+            packet_times: VecDeque::new(),
+            is_acknowledged: false,
+            acknowledged_by: None,
+            warn_times: HashMap::new(),
+            hooked_state: warn_state,
+            host_states: HashMap::new(),
+        };
+    }
+}
+
+//With --escalate, more than count WARNs from one host within window raise an ALERT, so a drumbeat
+//of warnings pages someone without the client having to decide to.
+#[derive(Clone, Copy)]
+struct Escalation {
+    count: usize,
+    window: Duration,
+}
+
+impl Escalation {
+    //Note a WARN from a host, received at time, and whether that makes one too many within the
+    //window. Packets queue up between frames, so it goes by when they arrived rather than were seen,
+    //but not by when the client says it sent them: that would let a client's clock put any number
+    //of WARNs in one window, or out of order.
+    fn is_crossed(&self, warn_times: &mut VecDeque<SystemTime>, time: SystemTime) -> bool {
+        warn_times.push_back(time);
+        while warn_times.len() > self.count + 1 || warn_times.front().map_or(false, |first| time.duration_since(*first).unwrap_or_default() > self.window) {
+            warn_times.pop_front();
+        }
+        return warn_times.len() > self.count;
+    }
+}
+
+//A client that --expect says must be heard from at least every interval. Any packet sent under its
+//name counts.
+struct Expectation {
+    name: String,
+    interval: Duration,
+    last_seen: Instant,
+    //The channel it was last heard on, where its alarm is raised. The first channel until then.
+    channel_index: usize,
+    is_missing: bool,
+    //While missing, the warn state its channel had before the watchdog raised it, to go back to
+    //once it's heard from again. None if another ALERT has come in since, so that isn't cleared.
+    restore_state: Option<WarnStates>,
+}
+
+impl Expectation {
+    fn new(name: String, interval: Duration) -> Self {
+        return Expectation {
+            name: name,
+            interval: interval,
+            last_seen: Instant::now(),
+            channel_index: 0,
+            is_missing: false,
+            restore_state: None,
+        };
+    }
+}
+
+struct State {
+    channels: Vec<Channel>,
+    //The channel on screen.
+    active_channel: usize,
+    //Resets never take a channel's warn_state below this; packets can still raise it higher.
+    min_state: WarnStates,
+    warn_state_ascii_art: WarnStateAsciiArt,
+    window_should_close: bool,
+    //The most entries each packet log holds, if capped, and which to drop when it's full.
+    log_limit: Option<usize>,
+    retention: Retention,
+    //Peers whose packets are logged but never raise the warn state.
+    muted: HashSet<IpAddr>,
+    //Clients watched for missed heartbeats.
+    expected: Vec<Expectation>,
+    escalation: Option<Escalation>,
+    hooks: Hooks,
+    //The label the packet log is narrowed to with --filter, and whether that's on; l toggles it.
+    filter: Option<(String, String)>,
+    is_filtering: bool,
+    //Packets per second on the channel on screen, as last drawn by the sparkline.
+    packet_rate: Vec<u32>,
+    //With --host-counts, how many hosts are at ALERT and WARN on the channel on screen, as last drawn.
+    is_showing_host_counts: bool,
+    //With --anonymize-peers, what client IPs are shown and logged as, and whether p has revealed
+    //the real ones on screen.
+    pseudonyms: Option<Arc<Pseudonyms>>,
+    is_revealing_peers: bool,
+    //With --resolve-peers, the host names of client IPs that have one, and with --short-ipv6,
+    //whether long IPv6 addresses are cut short on screen.
+    host_names: HashMap<IpAddr, String>,
+    is_short_ipv6: bool,
+    host_counts: (usize, usize),
+    //With --title, what this dashboard is called, shown at the top.
+    title: Option<String>,
+
+    is_focused_mode: bool,
+    //With --confirm-quit-on-alert, q or Esc during an ALERT asks first.
+    confirm_quit_on_alert: bool,
+    input_mode: InputMode,
+    //What / last searched the packet log for, if the search is still on.
+    search: Option<String>,
+    //A message for the prompt row, e.g. where a snapshot was saved, and when it was shown.
+    notice: Option<(String, Instant)>,
+    //The keybinding hints footer, and when it hides by itself if it's still up from startup.
+    is_showing_hints: bool,
+    hints_hide_at: Option<Instant>,
+    art_position: ArtPosition,
+    //One line per packet log entry, truncating long text, rather than wrapping it.
+    is_compact_log: bool,
+    //Packet text is cut to this many characters on screen, whatever the density.
+    max_display_len: Option<usize>,
+    use_color: bool,
+    //Whether --palette was given. Without it only the art and the ALERT border use the art colors;
+    //the default INFO color is too dark to read as text.
+    has_palette: bool,
+    border_chars: Vec<char>,
+    //Only ASCII is ever drawn: art is transliterated and the border uses the ascii preset.
+    ascii_only: bool,
+    clock: Clock,
+    //Who acknowledgements are made in the name of, from --operator.
+    operator: Option<String>,
+    //What e and --export-on-exit write the packet log as.
+    export_format: ExportFormat,
+
+    metrics: Arc<Metrics>,
+    //Consumers of --event-socket.
+    #[cfg(unix)]
+    event_stream: Option<events::EventStream>,
+    //The SIGTERM or SIGINT that asked ww to quit, if one has, else 0.
+    #[cfg(unix)]
+    caught_signal: Arc<AtomicUsize>,
+
+    //How long update() waits for input before rendering anyway, derived from --fps.
+    poll_interval: Duration,
+
+    //Blank the screen but for a clock after this long with nothing happening and all channels at
+    //NONE, if set.
+    screensaver_after: Option<Duration>,
+    last_activity: Instant,
+    is_screensaver: bool,
+    //Which position the screensaver clock is in; changes every SCREENSAVER_STEP_SECS.
+    screensaver_step: u64,
+    //The second the packet log's relative times were last drawn in, with --time-display relative
+    //or both, so they're redrawn as they age.
+    relative_time_step: u64,
+
+    //When the last of a burst of resize events arrived, if the terminal is still being resized.
+    resize_settling_since: Option<Instant>,
+
+    //Where the art was loaded from, for reloading. None means the default art.
+    info_art_path: Option<String>,
+    warn_art_path: Option<String>,
+    alert_art_path: Option<String>,
+    critical_art_path: Option<String>,
+}
+
+impl State {
+    fn active(&self) -> &Channel {
+        return &self.channels[self.active_channel];
+    }
+
+    fn active_mut(&mut self) -> &mut Channel {
+        return &mut self.channels[self.active_channel];
+    }
+
+    //The pseudonyms to show peers as on screen, unless p has revealed the real addresses.
+    fn shown_pseudonyms(&self) -> Option<&Pseudonyms> {
+        return self.pseudonyms.as_deref().filter(|_| !self.is_revealing_peers);
+    }
+
+    fn peer_display(&self) -> PeerDisplay<'_> {
+        return PeerDisplay {
+            pseudonyms: self.shown_pseudonyms(),
+            host_names: &self.host_names,
+            is_short_ipv6: self.is_short_ipv6,
+            ascii_only: self.ascii_only,
+        };
+    }
+
+    fn log_view(&self) -> LogView<'_> {
+        return LogView {
+            warn_art_max_height: self.warn_state_ascii_art.max_height(),
+            art_position: self.art_position,
+            filter: self.filter.as_ref().filter(|_| self.is_filtering),
+            search: self.search.as_deref(),
+            muted: &self.muted,
+            peers: self.peer_display(),
+            is_compact: self.is_compact_log,
+            max_display_len: self.max_display_len,
+            use_color: self.use_color,
+            ascii_only: self.ascii_only,
+            palette: self.palette(),
+            clock: self.clock,
+        };
+    }
+
+    //The art colors, if --palette set them for the rest of the UI to follow too.
+    fn palette(&self) -> Option<&WarnStateAsciiArt> {
+        if self.has_palette {
+            return Some(&self.warn_state_ascii_art);
+        }
+        return None;
+    }
+}
+
+struct RenderState {
+    focused_mode_changed: bool,
+    min_state_changed: bool,
+    filter_changed: bool,
+    prompt_changed: bool,
+    search_changed: bool,
+    host_counts_changed: bool,
+    hints_changed: bool,
+    channels_changed: bool,
+    warn_state_changed: bool,
+    packet_log_changed: bool,
+    sparkline_changed: bool,
+
+    //For when everything needs to be re-rendered e.g. on resize.
+    clear_background: bool,
+
+    //The row below the last one the packet log drew on, so the next render of it only blanks
+    //what it left behind.
+    packet_log_end: u16,
+}
+
+impl RenderState {
+    fn new() -> Self {
+        return RenderState {
+            focused_mode_changed: false,
+            min_state_changed: false,
+            filter_changed: false,
+            prompt_changed: false,
+            search_changed: false,
+            host_counts_changed: false,
+            hints_changed: false,
+            channels_changed: false,
+            warn_state_changed: false,
+            packet_log_changed: false,
+            sparkline_changed: false,
+
+            clear_background: false,
+
+            packet_log_end: 0,
+        };
+    }
+
+    fn rerender_all() -> Self {
+        return RenderState {
+            focused_mode_changed: true,
+            min_state_changed: true,
+            filter_changed: true,
+            prompt_changed: true,
+            search_changed: true,
+            host_counts_changed: true,
+            hints_changed: true,
+            channels_changed: true,
+            warn_state_changed: true,
+            packet_log_changed: true,
+            sparkline_changed: true,
+
+            clear_background: true,
+
+            packet_log_end: 0,
+        };
+    }
+}
+
+//The frame rate is a tradeoff: packets and the clock show up once per frame, so a low rate is
+//sluggish, but every frame is a full wakeup and render even on an idle dashboard.
+const DEFAULT_FPS: u64 = 2;
+const MIN_FPS: u64 = 1;
+const MAX_FPS: u64 = 60;
+//While an ALERT is on screen, frames come at least this often whatever --fps, so each step of the
+//animation is drawn as soon as it is due. It's only while there's an ALERT to look at that the CPU
+//is worth spending.
+const ALERT_FPS: u64 = 30;
+//The animation advances one step per tick, however often frames are rendered, as it was tuned at
+//2 fps. Stepping once per frame would run it faster at a higher frame rate, or on every keypress.
+const ANIMATION_TICK: Duration = Duration::from_millis(500);
+
+//The animation step to render at now, for an animation that was at first_step at started.
+fn animation_step(first_step: usize, started: Instant, now: Instant) -> usize {
+    let ticks = now.saturating_duration_since(started).as_millis() / ANIMATION_TICK.as_millis();
+    return first_step.wrapping_add(ticks as usize);
+}
+
+//Read art from the file at path, or from stdin if path is "-".
+fn load_art(path: &str) -> io::Result<String> {
+    if path == "-" {
+        let mut art = String::new();
+        io::stdin().read_to_string(&mut art)?;
+        return Ok(art);
+    }
+    return std::fs::read_to_string(path);
+}
+
+//Replace every non-ASCII character in art for --ascii-only: box-drawing and block glyphs with
+//their nearest ASCII lookalike, anything else with '?'. Returns the art and how many characters
+//were substituted.
+fn to_ascii(art: &str) -> (String, usize) {
+    let mut ascii = String::with_capacity(art.len());
+    let mut num_substituted = 0;
+    for c in art.chars() {
+        if c.is_ascii() {
+            ascii.push(c);
+            continue;
+        }
+        num_substituted += 1;
+        ascii.push(match c {
+            '─' | '━' | '═' | '╌' | '╍' | '┄' | '┅' => '-',
+            '│' | '┃' | '║' | '╎' | '╏' | '┆' | '┇' => '|',
+            '┌' | '┐' | '└' | '┘' | '├' | '┤' | '┬' | '┴' | '┼'
+                | '╔' | '╗' | '╚' | '╝' | '╠' | '╣' | '╦' | '╩' | '╬'
+                | '┏' | '┓' | '┗' | '┛' | '╭' | '╮' | '╯' | '╰' => '+',
+            '█' | '▓' | '▒' | '▀' | '▄' | '▌' | '▐' => '#',
+            '░' => '.',
+            '•' | '·' => '*',
+            '‘' | '’' => '\'',
+            '“' | '”' => '"',
+            '–' | '—' => '-',
+            '\u{a0}' => ' ',
+            _ => '?',
+        });
+    }
+    return (ascii, num_substituted);
+}
+
+//For --check-art: print each art's size and any problems with it. raw_arts are the arts as loaded,
+//before build() padded them, so ragged lines can still be found. Returns the number of problems.
+fn check_art(art: &WarnStateAsciiArt, raw_arts: [(WarnStates, &Option<String>, &str); 4], check_size: Option<(u16, u16)>) -> usize {
+    let mut num_problems = 0;
+    for (warn_state, path, raw_art) in raw_arts {
+        let width = art.width(&warn_state);
+        let height = art.height(&warn_state);
+        let name = match warn_state {
+            WarnStates::None => "info",
+            WarnStates::Warn => "warn",
+            WarnStates::Alert => "alert",
+            WarnStates::Critical => "critical",
+        };
+        println!("{} art ({}): {}x{}", name, path.as_deref().unwrap_or("default"), width, height);
+
+        let ragged_lines: Vec<String> = raw_art.lines()
+            .enumerate()
+            .filter(|(_, line)| line.chars().count() != width)
+            .map(|(i, _)| (i + 1).to_string())
+            .collect();
+        if ragged_lines.len() > 0 {
+            println!("  Not rectangular: line(s) {} are narrower than {} and would be padded with spaces.", ragged_lines.join(", "), width);
+            num_problems += 1;
+        }
+
+        //Same bounds render() enforces.
+        if let Some((cols, rows)) = check_size {
+            if width + 10 > cols as usize || height + 10 > rows as usize {
+                println!("  Too large for {}x{}: needs at least {}x{}.", cols, rows, width + 10, height + 10);
+                num_problems += 1;
+            }
+        }
+    }
+
+    if num_problems == 0 {
+        println!("OK");
+    }
+    else {
+        println!("{} problem(s) found.", num_problems);
+    }
+    return num_problems;
+}
+
+//For --list-colors: print the states --palette takes with their default colors, and the
+//--border-chars presets, each with a sample. Both come from the tables the parsers use.
+fn list_colors(use_color: bool, ascii_only: bool) -> io::Result<()> {
+    let mut out = stdout();
+    let swatch = if ascii_only { "######" } else { "██████" };
+    let defaults = WarnStateAsciiArt::new();
+
+    writeln!(out, "Palette states, for --palette <State>:#RRGGBB,...:")?;
+    for (name, warn_state) in PALETTE_STATES {
+        let color = defaults.color(&warn_state);
+        let hex = match color {
+            Color::Rgb { r, g, b } => format!("#{:02X}{:02X}{:02X}", r, g, b),
+            _ => format!("{:?}", color),
+        };
+        write!(out, "  {:<10}{}", name, hex)?;
+        if use_color {
+            queue!(out, Print("  "), SetForegroundColor(color), Print(swatch), ResetColor)?;
+        }
+        writeln!(out)?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "Border presets, for --border-chars <Preset>:")?;
+    for (name, glyphs) in BORDER_PRESETS {
+        if ascii_only && !glyphs.is_ascii() {
+            writeln!(out, "  {:<10}(not ASCII, so --ascii-only uses ascii instead)", name)?;
+        }
+        else {
+            writeln!(out, "  {:<10}{}", name, glyphs)?;
+        }
+    }
+    writeln!(out, "Any other --border-chars value is used as the glyphs themselves.")?;
+    return out.flush();
+}
+
+//Re-read the art files given on the command line, so art can be iterated on without restarting
+//and losing the packet log. Art read from stdin can't be re-read and is kept as is.
+//If the new art can't be read or is too large for the terminal the previous art is kept.
+fn reload_art(state: &mut State, render_state: &mut RenderState, log: Arc<Mutex<Log>>) -> io::Result<()> {
+    let current = &state.warn_state_ascii_art;
+    let art_paths = [
+        (&state.info_art_path, &current.info_art),
+        (&state.warn_art_path, &current.warn_art),
+        (&state.alert_art_path, &current.alert_art),
+        (&state.critical_art_path, &current.critical_art),
+    ];
+
+    let mut new_arts: Vec<String> = Vec::new();
+    for (path, current_art) in art_paths {
+        let art = match path {
+            Some(path) if path != "-" => match std::fs::read_to_string(path) {
+                Ok(art) => art,
+                Err(e) => {
+                    writeln!(log.lock().unwrap(), "ERROR: Could not reload art from {}: {}. Keeping the previous art.", path, e).unwrap();
+                    return Ok(());
+                },
+            },
+            _ => current_art.clone(),
+        };
+        new_arts.push(art);
+    }
+
+    if state.ascii_only {
+        let mut num_substituted = 0;
+        for art in new_arts.iter_mut() {
+            let (ascii, n) = to_ascii(art);
+            *art = ascii;
+            num_substituted += n;
+        }
+        if num_substituted > 0 {
+            writeln!(log.lock().unwrap(), "WARN: --ascii-only: substituted {} non-ASCII characters in the reloaded art.", num_substituted).unwrap();
+        }
+    }
+
+    let critical_art = new_arts.pop().unwrap();
+    let alert_art = new_arts.pop().unwrap();
+    let warn_art = new_arts.pop().unwrap();
+    let info_art = new_arts.pop().unwrap();
+    let new_art = WarnStateAsciiArt::build_with_color(
+        info_art,
+        warn_art,
+        alert_art,
+        critical_art,
+        current.info_color,
+        current.warn_color,
+        current.alert_color,
+        current.critical_color,
+    );
+
+    //Same bounds render() enforces, checked for every state so we can't switch into art that won't fit.
+    let (cols, rows) = terminal::size()?;
+    if new_art.max_width() as u16 + 10 > cols || new_art.max_height() as u16 + 10 > rows {
+        writeln!(log.lock().unwrap(), "ERROR: Reloaded art is too large to render on terminal. Keeping the previous art.").unwrap();
+        return Ok(());
+    }
+
+    writeln!(log.lock().unwrap(), "INFO: Reloaded art.").unwrap();
+    state.warn_state_ascii_art = new_art;
+    *render_state = RenderState::rerender_all();
+
+    return Ok(());
+}
+
+//The connection_manager thread lives as long as main.
+//It never exits, and continually handles incoming connections.
+fn listen_tcp(bind_addr: &str, listening_port: u16, tx: Sender<LogItem>, log: Arc<Mutex<Log>>, metrics: Arc<Metrics>, broadcast: Option<Arc<Broadcast>>, idle_timeout: Option<Duration>, redactions: Arc<Redactions>, max_payload: Option<MaxPayload>, pseudonyms: Option<Arc<Pseudonyms>>, resolver: Option<Arc<Resolver>>) {
+    let bind_addr = bind_addr.to_string();
+    let _connection_manager = thread::spawn(move || {
+        let listener = TcpListener::bind((bind_addr.as_str(), listening_port)).unwrap();
+
+        for connection in listener.incoming() {
+            //peer_addr only fails if the client has already gone again.
+            match connection.and_then(|c| Ok((c.peer_addr()?, c))) {
+                Ok((peer_addr, c)) => handle_connection(c, Peer::Tcp(peer_addr), tx.clone(), Arc::clone(&log), Arc::clone(&metrics), broadcast.clone(), idle_timeout, Arc::clone(&redactions), max_payload, pseudonyms.clone(), resolver.clone()),
+                Err(e) => {
+                    writeln!(log.lock().unwrap(), "ERROR: {}", e).unwrap();
+                }
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+fn listen_unix(path: &str, tx: Sender<LogItem>, log: Arc<Mutex<Log>>, metrics: Arc<Metrics>, broadcast: Option<Arc<Broadcast>>, idle_timeout: Option<Duration>, redactions: Arc<Redactions>, max_payload: Option<MaxPayload>, pseudonyms: Option<Arc<Pseudonyms>>) -> io::Result<()> {
+    //A socket file left behind by a previous run would make bind fail.
+    use std::os::unix::fs::FileTypeExt;
+    if std::fs::symlink_metadata(path).map_or(false, |m| m.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+
+    let _connection_manager = thread::spawn(move || {
+        let mut num_connections: u64 = 0;
+        for connection in listener.incoming() {
+            match connection {
+                Ok(c) => {
+                    num_connections += 1;
+                    handle_connection(c, Peer::Unix(num_connections), tx.clone(), Arc::clone(&log), Arc::clone(&metrics), broadcast.clone(), idle_timeout, Arc::clone(&redactions), max_payload, pseudonyms.clone(), None);
+                },
+                Err(e) => {
+                    writeln!(log.lock().unwrap(), "ERROR: {}", e).unwrap();
+                }
+            }
+        }
+    });
+
+    return Ok(());
+}
+
+const SERVER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+//The most events --event-history replays to a new consumer.
+const MAX_EVENT_HISTORY: usize = 256;
+
+use std::fs::File;
+use std::sync::Arc;
+use std::sync::Mutex;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{VecDeque, HashMap, HashSet};
+use std::env;
+
+fn main() -> io::Result<()> {
+    // env::set_var("RUST_BACKTRACE", "1");
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.iter().any(|arg| arg == "--help") {
+        print_usage();
+        std::process::exit(0);
+    }
+
+    if args.iter().any(|arg| arg == "--version") {
+        println!("ww {}", SERVER_VERSION);
+        std::process::exit(0);
+    }
+
+    //Refuse misspelled options, like --alrt-art, rather than silently running without them. The
+    //error goes after the usage, so it isn't scrolled out of sight.
+    let args = Args::parse(args).unwrap_or_else(|e| {
+        print_usage();
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    //Printed before the terminal is set up, so the list isn't lost to the alternate screen.
+    if args.is_set("--list-colors") {
+        let use_color = !args.is_set("--no-color")
+            && env::var_os("NO_COLOR").map_or(true, |v| v.is_empty());
+        list_colors(use_color, args.is_set("--ascii-only"))?;
+        std::process::exit(0);
+    }
+
+    let listening_port: u16;
+    if let Some(value) = args.value_or_env("-p") {
+        listening_port = value.parse().unwrap_or_else(|_| {
+            print_usage();
+            std::process::abort();
+        });
+    }
+    else {
+        listening_port = DEFAULT_PORT;
+    }
+
+    //Every --channel, in order. Without any there is one channel on listening_port.
+    let mut channel_specs: Vec<(String, u16)> = Vec::new();
+    for value in args.values("--channel") {
+        let (name, port) = value.rsplit_once(':').unwrap_or_else(|| {
+            print_usage();
+            std::process::abort();
+        });
+        let port: u16 = port.parse().unwrap_or_else(|_| {
+            print_usage();
+            std::process::abort();
+        });
+        channel_specs.push((name.to_string(), port));
+    }
+    if channel_specs.is_empty() {
+        channel_specs.push(("main".to_string(), listening_port));
+    }
+
+    let mut expected: Vec<Expectation> = Vec::new();
+    for value in args.values("--expect") {
+        let (name, secs) = value.rsplit_once(':').unwrap_or_else(|| {
+            print_usage();
+            std::process::abort();
+        });
+        let secs: u64 = match secs.parse() {
+            Ok(secs) if secs > 0 => secs,
+            _ => {
+                print_usage();
+                std::process::abort();
+            },
+        };
+        expected.push(Expectation::new(name.to_string(), Duration::from_secs(secs)));
+    }
+
+    let escalation: Option<Escalation>;
+    if let Some(value) = args.value("--escalate") {
+        let parsed = value.split_once(':').and_then(|(count, secs)| Some((count.parse::<usize>().ok()?, secs.parse::<u64>().ok()?)));
+        escalation = match parsed {
+            Some((count, secs)) if count > 0 && secs > 0 => Some(Escalation { count: count, window: Duration::from_secs(secs) }),
+            _ => {
+                print_usage();
+                std::process::abort();
+            },
+        };
+    }
+    else {
+        escalation = None;
+    }
+
+    //Every --redact, applied in order.
+    let mut redactions = Redactions::new();
+    for value in args.values("--redact") {
+        if let Err(e) = redactions.add(value) {
+            eprintln!("--redact: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let redactions = Arc::new(redactions);
+
+    let filter: Option<(String, String)>;
+    if let Some(value) = args.value("--filter") {
+        //The same syntax as a label in a METADATA packet.
+        filter = match parse_labels(value).as_deref() {
+            Some([label]) => Some(label.clone()),
+            _ => {
+                print_usage();
+                std::process::abort();
+            },
+        };
+    }
+    else {
+        filter = None;
+    }
+
+    let idle_timeout: Option<Duration>;
+    if let Some(value) = args.value("--idle-timeout") {
+        idle_timeout = match value.parse() {
+            Ok(secs) if secs > 0 => Some(Duration::from_secs(secs)),
+            _ => {
+                print_usage();
+                std::process::abort();
+            },
+        };
+    }
+    else {
+        idle_timeout = None;
+    }
+    //An --expect'ed client only has to be heard from once per interval, so it mustn't be dropped
+    //as idle in between.
+    if let Some(idle_timeout) = idle_timeout {
+        if let Some(expectation) = expected.iter().find(|expectation| expectation.interval > idle_timeout) {
+            eprintln!("--idle-timeout must be at least as long as every --expect interval, but {} is expected every {}s.", expectation.name, expectation.interval.as_secs());
+            std::process::exit(1);
+        }
+    }
+
+    let fps: u64;
+    if let Some(value) = args.value("--fps") {
+        fps = value.parse().unwrap_or_else(|_| {
+            print_usage();
+            std::process::abort();
+        });
+    }
+    else {
+        fps = DEFAULT_FPS;
+    }
+    let fps = fps.clamp(MIN_FPS, MAX_FPS);
+
+    let min_state: WarnStates;
+    if let Some(value) = args.value("--min-state") {
+        min_state = match value {
+            "none" => WarnStates::None,
+            "warn" => WarnStates::Warn,
+            "alert" => WarnStates::Alert,
+            "critical" => WarnStates::Critical,
+            _ => {
+                print_usage();
+                std::process::abort();
+            },
+        };
+    }
+    else {
+        min_state = WarnStates::None;
+    }
+
+    let mut muted: HashSet<IpAddr> = HashSet::new();
+    if let Some(value) = args.value("--mute") {
+        for addr in value.split(',') {
+            muted.insert(addr.trim().parse().unwrap_or_else(|_| {
+                print_usage();
+                std::process::abort();
+            }));
+        }
+    }
+
+    let is_compact_log: bool;
+    if let Some(value) = args.value("--log-density") {
+        is_compact_log = match value {
+            "full" => false,
+            "compact" => true,
+            _ => {
+                print_usage();
+                std::process::abort();
+            },
+        };
+    }
+    else {
+        is_compact_log = false;
+    }
+
+    let max_display_len: Option<usize>;
+    if let Some(value) = args.value("--max-display-len") {
+        max_display_len = match value.parse() {
+            Ok(0) | Err(_) => {
+                print_usage();
+                std::process::abort();
+            },
+            Ok(n) => Some(n),
+        };
+    }
+    else {
+        max_display_len = None;
+    }
+
+    let max_payload: Option<MaxPayload>;
+    if let Some(value) = args.value("--max-payload") {
+        let len = match value.parse() {
+            Ok(n) if n > 0 && n <= MAX_MSG_LEN => n,
+            _ => {
+                print_usage();
+                std::process::abort();
+            },
+        };
+        let oversize = match args.value("--oversize") {
+            Some("truncate") | None => Oversize::Truncate,
+            Some("reject") => Oversize::Reject,
+            Some(_) => {
+                print_usage();
+                std::process::abort();
+            },
+        };
+        max_payload = Some(MaxPayload { len: len, oversize: oversize });
+    }
+    else {
+        max_payload = None;
+    }
+
+    let operator: Option<String>;
+    if let Some(value) = args.value("--operator") {
+        if value.len() >= MAX_NAME_LEN || value.chars().any(|c| c.is_control()) {
+            print_usage();
+            std::process::abort();
+        }
+        operator = Some(value.to_string());
+    }
+    else {
+        operator = None;
+    }
+
+    let is_12_hour;
+    if let Some(value) = args.value("--clock") {
+        is_12_hour = match value {
+            "24" => false,
+            "12" => true,
+            _ => {
+                print_usage();
+                std::process::abort();
+            },
+        };
+    }
+    else {
+        is_12_hour = false;
+    }
+    let time_display;
+    if let Some(value) = args.value("--time-display") {
+        time_display = match value {
+            "absolute" => TimeDisplay::Absolute,
+            "relative" => TimeDisplay::Relative,
+            "both" => TimeDisplay::Both,
+            _ => {
+                print_usage();
+                std::process::abort();
+            },
+        };
+    }
+    else {
+        time_display = TimeDisplay::Absolute;
+    }
+    let clock = Clock {
+        is_utc: args.is_set("--utc"),
+        is_12_hour: is_12_hour,
+        time_display: time_display,
+    };
+
+    let log_limit: Option<usize>;
+    if let Some(value) = args.value("--log-limit") {
+        log_limit = Some(value.parse().unwrap_or_else(|_| {
+            print_usage();
+            std::process::abort();
+        }));
+    }
+    else {
+        log_limit = None;
+    }
+
+    let retention: Retention;
+    if let Some(value) = args.value("--retention") {
+        retention = match value {
+            "fifo" => Retention::Fifo,
+            "severity" => Retention::Severity,
+            _ => {
+                print_usage();
+                std::process::abort();
+            },
+        };
+    }
+    else {
+        retention = Retention::Fifo;
+    }
+
+    let export_format = match args.value("--export-format") {
+        Some("csv") | None => ExportFormat::Csv,
+        Some("json") => ExportFormat::Json,
+        Some(_) => {
+            print_usage();
+            std::process::abort();
+        },
+    };
+
+    let unix_path: Option<String>;
+    if let Some(value) = args.value("--unix") {
+        unix_path = Some(value.to_string());
+    }
+    else {
+        unix_path = None;
+    }
+    #[cfg(not(unix))]
+    if unix_path.is_some() {
+        eprintln!("--unix is only supported on Unix.");
+        std::process::exit(1);
+    }
+
+    let event_socket_path: Option<String>;
+    if let Some(value) = args.value("--event-socket") {
+        event_socket_path = Some(value.to_string());
+    }
+    else {
+        event_socket_path = None;
+    }
+    #[cfg(not(unix))]
+    if event_socket_path.is_some() {
+        eprintln!("--event-socket is only supported on Unix.");
+        std::process::exit(1);
+    }
+
+    let event_history: usize;
+    if let Some(value) = args.value("--event-history") {
+        event_history = match value.parse() {
+            Ok(n) if n <= MAX_EVENT_HISTORY => n,
+            _ => {
+                print_usage();
+                std::process::abort();
+            },
+        };
+    }
+    else {
+        event_history = 0;
+    }
+
+    let metrics_port: Option<u16>;
+    if let Some(value) = args.value("--metrics-port") {
+        metrics_port = Some(value.parse().unwrap_or_else(|_| {
+            print_usage();
+            std::process::abort();
+        }));
+    }
+    else {
+        metrics_port = None;
+    }
+    #[cfg(not(feature = "metrics"))]
+    if metrics_port.is_some() {
+        eprintln!("--metrics-port requires ww to be built with the \"metrics\" feature.");
+        std::process::exit(1);
+    }
+
+    let use_syslog = args.is_set("--syslog");
+    #[cfg(not(feature = "syslog"))]
+    if use_syslog {
+        eprintln!("--syslog requires ww to be built with the \"syslog\" feature.");
+        std::process::exit(1);
+    }
+    let no_log = args.is_set("--no-log");
+    let log_path = args.value_or_env("--log").unwrap_or_else(|| "./warning_window.log".to_string());
+    let bind_addr = args.value_or_env("--bind").unwrap_or_else(|| "localhost".to_string());
+    let is_broadcast = args.is_set("--broadcast");
+
+    let mut replay_events;
+    if let Some(value) = args.value("--replay") {
+        let contents = std::fs::read_to_string(value).unwrap_or_else(|_| {
+            print_usage();
+            std::process::abort();
+        });
+        replay_events = match replay::parse(&contents) {
+            Ok(events) => Some(events),
+            Err(e) => {
+                eprintln!("Could not parse replay file {}: {}.", value, e);
+                std::process::exit(1);
+            },
+        };
+    }
+    else {
+        replay_events = None;
+    }
+
+    let ascii_only = args.is_set("--ascii-only");
+
+    let mut border_chars;
+    if let Some(value) = args.value("--border-chars") {
+        border_chars = parse_border_chars(value);
+    }
+    else {
+        border_chars = parse_border_chars("");
+    }
+    if ascii_only && border_chars.iter().any(|c| !c.is_ascii()) {
+        border_chars = parse_border_chars("ascii");
+    }
+
+    let palette: Option<Vec<(WarnStates, Color)>>;
+    if let Some(value) = args.value("--palette") {
+        palette = Some(parse_palette(value).unwrap_or_else(|| {
+            print_usage();
+            std::process::abort();
+        }));
+    }
+    else {
+        palette = None;
+    }
+
+    let art_position: ArtPosition;
+    if let Some(value) = args.value("--art-position") {
+        art_position = match value {
+            "top" => ArtPosition::Top,
+            "center" => ArtPosition::Center,
+            "fifth" => ArtPosition::Fifth,
+            custom => match custom.strip_prefix("custom:").map(|row| row.parse()) {
+                Some(Ok(row)) => ArtPosition::Row(row),
+                _ => {
+                    print_usage();
+                    std::process::abort();
+                },
+            },
+        };
+    }
+    else {
+        art_position = ArtPosition::Fifth;
+    }
+
+    let screensaver_after: Option<Duration>;
+    if let Some(value) = args.value("--screensaver-after") {
+        screensaver_after = Some(Duration::from_secs(value.parse().unwrap_or_else(|_| {
+            print_usage();
+            std::process::abort();
+        })));
+    }
+    else {
+        screensaver_after = None;
+    }
+
+    let seed: Option<usize>;
+    if let Some(value) = args.value("--seed") {
+        seed = Some(value.parse().unwrap_or_else(|_| {
+            print_usage();
+            std::process::abort();
+        }));
+    }
+    else {
+        seed = None;
+    }
+
+    let info_art;
+    let mut info_art_path = None;
+    if let Some(value) = args.value("--info-art") {
+        info_art_path = Some(value.to_string());
+        info_art = load_art(value).unwrap_or_else(|_| {
+            print_usage();
+            std::process::abort();
+        });
+    }
+    else {
+        info_art = WarnStateAsciiArt::default_info_art();
+    }
+
+    let warn_art;
+    let mut warn_art_path = None;
+    if let Some(value) = args.value("--warn-art") {
+        warn_art_path = Some(value.to_string());
+        warn_art = load_art(value).unwrap_or_else(|_| {
+            print_usage();
+            std::process::abort();
+        });
+    }
+    else {
+        warn_art = WarnStateAsciiArt::default_warn_art();
+    }
+
+    let alert_art;
+    let mut alert_art_path = None;
+    if let Some(value) = args.value("--alert-art") {
+        alert_art_path = Some(value.to_string());
+        alert_art = load_art(value).unwrap_or_else(|_| {
+            print_usage();
+            std::process::abort();
+        });
+    }
+    else {
+        alert_art = WarnStateAsciiArt::default_alert_art();
+    }
+
+    let critical_art;
+    let mut critical_art_path = None;
+    if let Some(value) = args.value("--critical-art") {
+        critical_art_path = Some(value.to_string());
+        critical_art = load_art(value).unwrap_or_else(|_| {
+            print_usage();
+            std::process::abort();
+        });
+    }
+    else {
+        critical_art = WarnStateAsciiArt::default_critical_art();
+    }
+
+    let mut num_substituted = 0;
+    let (info_art, warn_art, alert_art, critical_art) = if ascii_only {
+        let (info_art, n_info) = to_ascii(&info_art);
+        let (warn_art, n_warn) = to_ascii(&warn_art);
+        let (alert_art, n_alert) = to_ascii(&alert_art);
+        let (critical_art, n_critical) = to_ascii(&critical_art);
+        num_substituted = n_info + n_warn + n_alert + n_critical;
+        (info_art, warn_art, alert_art, critical_art)
+    }
+    else {
+        (info_art, warn_art, alert_art, critical_art)
+    };
+
+    let raw_arts = [info_art.clone(), warn_art.clone(), alert_art.clone(), critical_art.clone()];
+    let mut warn_state_ascii_art = WarnStateAsciiArt::build(info_art, warn_art, alert_art, critical_art);
+
+    //Validate the art and exit, before the log file, the listeners or the terminal are touched.
+    if args.is_set("--check-art") {
+        let check_size: Option<(u16, u16)>;
+        if let Some(value) = args.value("--check-size") {
+            check_size = Some(value
+                .split_once('x')
+                .and_then(|(cols, rows)| Some((cols.parse().ok()?, rows.parse().ok()?)))
+                .unwrap_or_else(|| {
+                    print_usage();
+                    std::process::abort();
+                }));
+        }
+        else {
+            check_size = None;
+        }
+
+        let num_problems = check_art(&warn_state_ascii_art, [
+            (WarnStates::None, &info_art_path, &raw_arts[0]),
+            (WarnStates::Warn, &warn_art_path, &raw_arts[1]),
+            (WarnStates::Alert, &alert_art_path, &raw_arts[2]),
+            (WarnStates::Critical, &critical_art_path, &raw_arts[3]),
+        ], check_size);
+        std::process::exit(if num_problems == 0 { 0 } else { 1 });
+    }
+    if let Some(palette) = &palette {
+        for (warn_state, color) in palette {
+            match warn_state {
+                WarnStates::None => warn_state_ascii_art.info_color = *color,
+                WarnStates::Warn => warn_state_ascii_art.warn_color = *color,
+                WarnStates::Alert => warn_state_ascii_art.alert_color = *color,
+                WarnStates::Critical => warn_state_ascii_art.critical_color = *color,
+            }
+        }
+    }
+
+    //Honor NO_COLOR (https://no-color.org): any non-empty value disables color.
+    let use_color = !args.is_set("--no-color")
+        && env::var_os("NO_COLOR").map_or(true, |v| v.is_empty());
+
+    let mut state = State {
+        channels: channel_specs.iter().map(|(name, _)| Channel::new(name.clone(), min_state)).collect(),
+        active_channel: 0,
+        min_state: min_state,
+        warn_state_ascii_art: warn_state_ascii_art,
+        window_should_close: false,
+        log_limit: log_limit,
+        retention: retention,
+        muted: muted,
+        expected: expected,
+        escalation: escalation,
+        hooks: Hooks { on_alert: args.value("--on-alert").map(str::to_string), on_reset: args.value("--on-reset").map(str::to_string) },
+        is_filtering: filter.is_some(),
+        filter: filter,
+        packet_rate: Vec::new(),
+        is_showing_host_counts: args.is_set("--host-counts"),
+        pseudonyms: if args.is_set("--anonymize-peers") { Some(Arc::new(Pseudonyms::new())) } else { None },
+        is_revealing_peers: false,
+        host_names: HashMap::new(),
+        is_short_ipv6: args.is_set("--short-ipv6"),
+        title: args.value("--title").map(str::to_string),
+        host_counts: (0, 0),
+
+        is_focused_mode: false,
+        confirm_quit_on_alert: args.is_set("--confirm-quit-on-alert"),
+        input_mode: InputMode::Normal,
+        search: None,
+        notice: None,
+        is_showing_hints: !args.is_set("--no-hints"),
+        hints_hide_at: if args.is_set("--no-hints") { None } else { Some(Instant::now() + HINTS_SHOWN_FOR) },
+        art_position: art_position,
+        is_compact_log: is_compact_log,
+        max_display_len: max_display_len,
+        use_color: use_color,
+        has_palette: palette.is_some(),
+        border_chars: border_chars,
+        ascii_only: ascii_only,
+        clock: clock,
+        operator: operator,
+        export_format: export_format,
+
+        metrics: Arc::new(Metrics::new()),
+        #[cfg(unix)]
+        event_stream: None,
+        #[cfg(unix)]
+        caught_signal: Arc::new(AtomicUsize::new(0)),
+
+        poll_interval: Duration::from_millis(1000 / fps),
+
+        resize_settling_since: None,
+
+        screensaver_after: screensaver_after,
+        last_activity: Instant::now(),
+        is_screensaver: false,
+        screensaver_step: 0,
+        relative_time_step: 0,
+
+        info_art_path: info_art_path,
+        warn_art_path: warn_art_path,
+        alert_art_path: alert_art_path,
+        critical_art_path: critical_art_path,
+    };
+    let mut render_state = RenderState::rerender_all();
+
+    let log_file = if no_log {
+        None
+    }
+    else {
+        match File::create(&log_path) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                eprintln!("Could not create the log file {}: {}", log_path, e);
+                std::process::exit(1);
+            },
+        }
+    };
+    let log = Log::new(log_file);
+    #[cfg(feature = "syslog")]
+    let mut log = log;
+    #[cfg(feature = "syslog")]
+    if use_syslog {
+        if let Err(e) = log.connect_syslog() {
+            eprintln!("Could not connect to the system logger: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let log = Arc::new(Mutex::new(log));
+
+    let mut state_file = args.value("--state-file").map(StateFile::new);
+    if let Some(state_file) = &mut state_file {
+        if let Err(e) = state_file.update(&state) {
+            eprintln!("Could not write the state file {}: {}", args.value("--state-file").unwrap(), e);
+            std::process::exit(1);
+        }
+    }
+
+    if num_substituted > 0 {
+        writeln!(log.lock().unwrap(), "WARN: --ascii-only: substituted {} non-ASCII characters in the art.", num_substituted).unwrap();
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = event_socket_path {
+        state.event_stream = Some(events::EventStream::serve(&path, event_history, state.pseudonyms.clone(), Arc::clone(&log))?);
+    }
+
+    #[cfg(unix)]
+    catch_quit_signals(&state.caught_signal)?;
+
+    let first_step: usize = seed.unwrap_or_else(|| {
+        secs_since_epoch(SystemTime::now(), Arc::clone(&log)) as usize    //test value 36041;
+    });
+    let started = Instant::now();
+
+    //Init the window, clean up on drop.
+    let _wc = WindowContext::new(state.title.as_deref());
+
+    #[cfg(feature = "metrics")]
+    if let Some(port) = metrics_port {
+        metrics::serve(port, Arc::clone(&state.metrics), Arc::clone(&log));
+    }
+
+    let resolver = if args.is_set("--resolve-peers") { Some(Arc::new(Resolver::new())) } else { None };
+
+    //One hub for every channel's clients.
+    let broadcast = if is_broadcast { Some(Arc::new(Broadcast::new(state.pseudonyms.clone()))) } else { None };
+
+    let mut receivers: Vec<Receiver<LogItem>> = Vec::new();
+    for (i, (_, port)) in channel_specs.iter().enumerate() {
+        let (tx, rx) = channel::<LogItem>();
+        receivers.push(rx);
+
+        //Replays and the Unix domain socket feed the first channel.
+        if i == 0 {
+            if let Some(events) = replay_events.take() {
+                replay::play(events, tx.clone(), Arc::clone(&log));
+            }
+
+            #[cfg(unix)]
+            if let Some(path) = &unix_path {
+                listen_unix(path, tx, Arc::clone(&log), Arc::clone(&state.metrics), broadcast.clone(), idle_timeout, Arc::clone(&redactions), max_payload, state.pseudonyms.clone())?;
+                continue;
+            }
+        }
+
+        listen_tcp(&bind_addr, *port, tx, Arc::clone(&log), Arc::clone(&state.metrics), broadcast.clone(), idle_timeout, Arc::clone(&redactions), max_payload, state.pseudonyms.clone(), resolver.clone());
+    }
+
+    let mut stdout = stdout();
+    while !state.window_should_close {
+        //update() will poll for keypresses -- if there are none it continues after poll_interval.
+        update(&mut state, &mut render_state, &receivers, Arc::clone(&log))?;
+        if let Some(state_file) = &mut state_file {
+            if let Err(e) = state_file.update(&state) {
+                writeln!(log.lock().unwrap(), "ERROR: Could not write the state file {}: {}", args.value("--state-file").unwrap(), e).unwrap();
+            }
+        }
+        #[cfg(unix)]
+        if let Some(event_stream) = &state.event_stream {
+            event_stream.update_states(&state.channels);
+        }
+        //Always render -- after poll_interval or when a key is pressed.
+        let (cols, rows) = terminal::size()?;
+        let frame_number = animation_step(first_step, started, Instant::now());
+        render(&mut stdout, &state, &mut render_state, cols, rows, Arc::clone(&log), frame_number)?;
+    }
+
+    if let Some(state_file) = &mut state_file {
+        state_file.close(&log);
+    }
+
+    if let Some(path) = args.value("--export-on-exit") {
+        match export::save(&state, path, state.export_format) {
+            Ok(()) => writeln!(log.lock().unwrap(), "INFO: Exported the packet log to {}.", path).unwrap(),
+            Err(e) => writeln!(log.lock().unwrap(), "ERROR: Could not export the packet log to {}: {}", path, e).unwrap(),
+        }
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{MemoryStream, Session, BARE_WARN};
+
+    impl Stream for MemoryStream {
+        fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+            return MemoryStream::set_read_timeout(self, dur);
+        }
+
+        //Writes to a MemoryStream never block.
+        fn set_write_timeout(&self, _dur: Option<Duration>) -> io::Result<()> {
+            return Ok(());
+        }
+
+        fn set_nodelay(&self, _nodelay: bool) -> io::Result<()> {
+            return Ok(());
+        }
+
+        fn try_clone_writer(&self) -> io::Result<Box<dyn Write + Send>> {
+            return Ok(Box::new(self.try_clone()?));
+        }
+    }
+
+    //A client session associated with the server's end of a MemoryStream, as handle_connection
+    //would associate it.
+    fn associated() -> (Session<MemoryStream>, MemoryStream) {
+        let (client, mut server) = MemoryStream::pair();
+        let server_thread = thread::spawn(move || {
+            handle_association(&mut server).unwrap();
+            return server;
+        });
+        let session = Session::from_stream(client).unwrap();
+        return (session, server_thread.join().unwrap());
+    }
+
+    fn test_log() -> Arc<Mutex<Log>> {
+        return Arc::new(Mutex::new(Log::new(None)));
+    }
+
+    //The State main builds without any flags: one channel, at NONE, on a 24-hour clock. The clock is
+    //in UTC rather than local time, as with --utc, so times render the same in any timezone.
+    pub(crate) fn test_state() -> State {
+        return State {
+            channels: vec![Channel::new("default".to_string(), WarnStates::None)],
+            active_channel: 0,
+            min_state: WarnStates::None,
+            warn_state_ascii_art: WarnStateAsciiArt::new(),
+            window_should_close: false,
+            log_limit: None,
+            retention: Retention::Fifo,
+            muted: HashSet::new(),
+            expected: Vec::new(),
+            escalation: None,
+            hooks: Hooks { on_alert: None, on_reset: None },
+            filter: None,
+            is_filtering: false,
+            packet_rate: Vec::new(),
+            is_showing_host_counts: false,
+            pseudonyms: None,
+            is_revealing_peers: false,
+            host_names: HashMap::new(),
+            is_short_ipv6: false,
+            title: None,
+            host_counts: (0, 0),
+
+            is_focused_mode: false,
+            confirm_quit_on_alert: false,
+            input_mode: InputMode::Normal,
+            search: None,
+            notice: None,
+            is_showing_hints: false,
+            hints_hide_at: None,
+            art_position: ArtPosition::Top,
+            is_compact_log: false,
+            max_display_len: None,
+            use_color: false,
+            has_palette: false,
+            border_chars: parse_border_chars(""),
+            ascii_only: false,
+            clock: Clock { is_utc: true, is_12_hour: false, time_display: TimeDisplay::Absolute },
+            operator: None,
+            export_format: ExportFormat::Csv,
+
+            metrics: Arc::new(Metrics::new()),
+            #[cfg(unix)]
+            event_stream: None,
+            #[cfg(unix)]
+            caught_signal: Arc::new(AtomicUsize::new(0)),
+
+            poll_interval: Duration::from_millis(1000 / DEFAULT_FPS),
+
+            resize_settling_since: None,
+
+            screensaver_after: None,
+            last_activity: Instant::now(),
+            is_screensaver: false,
+            screensaver_step: 0,
+            relative_time_step: 0,
+
+            info_art_path: None,
+            warn_art_path: None,
+            alert_art_path: None,
+            critical_art_path: None,
+        };
+    }
+
+    fn test_peer() -> Peer {
+        return Peer::Tcp("127.0.0.1:5000".parse().unwrap());
+    }
+
+    fn test_packet(packet_type: PacketType, text: Option<&str>) -> Packet {
+        return Packet {
+            packet_type: packet_type,
+            text: text.map(str::to_string),
+            will_type: None,
+            timestamp: None,
+            wants_ack: false,
+            sequence: None,
+            has_invalid_utf8: false,
+        };
+    }
+
+    fn packet_item(packet_type: PacketType, text: Option<&str>) -> LogItem {
+        return LogItem::PacketLogItem { timestamp: SystemTime::now(), peer_addr: test_peer(), packet: test_packet(packet_type, text) };
+    }
+
+    //The packet log keeps the newest entry first.
+    fn log_packets(state: &mut State, items: Vec<LogItem>) {
+        for item in items {
+            state.active_mut().packet_log.push_front(item);
+        }
+    }
+
+    //What a terminal of cols by rows would show after the escape sequences render produces: the
+    //cursor movements it uses are followed, and colors and attributes dropped. Writing outside the
+    //terminal fails the test, as a real one would wrap or scroll.
+    fn screen(bytes: &[u8], cols: u16, rows: u16) -> Vec<String> {
+        let mut grid = vec![vec![' '; cols as usize]; rows as usize];
+        let (mut x, mut y) = (0usize, 0usize);
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '\x1b' {
+                assert!(y < grid.len() && x < grid[y].len(), "wrote {:?} outside the terminal at ({}, {})", c, x, y);
+                grid[y][x] = c;
+                x += 1;
+                continue;
+            }
+            assert_eq!(chars.next(), Some('['));
+            let mut params = String::new();
+            let command = loop {
+                match chars.next().unwrap() {
+                    c @ ('0'..='9' | ';' | '?') => params.push(c),
+                    c => break c,
+                }
+            };
+            let numbers: Vec<usize> = params.split(';').map(|n| n.parse().unwrap_or(1)).collect();
+            match command {
+                'H' => (y, x) = (numbers[0] - 1, numbers[1] - 1),
+                'B' => y = std::cmp::min(y + numbers[0], rows as usize - 1),
+                'G' => x = numbers[0] - 1,
+                'J' => grid = vec![vec![' '; cols as usize]; rows as usize],
+                _ => (),
+            }
+        }
+        return grid.into_iter().map(|row| row.into_iter().collect::<String>().trim_end().to_string()).collect();
+    }
+
+    fn render_screen(state: &State, cols: u16, rows: u16) -> Vec<String> {
+        let mut out: Vec<u8> = Vec::new();
+        render(&mut out, state, &mut RenderState::rerender_all(), cols, rows, test_log(), 0).unwrap();
+        return screen(&out, cols, rows);
+    }
+
+    //The row the text is on, if it's on screen.
+    fn row_of(screen: &[String], text: &str) -> Option<usize> {
+        return screen.iter().position(|row| row.contains(text));
+    }
+
+    #[test]
+    fn renders_to_any_writer_at_any_size() {
+        let mut state = test_state();
+        log_packets(&mut state, vec![packet_item(PacketType::Warn, Some("disk full"))]);
+
+        for (cols, rows) in [(80, 40), (120, 50)] {
+            let screen = render_screen(&state, cols, rows);
+            assert_eq!(screen.len(), rows as usize);
+            let row = row_of(&screen, "WARN | 127.0.0.1:5000 | disk full").unwrap();
+            assert!(screen[row].starts_with("    ["));
+        }
+    }
+
+    #[test]
+    fn renders_log_entries_on_consecutive_rows() {
+        let mut state = test_state();
+        log_packets(&mut state, vec![
+            packet_item(PacketType::Info, Some("first")),
+            LogItem::ConnectLogItem { timestamp: SystemTime::now(), peer_addr: test_peer(), host_name: None },
+            packet_item(PacketType::Alert, Some("third")),
+        ]);
+
+        let screen = render_screen(&state, 80, 40);
+        let third = row_of(&screen, "ALERT | 127.0.0.1:5000 | third").unwrap();
+        assert_eq!(row_of(&screen, "127.0.0.1:5000 has successfully associated."), Some(third + 1));
+        assert_eq!(row_of(&screen, "INFO | 127.0.0.1:5000 | first"), Some(third + 2));
+    }
+
+    #[test]
+    fn wraps_long_text_and_moves_the_next_entry_down() {
+        let mut state = test_state();
+        let long_text = "x".repeat(100);
+        log_packets(&mut state, vec![
+            packet_item(PacketType::Info, Some("after")),
+            packet_item(PacketType::Warn, Some(&long_text)),
+        ]);
+
+        let cols = 80;
+        let screen = render_screen(&state, cols, 40);
+        let first = row_of(&screen, "WARN | 127.0.0.1:5000 | x").unwrap();
+        //The text stops at the right margin and carries on at the left one.
+        assert_eq!(screen[first].chars().count(), cols as usize - 4);
+        assert!(screen[first + 1].starts_with("    x"));
+        assert_eq!(row_of(&screen, "INFO | 127.0.0.1:5000 | after"), Some(first + 2));
+        let wrapped: usize = screen[first..].iter().map(|row| row.matches('x').count()).sum();
+        assert_eq!(wrapped, long_text.len());
+    }
+
+    #[test]
+    fn marks_text_cut_off_at_the_bottom_of_a_small_terminal() {
+        let mut state = test_state();
+        state.warn_state_ascii_art = art_of_size(4, 1);
+        log_packets(&mut state, vec![packet_item(PacketType::Warn, Some(&"x".repeat(MAX_MSG_LEN)))]);
+
+        //Few rows, and just wide enough for the labels down the left.
+        let (cols, rows) = (50, 12);
+        //screen fails on anything drawn outside the terminal.
+        let screen = render_screen(&state, cols, rows);
+        let last = screen.iter().rposition(|row| row.len() > 0).unwrap();
+        assert!(screen[last].ends_with("…[truncated]"), "{:?}", screen);
+        //The log stops short of the prompt rows.
+        assert!(last < rows as usize - 2);
+        let shown: usize = screen.iter().map(|row| row.matches('x').count()).sum();
+        assert!(shown < MAX_MSG_LEN);
+    }
+
+    #[test]
+    fn returns_the_row_below_the_packet_log() {
+        let mut state = test_state();
+        log_packets(&mut state, vec![packet_item(PacketType::Warn, Some("a")), packet_item(PacketType::Warn, Some("b"))]);
+
+        let mut out: Vec<u8> = Vec::new();
+        let mut render_state = RenderState::rerender_all();
+        render(&mut out, &state, &mut render_state, 80, 40, test_log(), 0).unwrap();
+        let screen = screen(&out, 80, 40);
+        assert_eq!(render_state.packet_log_end as usize, row_of(&screen, "| a").unwrap() + 1);
+    }
+
+    #[test]
+    fn renders_timestamps_from_the_future_and_before_the_epoch() {
+        for time_display in [TimeDisplay::Absolute, TimeDisplay::Relative, TimeDisplay::Both] {
+            let mut state = test_state();
+            state.clock.time_display = time_display;
+            log_packets(&mut state, vec![
+                LogItem::PacketLogItem { timestamp: UNIX_EPOCH - Duration::from_secs(1), peer_addr: test_peer(), packet: test_packet(PacketType::Warn, Some("past")) },
+                LogItem::PacketLogItem { timestamp: SystemTime::now() + Duration::from_secs(5), peer_addr: test_peer(), packet: test_packet(PacketType::Warn, Some("future")) },
+            ]);
+
+            let screen = render_screen(&state, 80, 40);
+            assert!(row_of(&screen, "| future").is_some());
+            assert!(row_of(&screen, "| past").is_some());
+        }
+    }
+
+    #[test]
+    fn treats_times_before_the_epoch_as_zero() {
+        assert_eq!(secs_since_epoch(UNIX_EPOCH - Duration::from_secs(1), test_log()), 0);
+        assert_eq!(secs_since_epoch(UNIX_EPOCH + Duration::from_secs(90), test_log()), 90);
+    }
+
+    #[test]
+    fn shows_times_from_the_future_as_just_now() {
+        let now = SystemTime::now();
+        let clock = Clock { is_utc: true, is_12_hour: false, time_display: TimeDisplay::Relative };
+        assert_eq!(clock.format_log_time(now + Duration::from_secs(5), 0, now), "just now");
+    }
+
+    #[test]
+    fn cuts_multibyte_text_between_characters() {
+        assert_eq!(truncate_with_ellipsis("héllo wörld".to_string(), 6, "…"), "héllo…");
+        assert_eq!(truncate_with_ellipsis("日本語のテキスト".to_string(), 4, "…"), "日本語…");
+        assert_eq!(truncate_with_ellipsis("ñandú".to_string(), 5, "…"), "ñandú");
+        assert_eq!(truncate_with_ellipsis("ñandú ñandú".to_string(), 7, "..."), "ñand...");
+    }
+
+    #[test]
+    fn shows_text_past_the_max_display_len_cut_while_the_log_keeps_it_whole() {
+        let mut state = test_state();
+        state.max_display_len = Some(6);
+        log_packets(&mut state, vec![packet_item(PacketType::Warn, Some("héllo wörld"))]);
+
+        let screen = render_screen(&state, 80, 40);
+        assert!(row_of(&screen, "| héllo…").is_some());
+        assert!(row_of(&screen, "wörld").is_none());
+        match &state.active().packet_log[0] {
+            LogItem::PacketLogItem { packet, .. } => assert_eq!(packet.text.as_deref(), Some("héllo wörld")),
+            _ => panic!("expected the packet"),
         }
     }
-    queue!(stdout, style::ResetColor)?;
 
-    return Ok(());
-}
+    #[test]
+    fn steps_the_animation_by_time_not_by_frame() {
+        let started = Instant::now();
+        assert_eq!(animation_step(7, started, started), 7);
+        //However many frames are rendered within a tick, they show the same step.
+        for _ in 0..100 {
+            assert_eq!(animation_step(7, started, started + ANIMATION_TICK - Duration::from_millis(1)), 7);
+        }
+        assert_eq!(animation_step(7, started, started + ANIMATION_TICK), 8);
+        assert_eq!(animation_step(7, started, started + ANIMATION_TICK * 20 + Duration::from_millis(250)), 27);
+        assert_eq!(animation_step(usize::MAX, started, started + ANIMATION_TICK), 0);
+    }
 
-fn render(state: &State, render_state: &mut RenderState, log: Arc<Mutex<File>>, frame_number: usize) -> io::Result<()> {
-    let mut stdout = stdout();
+    #[test]
+    fn formats_a_known_time_in_utc_on_either_clock() {
+        let clock_24 = Clock { is_utc: true, is_12_hour: false, time_display: TimeDisplay::Absolute };
+        let clock_12 = Clock { is_utc: true, is_12_hour: true, time_display: TimeDisplay::Absolute };
+        //2023-11-14 22:13:20 UTC.
+        assert_eq!(clock_24.format(1_700_000_000), "22:13");
+        assert_eq!(clock_12.format(1_700_000_000), "10:13 PM");
+        //Midnight and noon, where 12-hour clocks say 12 rather than 0.
+        assert_eq!(clock_24.format(0), "00:00");
+        assert_eq!(clock_12.format(0), "12:00 AM");
+        assert_eq!(clock_24.format(12 * 60 * 60 + 5 * 60), "12:05");
+        assert_eq!(clock_12.format(12 * 60 * 60 + 5 * 60), "12:05 PM");
+        assert_eq!(clock_12.format(14 * 60 * 60 + 5 * 60), "02:05 PM");
+        //Past anything chrono can represent, shown as the epoch.
+        assert_eq!(clock_24.format(u64::MAX), "00:00");
+    }
 
-    let (cols, rows) = terminal::size()?;
-    let min_cols = state.warn_state_ascii_art.width(&state.warn_state) as u16 + 10;
-    let min_rows = state.warn_state_ascii_art.height(&state.warn_state) as u16 + 10;
-    if cols < min_cols || rows < min_rows {
-        writeln!(log.lock().unwrap(), "ERROR: ascii art is too large to render on terminal.").unwrap();
-        return Err(Error::new(
-            ErrorKind::Other,
-            "ERROR: ascii art is too large to render on terminal.",
-        ));
+    #[test]
+    fn shows_both_the_time_and_the_age() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = Clock { is_utc: true, is_12_hour: true, time_display: TimeDisplay::Both };
+        assert_eq!(clock.format_log_time(now - Duration::from_secs(5), 1_700_000_000 - 5, now), "10:13 PM, 5s ago");
     }
 
-    if render_state.clear_background {
-        queue!(
-            stdout,
-            terminal::Clear(terminal::ClearType::All),
-        )?;
+    #[test]
+    fn formats_ages_to_the_largest_whole_unit() {
+        let age = |secs: u64| format_age(Some(Duration::from_secs(secs)));
+        assert_eq!(format_age(Some(Duration::from_millis(999))), "just now");
+        assert_eq!(format_age(None), "just now");
+        assert_eq!(age(5), "5s ago");
+        assert_eq!(age(59), "59s ago");
+        assert_eq!(age(60), "1m ago");
+        assert_eq!(age(2 * 60 + 59), "2m ago");
+        assert_eq!(age(60 * 60 - 1), "59m ago");
+        assert_eq!(age(60 * 60), "1h ago");
+        assert_eq!(age(24 * 60 * 60 - 1), "23h ago");
+        assert_eq!(age(24 * 60 * 60), "1d ago");
+        assert_eq!(age(400 * 24 * 60 * 60), "400d ago");
     }
 
-    //Print the ascii art representing the warn state.
-    render_warn_state(&state.warn_state_ascii_art, &state.warn_state, false, frame_number)?;
+    #[test]
+    fn measures_ages_from_the_render_time() {
+        let now = SystemTime::now();
+        let clock = Clock { is_utc: true, is_12_hour: false, time_display: TimeDisplay::Relative };
+        assert_eq!(clock.format_log_time(now - Duration::from_secs(125), 0, now), "2m ago");
+    }
 
-    //Print the border art when alert.
-    if state.warn_state == WarnStates::Alert {
-        render_alert_border(frame_number, &state.warn_state_ascii_art)?;
+    #[test]
+    fn border_streams_use_every_glyph_in_the_set() {
+        for (name, preset) in BORDER_PRESETS {
+            let glyphs = parse_border_chars(name);
+            assert_eq!(glyphs, preset.chars().collect::<Vec<char>>());
+            for stream in 0..8 {
+                let drawn: HashSet<char> = (0..1000).map(|position| get_rand_char(0, position, stream, &glyphs)).collect();
+                assert_eq!(drawn, glyphs.iter().copied().collect(), "{} stream {}", name, stream);
+            }
+        }
     }
-    else {
-        //Blank out the border if we have changed away from alert state.
-        //Unfortunately, this code also triggers when switching from NONE to WARN.
-        if render_state.warn_state_changed {
-            //Blank out the border.
-            for y in 0..rows {
-                let xs: [u16; 8] = [0, 1, 2, 3, cols-4, cols-3, cols-2, cols-1];
-                for x in xs {
-                    queue!(stdout, cursor::MoveTo(x, y), style::Print(' '))?;
-                }
+
+    #[test]
+    fn border_streams_keep_their_density_whatever_the_set() {
+        for glyphs in [parse_border_chars("binary"), parse_border_chars("matrix")] {
+            for rand in 0..12 {
+                assert_eq!(get_rand_char(rand, 17, 0, &glyphs) == ' ', rand >= 6);
             }
         }
     }
 
-    if render_state.focused_mode_changed {
-        if state.is_focused_mode {
-            queue!(stdout, cursor::MoveTo(0, 5), style::Print("Focus!"))?;
+    #[test]
+    fn parses_custom_and_default_border_chars() {
+        assert_eq!(parse_border_chars(""), parse_border_chars("ascii"));
+        assert_eq!(parse_border_chars("xo"), vec!['x', 'o']);
+    }
+
+    #[test]
+    fn border_glyphs_fall_a_row_per_frame() {
+        let (cols, rows) = (40, 30);
+        let glyphs = parse_border_chars("matrix");
+        let frame = |frame_number: usize| {
+            let mut out: Vec<u8> = Vec::new();
+            render_alert_border(&mut out, cols, rows, frame_number, &WarnStateAsciiArt::new(), &WarnStates::Alert, &glyphs, false).unwrap();
+            return screen(&out, cols, rows).into_iter().map(|row| format!("{:<width$}", row, width = cols as usize).chars().collect::<Vec<char>>()).collect::<Vec<_>>();
+        };
+
+        let (before, after) = (frame(1000), frame(1001));
+        //Columns 0 and cols - 1 also flash the border's edge; the rest are only streams.
+        for x in [1, 2, 3, cols as usize - 4, cols as usize - 3, cols as usize - 2] {
+            for y in 0..rows as usize - 1 {
+                assert_eq!(before[y][x], after[y + 1][x], "column {} row {}", x, y);
+            }
         }
-        else {
-            queue!(stdout, cursor::MoveTo(0, 5), style::Print("      "))?;
+        assert_eq!(frame(1000), before);
+    }
+
+    fn line_widths(art: &str) -> Vec<usize> {
+        return art.lines().map(|line| line.chars().count()).collect();
+    }
+
+    #[test]
+    fn pads_ragged_art_to_a_rectangle() {
+        let ragged = "###\n#\n\n##\n".to_string();
+        let art = WarnStateAsciiArt::build(ragged.clone(), "█▓\n█".to_string(), ragged.clone(), ragged);
+        assert_eq!(line_widths(&art.info_art), vec![3, 3, 3, 3]);
+        assert_eq!(art.info_art, "###\n#  \n   \n## \n");
+        //Counted in chars, not bytes.
+        assert_eq!(line_widths(&art.warn_art), vec![2, 2]);
+        assert_eq!(art.width(&WarnStates::Warn), 2);
+        assert_eq!(art.width(&WarnStates::Alert), 3);
+        assert_eq!(art.height(&WarnStates::Alert), 4);
+    }
+
+    #[test]
+    fn default_art_is_rectangular() {
+        let art = WarnStateAsciiArt::build(String::new(), String::new(), String::new(), String::new());
+        for warn_state in [WarnStates::None, WarnStates::Warn, WarnStates::Alert, WarnStates::Critical] {
+            let widths = line_widths(art.to_ascii_art(&warn_state));
+            assert!(widths.iter().all(|&width| width == widths[0]));
+            assert_eq!(art.width(&warn_state), widths[0]);
         }
     }
 
-    if render_state.packet_log_changed {
-        render_packet_log(&state.packet_log, state.warn_state_ascii_art.max_height(), &state.peer_names)?;
+    #[test]
+    fn check_art_reports_ragged_art() {
+        let ragged = "###\n#\n".to_string();
+        let art = WarnStateAsciiArt::build(ragged.clone(), String::new(), String::new(), String::new());
+        let default_warn = WarnStateAsciiArt::default_warn_art();
+        let default_alert = WarnStateAsciiArt::default_alert_art();
+        let default_critical = WarnStateAsciiArt::default_critical_art();
+        let raw_arts = [
+            (WarnStates::None, &None, ragged.as_str()),
+            (WarnStates::Warn, &None, default_warn.as_str()),
+            (WarnStates::Alert, &None, default_alert.as_str()),
+            (WarnStates::Critical, &None, default_critical.as_str()),
+        ];
+        assert_eq!(check_art(&art, raw_arts, None), 1);
     }
 
-    stdout.flush()?;
+    fn plain_peers(host_names: &HashMap<IpAddr, String>) -> PeerDisplay<'_> {
+        return PeerDisplay { pseudonyms: None, host_names: host_names, is_short_ipv6: false, ascii_only: false };
+    }
 
-    //It is implicit that render() will deal with every field in render_state if true,
-    //so to avoid manually tracking that we have dealt with everything, we simply create
-    //a new render_state where everything is false.
-    *render_state = RenderState::new();
+    #[test]
+    fn names_packets_by_ip_and_everything_else_by_name() {
+        let host_names = HashMap::new();
+        let mut peer_names = HashMap::new();
+        peer_names.insert(test_peer(), "db-monitor".to_string());
 
-    return Ok(());
-}
+        assert_eq!(peer_label(&PacketType::Name, &test_peer(), &peer_names, plain_peers(&host_names)), "127.0.0.1:5000");
+        for packet_type in [PacketType::Info, PacketType::Warn, PacketType::Alert, PacketType::Critical, PacketType::Reset, PacketType::LastWill, PacketType::Metadata] {
+            assert_eq!(peer_label(&packet_type, &test_peer(), &peer_names, plain_peers(&host_names)), "db-monitor");
+        }
+    }
 
-use std::io::{Error, ErrorKind, Read, Write}; //Import the Read, Write traits for TcpStream.
-use std::sync::mpsc::Sender;
-use std::time::Duration;
+    #[test]
+    fn shows_the_ip_of_a_peer_without_a_name() {
+        let host_names = HashMap::new();
+        let mut peer_names = HashMap::new();
+        peer_names.insert(Peer::Tcp("127.0.0.1:6000".parse().unwrap()), "other".to_string());
+        assert_eq!(peer_label(&PacketType::Warn, &test_peer(), &peer_names, plain_peers(&host_names)), "127.0.0.1:5000");
+    }
 
-fn handle_association(connection: &mut TcpStream) -> Result<(), Error> {
-    //Set timeout so connections must associate or be dropped.
-    connection
-        .set_read_timeout(Some(Duration::from_millis(200)))
-        .expect("No errors unless duration is 0.");
+    #[test]
+    fn sanitizes_names_from_clients() {
+        let host_names = HashMap::new();
+        let mut peer_names = HashMap::new();
+        peer_names.insert(test_peer(), "db\x1b[2J".to_string());
+        assert_eq!(peer_label(&PacketType::Warn, &test_peer(), &peer_names, plain_peers(&host_names)), "db^[[2J");
+    }
 
-    let mut buf: [u8; 2] = [0; 2];
-    let num_bytes_read = match connection.read(&mut buf) {
-        Ok(0) => {
-            //Drop the connection without logging anything - client disconnected for some reason.
-            return Err(Error::from(ErrorKind::UnexpectedEof));
+    #[test]
+    fn marks_each_kind_of_entry_with_its_own_glyph() {
+        let items = [
+            LogItem::ConnectLogItem { timestamp: SystemTime::now(), peer_addr: test_peer(), host_name: None },
+            LogItem::DisconnectLogItem { timestamp: SystemTime::now(), peer_addr: test_peer(), how: Disconnect::Clean },
+            packet_item(PacketType::Info, Some("info")),
+            packet_item(PacketType::Name, Some("name")),
+            packet_item(PacketType::Warn, Some("warn")),
+            packet_item(PacketType::Alert, Some("alert")),
+        ];
+        for ascii_only in [false, true] {
+            let glyphs: Vec<char> = items.iter().map(|item| log_item_glyph(item, ascii_only, None).0).collect();
+            let distinct: HashSet<char> = glyphs.iter().copied().collect();
+            assert_eq!(distinct.len(), glyphs.len(), "{:?}", glyphs);
+            if ascii_only {
+                assert!(glyphs.iter().all(char::is_ascii), "{:?}", glyphs);
+            }
         }
-        Ok(n) => n,
-        Err(e) => {
-            //In the case of any error - whether TimedOut, WouldBlock, even Interrupted - drop the
-            //connection. Association is not expensive.
-            return Err(e);
+        assert_eq!(log_item_glyph(&items[0], false, None).0, '→');
+        assert_eq!(log_item_glyph(&items[1], false, None).0, '←');
+    }
+
+    //The texts in a packet log, oldest first.
+    fn log_texts(packet_log: &VecDeque<LogItem>) -> Vec<&str> {
+        return packet_log.iter().rev().map(|item| match item {
+            LogItem::PacketLogItem { packet, .. } => packet.text.as_deref().unwrap_or(""),
+            _ => "",
+        }).collect();
+    }
+
+    //An ALERT, a WARN, then a flood of INFO.
+    fn flooded_log() -> VecDeque<LogItem> {
+        let mut packet_log = VecDeque::new();
+        packet_log.push_front(packet_item(PacketType::Alert, Some("alert")));
+        packet_log.push_front(packet_item(PacketType::Warn, Some("warn")));
+        for i in 0..10 {
+            packet_log.push_front(packet_item(PacketType::Info, Some(&format!("info {}", i))));
         }
-    };
+        return packet_log;
+    }
 
-    //Okay, we got something from the client.
+    #[test]
+    fn keeps_alerts_through_a_flood_of_info_by_severity() {
+        let mut packet_log = flooded_log();
+        trim_packet_log(&mut packet_log, 4, Retention::Severity);
+        assert_eq!(log_texts(&packet_log), ["alert", "warn", "info 8", "info 9"]);
 
-    if num_bytes_read != 2 {
-        //But it must be two bytes! The exact size of the association request.
-        //If the client only manages to send one byte they should simply retry association.
-        //If they send more the packet isn't an association request.
-        return Err(Error::new(
-            ErrorKind::Other,
-            "Could not associate: received incorrect num of bytes from client.",
-        ));
+        //Once only WARN and ALERT are left, the WARN goes first.
+        trim_packet_log(&mut packet_log, 1, Retention::Severity);
+        assert_eq!(log_texts(&packet_log), ["alert"]);
     }
 
-    //Check that it *is* an association request.
-    if buf[0] != 1 && buf[1] != 1 {
-        return Err(Error::new(
-            ErrorKind::Other,
-            "Could not associate: packet received from client was not an association request.",
-        ));
+    #[test]
+    fn drops_the_oldest_entries_first_by_fifo() {
+        let mut packet_log = flooded_log();
+        trim_packet_log(&mut packet_log, 4, Retention::Fifo);
+        assert_eq!(log_texts(&packet_log), ["info 6", "info 7", "info 8", "info 9"]);
     }
 
-    //Must send association accept, but timeout if the client suddenly decides to stop ACKing.
-    connection
-        .set_write_timeout(Some(Duration::from_millis(200)))
-        .expect("No errors unless duration is 0.");
+    #[test]
+    fn leaves_a_log_under_the_limit_alone() {
+        let mut packet_log = flooded_log();
+        trim_packet_log(&mut packet_log, 12, Retention::Severity);
+        assert_eq!(packet_log.len(), 12);
+    }
 
-    let buf: [u8; 2] = [1, 1];
-    let num_bytes_wrote = match connection.write(&buf) {
-        Ok(0) => {
-            //Drop the connection without logging anything - socket is broken for some reason.
-            return Err(Error::from(ErrorKind::UnexpectedEof));
+    fn packet_from(port: u16, packet_type: PacketType, text: Option<&str>) -> LogItem {
+        return LogItem::PacketLogItem { timestamp: SystemTime::now(), peer_addr: Peer::Tcp(SocketAddr::from(([127, 0, 0, 1], port))), packet: test_packet(packet_type, text) };
+    }
+
+    #[test]
+    fn a_reconnecting_client_takes_its_name_over() {
+        let mut state = test_state();
+        let log = test_log();
+        take_log_item(&mut state, &mut RenderState::new(), 0, packet_from(5000, PacketType::Name, Some("db")), &log);
+        //Reconnected on a new port before the old connection was noticed as dropped.
+        take_log_item(&mut state, &mut RenderState::new(), 0, packet_from(5001, PacketType::Name, Some("db")), &log);
+
+        let peer_names = &state.active().peer_names;
+        assert_eq!(peer_names.len(), 1);
+        assert_eq!(peer_names.get(&Peer::Tcp(SocketAddr::from(([127, 0, 0, 1], 5001)))).map(String::as_str), Some("db"));
+
+        //The old connection's drop, once noticed, leaves the new one's name alone.
+        let old_drop = LogItem::DisconnectLogItem { timestamp: SystemTime::now(), peer_addr: Peer::Tcp(SocketAddr::from(([127, 0, 0, 1], 5000))), how: Disconnect::Lost };
+        take_log_item(&mut state, &mut RenderState::new(), 0, old_drop, &log);
+        assert_eq!(state.active().peer_names.len(), 1);
+    }
+
+    #[test]
+    fn clients_with_different_names_are_both_listed() {
+        let mut state = test_state();
+        take_log_item(&mut state, &mut RenderState::new(), 0, packet_from(5000, PacketType::Name, Some("db")), &test_log());
+        take_log_item(&mut state, &mut RenderState::new(), 0, packet_from(5001, PacketType::Name, Some("web")), &test_log());
+        assert_eq!(state.active().peer_names.len(), 2);
+    }
+
+    #[test]
+    fn clients_on_different_hosts_with_the_same_name_are_both_listed() {
+        let mut state = test_state();
+        for host in [1, 2] {
+            let item = LogItem::PacketLogItem { timestamp: SystemTime::now(), peer_addr: Peer::Tcp(SocketAddr::from(([10, 0, 0, host], 5000))), packet: test_packet(PacketType::Name, Some("worker")) };
+            take_log_item(&mut state, &mut RenderState::new(), 0, item, &test_log());
         }
-        Ok(n) => n,
-        Err(e) => {
-            //In the case of any error - whether TimedOut, WouldBlock, even Interrupted - drop the
-            //connection. Association is not expensive.
-            return Err(e);
+        assert_eq!(state.active().peer_names.len(), 2);
+
+        //The same host on a new port is the same client reconnecting.
+        let item = LogItem::PacketLogItem { timestamp: SystemTime::now(), peer_addr: Peer::Tcp(SocketAddr::from(([10, 0, 0, 1], 5001))), packet: test_packet(PacketType::Name, Some("worker")) };
+        take_log_item(&mut state, &mut RenderState::new(), 0, item, &test_log());
+        assert_eq!(state.active().peer_names.len(), 2);
+        assert!(!state.active().peer_names.contains_key(&Peer::Tcp(SocketAddr::from(([10, 0, 0, 1], 5000)))));
+    }
+
+    fn packet_from_host(host: u8, packet_type: PacketType) -> LogItem {
+        return LogItem::PacketLogItem { timestamp: SystemTime::now(), peer_addr: Peer::Tcp(SocketAddr::from(([10, 0, 0, host], 5000))), packet: test_packet(packet_type, Some("x")) };
+    }
+
+    #[test]
+    fn counts_hosts_by_their_most_severe_state() {
+        let mut state = test_state();
+        for (host, packet_type) in [(1, PacketType::Warn), (2, PacketType::Warn), (3, PacketType::Alert), (3, PacketType::Warn), (4, PacketType::Info)] {
+            take_log_item(&mut state, &mut RenderState::new(), 0, packet_from_host(host, packet_type), &test_log());
         }
-    };
+        assert_eq!(host_counts(&state.active().host_states), (1, 2));
+        assert!(state.active().warn_state == WarnStates::Alert);
 
-    if num_bytes_wrote != 2 {
-        //If the server only manages to send one byte it should simply drop the connection and
-        //let the client retry association.
-        return Err(Error::new(
-            ErrorKind::Other,
-            "Could not associate: wrote incorrect num of bytes to client.",
-        ));
+        //A CRITICAL host counts as ALERT, and raises the channel above every other host.
+        take_log_item(&mut state, &mut RenderState::new(), 0, packet_from_host(5, PacketType::Critical), &test_log());
+        assert_eq!(host_counts(&state.active().host_states), (2, 2));
+        assert!(state.active().warn_state == WarnStates::Critical);
     }
 
-    //Set back to blocking - associated clients CAN slow loris.
-    connection
-        .set_read_timeout(None)
-        .expect("No errors unless duration is 0.");
-    connection
-        .set_write_timeout(None)
-        .expect("No errors unless duration is 0.");
+    #[test]
+    fn shows_the_title_along_the_top() {
+        let mut state = test_state();
+        state.title = Some("prod cluster".to_string());
+        let mut out: Vec<u8> = Vec::new();
+        render(&mut out, &state, &mut RenderState::rerender_all(), 80, 40, test_log(), 0).unwrap();
+        assert!(String::from_utf8_lossy(&out).contains("prod cluster"));
+
+        let screen = screen(&out, 80, 40);
+        assert_eq!(row_of(&screen, "prod cluster"), Some(0));
+        assert_eq!(screen[0].find("prod cluster"), Some(5));
+
+        //Cut short before it runs into the sparkline.
+        state.title = Some("x".repeat(200));
+        let screen = render_screen(&state, 80, 40);
+        assert!(screen[0].contains("xxx…"));
+        assert!(!screen[0].contains(&"x".repeat(80 - 5 - SPARKLINE_SECS as usize)));
+    }
 
-    //We are associated! We can start receiving data!
-    return Ok(());
-}
+    #[test]
+    fn keeps_the_host_counts_on_screen_under_the_alert_border() {
+        let mut state = test_state();
+        state.is_showing_host_counts = true;
+        state.host_counts = (1, 2);
+        state.active_mut().shown_warn_state = WarnStates::Alert;
+
+        //A frame with nothing new still blanks the border, after the first drew the counts.
+        let mut out: Vec<u8> = Vec::new();
+        render(&mut out, &state, &mut RenderState::rerender_all(), 80, 40, test_log(), 0).unwrap();
+        render(&mut out, &state, &mut RenderState::new(), 80, 40, test_log(), 1).unwrap();
+        assert!(row_of(&screen(&out, 80, 40), "Hosts: 1 ALERT 2 WARN").is_some());
+    }
 
-#[derive(Debug, Copy, Clone)]
-enum PacketType {
-    Info,
-    Warn,
-    Alert,
-    Name,
-}
+    //Whether each of the WARNs, sent secs after the first, crosses the escalation.
+    fn crossings(escalation: Escalation, secs: &[u64]) -> Vec<bool> {
+        let mut warn_times = VecDeque::new();
+        return secs.iter().map(|secs| escalation.is_crossed(&mut warn_times, UNIX_EPOCH + Duration::from_secs(*secs))).collect();
+    }
+
+    #[test]
+    fn escalates_once_more_than_count_warns_fall_within_the_window() {
+        let escalation = Escalation { count: 3, window: Duration::from_secs(10) };
+        assert_eq!(crossings(escalation, &[0, 3, 6, 9]), [false, false, false, true]);
+        //The same four, spread past the window, never have more than three in it.
+        assert_eq!(crossings(escalation, &[0, 4, 8, 12, 16]), [false, false, false, false, false]);
+    }
 
-impl PacketType {
-    fn from_type_number(type_number: u8) -> Result<PacketType, Error> {
-        match type_number {
-            2 => Ok(PacketType::Info),
-            3 => Ok(PacketType::Warn),
-            4 => Ok(PacketType::Alert),
-            5 => Ok(PacketType::Name),
-            _ => Err(Error::new(ErrorKind::Other, "Invalid packet type.")),
+    #[test]
+    fn raises_an_alert_on_the_warn_that_crosses_the_escalation() {
+        let mut state = test_state();
+        state.escalation = Some(Escalation { count: 3, window: Duration::from_secs(10) });
+        let start = SystemTime::now();
+        for secs in [0, 3, 6] {
+            let warn = LogItem::PacketLogItem { timestamp: start + Duration::from_secs(secs), peer_addr: test_peer(), packet: test_packet(PacketType::Warn, Some("slow")) };
+            take_log_item(&mut state, &mut RenderState::new(), 0, warn, &test_log());
+            assert!(state.active().warn_state == WarnStates::Warn);
         }
+        let warn = LogItem::PacketLogItem { timestamp: start + Duration::from_secs(9), peer_addr: test_peer(), packet: test_packet(PacketType::Warn, Some("slow")) };
+        take_log_item(&mut state, &mut RenderState::new(), 0, warn, &test_log());
+        assert!(state.active().warn_state == WarnStates::Alert);
     }
 
-    fn to_type_number(&self) -> u8 {
-        match self {
-            PacketType::Info => 2,
-            PacketType::Warn => 3,
-            PacketType::Alert => 4,
-            PacketType::Name => 5,
+    #[test]
+    fn escalates_by_when_warns_arrive_not_when_the_client_says_they_were_sent() {
+        let mut state = test_state();
+        state.escalation = Some(Escalation { count: 1, window: Duration::from_secs(10) });
+        let start = SystemTime::now();
+        let stamped_warn = |received: u64, sent: SystemTime| {
+            let mut packet = test_packet(PacketType::Warn, Some("stamped"));
+            packet.timestamp = Some(sent);
+            return LogItem::PacketLogItem { timestamp: start + Duration::from_secs(received), peer_addr: test_peer(), packet: packet };
+        };
+
+        //Stamped with the same time, but arriving far apart.
+        take_log_item(&mut state, &mut RenderState::new(), 0, stamped_warn(0, start), &test_log());
+        take_log_item(&mut state, &mut RenderState::new(), 0, stamped_warn(20, start), &test_log());
+        assert!(state.active().warn_state == WarnStates::Warn);
+        //Stamped far apart, and out of order, but arriving together.
+        take_log_item(&mut state, &mut RenderState::new(), 0, stamped_warn(21, start - Duration::from_secs(3600)), &test_log());
+        assert!(state.active().warn_state == WarnStates::Alert);
+        //Shown and exported at the time the client gave.
+        assert_eq!(state.active().packet_log[0].timestamp(), start - Duration::from_secs(3600));
+    }
+
+    //A packet arriving, as update takes it.
+    fn arrive(state: &mut State, log_item: LogItem) {
+        check_in(state, &mut RenderState::new(), 0, &log_item, test_log());
+        take_log_item(state, &mut RenderState::new(), 0, log_item, &test_log());
+    }
+
+    //Raise whatever alarms are due, as update does.
+    fn check_alarms(state: &mut State) -> usize {
+        let alarms = check_heartbeats(state, test_log());
+        let num_alarms = alarms.len();
+        for (channel_index, log_item) in alarms {
+            take_log_item(state, &mut RenderState::new(), channel_index, log_item, &test_log());
         }
+        return num_alarms;
     }
 
-    fn to_string(&self) -> &str {
-        match self {
-            PacketType::Info => "INFO",
-            PacketType::Warn => "WARN",
-            PacketType::Alert => "ALERT",
-            PacketType::Name => "NAME",
+    //Pretend a client was last heard from this long ago.
+    fn last_seen_ago(state: &mut State, ago: Duration) {
+        state.expected[0].last_seen = Instant::now().checked_sub(ago).unwrap();
+    }
+
+    #[test]
+    fn alerts_on_a_missed_heartbeat_and_clears_once_heard_from() {
+        let mut state = test_state();
+        state.expected.push(Expectation::new("db".to_string(), Duration::from_secs(10)));
+        arrive(&mut state, packet_item(PacketType::Name, Some("db")));
+        assert_eq!(check_alarms(&mut state), 0);
+
+        last_seen_ago(&mut state, Duration::from_secs(11));
+        assert_eq!(check_alarms(&mut state), 1);
+        assert!(state.active().warn_state == WarnStates::Alert);
+        //Raised once, not every frame it stays missing.
+        assert_eq!(check_alarms(&mut state), 0);
+
+        arrive(&mut state, packet_item(PacketType::Info, Some("still here")));
+        assert!(!state.expected[0].is_missing);
+        assert!(state.active().warn_state == WarnStates::None);
+    }
+
+    #[test]
+    fn stays_quiet_while_heartbeats_keep_coming() {
+        let mut state = test_state();
+        state.expected.push(Expectation::new("db".to_string(), Duration::from_secs(10)));
+        arrive(&mut state, packet_item(PacketType::Name, Some("db")));
+        for _ in 0..3 {
+            last_seen_ago(&mut state, Duration::from_secs(9));
+            arrive(&mut state, packet_item(PacketType::Info, Some("heartbeat")));
+            assert_eq!(check_alarms(&mut state), 0);
         }
+        assert!(state.active().warn_state == WarnStates::None);
     }
-}
 
-#[derive(Debug, Clone)]
-struct Packet {
-    packet_type: PacketType,
-    text: Option<String>,
-}
+    #[test]
+    fn keeps_an_alert_raised_meanwhile_when_the_client_comes_back() {
+        let mut state = test_state();
+        state.expected.push(Expectation::new("db".to_string(), Duration::from_secs(10)));
+        arrive(&mut state, packet_item(PacketType::Name, Some("db")));
+        last_seen_ago(&mut state, Duration::from_secs(11));
+        check_alarms(&mut state);
+
+        arrive(&mut state, packet_from(6000, PacketType::Alert, Some("disk full")));
+        arrive(&mut state, packet_item(PacketType::Info, Some("back")));
+        assert!(state.active().warn_state == WarnStates::Alert);
+    }
 
-fn handle_packet(connection: &mut TcpStream, peer_addr: &str, log: Arc<Mutex<File>>) -> Result<Packet, Error> {
-    //Read exactly one byte from the kernel's read queue. The first byte of every packet is the
-    //length of the packet in total bytes. This prevents us from reading multiple packets from the
-    //queue at once.
-    let mut buf: [u8; 256] = [0; 256];
-    let num_bytes_read = match connection.read(&mut buf[0..1]) {
-        Ok(0) => 0,
-        Ok(n) => n,
-        Err(e) => {
-            //In the case of any error - whether TimedOut, WouldBlock, even Interrupted - drop the
-            //connection.
-            //TODO: Make reading packets error-tolerant.
-            return Err(e);
+    //The sparkline render_sparkline draws, without the cursor movement.
+    fn sparkline(packet_rate: &[u32], ascii_only: bool) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        render_sparkline(&mut out, packet_rate, 80, false, ascii_only).unwrap();
+        return screen(&out, 80, 1)[0].trim_start().to_string();
+    }
+
+    #[test]
+    fn scales_the_sparkline_to_the_busiest_second() {
+        assert_eq!(sparkline(&[1, 2, 3, 4, 5, 6, 7, 8], false), "▁▂▃▄▅▆▇█");
+        assert_eq!(sparkline(&[8, 0, 4, 0, 1], false), "█ ▄ ▁");
+        assert_eq!(sparkline(&[3, 0, 2, 1], true), "| :.");
+    }
+
+    #[test]
+    fn shows_a_single_packet_beside_a_busy_second() {
+        assert_eq!(sparkline(&[1000, 1], false), "█▁");
+        assert_eq!(sparkline(&[1000, 1], true), "|.");
+    }
+
+    #[test]
+    fn leaves_a_quiet_sparkline_blank() {
+        assert_eq!(sparkline(&[0; SPARKLINE_SECS as usize], false), "");
+    }
+
+    #[test]
+    fn ends_the_sparkline_clear_of_the_border() {
+        let mut out: Vec<u8> = Vec::new();
+        render_sparkline(&mut out, &[1; SPARKLINE_SECS as usize], 80, false, false).unwrap();
+        let screen = screen(&out, 80, 1);
+        assert_eq!(screen[0], format!("{}{}", " ".repeat(45), "█".repeat(SPARKLINE_SECS as usize)));
+    }
+
+    #[test]
+    fn buckets_packets_by_second_ending_now() {
+        let now = Instant::now();
+        let times: VecDeque<Instant> = [Duration::from_millis(40_000), Duration::from_millis(2_500), Duration::from_millis(2_100), Duration::ZERO]
+            .iter()
+            .map(|ago| now.checked_sub(*ago).unwrap())
+            .collect();
+        let rate = packet_rate(&times);
+        assert_eq!(rate.len(), SPARKLINE_SECS as usize);
+        assert_eq!(rate[SPARKLINE_SECS as usize - 1], 1);
+        assert_eq!(rate[SPARKLINE_SECS as usize - 3], 2);
+        assert_eq!(rate.iter().sum::<u32>(), 3);
+    }
+
+    fn key(c: char) -> KeyEvent {
+        return KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+    }
+
+    #[test]
+    fn a_muted_peers_alerts_are_logged_without_raising_the_state() {
+        let mut state = test_state();
+        state.muted.insert(test_peer().ip().unwrap());
+        take_log_item(&mut state, &mut RenderState::new(), 0, packet_item(PacketType::Alert, Some("noise")), &test_log());
+        assert!(state.active().warn_state == WarnStates::None);
+        assert_eq!(state.active().packet_log.len(), 1);
+
+        //Anyone else still raises it.
+        let other = LogItem::PacketLogItem { timestamp: SystemTime::now(), peer_addr: Peer::Tcp("10.0.0.2:5000".parse().unwrap()), packet: test_packet(PacketType::Alert, Some("real")) };
+        take_log_item(&mut state, &mut RenderState::new(), 0, other, &test_log());
+        assert!(state.active().warn_state == WarnStates::Alert);
+    }
+
+    #[test]
+    fn m_mutes_and_unmutes_the_peer_of_the_newest_entry() {
+        let mut state = test_state();
+        log_packets(&mut state, vec![packet_item(PacketType::Warn, Some("noise"))]);
+        let ip = test_peer().ip().unwrap();
+
+        handle_normal_key(&mut state, &mut RenderState::new(), key('m'), test_log()).unwrap();
+        assert!(state.muted.contains(&ip));
+        handle_normal_key(&mut state, &mut RenderState::new(), key('m'), test_log()).unwrap();
+        assert!(!state.muted.contains(&ip));
+    }
+
+    #[test]
+    fn clearing_the_log_empties_it_and_blanks_it_on_screen() {
+        let mut state = test_state();
+        log_packets(&mut state, vec![packet_item(PacketType::Info, Some("first")), packet_item(PacketType::Info, Some("second"))]);
+
+        //One terminal, drawn on once with the entries and again after C.
+        let mut out: Vec<u8> = Vec::new();
+        let mut render_state = RenderState::rerender_all();
+        render(&mut out, &state, &mut render_state, 80, 40, test_log(), 0).unwrap();
+        assert!(row_of(&screen(&out, 80, 40), "| second").is_some());
+
+        handle_normal_key(&mut state, &mut render_state, key('C'), test_log()).unwrap();
+        assert!(state.active().packet_log.is_empty());
+        render(&mut out, &state, &mut render_state, 80, 40, test_log(), 1).unwrap();
+        let screen = screen(&out, 80, 40);
+        assert!(row_of(&screen, "| first").is_none());
+        assert!(row_of(&screen, "| second").is_none());
+    }
+
+    #[test]
+    fn closes_once_a_channel_stops_receiving() {
+        let mut state = test_state();
+        state.channels.push(Channel::new("staging".to_string(), WarnStates::None));
+        //The default channel is still listening; staging's listener has gone.
+        let (_tx, rx) = channel();
+        let (staging_tx, staging_rx) = channel();
+        drop(staging_tx);
+
+        //Returns at once rather than panicking or waiting on input.
+        update(&mut state, &mut RenderState::new(), &[rx, staging_rx], test_log()).unwrap();
+        assert!(state.window_should_close);
+    }
+
+    //Registers a handler for the whole test process, so no other test may raise these signals.
+    #[cfg(unix)]
+    #[test]
+    fn closes_cleanly_on_sigterm() {
+        let path = std::env::temp_dir().join(format!("ww-sigterm-test-{}.log", std::process::id()));
+        let log = Arc::new(Mutex::new(Log::new(Some(File::create(&path).unwrap()))));
+        let mut state = test_state();
+        catch_quit_signals(&state.caught_signal).unwrap();
+        let (_tx, rx) = channel();
+
+        signal_hook::low_level::raise(signal_hook::consts::SIGTERM).unwrap();
+        //The main loop, which returns once update() has seen the signal instead of waiting on input.
+        while !state.window_should_close {
+            update(&mut state, &mut RenderState::new(), std::slice::from_ref(&rx), Arc::clone(&log)).unwrap();
         }
-    };
 
-    // writeln!(log, "DEBUG: Received packet from {}.", peer_addr);
+        drop(log);
+        let logged = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(logged, format!("INFO: Caught signal {}; closing.\n", signal_hook::consts::SIGTERM));
+    }
 
-    if num_bytes_read == 0 {
-        //The other side has closed the connection; terminate the thread.
-        writeln!(log.lock().unwrap(), "INFO: Closed connection to {peer_addr}: client disconnected.").unwrap();
-        return Err(Error::new(
-            ErrorKind::Other,
-            "Client closed the connection.",
-        ));
+    fn type_text(state: &mut State, text: &str) {
+        for c in text.chars() {
+            handle_mode_key(state, &mut RenderState::new(), key(c), test_log());
+        }
     }
 
-    //                                          Add one back into num_bytes to get the true number.
-    //                                          v
-    let num_bytes_in_packet = buf[0] as usize + 1;
-    if num_bytes_in_packet == 1 {
-        //Ill-formed packet! The client is sending junk! Close the connection.
-        //Protocol does not handle single-byte packets.
-        //num_bytes_in_packet will never exceed 256, as buf[0] is only a u8.
-        writeln!(log.lock().unwrap(), "INFO: Closed connection to {peer_addr}: num_bytes_in_packet invalid, ({num_bytes_in_packet}).").unwrap();
-        return Err(Error::new(
-            ErrorKind::Other,
-            "Invalid number of bytes declared by packet header.",
-        ));
+    #[test]
+    fn searches_once_the_search_is_entered() {
+        let mut state = test_state();
+        handle_normal_key(&mut state, &mut RenderState::new(), key('/'), test_log()).unwrap();
+        assert!(state.input_mode == InputMode::Search(String::new()));
+
+        //Keybindings are text while searching.
+        type_text(&mut state, "qa");
+        assert!(state.input_mode == InputMode::Search("qa".to_string()));
+        assert!(!state.window_should_close);
+
+        handle_mode_key(&mut state, &mut RenderState::new(), KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE), test_log());
+        assert!(state.input_mode == InputMode::Normal);
+        assert_eq!(state.search.as_deref(), Some("qa"));
     }
 
-    // writeln!(log, "DEBUG: Packet reports it is {} bytes long.", num_bytes_in_packet);
+    #[test]
+    fn esc_abandons_a_partly_entered_command() {
+        let mut state = test_state();
+        state.search = Some("db".to_string());
+        handle_normal_key(&mut state, &mut RenderState::new(), key('/'), test_log()).unwrap();
+        type_text(&mut state, "web");
+        handle_mode_key(&mut state, &mut RenderState::new(), KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE), test_log());
+        assert!(state.input_mode == InputMode::Normal);
+        assert_eq!(state.search.as_deref(), Some("db"));
+
+        take_log_item(&mut state, &mut RenderState::new(), 0, packet_item(PacketType::Alert, Some("db down")), &test_log());
+        handle_normal_key(&mut state, &mut RenderState::new(), key('a'), test_log()).unwrap();
+        assert!(state.input_mode == InputMode::AckName(String::new()));
+        type_text(&mut state, "ops");
+        handle_mode_key(&mut state, &mut RenderState::new(), KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE), test_log());
+        assert!(state.input_mode == InputMode::Normal);
+        assert!(!state.active().is_acknowledged);
+    }
 
-    //Good. We know how large the packet will be. Let's try to read the rest of it.
-    let num_bytes_read = match connection.read(&mut buf[1..num_bytes_in_packet]) {
-        Ok(0) => 0,
-        Ok(n) => n,
-        Err(e) => {
-            //In the case of any error - whether TimedOut, WouldBlock, even Interrupted - drop the
-            //connection.
-            //TODO: Make reading packets error-tolerant.
-            return Err(e);
+    #[test]
+    fn keeps_the_acknowledgement_on_screen_under_the_alert_border() {
+        let mut state = test_state();
+        take_log_item(&mut state, &mut RenderState::new(), 0, packet_item(PacketType::Alert, Some("db down")), &test_log());
+        state.active_mut().shown_warn_state = WarnStates::Alert;
+        acknowledge(&mut state, &mut RenderState::new(), Some("ops".to_string()), test_log());
+
+        //A frame with nothing new still blanks the border, after the first drew the label.
+        let mut out: Vec<u8> = Vec::new();
+        render(&mut out, &state, &mut RenderState::rerender_all(), 80, 40, test_log(), 0).unwrap();
+        render(&mut out, &state, &mut RenderState::new(), 80, 40, test_log(), 1).unwrap();
+        assert!(row_of(&screen(&out, 80, 40), "ACK by ops").is_some());
+    }
+
+    #[test]
+    fn centers_with_any_odd_cell_after() {
+        assert_eq!(center_start(80, 10), 35);
+        assert_eq!(center_start(81, 10), 35);
+        assert_eq!(center_start(80, 11), 34);
+        assert_eq!(center_start(81, 11), 35);
+        for total in 0..30u16 {
+            for size in 0..=total as usize {
+                let start = center_start(total, size) as usize;
+                let after = total as usize - size - start;
+                assert!(after == start || after == start + 1, "{} in {} starts at {}", size, total, start);
+            }
         }
-    };
+    }
 
-    // writeln!(log, "DEBUG: Successfully read {} more bytes of the packet.", num_bytes_read);
+    #[test]
+    fn starts_anything_larger_than_the_terminal_at_zero() {
+        assert_eq!(center_start(10, 11), 0);
+        assert_eq!(center_start(0, 1), 0);
+        assert_eq!(center_start(10, usize::MAX), 0);
+    }
 
-    //                                 Plus one for the initial byte.
-    //                                         v
-    if num_bytes_in_packet != num_bytes_read + 1 {
-        //TODO: Read may have been interrupted by a signal; try to get the rest of it.
-        //For now, close the connection.
-        writeln!(log.lock().unwrap(),
-            "INFO: Closed connection to {}: num_bytes_in_packet != total_num_bytes_read, ({} != {}).",
-            peer_addr,
-            num_bytes_in_packet,
-            num_bytes_read + 1
-        ).unwrap();
-        return Err(Error::new(ErrorKind::Other, "Num of bytes read does not match num of bytes declared in header by client."));
+    #[test]
+    fn keeps_art_from_running_off_the_bottom() {
+        let positions = [ArtPosition::Top, ArtPosition::Center, ArtPosition::Fifth, ArtPosition::Row(50)];
+        for rows in [1, 2, 3, 4, 9, 10, 24, 25] {
+            for height in 1..=rows as usize {
+                for position in positions {
+                    assert!(position.art_y(rows, height) as usize + height <= rows as usize);
+                }
+            }
+        }
+        assert_eq!(ArtPosition::Center.art_y(24, 10), 7);
+        assert_eq!(ArtPosition::Center.art_y(25, 10), 7);
+        assert_eq!(ArtPosition::Top.art_y(24, 10), 3);
     }
 
-    let packet_type_number = buf[1];
-    let packet_type = PacketType::from_type_number(packet_type_number)?;
-
-    let packet_text: Option<String>;
-    //If the packet is longer than two bytes there is optional text.
-    //Move this section into a match statement if the protocol expands to have more than optional text
-    //fields.
-    if num_bytes_in_packet - 2 > 0 {
-        packet_text = Some(String::from_utf8_lossy(&buf[2..num_bytes_in_packet]).to_string());
-        // writeln!(log, "DEBUG: Received text: {} of {} bytes.", packet_text.clone().unwrap(), packet_text.clone().unwrap().len();
-    } else {
-        packet_text = None;
+    //Art of width by height, the same for every state.
+    fn art_of_size(width: usize, height: usize) -> WarnStateAsciiArt {
+        let art = format!("{}\n", "#".repeat(width)).repeat(height);
+        return WarnStateAsciiArt::build(art.clone(), art.clone(), art.clone(), art);
     }
 
-    let mut _log = log.lock().unwrap();
-    match packet_type {
-        PacketType::Info => {
-            if packet_text == None {
-                writeln!(_log, "INFO: Closed connection to {peer_addr}: sent INFO packet without text.").unwrap();
-                return Err(Error::new(ErrorKind::Other, "Client sent INFO packet without text."));
+    #[test]
+    fn draws_art_exactly_the_size_of_the_terminal_inside_it() {
+        for (cols, rows) in [(10, 4), (11, 5), (1, 1)] {
+            let art = art_of_size(cols as usize, rows as usize);
+            for position in [ArtPosition::Top, ArtPosition::Center, ArtPosition::Fifth, ArtPosition::Row(3)] {
+                for is_acknowledged in [false, true] {
+                    for frame_number in 0..400 {
+                        let mut out: Vec<u8> = Vec::new();
+                        render_warn_state(&mut out, cols, rows, &art, &WarnStates::Alert, position, frame_number, is_acknowledged, true).unwrap();
+                        //screen fails on anything drawn outside the terminal.
+                        let screen = screen(&out, cols, rows);
+                        if is_acknowledged {
+                            assert!(screen.iter().all(|row| *row == "#".repeat(cols as usize)));
+                        }
+                    }
+                }
             }
-            write!(_log, "INFO: Received INFO packet from {peer_addr}").unwrap();
-        }
-        PacketType::Warn => {
-            write!(_log, "INFO: Received WARN packet from {peer_addr}").unwrap();
-        }
-        PacketType::Alert => {
-            write!(_log, "INFO: Received ALERT packet from {peer_addr}").unwrap();
         }
-        PacketType::Name => {
-            if packet_text == None {
-                writeln!(_log, "INFO: Closed connection to {peer_addr}: sent NAME packet without text.").unwrap();
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    "Client sent NAME packet without text.",
-                ));
-            }
-            write!(_log, "INFO: Recieved NAME packet from {peer_addr}").unwrap();
+    }
+
+    #[test]
+    fn refuses_art_larger_than_the_terminal() {
+        let mut out: Vec<u8> = Vec::new();
+        assert!(render_warn_state(&mut out, 10, 4, &art_of_size(11, 4), &WarnStates::Warn, ArtPosition::Top, 0, false, false).is_err());
+        assert!(render_warn_state(&mut out, 10, 4, &art_of_size(10, 5), &WarnStates::Warn, ArtPosition::Top, 0, false, false).is_err());
+    }
+
+    #[test]
+    fn centers_art_on_screen() {
+        let art = art_of_size(4, 2);
+        for (cols, expected) in [(10, "   ####"), (11, "   ####"), (12, "    ####")] {
+            let mut out: Vec<u8> = Vec::new();
+            render_warn_state(&mut out, cols, 10, &art, &WarnStates::Warn, ArtPosition::Center, 0, true, false).unwrap();
+            let screen = screen(&out, cols, 10);
+            assert_eq!(screen[4], expected);
+            assert_eq!(screen[5], expected);
         }
     }
 
-    if packet_text.is_some() {
-        writeln!(_log, " with text: \"{}\".", packet_text.as_deref().unwrap()).unwrap();
-    } else {
-        writeln!(_log, ".").unwrap();
+    #[test]
+    fn shows_control_characters_visibly() {
+        assert_eq!(sanitize_for_terminal("a\x1b[2Jb"), "a^[[2Jb");
+        assert_eq!(sanitize_for_terminal("\0\x07\t\r\n\x7f"), "^@^G^I^M^J^?");
+        assert_eq!(sanitize_for_terminal("\u{9b}2J"), "\\u{9b}2J");
+        assert_eq!(sanitize_for_terminal("héllo ‼ wörld"), "héllo ‼ wörld");
     }
 
-    return Ok(Packet {
-        packet_type: packet_type,
-        text: packet_text,
-    });
-}
+    #[test]
+    fn neutralizes_escape_sequences_in_packet_text_before_the_terminal() {
+        let mut state = test_state();
+        log_packets(&mut state, vec![packet_item(PacketType::Warn, Some("a\x1b[2Jb\x1b[31mc"))]);
+        let mut out: Vec<u8> = Vec::new();
+        render(&mut out, &state, &mut RenderState::rerender_all(), 80, 40, test_log(), 0).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(!out.contains("\x1b[2Jb"));
+        assert!(!out.contains("\x1b[31mc"));
+        assert!(row_of(&screen(out.as_bytes(), 80, 40), "a^[[2Jb^[[31mc").is_some());
+    }
 
-fn handle_connection(mut connection: TcpStream, tx: Sender<LogItem>, log: Arc<Mutex<File>>) {
-    //connection_thread handles the particulars of each connection,
-    //before sending out data through the channel to the main thread.
-    let _connection_thread = thread::spawn(move || {
-        //First, associate with the client without allocating state or logging.
-        handle_association(&mut connection).unwrap();
+    fn read_packet<S: Read>(connection: &mut S) -> Result<Packet, Error> {
+        return handle_packet(connection, "peer", Arc::new(Mutex::new(Log::new(None))), &Metrics::new(), &Redactions::new(), None);
+    }
 
-        let peer_addr = connection
-            .peer_addr()
-            .expect("Client is already connected.");
-        let peer_addr_str = peer_addr.to_string();
+    //handle_packet on exactly these bytes, with the client gone after them.
+    fn read_bytes(bytes: &[u8]) -> Result<Packet, Error> {
+        let (mut client, mut server) = MemoryStream::pair();
+        client.write_all(bytes).unwrap();
+        drop(client);
+        return read_packet(&mut server);
+    }
 
-        //Send a connection notice to the packet_log.
-        writeln!(log.lock().unwrap(), "INFO: Received connection from {peer_addr_str}.").unwrap();
-        let log_item = LogItem::ConnectLogItem {
-            timestamp: SystemTime::now(),
-            peer_addr: peer_addr,
-        };
-        tx.send(log_item).expect("Unable to send on channel.");
+    #[test]
+    fn round_trips_every_packet_type() {
+        let (mut session, mut server) = associated();
+        assert_eq!(session.server_version(), Some(SERVER_VERSION));
+
+        session.send_info("info").unwrap();
+        session.send_warn("warn").unwrap();
+        session.send_alert("alert").unwrap();
+        session.change_name("name").unwrap();
+        session.reset().unwrap();
+        session.set_last_will(PacketType::Alert, "gone").unwrap();
+        session.set_labels(&[("env", "prod")]).unwrap();
+        session.send_critical("critical").unwrap();
+
+        let expected = [
+            (PacketType::Info, Some("info")),
+            (PacketType::Warn, Some("warn")),
+            (PacketType::Alert, Some("alert")),
+            (PacketType::Name, Some("name")),
+            (PacketType::Reset, None),
+            (PacketType::LastWill, Some("gone")),
+            (PacketType::Metadata, Some("env=prod")),
+            (PacketType::Critical, Some("critical")),
+        ];
+        for (packet_type, text) in expected {
+            let packet = read_packet(&mut server).unwrap();
+            assert_eq!(packet.packet_type, packet_type);
+            assert_eq!(packet.text.as_deref(), text);
+        }
+    }
 
-        loop {
-            //Read exactly one packet from kernel's internal buffer and return it.
-            let packet = match handle_packet(&mut connection, &peer_addr_str, Arc::clone(&log)) {
-                Ok(p) => Some(p),
-                Err(_) => None,
-            };
+    #[test]
+    fn round_trips_a_last_will() {
+        let (mut session, mut server) = associated();
+        session.set_last_will(PacketType::Warn, "gone").unwrap();
+        session.cancel_last_will().unwrap();
 
-            //Send structured data from packet to main thread.
-            if packet.is_some() {
-                let log_item = LogItem::PacketLogItem {
-                    timestamp: SystemTime::now(),
-                    peer_addr: peer_addr,
-                    packet: packet.unwrap()
-                };
+        assert_eq!(read_packet(&mut server).unwrap().will_type, Some(PacketType::Warn));
+        let cancel = read_packet(&mut server).unwrap();
+        assert_eq!(cancel.will_type, None);
+        assert_eq!(cancel.text, None);
+    }
 
-                tx.send(log_item).expect("Unable to send on channel.");
-            } else {
-                //Send a disconnect notice to packet_log before exiting.
-                let log_item = LogItem::DisconnectLogItem {
-                    timestamp: SystemTime::now(),
-                    peer_addr: peer_addr,
-                };
-                tx.send(log_item).expect("Unable to send on channel.");
-                return;
-            }
-        }
-    });
-}
+    #[test]
+    fn round_trips_timestamps_sequences_and_compression() {
+        let (mut session, mut server) = associated();
+        session.enable_sequence_numbers().unwrap();
+        session.enable_compression().unwrap();
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_000);
+        let long_text = "a".repeat(api::COMPRESSION_THRESHOLD * 4);
+        session.send_packet_at(PacketType::Warn, Some(&long_text), time).unwrap();
+
+        let packet = read_packet(&mut server).unwrap();
+        assert_eq!(packet.packet_type, PacketType::Warn);
+        assert_eq!(packet.text, Some(long_text));
+        assert_eq!(packet.timestamp, Some(time));
+        assert_eq!(packet.sequence, Some(0));
+    }
 
-//The protocol:
-//
-//HEADER:
-//[u8][u8]
-//  ^   ^----------------------------\
-//  |                                |
-//num_bytes (inclusive)          packet type
-//
-//NOTE: num_bytes is mapped to be one less than it actually is, i.e.
-//if num_bytes is 00000000, the true num_bytes is 1.
-//This is so that 11111111 represents 256 instead of 255.
-//You may conceptualize it as the number of bytes that follow the initial one.
-//A num_bytes of 00000000 is invalid, as there must be a packet type.
-//
-//The payload is optional, and depends on the packet type.
-//
-//PACKET TYPES:
-//00000000 - ASSOCIATION REQUEST
-//00000001 - ASSOCIATION ACCEPT
-//00000010 - CLIENT INFO - text payload
-//00000011 - CLIENT WARN - optional text payload
-//00000100 - CLIENT ALERT - optional text payload
-//00000101 - CLIENT NAME CHANGE - text payload
+    #[test]
+    fn reports_a_client_that_disconnects() {
+        let (session, mut server) = associated();
+        drop(session);
+        let e = read_packet(&mut server).unwrap_err();
+        assert!(e.get_ref().is_some_and(|e| e.is::<ClientClosed>()));
+    }
 
-// use std::env;
+    #[test]
+    fn rejects_malformed_packets() {
+        let malformed: [&[u8]; 10] = [
+            //A single-byte packet, with no type.
+            &[0],
+            //An unknown type.
+            &[1, 99],
+            //INFO and NAME without text.
+            &[1, 2],
+            &[1, 5],
+            //Shorter than its length byte says.
+            &[5, 3, b'a'],
+            //A LASTWILL with a will that isn't INFO, WARN, ALERT or CRITICAL.
+            &[2, 7, 5],
+            //Too short for the timestamp or sequence number flagged.
+            &[3, 3 | TIMESTAMP_FLAG, 0, 0],
+            &[3, 3 | SEQUENCE_FLAG, 0, 0],
+            //A compressed NAME.
+            &[2, 5 | COMPRESSED_FLAG, 0],
+            //Labels without a '='.
+            &[2, 11, b'x'],
+        ];
+        for bytes in malformed {
+            assert!(read_bytes(bytes).is_err(), "{:?} was accepted", bytes);
+        }
+    }
 
-struct WindowContext {}
+    #[test]
+    fn reads_a_bare_warn_as_a_warn_without_text() {
+        let packet = read_bytes(&[1, 3]).unwrap();
+        assert_eq!(packet.packet_type, PacketType::Warn);
+        assert_eq!(packet.text, None);
+    }
 
-impl WindowContext {
-    fn new() -> WindowContext {
-        terminal::enable_raw_mode().unwrap();
-        execute!(stdout(), terminal::EnterAlternateScreen).unwrap();
-        execute!(stdout(), terminal::Clear(terminal::ClearType::All)).unwrap();
-        execute!(stdout(), cursor::Hide).unwrap();
-        return WindowContext {};
+    #[test]
+    fn reads_a_warn_with_one_byte_of_text() {
+        let packet = read_bytes(&[2, 3, b'x']).unwrap();
+        assert_eq!(packet.packet_type, PacketType::Warn);
+        assert_eq!(packet.text.as_deref(), Some("x"));
     }
-}
 
-impl Drop for WindowContext {
-    fn drop(&mut self) {
-        terminal::disable_raw_mode().unwrap();
-        execute!(stdout(), terminal::LeaveAlternateScreen).unwrap();
-        execute!(stdout(), cursor::Show).unwrap();
+    #[test]
+    fn reads_the_largest_packet_whole() {
+        let mut bytes = vec![255, 3];
+        bytes.extend([b'a'; 254]);
+        let packet = read_bytes(&bytes).unwrap();
+        assert_eq!(packet.packet_type, PacketType::Warn);
+        assert_eq!(packet.text, Some("a".repeat(254)));
     }
-}
 
-use std::time::{SystemTime, UNIX_EPOCH};
+    #[test]
+    fn reads_a_largest_packet_arriving_in_pieces() {
+        let (mut client, mut server) = MemoryStream::pair();
+        let writer = thread::spawn(move || {
+            client.write_all(&[255, 3]).unwrap();
+            for _ in 0..2 {
+                thread::sleep(Duration::from_millis(20));
+                client.write_all(&[b'a'; 127]).unwrap();
+            }
+        });
+        assert_eq!(read_packet(&mut server).unwrap().text, Some("a".repeat(254)));
+        writer.join().unwrap();
+    }
 
-enum LogItem {
-    PacketLogItem {
-        timestamp: SystemTime,
-        peer_addr: SocketAddr,
-        packet: Packet,
-    },
-    ConnectLogItem {
-        timestamp: SystemTime,
-        peer_addr: SocketAddr,
-    },
-    DisconnectLogItem {
-        timestamp: SystemTime,
-        peer_addr: SocketAddr,
+    //A packet of the type and text, read with a --max-payload of len under the policy.
+    #[test]
+    fn logs_invalid_utf8_with_a_hex_dump_of_what_was_sent() {
+        let path = std::env::temp_dir().join(format!("ww-invalid-utf8-test-{}.log", std::process::id()));
+        let log = Arc::new(Mutex::new(Log::new(Some(File::create(&path).unwrap()))));
+        let (mut client, mut server) = MemoryStream::pair();
+        client.write_all(&[6, PacketType::Warn.to_type_number(), b'd', b'i', 0xff, b's', 0xc3]).unwrap();
+        client.write_all(&[5, PacketType::Warn.to_type_number(), b'd', b'i', b's', b'k']).unwrap();
+
+        let packet = handle_packet(&mut server, "peer", Arc::clone(&log), &Metrics::new(), &Redactions::new(), None).unwrap();
+        assert!(packet.has_invalid_utf8);
+        assert_eq!(packet.text.as_deref(), Some("di\u{FFFD}s\u{FFFD}"));
+        let packet = handle_packet(&mut server, "peer", Arc::clone(&log), &Metrics::new(), &Redactions::new(), None).unwrap();
+        assert!(!packet.has_invalid_utf8);
+
+        drop(log);
+        let logged = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let warnings: Vec<&str> = logged.lines().filter(|line| line.starts_with("WARN:")).collect();
+        assert_eq!(warnings, ["WARN: peer sent WARN packet with invalid UTF-8 text, shown with replacement characters. The bytes were: 64 69 ff 73 c3"]);
     }
-}
 
-impl LogItem {
-    fn timestamp(&self) -> SystemTime {
-        match self {
-            LogItem::PacketLogItem { timestamp, .. } => *timestamp,
-            LogItem::ConnectLogItem { timestamp, .. } => *timestamp,
-            LogItem::DisconnectLogItem { timestamp, .. } => *timestamp,
+    fn read_capped(packet_type: PacketType, text: &str, len: usize, oversize: Oversize) -> Result<Packet, Error> {
+        let mut bytes = vec![text.len() as u8 + 1, packet_type.to_type_number()];
+        bytes.extend_from_slice(text.as_bytes());
+        let (mut client, mut server) = MemoryStream::pair();
+        client.write_all(&bytes).unwrap();
+        return handle_packet(&mut server, "peer", test_log(), &Metrics::new(), &Redactions::new(), Some(MaxPayload { len: len, oversize: oversize }));
+    }
+
+    #[test]
+    fn leaves_text_within_the_max_payload_alone() {
+        for oversize in [Oversize::Truncate, Oversize::Reject] {
+            assert_eq!(read_capped(PacketType::Warn, "disk full", 9, oversize).unwrap().text.as_deref(), Some("disk full"));
+            assert_eq!(read_capped(PacketType::Warn, "disk", 9, oversize).unwrap().text.as_deref(), Some("disk"));
         }
     }
-}
 
-struct State {
-    warn_state: WarnStates,
-    warn_state_ascii_art: WarnStateAsciiArt,
-    window_should_close: bool,
-    packet_log: VecDeque<LogItem>,
-    peer_names: HashMap<SocketAddr, String>,
+    #[test]
+    fn truncates_text_over_the_max_payload_between_characters() {
+        assert_eq!(read_capped(PacketType::Warn, "disk full", 4, Oversize::Truncate).unwrap().text.as_deref(), Some("disk"));
+        assert_eq!(read_capped(PacketType::Alert, "dé", 2, Oversize::Truncate).unwrap().text.as_deref(), Some("d"));
+    }
 
-    is_focused_mode: bool,
-}
+    #[test]
+    fn rejects_text_over_the_max_payload() {
+        assert!(read_capped(PacketType::Warn, "disk full", 4, Oversize::Reject).is_err());
+        //Names have a limit of their own.
+        assert_eq!(read_capped(PacketType::Name, "database", 4, Oversize::Reject).unwrap().text.as_deref(), Some("database"));
+    }
 
-struct RenderState {
-    focused_mode_changed: bool,
-    warn_state_changed: bool,
-    packet_log_changed: bool,
+    #[test]
+    fn rejects_requests_that_are_not_association_requests() {
+        let (mut client, mut server) = MemoryStream::pair();
+        client.write_all(&BARE_WARN).unwrap();
+        assert!(handle_association(&mut server).is_err());
+    }
 
-    //For when everything needs to be re-rendered e.g. on resize.
-    clear_background: bool,
-}
+    #[test]
+    fn resets_no_lower_than_the_floor() {
+        let mut channel = Channel::new("default".to_string(), WarnStates::None);
+        channel.warn_state = WarnStates::Alert;
+        channel.is_acknowledged = true;
+        channel.host_states.insert(test_peer().host(), WarnStates::Alert);
+
+        reset_warn_state(&mut channel, WarnStates::Warn);
+        assert!(channel.warn_state == WarnStates::Warn);
+        assert!(channel.host_states.is_empty());
+        assert!(!channel.is_acknowledged);
+    }
 
-impl RenderState {
-    fn new() -> Self {
-        return RenderState {
-            focused_mode_changed: false,
-            warn_state_changed: false,
-            packet_log_changed: false,
+    //Hooks run in the background, so wait a while for what they write.
+    #[cfg(unix)]
+    fn wait_for_lines(path: &std::path::Path, num_lines: usize) -> String {
+        for _ in 0..100 {
+            if let Ok(contents) = std::fs::read_to_string(path) {
+                if contents.lines().count() >= num_lines && contents.ends_with('\n') {
+                    return contents;
+                }
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("{} never had {} lines", path.display(), num_lines);
+    }
 
-            clear_background: false,
-        };
+    #[cfg(unix)]
+    #[test]
+    fn runs_hooks_once_on_entering_and_leaving_alert() {
+        let path = std::env::temp_dir().join(format!("ww-hooks-test-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut state = test_state();
+        let append = format!("echo \"$0 $1 $2\" >> '{}'", path.display());
+        state.hooks = Hooks { on_alert: Some(append.replace("$0", "alert")), on_reset: Some(append.replace("$0", "reset")) };
+        log_packets(&mut state, vec![packet_item(PacketType::Alert, Some("db down"))]);
+
+        state.active_mut().warn_state = WarnStates::Alert;
+        run_hooks(&mut state, &test_log());
+        assert_eq!(wait_for_lines(&path, 1), "alert default db down\n");
+
+        //Staying at ALERT runs nothing more.
+        run_hooks(&mut state, &test_log());
+        reset_warn_state(state.active_mut(), WarnStates::None);
+        run_hooks(&mut state, &test_log());
+        assert_eq!(wait_for_lines(&path, 2), "alert default db down\nreset default db down\n");
+        std::fs::remove_file(&path).unwrap();
     }
 
-    fn rerender_all() -> Self {
-        return RenderState {
-            focused_mode_changed: true,
-            warn_state_changed: true,
-            packet_log_changed: true,
+    //handle_connection on the server's end of a MemoryStream, with no flags.
+    fn connect(server: MemoryStream) -> Receiver<LogItem> {
+        let (tx, rx) = channel();
+        handle_connection(server, test_peer(), tx, test_log(), Arc::new(Metrics::new()), None, None, Arc::new(Redactions::new()), None, None, None);
+        return rx;
+    }
 
-            clear_background: true,
-        };
+    #[test]
+    fn associates_a_request_split_across_writes() {
+        let (mut client, server) = MemoryStream::pair();
+        let rx = connect(server);
+        client.write_all(&ASSOC_REQUEST[..1]).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        client.write_all(&ASSOC_REQUEST[1..]).unwrap();
+
+        let mut accept = [0; 2];
+        client.read_exact(&mut accept).unwrap();
+        assert_eq!(accept, ASSOC_ACCEPT);
+        assert!(matches!(rx.recv().unwrap(), LogItem::ConnectLogItem { .. }));
     }
-}
 
-fn print_usage() {
-    eprintln!("Usage: ww [Options]");
-    eprintln!("Accept networked notifications from client programs.");
+    #[test]
+    fn drops_a_connection_that_fails_to_associate_without_panicking() {
+        let (mut client, server) = MemoryStream::pair();
+        let rx = connect(server);
+        client.write_all(&BARE_WARN).unwrap();
 
-    eprintln!("--info-art <Path>: Change the info art with text found at Path. Art must be rectangular to render properly.");
-    eprintln!("--warn-art <Path>: Change the warn art with text found at Path. Art must be rectangular to render properly.");
-    eprintln!("--alert-art <Path>: Change the alert art with text found at Path. Art must be rectangular to render properly.");
+        //The connection thread returns, dropping its end and its sender, and logs nothing more.
+        assert!(rx.recv().is_err());
+        assert_eq!(client.read(&mut [0; 2]).unwrap(), 0);
+    }
 
-    eprintln!("--help: Show usage and exit.");
-}
+    //A WARN with the sequence number, as a client with sequence numbers enabled sends it.
+    fn sequenced_warn(sequence: u32) -> Vec<u8> {
+        let mut bytes = vec![6, PacketType::Warn.to_type_number() | SEQUENCE_FLAG];
+        bytes.extend_from_slice(&sequence.to_be_bytes());
+        bytes.push(b'x');
+        return bytes;
+    }
 
-use std::fs::File;
-use std::sync::Arc;
-use std::sync::Mutex;
-use std::collections::{VecDeque, HashMap};
-use std::env;
+    //What handle_connection passes on for the sequence numbers, after associating: None for a
+    //packet, and how many were missed for a gap.
+    fn gaps(sequences: &[u32]) -> Vec<Option<u32>> {
+        let (mut client, server) = MemoryStream::pair();
+        let rx = connect(server);
+        client.write_all(&ASSOC_REQUEST).unwrap();
+        client.read_exact(&mut [0; 2]).unwrap();
+        for sequence in sequences {
+            client.write_all(&sequenced_warn(*sequence)).unwrap();
+        }
+        drop(client);
 
-fn main() -> io::Result<()> {
-    // env::set_var("RUST_BACKTRACE", "1");
-    let args: Vec<String> = env::args().collect();
+        return rx.iter().filter_map(|log_item| match log_item {
+            LogItem::GapLogItem { missed, .. } => Some(Some(missed)),
+            LogItem::PacketLogItem { .. } => Some(None),
+            _ => None,
+        }).collect();
+    }
 
-    if args.iter().any(|arg| arg == "--help") {
-        print_usage();
-        std::process::exit(0);
+    #[test]
+    fn logs_no_gap_for_sequence_numbers_in_order() {
+        assert_eq!(gaps(&[0, 1, 2, 3]), [None, None, None, None]);
+        //Counting on past the largest number isn't a gap either.
+        assert_eq!(gaps(&[u32::MAX, 0]), [None, None]);
     }
 
-    let listening_port: u16;
-    if let Some(i) = args.iter().position(|arg| arg == "-p") {
-        if i + 1 < args.len() {
-            listening_port = args[i + 1].parse().unwrap_or_else(|_| {
-                print_usage();
-                std::process::abort();
-            });
-        }
-        else {
-            listening_port = 44444;
-        }
+    #[test]
+    fn logs_a_gap_before_the_packet_that_reveals_it() {
+        assert_eq!(gaps(&[0, 1, 4, 5]), [None, None, Some(2), None, None]);
+        assert_eq!(gaps(&[7, 9, 10, 20]), [None, Some(1), None, None, Some(9), None]);
     }
-    else {
-        listening_port = 44444;
+
+    #[test]
+    fn logs_no_gap_when_the_client_starts_counting_again() {
+        assert_eq!(gaps(&[0, 1, 2, 0, 1]), [None, None, None, None, None]);
+        assert_eq!(gaps(&[5, 5]), [None, None]);
     }
 
-    let info_art;
-    if let Some(i) = args.iter().position(|arg| arg == "--info-art") {
-        if i + 1 < args.len() {
-            info_art = std::fs::read_to_string(args[i + 1].clone()).unwrap_or_else(|_| {
-                print_usage();
-                std::process::abort();
-            });
-        }
-        else {
-            info_art = WarnStateAsciiArt::default_info_art();
+    //The server's end of a MemoryStream whose connection is reset, rather than closed, when the
+    //client's end goes: where a read would return 0 bytes it fails with ConnectionReset.
+    struct ResetStream(MemoryStream);
+
+    impl Read for ResetStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf) {
+                Ok(0) if buf.len() > 0 => return Err(Error::from(ErrorKind::ConnectionReset)),
+                result => return result,
+            }
         }
     }
-    else {
-        info_art = WarnStateAsciiArt::default_info_art();
-    }
 
-    let warn_art;
-    if let Some(i) = args.iter().position(|arg| arg == "--warn-art") {
-        if i + 1 < args.len() {
-            warn_art = std::fs::read_to_string(args[i + 1].clone()).unwrap_or_else(|_| {
-                print_usage();
-                std::process::abort();
-            });
+    impl Write for ResetStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            return self.0.write(buf);
         }
-        else {
-            warn_art = WarnStateAsciiArt::default_warn_art();
+
+        fn flush(&mut self) -> io::Result<()> {
+            return self.0.flush();
         }
     }
-    else {
-        warn_art = WarnStateAsciiArt::default_warn_art();
-    }
 
-    let alert_art;
-    if let Some(i) = args.iter().position(|arg| arg == "--alert-art") {
-        if i + 1 < args.len() {
-            alert_art = std::fs::read_to_string(args[i + 1].clone()).unwrap_or_else(|_| {
-                print_usage();
-                std::process::abort();
-            });
+    impl Stream for ResetStream {
+        fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+            return Stream::set_read_timeout(&self.0, dur);
         }
-        else {
-            alert_art = WarnStateAsciiArt::default_alert_art();
+
+        fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+            return Stream::set_write_timeout(&self.0, dur);
         }
-    }
-    else {
-        alert_art = WarnStateAsciiArt::default_alert_art();
-    }
 
-    let mut state = State {
-        warn_state: WarnStates::None,
-        warn_state_ascii_art: WarnStateAsciiArt::build(info_art, warn_art, alert_art),
-        window_should_close: false,
-        packet_log: VecDeque::new(),
-        peer_names: HashMap::new(),
+        fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+            return Stream::set_nodelay(&self.0, nodelay);
+        }
 
-        is_focused_mode: false,
-    };
-    let mut render_state = RenderState::rerender_all();
-    let mut frame_number: usize = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards.").as_secs() as usize;    //test value 36041;
+        fn try_clone_writer(&self) -> io::Result<Box<dyn Write + Send>> {
+            return self.0.try_clone_writer();
+        }
+    }
 
-    let log = Arc::new(Mutex::new(File::create("./warning_window.log")?));
+    //How handle_connection says the connection on server ended, once the client has associated,
+    //sent bytes, and gone.
+    fn how_disconnected<S: Stream + Send + 'static>(mut client: MemoryStream, server: S, bytes: &[u8]) -> Disconnect {
+        let (tx, rx) = channel();
+        handle_connection(server, test_peer(), tx, test_log(), Arc::new(Metrics::new()), None, None, Arc::new(Redactions::new()), None, None, None);
+        client.write_all(&ASSOC_REQUEST).unwrap();
+        client.read_exact(&mut [0; 2]).unwrap();
+        client.write_all(bytes).unwrap();
+        drop(client);
+
+        return rx.iter().find_map(|log_item| match log_item {
+            LogItem::DisconnectLogItem { how, .. } => Some(how),
+            _ => None,
+        }).unwrap();
+    }
 
-    //Init the window, clean up on drop.
-    let _wc = WindowContext::new();
+    #[test]
+    fn tells_a_clean_close_from_a_reset() {
+        let (client, server) = MemoryStream::pair();
+        assert!(how_disconnected(client, server, &BARE_WARN) == Disconnect::Clean);
 
-    let (tx, rx) = channel::<LogItem>();
-    let mut _log = Arc::clone(&log);
+        let (client, server) = MemoryStream::pair();
+        assert!(how_disconnected(client, ResetStream(server), &BARE_WARN) == Disconnect::Lost);
+    }
 
-    //The connection_manager thread lives as long as main.
-    //It never exits, and continually handles incoming connections.
-    let _connection_manager = thread::spawn(move || {
-        let listener = TcpListener::bind(format!("localhost:{}", listening_port)).unwrap();
+    #[test]
+    fn counts_a_packet_cut_short_as_lost_and_junk_as_dropped() {
+        let (client, server) = MemoryStream::pair();
+        assert!(how_disconnected(client, server, &[5, 3, b'd', b'i']) == Disconnect::Lost);
 
-        for connection in listener.incoming() {
-            let mut __log = Arc::clone(&_log);
-            match connection {
-                Ok(c) => handle_connection(c, tx.clone(), __log),
-                Err(e) => {
-                    writeln!(_log.lock().unwrap(), "ERROR: {}", e).unwrap();
-                }
-            }
+        let (client, server) = MemoryStream::pair();
+        assert!(how_disconnected(client, server, &[0]) == Disconnect::Dropped);
+    }
+
+    #[test]
+    fn reaps_an_associated_client_idle_past_the_timeout() {
+        let (mut client, server) = MemoryStream::pair();
+        let (tx, rx) = channel();
+        let idle_timeout = Duration::from_millis(200);
+        handle_connection(server, test_peer(), tx, test_log(), Arc::new(Metrics::new()), None, Some(idle_timeout), Arc::new(Redactions::new()), None, None, None);
+        client.write_all(&ASSOC_REQUEST).unwrap();
+        client.read_exact(&mut [0; 2]).unwrap();
+        assert!(matches!(rx.recv().unwrap(), LogItem::ConnectLogItem { .. }));
+
+        //Each packet starts the clock over, so a client sending more often than that stays.
+        let start = Instant::now();
+        for _ in 0..4 {
+            thread::sleep(idle_timeout / 2);
+            client.write_all(&BARE_WARN).unwrap();
+            assert!(matches!(rx.recv().unwrap(), LogItem::PacketLogItem { .. }));
         }
-    });
+        assert!(start.elapsed() > idle_timeout);
 
-    while !state.window_should_close {
-        //update() will poll for keypresses -- if there are none it continues after 500 ms.
-        update(&mut state, &mut render_state, &rx, Arc::clone(&log))?;
-        //Always render -- after 500 ms or when a key is pressed.
-        render(&state, &mut render_state, Arc::clone(&log), frame_number)?;
-        frame_number = frame_number.wrapping_add(1);
+        //Then it goes quiet, and is dropped.
+        let quiet_since = Instant::now();
+        assert!(matches!(rx.recv().unwrap(), LogItem::DisconnectLogItem { how: Disconnect::Dropped, .. }));
+        assert!(quiet_since.elapsed() >= idle_timeout / 2);
+        assert_eq!(client.read(&mut [0; 2]).unwrap(), 0);
     }
-
-    return Ok(());
 }
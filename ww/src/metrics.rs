@@ -0,0 +1,247 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{PacketType, WarnStates};
+
+//Counters for monitoring ww itself. They are always kept, as they are cheap; serving them over
+//HTTP is behind the "metrics" feature.
+pub struct Metrics {
+    packets_info: AtomicU64,
+    packets_warn: AtomicU64,
+    packets_alert: AtomicU64,
+    packets_name: AtomicU64,
+    packets_reset: AtomicU64,
+    packets_last_will: AtomicU64,
+    packets_metadata: AtomicU64,
+    packets_critical: AtomicU64,
+    //Packets the server refused to parse, which always closes the connection.
+    packets_rejected: AtomicU64,
+    connected_clients: AtomicU64,
+    warn_state: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        return Metrics {
+            packets_info: AtomicU64::new(0),
+            packets_warn: AtomicU64::new(0),
+            packets_alert: AtomicU64::new(0),
+            packets_name: AtomicU64::new(0),
+            packets_reset: AtomicU64::new(0),
+            packets_last_will: AtomicU64::new(0),
+            packets_metadata: AtomicU64::new(0),
+            packets_critical: AtomicU64::new(0),
+            packets_rejected: AtomicU64::new(0),
+            connected_clients: AtomicU64::new(0),
+            warn_state: AtomicU64::new(0),
+        };
+    }
+
+    pub fn count_packet(&self, packet_type: &PacketType) {
+        let counter = match packet_type {
+            PacketType::Info => &self.packets_info,
+            PacketType::Warn => &self.packets_warn,
+            PacketType::Alert => &self.packets_alert,
+            PacketType::Name => &self.packets_name,
+            PacketType::Reset => &self.packets_reset,
+            PacketType::LastWill => &self.packets_last_will,
+            PacketType::Metadata => &self.packets_metadata,
+            PacketType::Critical => &self.packets_critical,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_rejected(&self) {
+        self.packets_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_connected(&self) {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_disconnected(&self) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn set_warn_state(&self, warn_state: &WarnStates) {
+        let value = match warn_state {
+            WarnStates::None => 0,
+            WarnStates::Warn => 1,
+            WarnStates::Alert => 2,
+            WarnStates::Critical => 3,
+        };
+        self.warn_state.store(value, Ordering::Relaxed);
+    }
+
+    //Format the counters in the Prometheus text exposition format.
+    #[cfg(feature = "metrics")]
+    fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP ww_packets_total Packets received, by packet type.\n");
+        out.push_str("# TYPE ww_packets_total counter\n");
+        let packets = [
+            ("info", &self.packets_info),
+            ("warn", &self.packets_warn),
+            ("alert", &self.packets_alert),
+            ("name", &self.packets_name),
+            ("reset", &self.packets_reset),
+            ("last_will", &self.packets_last_will),
+            ("metadata", &self.packets_metadata),
+            ("critical", &self.packets_critical),
+        ];
+        for (packet_type, counter) in packets {
+            out.push_str(&format!("ww_packets_total{{type=\"{}\"}} {}\n", packet_type, counter.load(Ordering::Relaxed)));
+        }
+
+        out.push_str("# HELP ww_packets_rejected_total Malformed packets that caused a connection to be dropped.\n");
+        out.push_str("# TYPE ww_packets_rejected_total counter\n");
+        out.push_str(&format!("ww_packets_rejected_total {}\n", self.packets_rejected.load(Ordering::Relaxed)));
+
+        out.push_str("# HELP ww_connected_clients Currently associated clients.\n");
+        out.push_str("# TYPE ww_connected_clients gauge\n");
+        out.push_str(&format!("ww_connected_clients {}\n", self.connected_clients.load(Ordering::Relaxed)));
+
+        out.push_str("# HELP ww_warn_state Current warn state: 0 is NONE, 1 is WARN, 2 is ALERT, 3 is CRITICAL.\n");
+        out.push_str("# TYPE ww_warn_state gauge\n");
+        out.push_str(&format!("ww_warn_state {}\n", self.warn_state.load(Ordering::Relaxed)));
+
+        return out;
+    }
+}
+
+#[cfg(feature = "metrics")]
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+#[cfg(feature = "metrics")]
+use crate::logger::Log;
+
+//How long a scrape may take to send its request or read the response. Connections are answered
+//one at a time, so one that stalls holds up the rest until then.
+#[cfg(feature = "metrics")]
+const METRICS_TIMEOUT: Duration = Duration::from_secs(1);
+
+//Serve the metrics at /metrics on their own thread. This is a bare-minimum HTTP/1.0 responder:
+//it reads one request per connection, answers it, and closes.
+#[cfg(feature = "metrics")]
+pub fn serve(port: u16, metrics: Arc<Metrics>, log: Arc<Mutex<Log>>) {
+    let _metrics_server = thread::spawn(move || {
+        let listener = match TcpListener::bind(format!("localhost:{}", port)) {
+            Ok(l) => l,
+            Err(e) => {
+                writeln!(log.lock().unwrap(), "ERROR: Could not bind metrics port {}: {}", port, e).unwrap();
+                return;
+            },
+        };
+        respond(listener, &metrics);
+    });
+}
+
+//Answer each connection to listener in turn, for as long as it's open.
+#[cfg(feature = "metrics")]
+fn respond(listener: TcpListener, metrics: &Metrics) {
+    for connection in listener.incoming() {
+        let mut connection = match connection {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+        if connection.set_read_timeout(Some(METRICS_TIMEOUT)).is_err() || connection.set_write_timeout(Some(METRICS_TIMEOUT)).is_err() {
+            continue;
+        }
+
+        let mut buf: [u8; 1024] = [0; 1024];
+        let num_bytes_read = match connection.read(&mut buf) {
+            Ok(n) => n,
+            Err(_) => continue,
+        };
+
+        let response;
+        if buf[..num_bytes_read].starts_with(b"GET /metrics ") {
+            let body = metrics.to_prometheus();
+            response = format!(
+                "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+        }
+        else {
+            response = "HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string();
+        }
+
+        //The scraper will retry; nothing to do if it has gone away.
+        let _ = connection.write_all(response.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_packets_by_type() {
+        let metrics = Metrics::new();
+        metrics.count_packet(&PacketType::Warn);
+        metrics.count_packet(&PacketType::Warn);
+        metrics.count_packet(&PacketType::Critical);
+        metrics.count_rejected();
+        assert_eq!(metrics.packets_warn.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.packets_critical.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.packets_info.load(Ordering::Relaxed), 0);
+        assert_eq!(metrics.packets_rejected.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn tracks_connected_clients_and_the_warn_state() {
+        let metrics = Metrics::new();
+        metrics.client_connected();
+        metrics.client_connected();
+        metrics.client_disconnected();
+        metrics.set_warn_state(&WarnStates::Alert);
+        assert_eq!(metrics.connected_clients.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.warn_state.load(Ordering::Relaxed), 2);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn formats_the_counters_for_prometheus() {
+        let metrics = Metrics::new();
+        metrics.count_packet(&PacketType::LastWill);
+        metrics.set_warn_state(&WarnStates::Critical);
+        let text = metrics.to_prometheus();
+        assert!(text.contains("ww_packets_total{type=\"last_will\"} 1\n"));
+        assert!(text.contains("ww_packets_total{type=\"info\"} 0\n"));
+        assert!(text.contains("ww_warn_state 3\n"));
+        assert!(text.contains("# TYPE ww_connected_clients gauge\n"));
+    }
+
+    #[cfg(feature = "metrics")]
+    fn scrape(addr: std::net::SocketAddr, request: &str) -> String {
+        let mut connection = std::net::TcpStream::connect(addr).unwrap();
+        connection.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        connection.read_to_string(&mut response).unwrap();
+        return response;
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn answers_past_a_connection_that_never_sends() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _server = thread::spawn(move || respond(listener, &Metrics::new()));
+
+        let _stalled = std::net::TcpStream::connect(addr).unwrap();
+        let start = std::time::Instant::now();
+        let response = scrape(addr, "GET /metrics HTTP/1.0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response.contains("ww_warn_state 0"));
+        assert!(start.elapsed() < METRICS_TIMEOUT * 3);
+
+        assert!(scrape(addr, "GET / HTTP/1.0\r\n\r\n").starts_with("HTTP/1.0 404 Not Found"));
+    }
+}
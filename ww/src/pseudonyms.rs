@@ -0,0 +1,73 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    net::IpAddr,
+};
+
+use crate::Peer;
+
+//With --anonymize-peers, client IPs are shown and logged as pseudonyms such as peer-3fa2c41e, for
+//dashboards on a shared screen, in screenshots or in public demos. A host keeps its pseudonym for
+//as long as ww runs, so its entries can still be followed and told apart, but the hash is keyed
+//afresh each run, so a pseudonym can't be looked up against a list of IPs. The real addresses stay
+//in memory only, for the operator to reveal with p.
+pub struct Pseudonyms {
+    key: RandomState,
+}
+
+impl Pseudonyms {
+    pub fn new() -> Self {
+        return Pseudonyms { key: RandomState::new() };
+    }
+
+    pub fn show_ip(&self, ip: &IpAddr) -> String {
+        //32 bits, so two hosts sharing a pseudonym is as good as unheard of.
+        return format!("peer-{:08x}", self.key.hash_one(ip) as u32);
+    }
+
+    //The port stays, so clients on the same host can still be told apart. Unix socket clients
+    //have no address to hide.
+    pub fn show(&self, peer: &Peer) -> String {
+        match peer {
+            Peer::Tcp(addr) => return format!("{}:{}", self.show_ip(&addr.ip()), addr.port()),
+            #[cfg(unix)]
+            Peer::Unix(_) => return peer.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_a_host_under_one_pseudonym() {
+        let pseudonyms = Pseudonyms::new();
+        let ip: IpAddr = "192.168.1.7".parse().unwrap();
+        let pseudonym = pseudonyms.show_ip(&ip);
+        assert_eq!(pseudonyms.show_ip(&ip), pseudonym);
+        assert_eq!(pseudonyms.show(&Peer::Tcp("192.168.1.7:51884".parse().unwrap())), format!("{}:51884", pseudonym));
+        assert_eq!(pseudonyms.show(&Peer::Tcp("192.168.1.7:40000".parse().unwrap())), format!("{}:40000", pseudonym));
+    }
+
+    #[test]
+    fn shows_pseudonyms_as_eight_hex_digits() {
+        let pseudonym = Pseudonyms::new().show_ip(&"10.0.0.1".parse().unwrap());
+        let digits = pseudonym.strip_prefix("peer-").unwrap();
+        assert_eq!(digits.len(), 8);
+        assert!(digits.chars().all(|c| c.is_ascii_hexdigit()));
+        assert!(!pseudonym.contains("10.0.0.1"));
+    }
+
+    //Among a couple of hundred hosts, 16 bits gave two the same pseudonym about a quarter of the
+    //time; 32 bits all but never do.
+    #[test]
+    fn tells_hosts_apart() {
+        let pseudonyms = Pseudonyms::new();
+        let mut shown = std::collections::HashSet::new();
+        for i in 0..200u32 {
+            let ip = IpAddr::from(std::net::Ipv4Addr::from(0x0a00_0000 + i));
+            assert!(shown.insert(pseudonyms.show_ip(&ip)), "{} shares a pseudonym", ip);
+        }
+    }
+}
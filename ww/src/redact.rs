@@ -0,0 +1,97 @@
+use api::MAX_COMPRESSED_MSG_LEN;
+use regex::Regex;
+
+use crate::truncate_to_bytes;
+
+//With --redact, packet text is rewritten as it arrives, before it is logged, shown or forwarded, so
+//secrets a client puts in its messages never reach the log file or the screen.
+pub struct Redactions {
+    rules: Vec<(Regex, String)>,
+}
+
+impl Redactions {
+    pub fn new() -> Self {
+        return Redactions { rules: Vec::new() };
+    }
+
+    //Add a rule from a --redact argument, <Regex>=<Replacement>, split at the last '=' so the
+    //regex may contain one. The replacement may refer to capture groups, as in $1.
+    pub fn add(&mut self, spec: &str) -> Result<(), String> {
+        let (pattern, replacement) = match spec.rsplit_once('=') {
+            Some(split) => split,
+            None => return Err(format!("\"{}\" is not <Regex>=<Replacement>.", spec)),
+        };
+        let regex = match Regex::new(pattern) {
+            Ok(regex) => regex,
+            Err(e) => return Err(format!("Could not compile \"{}\": {}", pattern, e)),
+        };
+        self.rules.push((regex, replacement.to_string()));
+        return Ok(());
+    }
+
+    //Apply every rule in the order given. A replacement longer than what it replaces can lengthen
+    //the text without end, so the result is cut back to the most a packet can carry once
+    //inflated, MAX_COMPRESSED_MSG_LEN bytes, on a character boundary. --broadcast cuts what it
+    //forwards to fit in a packet itself.
+    pub fn apply(&self, text: String) -> String {
+        let mut text = text;
+        for (regex, replacement) in self.rules.iter() {
+            text = regex.replace_all(&text, replacement.as_str()).into_owned();
+        }
+
+        truncate_to_bytes(&mut text, MAX_COMPRESSED_MSG_LEN);
+        return text;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactions(specs: &[&str]) -> Redactions {
+        let mut redactions = Redactions::new();
+        for spec in specs {
+            redactions.add(spec).unwrap();
+        }
+        return redactions;
+    }
+
+    #[test]
+    fn redacts_emails_and_api_keys() {
+        let redactions = redactions(&[r"[\w.+-]+@[\w-]+\.[\w.]+=<email>", r"sk_live_[0-9a-zA-Z]+=<key>"]);
+        assert_eq!(redactions.apply("login failed for ops.team@example.com with sk_live_4eC39HqLyjWDarjtT1".to_string()), "login failed for <email> with <key>");
+        assert_eq!(redactions.apply("nothing to hide".to_string()), "nothing to hide");
+    }
+
+    #[test]
+    fn splits_at_the_last_equals_so_the_regex_may_contain_one() {
+        let redactions = redactions(&["password=\\S+=***"]);
+        assert_eq!(redactions.apply("connect user=db password=hunter2 now".to_string()), "connect user=db *** now");
+    }
+
+    #[test]
+    fn replaces_capture_groups() {
+        let redactions = redactions(&[r"token (\w{4})\w+=token $1..."]);
+        assert_eq!(redactions.apply("bad token abcd1234efgh".to_string()), "bad token abcd...");
+    }
+
+    #[test]
+    fn applies_rules_in_the_order_given() {
+        let redactions = redactions(&["secret=hidden", "hidden=gone"]);
+        assert_eq!(redactions.apply("a secret".to_string()), "a gone");
+    }
+
+    #[test]
+    fn refuses_specs_without_an_equals_or_with_a_bad_regex() {
+        assert!(Redactions::new().add("no replacement").is_err());
+        assert!(Redactions::new().add("(unclosed=x").is_err());
+    }
+
+    #[test]
+    fn cuts_text_lengthened_without_end_back_to_what_a_packet_carries() {
+        let redactions = redactions(&["a=ééé"]);
+        let text = redactions.apply("a".repeat(MAX_COMPRESSED_MSG_LEN));
+        assert!(text.len() <= MAX_COMPRESSED_MSG_LEN);
+        assert!(text.chars().all(|c| c == 'é'));
+    }
+}
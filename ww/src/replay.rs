@@ -0,0 +1,243 @@
+use std::{
+    io::Write,
+    net::SocketAddr,
+    sync::{mpsc::Sender, Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use api::{PacketType, MAX_MSG_LEN, MAX_NAME_LEN};
+
+use crate::{logger::Log, Disconnect, LogItem, Packet, Peer};
+
+//Replayed events come from this address, so they can't be confused with a real client.
+const REPLAY_ADDR: ([u8; 4], u16) = ([0, 0, 0, 0], 0);
+
+pub struct ReplayEvent {
+    delay: Duration,
+    kind: ReplayEventKind,
+}
+
+enum ReplayEventKind {
+    Connect,
+    Disconnect,
+    Packet(PacketType, Option<String>),
+}
+
+//The replay format, one event per line:
+//
+//  +<Seconds> <Event> ["<Text>"]
+//
+//Seconds is the delay after the previous event (or after startup for the first), and may be
+//fractional e.g. +0.5. Event is one of:
+//  info "<Text>"      - text is required.
+//  warn ["<Text>"]
+//  alert ["<Text>"]
+//  critical ["<Text>"]
+//  name "<Text>"      - names the replay client, fewer than MAX_NAME_LEN bytes.
+//  reset
+//  connect            - as if the replay client had just connected.
+//  disconnect         - as if the replay client had just dropped.
+//The quotes around text are optional. Blank lines and lines starting with # are ignored.
+//
+//Example:
+//  # A database falling over.
+//  +0 name "db-monitor"
+//  +0.5 warn "db slow"
+//  +2 alert "db down"
+//  +5 reset
+pub fn parse(contents: &str) -> Result<Vec<ReplayEvent>, String> {
+    let mut events = Vec::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.trim();
+        if line == "" || line.starts_with('#') {
+            continue;
+        }
+
+        let (delay, rest) = match line.split_once(char::is_whitespace) {
+            Some((delay, rest)) => (delay, rest.trim()),
+            None => (line, ""),
+        };
+        let delay = match delay.strip_prefix('+').map(|secs| secs.parse::<f64>()) {
+            Some(Ok(secs)) if secs.is_finite() && secs >= 0.0 => Duration::from_secs_f64(secs),
+            _ => return Err(format!("line {}: expected a delay like +0.5, found \"{}\"", line_number, delay)),
+        };
+
+        let (event, text) = match rest.split_once(char::is_whitespace) {
+            Some((event, text)) => (event, Some(unquote(text.trim()))),
+            None => (rest, None),
+        };
+        let text = text.filter(|text| *text != "");
+
+        let kind = match event {
+            "connect" => ReplayEventKind::Connect,
+            "disconnect" => ReplayEventKind::Disconnect,
+            "info" => ReplayEventKind::Packet(PacketType::Info, text),
+            "warn" => ReplayEventKind::Packet(PacketType::Warn, text),
+            "alert" => ReplayEventKind::Packet(PacketType::Alert, text),
+            "critical" => ReplayEventKind::Packet(PacketType::Critical, text),
+            "name" => ReplayEventKind::Packet(PacketType::Name, text),
+            "reset" => ReplayEventKind::Packet(PacketType::Reset, None),
+            "" => return Err(format!("line {}: missing event after the delay", line_number)),
+            _ => return Err(format!("line {}: unknown event \"{}\"", line_number, event)),
+        };
+
+        //Hold replayed packets to the same limits the server puts on real ones.
+        if let ReplayEventKind::Packet(packet_type, text) = &kind {
+            match (packet_type, text) {
+                (PacketType::Info, None) | (PacketType::Name, None) => {
+                    return Err(format!("line {}: {} needs text", line_number, event));
+                },
+                (PacketType::Name, Some(text)) if text.len() >= MAX_NAME_LEN => {
+                    return Err(format!("line {}: name must be shorter than {} bytes", line_number, MAX_NAME_LEN));
+                },
+                (_, Some(text)) if text.len() > MAX_MSG_LEN => {
+                    return Err(format!("line {}: text is longer than {} bytes", line_number, MAX_MSG_LEN));
+                },
+                _ => (),
+            }
+        }
+
+        events.push(ReplayEvent { delay: delay, kind: kind });
+    }
+
+    return Ok(events);
+}
+
+fn unquote(text: &str) -> String {
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        return text[1..text.len() - 1].to_string();
+    }
+    return text.to_string();
+}
+
+//Feed the events through tx on their own thread, sleeping between them, exactly as if a client
+//had sent them. The network listener keeps running alongside.
+pub fn play(events: Vec<ReplayEvent>, tx: Sender<LogItem>, log: Arc<Mutex<Log>>) {
+    let _replay_thread = thread::spawn(move || {
+        let peer_addr = Peer::Tcp(SocketAddr::from(REPLAY_ADDR));
+        writeln!(log.lock().unwrap(), "INFO: Replaying {} events.", events.len()).unwrap();
+
+        for event in events {
+            thread::sleep(event.delay);
+
+            let log_item = match event.kind {
+                ReplayEventKind::Connect => LogItem::ConnectLogItem {
+                    timestamp: SystemTime::now(),
+                    peer_addr: peer_addr,
+                    host_name: None,
+                },
+                ReplayEventKind::Disconnect => LogItem::DisconnectLogItem {
+                    timestamp: SystemTime::now(),
+                    peer_addr: peer_addr,
+                    how: Disconnect::Clean,
+                },
+                ReplayEventKind::Packet(packet_type, text) => LogItem::PacketLogItem {
+                    timestamp: SystemTime::now(),
+                    peer_addr: peer_addr,
+                    packet: Packet {
+                        packet_type: packet_type,
+                        text: text,
+                        will_type: None,
+                        timestamp: None,
+                        wants_ack: false,
+                        sequence: None,
+                        has_invalid_utf8: false,
+                    },
+                },
+            };
+
+            //The main thread only goes away when ww is quitting.
+            if tx.send(log_item).is_err() {
+                return;
+            }
+        }
+
+        writeln!(log.lock().unwrap(), "INFO: Finished replaying.").unwrap();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn packet(event: &ReplayEvent) -> (PacketType, Option<&str>) {
+        match &event.kind {
+            ReplayEventKind::Packet(packet_type, text) => (*packet_type, text.as_deref()),
+            _ => panic!("not a packet"),
+        }
+    }
+
+    #[test]
+    fn parses_every_event() {
+        let events = parse("# A database falling over.\n\n+0 name \"db-monitor\"\n+0.5 warn \"db slow\"\n+2 alert db down\n+0 critical\n+5 reset\n+1 info \"up\"\n+0 connect\n+0 disconnect\n").unwrap();
+        assert_eq!(events.len(), 8);
+        assert_eq!(packet(&events[0]), (PacketType::Name, Some("db-monitor")));
+        assert_eq!(packet(&events[1]), (PacketType::Warn, Some("db slow")));
+        assert_eq!(events[1].delay, Duration::from_millis(500));
+        assert_eq!(packet(&events[2]), (PacketType::Alert, Some("db down")));
+        assert_eq!(packet(&events[3]), (PacketType::Critical, None));
+        assert_eq!(packet(&events[4]), (PacketType::Reset, None));
+        assert_eq!(packet(&events[5]), (PacketType::Info, Some("up")));
+        assert!(matches!(events[6].kind, ReplayEventKind::Connect));
+        assert!(matches!(events[7].kind, ReplayEventKind::Disconnect));
+    }
+
+    #[test]
+    fn accepts_names_shorter_than_the_server_limit() {
+        let name = "a".repeat(MAX_NAME_LEN - 1);
+        let events = parse(&format!("+0 name {}", name)).unwrap();
+        assert_eq!(packet(&events[0]), (PacketType::Name, Some(name.as_str())));
+    }
+
+    //The server drops names of MAX_NAME_LEN bytes or more, so a replay mustn't send them.
+    #[test]
+    fn rejects_names_the_server_would_drop() {
+        for len in [MAX_NAME_LEN, MAX_NAME_LEN + 1] {
+            let e = parse(&format!("+0 name {}", "a".repeat(len))).err().unwrap();
+            assert_eq!(e, format!("line 1: name must be shorter than {} bytes", MAX_NAME_LEN));
+        }
+    }
+
+    #[test]
+    fn rejects_text_longer_than_a_packet_holds() {
+        assert!(parse(&format!("+0 warn {}", "a".repeat(MAX_MSG_LEN))).is_ok());
+        assert!(parse(&format!("+0 warn {}", "a".repeat(MAX_MSG_LEN + 1))).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert_eq!(parse("+0 info").err().unwrap(), "line 1: info needs text");
+        assert_eq!(parse("+0 name \"\"").err().unwrap(), "line 1: name needs text");
+        assert_eq!(parse("# comment\n+1").err().unwrap(), "line 2: missing event after the delay");
+        assert_eq!(parse("+1 shout").err().unwrap(), "line 1: unknown event \"shout\"");
+        for delay in ["1", "+x", "+-1", "+inf"] {
+            assert!(parse(&format!("{} warn", delay)).is_err(), "{} was accepted", delay);
+        }
+    }
+
+    #[test]
+    fn plays_events_from_the_replay_address() {
+        let (tx, rx) = mpsc::channel();
+        play(parse("+0 connect\n+0 warn \"db slow\"").unwrap(), tx, Arc::new(Mutex::new(Log::new(None))));
+
+        let replay_peer = Peer::Tcp(SocketAddr::from(REPLAY_ADDR));
+        match rx.recv().unwrap() {
+            LogItem::ConnectLogItem { peer_addr, .. } => assert!(peer_addr == replay_peer),
+            _ => panic!("expected a connect"),
+        }
+        match rx.recv().unwrap() {
+            LogItem::PacketLogItem { peer_addr, packet, .. } => {
+                assert!(peer_addr == replay_peer);
+                assert_eq!(packet.packet_type, PacketType::Warn);
+                assert_eq!(packet.text.as_deref(), Some("db slow"));
+            },
+            _ => panic!("expected a packet"),
+        }
+        //The replay thread finishes, dropping its sender.
+        assert!(rx.recv().is_err());
+    }
+}
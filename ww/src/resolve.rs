@@ -0,0 +1,54 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    net::IpAddr,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::logger::Log;
+
+//How long a reverse lookup may hold up a new connection before its address is shown instead.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+//With --resolve-peers, a client's IP is looked up in reverse DNS when it connects, so hosts that
+//haven't sent a NAME can still be told apart at a glance. Lookups run on the connection's thread,
+//never the render loop, and the result, a name or none, is kept for as long as ww runs, so a slow
+//or unreachable DNS server only holds up the first connection from each host.
+pub struct Resolver {
+    names: Mutex<HashMap<IpAddr, Option<String>>>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        return Resolver { names: Mutex::new(HashMap::new()) };
+    }
+
+    //The host name of ip, if it has one. shown_peer is how the peer appears in the log.
+    pub fn resolve(&self, ip: IpAddr, shown_peer: &str, log: &Arc<Mutex<Log>>) -> Option<String> {
+        if let Some(name) = self.names.lock().unwrap().get(&ip) {
+            return name.clone();
+        }
+
+        //getnameinfo can't be given a timeout, so it runs on a thread of its own that is left
+        //behind if it takes too long. An IPv4 client of a dual-stack listener arrives IPv4-mapped,
+        //which the IPv4 reverse zone knows nothing about.
+        let (tx, rx) = mpsc::channel();
+        let canonical_ip = ip.to_canonical();
+        thread::spawn(move || {
+            let _ = tx.send(dns_lookup::lookup_addr(&canonical_ip));
+        });
+        let name = match rx.recv_timeout(LOOKUP_TIMEOUT) {
+            Ok(Ok(name)) => Some(name),
+            //Most addresses on a LAN have no name, which is no reason to log anything.
+            Ok(Err(_)) => None,
+            Err(_) => {
+                writeln!(log.lock().unwrap(), "WARN: Looking up the host name of {} took over {}s; showing its address.", shown_peer, LOOKUP_TIMEOUT.as_secs()).unwrap();
+                None
+            },
+        };
+        self.names.lock().unwrap().insert(ip, name.clone());
+        return name;
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use chrono::{Local, Utc};
+
+use api::{PacketType, parse_labels};
+
+use crate::{
+    disconnect_message, format_labels, gap_message, invalid_utf8_marker, log_item_glyph, peer_label, sanitize_for_terminal, secs_since_epoch,
+    logger::Log, LogItem, State, WarnStates,
+};
+
+//Write what the channel on screen shows to ww-snapshot-<Time>.txt in the working directory, for
+//incident notes: the warn state, who's connected and the whole packet log, including what has
+//scrolled off the screen. Plain text, so it can be searched and pasted. Returns the file's name.
+pub fn save(state: &State, log: Arc<Mutex<Log>>) -> io::Result<String> {
+    let now = Local::now();
+    let (taken_at, file_time) = if state.clock.is_utc {
+        let now = now.with_timezone(&Utc);
+        (now.format("%Y-%m-%d %H:%M:%S UTC").to_string(), now.format("%Y%m%d-%H%M%S").to_string())
+    }
+    else {
+        (now.format("%Y-%m-%d %H:%M:%S").to_string(), now.format("%Y%m%d-%H%M%S").to_string())
+    };
+    let file_name = format!("ww-snapshot-{}.txt", file_time);
+
+    let mut out = File::create(&file_name)?;
+    write_snapshot(&mut out, state, &taken_at, log)?;
+    return Ok(file_name);
+}
+
+fn write_snapshot(out: &mut impl Write, state: &State, taken_at: &str, log: Arc<Mutex<Log>>) -> io::Result<()> {
+    let channel = state.active();
+    writeln!(out, "warning_window snapshot, {}", taken_at)?;
+    if let Some(title) = &state.title {
+        writeln!(out, "Title: {}", sanitize_for_terminal(title))?;
+    }
+    writeln!(out, "Channel: {}", channel.name)?;
+    match (channel.is_acknowledged, &channel.acknowledged_by) {
+        (true, Some(operator)) => writeln!(out, "State: {}, acknowledged by {}", channel.warn_state.to_string(), operator)?,
+        (true, None) => writeln!(out, "State: {}, acknowledged", channel.warn_state.to_string())?,
+        (false, _) => writeln!(out, "State: {}", channel.warn_state.to_string())?,
+    }
+    if state.min_state != WarnStates::None {
+        writeln!(out, "Floor: {}", state.min_state.to_string())?;
+    }
+    if let (Some((key, value)), true) = (&state.filter, state.is_filtering) {
+        writeln!(out, "Filter: {}={} (the log below is unfiltered)", key, value)?;
+    }
+    if state.muted.len() > 0 {
+        let muted: Vec<String> = state.muted.iter()
+            .map(|ip| state.shown_pseudonyms().map_or(ip.to_string(), |pseudonyms| pseudonyms.show_ip(ip)))
+            .collect();
+        writeln!(out, "Muted: {}", muted.join(", "))?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "Named clients:")?;
+    for (peer_addr, name) in channel.peer_names.iter() {
+        let peer = state.peer_display().show(peer_addr);
+        match channel.peer_labels.get(peer_addr) {
+            Some(labels) => writeln!(out, "  {} ({}) [{}]", sanitize_for_terminal(name), peer, format_labels(labels))?,
+            None => writeln!(out, "  {} ({})", sanitize_for_terminal(name), peer)?,
+        }
+    }
+
+    writeln!(out)?;
+    writeln!(out, "Packet log, newest first:")?;
+    let now = SystemTime::now();
+    let peers = state.peer_display();
+    for log_item in channel.packet_log.iter() {
+        let time = state.clock.format_log_time(log_item.timestamp(), secs_since_epoch(log_item.timestamp(), Arc::clone(&log)), now);
+        let (glyph, _) = log_item_glyph(log_item, state.ascii_only, None);
+        let entry = match log_item {
+            LogItem::ConnectLogItem { peer_addr, .. } => format!("{} has successfully associated.", peers.show(peer_addr)),
+            LogItem::DisconnectLogItem { peer_addr, how, .. } => format!("{} {}", peers.show(peer_addr), disconnect_message(*how)),
+            LogItem::GapLogItem { peer_addr, missed, .. } => format!("{} {}", peers.show(peer_addr), gap_message(*missed)),
+            LogItem::PacketLogItem { peer_addr, packet, .. } => {
+                let mut peer = peer_label(&packet.packet_type, peer_addr, &channel.peer_names, peers);
+                if let Some(labels) = channel.peer_labels.get(peer_addr) {
+                    if packet.packet_type != PacketType::Metadata {
+                        peer.push_str(&format!(" [{}]", format_labels(labels)));
+                    }
+                }
+                let mut msg = match packet.packet_type {
+                    PacketType::Metadata => format_labels(&parse_labels(packet.text.as_deref().unwrap_or("")).unwrap_or_default()),
+                    _ => sanitize_for_terminal(packet.text.as_deref().unwrap_or("")),
+                };
+                if packet.has_invalid_utf8 {
+                    msg = format!("{} {}", invalid_utf8_marker(state.ascii_only), msg);
+                }
+                format!("{} | {} | {}", packet.packet_type.to_string(), peer, msg)
+            },
+        };
+        writeln!(out, "[{}] {} {}", time, glyph, entry)?;
+    }
+    return Ok(());
+}
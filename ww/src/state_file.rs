@@ -0,0 +1,115 @@
+use std::{
+    fs,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use crate::{logger::Log, State, WarnStates};
+
+//With --state-file, the warn state as a small JSON file, for tools that would rather poll a file
+//than speak the protocol, e.g. a status bar module running cat on it:
+//
+//  {"state":"alert","channels":[{"name":"main","state":"alert","acknowledged":false}]}
+//
+//state is the most severe of the channels'. The file is rewritten whenever any of it changes, and
+//says none once ww quits. Each write goes to a temporary file beside it that's renamed over it, so
+//a reader never sees half a file.
+pub struct StateFile {
+    path: String,
+    //What the file was last written with, to only write when it changes.
+    contents: Option<String>,
+}
+
+impl StateFile {
+    pub fn new(path: &str) -> Self {
+        return StateFile { path: path.to_string(), contents: None };
+    }
+
+    //Write the state out if it changed since the last write.
+    pub fn update(&mut self, state: &State) -> io::Result<()> {
+        let contents = format_state(state);
+        if self.contents.as_ref() == Some(&contents) {
+            return Ok(());
+        }
+        //Whether or not it works, so a failing write is reported once, not every frame.
+        self.contents = Some(contents.clone());
+        return self.write(&contents);
+    }
+
+    //Leave the file saying nothing is wrong, as nothing is being watched any more.
+    pub fn close(&mut self, log: &Arc<Mutex<Log>>) {
+        let contents = format!("{{\"state\":\"{}\",\"channels\":[]}}\n", state_name(WarnStates::None));
+        if let Err(e) = self.write(&contents) {
+            writeln!(log.lock().unwrap(), "ERROR: Could not write the state file {}: {}", self.path, e).unwrap();
+        }
+    }
+
+    fn write(&self, contents: &str) -> io::Result<()> {
+        let temp_path = format!("{}.tmp", self.path);
+        fs::write(&temp_path, contents)?;
+        return fs::rename(&temp_path, &self.path);
+    }
+}
+
+fn state_name(warn_state: WarnStates) -> &'static str {
+    match warn_state {
+        WarnStates::None => "none",
+        WarnStates::Warn => "warn",
+        WarnStates::Alert => "alert",
+        WarnStates::Critical => "critical",
+    }
+}
+
+fn format_state(state: &State) -> String {
+    let overall = state.channels.iter().map(|channel| channel.warn_state).max().unwrap_or(WarnStates::None);
+    let channels: Vec<String> = state.channels.iter()
+        .map(|channel| format!("{{\"name\":{},\"state\":\"{}\",\"acknowledged\":{}}}", json_string(&channel.name), state_name(channel.warn_state), channel.is_acknowledged))
+        .collect();
+    return format!("{{\"state\":\"{}\",\"channels\":[{}]}}\n", state_name(overall), channels.join(","));
+}
+
+//Text as a quoted JSON string.
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\u{0}'..='\u{1f}' => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    return quoted;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_state;
+
+    #[test]
+    fn writes_the_state_as_it_changes_and_none_once_closed() {
+        let path = std::env::temp_dir().join(format!("ww-state-file-test-{}.json", std::process::id()));
+        let mut state_file = StateFile::new(path.to_str().unwrap());
+        let mut state = test_state();
+        state_file.update(&state).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"state\":\"none\",\"channels\":[{\"name\":\"default\",\"state\":\"none\",\"acknowledged\":false}]}\n");
+
+        state.channels[0].warn_state = WarnStates::Alert;
+        state.channels[0].is_acknowledged = true;
+        state_file.update(&state).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"state\":\"alert\",\"channels\":[{\"name\":\"default\",\"state\":\"alert\",\"acknowledged\":true}]}\n");
+
+        state_file.close(&Arc::new(Mutex::new(Log::new(None))));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"state\":\"none\",\"channels\":[]}\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn says_the_most_severe_channels_state() {
+        let mut state = test_state();
+        state.channels.push(crate::Channel::new("db \"primary\"".to_string(), WarnStates::Warn));
+        assert_eq!(format_state(&state), "{\"state\":\"warn\",\"channels\":[{\"name\":\"default\",\"state\":\"none\",\"acknowledged\":false},{\"name\":\"db \\\"primary\\\"\",\"state\":\"warn\",\"acknowledged\":false}]}\n");
+    }
+}
@@ -0,0 +1,83 @@
+use std::env;
+use std::process::ExitCode;
+
+//How long to wait between attempts to reconnect to the event socket.
+#[cfg(unix)]
+const RECONNECT_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+fn print_usage() {
+    eprintln!("Usage: ww-attach [Options] <Path>");
+    eprintln!("Print every event of a warning_window server started with --event-socket <Path>, one per line, as they happen.");
+    eprintln!("If the server goes away, a line starting with # is printed and ww-attach keeps trying to reconnect.");
+
+    eprintln!("--once: Exit when the server goes away instead of reconnecting.");
+    eprintln!("--help: Show usage and exit.");
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.iter().any(|arg| arg == "--help") {
+        print_usage();
+        return ExitCode::SUCCESS;
+    }
+
+    let is_once = args.iter().any(|arg| arg == "--once");
+
+    //What's left is the path.
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--once").collect();
+    if paths.len() != 1 {
+        print_usage();
+        return ExitCode::FAILURE;
+    }
+
+    return attach(paths[0], is_once);
+}
+
+#[cfg(unix)]
+fn attach(path: &str, is_once: bool) -> ExitCode {
+    use api::EventSubscriber;
+
+    //Whether the last attempt got through, so a dashboard that stays down is only reported once.
+    let mut was_connected = true;
+    loop {
+        match EventSubscriber::connect(path) {
+            Ok(mut events) => {
+                println!("# Attached to {}.", path);
+                was_connected = true;
+                loop {
+                    match events.next_event() {
+                        Ok(Some(event)) => println!("{}", event),
+                        Ok(None) => break,
+                        Err(e) => {
+                            println!("# Lost {}: {}", path, e);
+                            break;
+                        },
+                    }
+                }
+                println!("# Detached from {}.", path);
+            },
+            Err(e) => {
+                if is_once {
+                    eprintln!("Could not attach to {}: {}", path, e);
+                    return ExitCode::FAILURE;
+                }
+                if was_connected {
+                    println!("# Could not attach to {}: {}. Retrying.", path, e);
+                    was_connected = false;
+                }
+            },
+        }
+
+        if is_once {
+            return ExitCode::SUCCESS;
+        }
+        std::thread::sleep(RECONNECT_DELAY);
+    }
+}
+
+#[cfg(not(unix))]
+fn attach(_path: &str, _is_once: bool) -> ExitCode {
+    eprintln!("ww-attach needs --event-socket, which is only supported on Unix.");
+    return ExitCode::FAILURE;
+}
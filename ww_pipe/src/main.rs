@@ -0,0 +1,159 @@
+use api::{PacketType, Session, SessionError, DEFAULT_PORT, MAX_MSG_LEN};
+
+use std::env;
+use std::io::{self, BufRead, Read, Write};
+use std::process::ExitCode;
+
+fn print_usage() {
+    eprintln!("Usage: ww-pipe [Options]");
+    eprintln!("Send every line read from stdin to a warning_window server as it arrives, until stdin closes.");
+    eprintln!("Empty lines are skipped. A line too long for one packet is sent as several.");
+
+    eprintln!("--server <Address>: The server to send to, as host:port, with IPv6 addresses in brackets like [::1]:{} (default localhost:{}).", DEFAULT_PORT, DEFAULT_PORT);
+    eprintln!("--as <Kind>: Send each line as info, warn, alert or critical (default info).");
+    eprintln!("--help: Show usage and exit.");
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    if args.iter().any(|arg| arg == "--help") {
+        print_usage();
+        return ExitCode::SUCCESS;
+    }
+
+    let server_addr;
+    if let Some(i) = args.iter().position(|arg| arg == "--server") {
+        if i + 1 < args.len() {
+            server_addr = args[i + 1].clone();
+            args.drain(i..=i + 1);
+        }
+        else {
+            print_usage();
+            return ExitCode::FAILURE;
+        }
+    }
+    else {
+        server_addr = format!("localhost:{}", DEFAULT_PORT);
+    }
+
+    let kind;
+    if let Some(i) = args.iter().position(|arg| arg == "--as") {
+        kind = match args.get(i + 1).map(|s| s.as_str()) {
+            Some("info") => PacketType::Info,
+            Some("warn") => PacketType::Warn,
+            Some("alert") => PacketType::Alert,
+            Some("critical") => PacketType::Critical,
+            _ => {
+                print_usage();
+                return ExitCode::FAILURE;
+            },
+        };
+        args.drain(i..=i + 1);
+    }
+    else {
+        kind = PacketType::Info;
+    }
+
+    if args.len() > 0 {
+        print_usage();
+        return ExitCode::FAILURE;
+    }
+
+    let mut session = match Session::connect(&server_addr) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Could not connect to {}: {}", server_addr, e);
+            return ExitCode::FAILURE;
+        },
+    };
+
+    if let Err(e) = pipe(io::stdin().lock(), &mut session, kind) {
+        eprintln!("Could not send to {}: {}", server_addr, e);
+        return ExitCode::FAILURE;
+    }
+
+    //Stdin closed. Dropping the session closes the connection, which the server logs as a
+    //disconnect.
+    return ExitCode::SUCCESS;
+}
+
+//Send each line of input as a packet of kind, as soon as it's read. A send blocks while the server
+//isn't keeping up, so a fast writer is held back rather than lines piling up here.
+fn pipe<S: Read + Write>(input: impl BufRead, session: &mut Session<S>, kind: PacketType) -> Result<(), SessionError> {
+    for line in input.split(b'\n') {
+        //Piped output isn't always UTF-8; keep what can be shown.
+        let line = line?;
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches('\r');
+
+        for part in split_to_packets(line) {
+            session.send_packet(kind, Some(part))?;
+        }
+    }
+    return Ok(());
+}
+
+//Split a line into pieces that each fit in a packet, never splitting a character. An empty line has
+//no pieces.
+fn split_to_packets(line: &str) -> Vec<&str> {
+    let mut parts: Vec<&str> = Vec::new();
+    let mut rest = line;
+    while rest.len() > 0 {
+        let mut end = std::cmp::min(rest.len(), MAX_MSG_LEN);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (part, remainder) = rest.split_at(end);
+        parts.push(part);
+        rest = remainder;
+    }
+    return parts;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{MemoryStream, ASSOC_ACCEPT};
+    use std::io::Cursor;
+
+    //Everything pipe sends for input, as (type byte, text) per packet.
+    fn piped(input: &str, kind: PacketType) -> Vec<(u8, String)> {
+        let (client, mut server) = MemoryStream::pair();
+        server.write_all(&ASSOC_ACCEPT).unwrap();
+        let mut session = Session::from_stream(client).unwrap();
+        pipe(Cursor::new(input.as_bytes()), &mut session, kind).unwrap();
+        drop(session);
+
+        let mut sent = Vec::new();
+        server.read_to_end(&mut sent).unwrap();
+        //Past the association request, one packet after another, length byte first.
+        let mut packets = Vec::new();
+        let mut rest = &sent[2..];
+        while let Some((&num_bytes, after)) = rest.split_first() {
+            let (packet, after) = after.split_at(num_bytes as usize);
+            packets.push((packet[0], String::from_utf8(packet[1..].to_vec()).unwrap()));
+            rest = after;
+        }
+        return packets;
+    }
+
+    #[test]
+    fn sends_each_line_as_a_packet_skipping_empty_ones() {
+        let warn = PacketType::Warn.to_type_number();
+        assert_eq!(piped("disk full\r\n\nstill full\n", PacketType::Warn), [(warn, "disk full".to_string()), (warn, "still full".to_string())]);
+        //The last line needn't end in a newline.
+        assert_eq!(piped("done", PacketType::Warn), [(warn, "done".to_string())]);
+    }
+
+    #[test]
+    fn splits_a_long_line_between_characters() {
+        //Two-byte characters after one one-byte one, so MAX_MSG_LEN falls inside a character.
+        let line = format!("a{}", "é".repeat(MAX_MSG_LEN));
+        let packets = piped(&format!("{}\n", line), PacketType::Info);
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[0].1.len(), MAX_MSG_LEN - 1);
+        assert!(packets.iter().all(|(packet_type, text)| *packet_type == PacketType::Info.to_type_number() && text.len() <= MAX_MSG_LEN));
+        assert_eq!(packets.iter().map(|(_, text)| text.as_str()).collect::<String>(), line);
+    }
+}
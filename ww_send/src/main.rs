@@ -0,0 +1,110 @@
+use api::{Session, COMPRESSION_SINCE_VERSION, DEFAULT_PORT, MAX_COMPRESSED_MSG_LEN};
+
+use std::env;
+use std::process::ExitCode;
+
+fn print_usage() {
+    eprintln!("Usage: ww-send [Options] <Command> [Message]");
+    eprintln!("Send exactly one packet to a warning_window server and exit.");
+
+    eprintln!("Commands:");
+    eprintln!("  info <Message>: Send an INFO packet. The message must be non-empty.");
+    eprintln!("  warn [Message]: Send a WARN packet.");
+    eprintln!("  alert [Message]: Send an ALERT packet.");
+    eprintln!("  critical [Message]: Send a CRITICAL packet, above ALERT. Older servers drop the connection over it.");
+    eprintln!("  reset: Reset the server's warn state.");
+    eprintln!("  name <Name>: Change this connection's name. Mostly useful for testing.");
+
+    eprintln!("--server <Address>: The server to send to, as host:port, with IPv6 addresses in brackets like [::1]:{} (default localhost:{}).", DEFAULT_PORT, DEFAULT_PORT);
+    eprintln!("--compress: Compress long messages, so up to {} bytes can be sent if they compress well enough. Servers older than {} don't support it, and get the message uncompressed.", MAX_COMPRESSED_MSG_LEN, COMPRESSION_SINCE_VERSION);
+    eprintln!("--help: Show usage and exit.");
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    if args.iter().any(|arg| arg == "--help") {
+        print_usage();
+        return ExitCode::SUCCESS;
+    }
+
+    let server_addr;
+    if let Some(i) = args.iter().position(|arg| arg == "--server") {
+        if i + 1 < args.len() {
+            server_addr = args[i + 1].clone();
+            args.drain(i..=i + 1);
+        }
+        else {
+            print_usage();
+            return ExitCode::FAILURE;
+        }
+    }
+    else {
+        server_addr = format!("localhost:{}", DEFAULT_PORT);
+    }
+
+    let compress;
+    if let Some(i) = args.iter().position(|arg| arg == "--compress") {
+        compress = true;
+        args.remove(i);
+    }
+    else {
+        compress = false;
+    }
+
+    //What's left is the command and its message.
+    if args.len() == 0 || args.len() > 2 {
+        print_usage();
+        return ExitCode::FAILURE;
+    }
+    let command = args[0].as_str();
+    let msg = args.get(1).map(|s| s.as_str()).unwrap_or("");
+
+    let mut session = match Session::connect(&server_addr) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Could not connect to {}: {}", server_addr, e);
+            return ExitCode::FAILURE;
+        },
+    };
+
+    if compress {
+        if session.enable_compression().is_err() {
+            eprintln!("{} is too old to take compressed messages; sending uncompressed.", server_addr);
+        }
+    }
+
+    let result = match command {
+        "info" => {
+            if msg.len() == 0 {
+                eprintln!("INFO messages must be non-empty.");
+                return ExitCode::FAILURE;
+            }
+            session.send_info(msg)
+        },
+        "warn" => session.send_warn(msg),
+        "alert" => session.send_alert(msg),
+        "critical" => session.send_critical(msg),
+        "reset" => session.reset(),
+        "name" => {
+            if msg.len() == 0 {
+                eprintln!("Names must be non-empty.");
+                return ExitCode::FAILURE;
+            }
+            session.change_name(msg)
+        },
+        _ => {
+            eprintln!("Unknown command: {}", command);
+            print_usage();
+            return ExitCode::FAILURE;
+        },
+    };
+
+    if let Err(e) = result {
+        eprintln!("Could not send to {}: {}", server_addr, e);
+        return ExitCode::FAILURE;
+    }
+
+    //Dropping the session closes the connection, which the server logs as a disconnect.
+    return ExitCode::SUCCESS;
+}